@@ -1,252 +1,3311 @@
 
 use cpal::traits::{HostTrait, DeviceTrait, StreamTrait};
 
-use std::sync::{Arc, Mutex};
-use std::sync::mpsc;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::io::{Read, Seek};
+use std::path::Path;
+use std::time::Duration;
+
+mod agc;
+mod builder;
+mod cache;
+mod capture;
+mod chapter;
+mod command;
+mod control;
+mod correlation;
+mod crossfader;
+mod denormal;
+mod effect;
+mod error;
+mod event;
+#[cfg(feature = "flac-encode")]
+mod flac_encoder;
+mod follow;
+#[cfg(target_os = "android")]
+mod focus;
+mod format;
+mod loudness;
+#[cfg(any(feature = "flac", feature = "flac-encode"))]
+mod md5;
+mod metadata;
+mod mirror;
+mod normalize;
+mod null;
+mod pace;
+mod playlist;
+mod priority;
+mod queue;
+mod recorder;
+mod registry;
+mod render;
+mod replay_gain;
+mod report;
+mod resample;
+mod safe;
+mod socket;
+mod sink;
+mod source;
+mod splitter;
+mod stretch;
+mod system_volume;
+mod timestamp;
+mod timing;
+mod transport;
+mod trim;
+#[cfg(feature = "true-peak")]
+mod true_peak;
+mod watchdog;
+mod waveform;
+
+pub use self::builder::{AudioCableBuilder, ChannelFill, DeviceRatePolicy, ShareMode};
+pub use self::cache::CachedSource;
+pub use self::chapter::Chapter;
+pub use self::command::ControlChannel;
+pub use self::control::PlaybackControl;
+pub use self::crossfader::{CrossfadeCurve, Crossfader};
+pub use self::effect::{Effect, VolumeStage};
+pub use self::error::AudioError;
+pub use self::event::PlaybackEvent;
+#[cfg(target_os = "android")]
+pub use self::focus::{AudioFocus, AudioFocusHandler};
+pub use self::format::{supported_extensions, Format, PcmFormat};
+pub use self::loudness::measure_loudness;
+pub use self::metadata::{available_audio_tracks, AudioTrackInfo, BroadcastInfo, BroadcastLoudness, TrackMetadata};
+pub use self::null::OutputMode;
+pub use self::playlist::PlaylistCursor;
+pub use self::priority::CallbackPriority;
+pub use self::queue::RepeatMode;
+pub use self::recorder::{AudioRecorder, FlacOptions, WavSampleFormat};
+pub use self::registry::{CableId, CableRegistry};
+pub use self::render::{first_difference, preview, render_to_vec, samples_equal, transcode, verify_integrity, RenderedAudio};
+pub use self::replay_gain::ReplayGainMode;
+pub use self::resample::ResampleQuality;
+pub use self::safe::SafeOutput;
+pub use self::sink::SampleSink;
+pub use self::system_volume::{set_system_volume, system_volume};
+pub use self::timing::CallbackTiming;
+pub use self::waveform::waveform_peaks;
+
+use self::capture::InputCapture;
+use self::command::CommandSender;
+use self::correlation::PhaseMeter;
+use self::follow::DefaultDeviceFollower;
+use self::denormal::FlushToZero;
+use self::effect::Gain;
+use self::format::SNIFF_LEN;
+use self::metadata::MetadataFeed;
+use self::mirror::{ClockSync, MirrorOutput, MirrorTap, MIRROR_QUEUE_BLOCKS};
+use self::normalize::{scan_peak, PeakNormalizer};
+use self::null::{NullOutput, NULL_CHANNELS, NULL_SAMPLE_RATE};
+use self::queue::{Track, TrackQueue, DEFAULT_PREFETCH_TRACKS};
+use self::report::{RealtimeMessage, RealtimeReporter};
+use self::resample::Resampler;
+use self::safe::Limiter;
+use self::socket::SocketOutput;
+use self::source::{open_reader, AudioSource, CoverArt, DeviceSource, ManualSource, IcyReader, IcyStream, PcmSource, PipeReader, ReadSeek, TrackChoice};
+use self::splitter::Splitter;
+use self::stretch::{TimeStretch, TEMPO_RANGE};
+use self::timestamp::PlaybackTimestamp;
+use self::watchdog::{Reopen, Watchdog, RESTART_DELAY};
+use self::timing::CallbackTimer;
+use self::trim::SilenceTrim;
+use self::transport::FrameReceiver;
+#[cfg(feature = "flac")]
+use self::source::FlacSource;
+#[cfg(any(feature = "aac", feature = "symphonia"))]
+use self::source::SymphoniaSource;
+#[cfg(feature = "symphonia")]
+use self::source::LargeWavSource;
+#[cfg(feature = "tokio")]
+use self::source::AsyncReadBridge;
+
+// Number of frames the producer may decode ahead of the output device, unless the builder asks
+// for another capacity. The data channel is bounded to this many frames, so once it fills the
+// decode thread stops decoding until the consumer drains it. This keeps memory flat regardless
+// of the source file's length.
+const DEFAULT_CAPACITY_FRAMES: usize = 8192;
+
+// Frames the producer must have buffered before the output stream starts, so playback doesn't
+// open with an underrun. Capped at the data channel's capacity.
+const DEFAULT_WARMUP_FRAMES: usize = 2048;
+
+// Longest the warmup waits for the producer before starting the stream anyway.
+const WARMUP_TIMEOUT: Duration = Duration::from_millis(500);
+
+// Data channel capacity AudioCable::monitor uses, kept small since every buffered frame delays
+// the speakers behind the microphone.
+const MONITOR_CAPACITY_FRAMES: usize = 1024;
+
+// How much captured audio may queue up for the recorder's writer thread.
+const RECORDER_QUEUE: Duration = Duration::from_secs(1);
+
+// Sample formats tried when picking the output configuration, best first. The pipeline is f32
+// throughout, so F32 needs no conversion in the output callback.
+const DEFAULT_SAMPLE_FORMATS: [cpal::SampleFormat; 3] = [cpal::SampleFormat::F32, cpal::SampleFormat::I16, cpal::SampleFormat::U16];
+
+// Sample rates a device configuration may use. Anything outside is a bogus value from a broken
+// or virtual device rather than a real rate.
+const SAMPLE_RATE_RANGE: std::ops::RangeInclusive<u32> = 8_000..=768_000;
+
+// How often AudioProducer::wait_for_samples checks on the producer, and a mirror retries its full
+// data channel.
+const PRODUCER_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+// How often AudioCable::stop_draining checks whether the output has played everything.
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+// Frames of start padding sent at a time.
+const PADDING_BLOCK_FRAMES: u64 = 4096;
+
+// How often a producer at the end of its track checks whether a queued track it is waiting for
+// was skipped.
+const QUEUE_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+// Output gains at or below this are treated as silence by the decibel volume API.
+pub const MIN_OUTPUT_GAIN_DB: f32 = -60.0;
 
 #[allow(dead_code)]
 enum AudioChannelMessage {
 	SendNext(i32),
-	NOP
+	NOP,
+	// Move the source to this offset from its start.
+	Seek(Duration),
+	// Resample to this rate from now on; the consumer's stream was rebuilt at it.
+	SetOutputRate(u32),
+	// Change the playback speed by this ratio without changing the pitch.
+	SetTempo(f32),
+	// Loop the current track between these source frames, or stop looping.
+	SetLoopPoints(Option<(u64, u64)>),
+	// Play the current track this many times in all, counting the pass under way; 0 for
+	// endlessly.
+	SetLoopCount(u32),
+	// Start decoding. The thread holds off until the cable is first played.
+	Start,
+	// Start decoding as Start does, and send PlaybackEvent::Cued once the data channel is full.
+	Cue,
+	// Stop or resume decoding and sending, leaving the output alone.
+	SetSourcePaused(bool),
+	// Stop decoding and end the producer thread.
+	Stop,
+	// End the producer thread like Stop, but leave what was sent in the data channel to be played.
+	StopSending,
 }
 
 
 enum SourceType {
+	#[cfg(feature = "flac")]
 	FLAC(String),
+	// Anything symphonia decodes: AAC-LC (.aac, .m4a) with the aac feature, every Format with the
+	// symphonia feature.
+	#[cfg(any(feature = "aac", feature = "symphonia"))]
+	SYMPHONIA(String),
+	// Decoded from a reader handed to AudioProducer::from_reader.
+	READER,
+	// Captured from an input device.
+	DEVICE,
 	SOURCELESS,
 	UNSUPPORTED
 }
 impl SourceType {
-	fn from_local(file: String) -> SourceType {
-		let mut split = file.split(".");
-		// This doesn't support non-trivial file names. (e.g., untitled.new.flac which would be
-		// processed as (untitled, new) instead of (untitled.new, flac) as a
-		// (filename, extension) tuple)
-		// Discard filename
-		split.next().expect("empty string provided for audio source.").to_string();
-		// Use extension
-		let file_extension = split.next().expect("source audio file has no file extension.").to_string();
+	fn from_local(file: String) -> Result<SourceType, AudioError> {
+		// Empty paths and paths made only of dots have neither a filename nor an extension.
+		if file.chars().all(|c| c == '.') {
+			return Err(AudioError::InvalidPath(file));
+		}
+		// A trailing dot names no source at all.
+		if Path::new(&file).extension().is_some_and(|extension| extension.is_empty()) {
+			return Ok(SourceType::SOURCELESS);
+		}
+		// There is no WavPack decoder to build in, so .wv files are named as such rather than
+		// reported as an unknown extension.
+		if Path::new(&file).extension().is_some_and(|extension| extension.eq_ignore_ascii_case("wv")) {
+			return Err(AudioError::Unsupported(String::from("WavPack")));
+		}
+
+		match Format::from_path(Path::new(&file)) {
+			// claxon stays the FLAC decoder whenever it's built; the symphonia backend takes over
+			// every other format.
+			#[cfg(feature = "flac")]
+			Ok(Format::FLAC) => Ok(SourceType::FLAC(file)),
+			#[cfg(feature = "symphonia")]
+			Ok(_) => Ok(SourceType::SYMPHONIA(file)),
+			#[cfg(all(feature = "aac", not(feature = "symphonia")))]
+			Ok(Format::AAC) => Ok(SourceType::SYMPHONIA(file)),
+			// Formats only report themselves enabled once a decoder for them is built, so this
+			// only guards against the two falling out of step.
+			#[cfg(not(feature = "symphonia"))]
+			Ok(format) => Err(AudioError::UnsupportedFormat { extension: String::from(format.extension()), feature: Some(format.feature()) }),
+			// Unknown extensions, and known ones whose decoder wasn't built, are reported with
+			// the extension rather than playing silence.
+			Err(err) => Err(err)
+		}
+	}
+
+	// Opens the file of a local source type up front, so a missing or malformed file is reported
+	// to the caller rather than killing the decode thread. None when there is nothing to decode.
+	fn open(&self) -> Result<Option<Box<dyn AudioSource>>, AudioError> {
+		match self {
+			#[cfg(feature = "flac")]
+			SourceType::FLAC(flac_file) => Ok(Some(Box::new(FlacSource::open(flac_file)?))),
+			#[cfg(any(feature = "aac", feature = "symphonia"))]
+			SourceType::SYMPHONIA(file) => {
+				// symphonia only reads WAV files in plain RIFF; RF64 and Wave64 are read here.
+				#[cfg(feature = "symphonia")]
+				if Format::from_path(Path::new(file)).ok() == Some(Format::WAV) {
+					if let Ok(source) = LargeWavSource::probe(std::fs::File::open(file)?)? {
+						return Ok(Some(Box::new(source)));
+					}
+				}
+				Ok(Some(Box::new(SymphoniaSource::open(file)?)))
+			},
+			SourceType::SOURCELESS => Ok(None),
+			SourceType::READER | SourceType::DEVICE | SourceType::UNSUPPORTED => Err(AudioError::Unsupported(String::from("there is no decoder for this source")))
+		}
+	}
 
-		match file_extension.to_lowercase().as_str() {
-			"flac" => SourceType::FLAC(file),
-			"" => SourceType::SOURCELESS,
-			_ => SourceType::UNSUPPORTED
+	// The file a local source type plays.
+	fn path(&self) -> Option<&str> {
+		match self {
+			#[cfg(feature = "flac")]
+			SourceType::FLAC(file) => Some(file),
+			#[cfg(any(feature = "aac", feature = "symphonia"))]
+			SourceType::SYMPHONIA(file) => Some(file),
+			_ => None
 		}
 	}
 
+	#[allow(dead_code)]
 	fn from_stream() -> SourceType {
 		SourceType::UNSUPPORTED
 	}
 }
 
+#[allow(dead_code)]
 struct PhyiscalAudioDevice {
 	host: cpal::Host,
 	device: cpal::Device,
-	supported_configs_range: cpal::SupportedOutputConfigs,
+	supported_configs: Vec<cpal::SupportedStreamConfigRange>,
 	sample_format: cpal::SampleFormat,
 	config: cpal::StreamConfig,
 	stream: Option<cpal::Stream>,
 }
+// Only ever one per consumer, so the size of the PHYSICAL variant (bigger still with the JACK
+// host compiled in) isn't worth boxing.
+#[allow(dead_code, clippy::large_enum_variant)]
 enum AudioDevice {
 	PHYSICAL(PhyiscalAudioDevice),
-	VIRUTAL,
+	// Pulled by the application through AudioConsumer::fill, in this layout.
+	VIRUTAL(cpal::StreamConfig),
 	NONE
 }
 /* Sink Trait: A trait intended for a Consumer that discards the passed audio.
  */
+#[allow(dead_code)]
 trait AudioSink {
-	fn new(rx_channel: mpsc::Receiver<f32>) -> Self;
-	fn connect(&mut self) -> ();
+	fn new(rx_channel: crossbeam_channel::Receiver<f32>) -> Self;
+	fn connect(&mut self);
+}
+// Lets the producer side throw away samples that are queued but no longer wanted, e.g. after a
+// seek. Only drain while nothing is sending, or fresh samples may be lost too.
+#[derive(Clone)]
+struct StaleSampleDrain(Arc<Mutex<FrameReceiver>>, Arc<AtomicU64>);
+
+impl StaleSampleDrain {
+	fn drain(&self) {
+		self.0.lock().unwrap_or_else(PoisonError::into_inner).reset();
+	}
+
+	fn sent_samples(&self) -> &AtomicU64 {
+		&self.1
+	}
+}
+
+// Output-side settings collected by the AudioCableBuilder.
+#[derive(Clone, Debug)]
+struct ConsumerOptions {
+	share_mode: ShareMode,
+	warmup_frames: usize,
+	capacity_frames: usize,
+	// Name of the output device to open instead of the default one.
+	output_device: Option<String>,
+	// Output sample formats in order of preference.
+	sample_formats: Vec<cpal::SampleFormat>,
+	// Name to register with JACK under instead of using the default host.
+	#[cfg(all(feature = "jack", target_os = "linux"))]
+	jack_client: Option<String>,
+	// Sample rate and channel count of an output pulled with AudioConsumer::fill instead of a
+	// device.
+	pull_output: Option<(u32, u16)>,
+	// The safe output stage, set up by the builder from a SafeOutput.
+	limiter: Option<Limiter>,
+	// Move the stream along with the OS default output device.
+	follow_default_device: bool,
+	// Longest the output callback waits for a late producer once the data channel runs dry.
+	underrun_wait: Duration,
+	// Raise the device callback's thread to realtime priority on its first callback.
+	realtime_priority: bool,
+}
+
+impl Default for ConsumerOptions {
+	fn default() -> Self {
+		ConsumerOptions {
+			share_mode: ShareMode::Shared,
+			warmup_frames: DEFAULT_WARMUP_FRAMES,
+			capacity_frames: DEFAULT_CAPACITY_FRAMES,
+			output_device: None,
+			sample_formats: DEFAULT_SAMPLE_FORMATS.to_vec(),
+			#[cfg(all(feature = "jack", target_os = "linux"))]
+			jack_client: None,
+			pull_output: None,
+			limiter: None,
+			follow_default_device: false,
+			underrun_wait: Duration::ZERO,
+			realtime_priority: false,
+		}
+	}
 }
-struct AudioConsumer {
-	data_channel: Arc<Mutex<mpsc::Receiver<f32>>>,
-	audio_device: AudioDevice
+
+/* AudioConsumer: Drains the data channel into an output device, or into the application's own
+ * buffers through fill.
+ */
+pub struct AudioConsumer {
+	data_channel: Arc<Mutex<FrameReceiver>>,
+	// Bound of the data channel, in samples.
+	capacity: usize,
+	audio_device: AudioDevice,
+	controls: Arc<ConsumerControls>,
+	// Samples the output callback has taken from the data channel.
+	played_samples: Arc<AtomicU64>,
+	// Samples put into the data channel, less those drained as stale. Senders count a sample
+	// before sending it, so past played_samples (read first) the difference never reads low.
+	sent_samples: Arc<AtomicU64>,
+	// Custom effects, run in order by the output callback ahead of the volume unless it's set to
+	// VolumeStage::PreEffects.
+	effects: Arc<Mutex<Vec<Box<dyn Effect>>>>,
+	// Underruns counted by the output callback, and its messages until the builder forwards them.
+	reporter: Arc<RealtimeReporter>,
+	reports: Option<crossbeam_channel::Receiver<RealtimeMessage>>,
+	// When the latest callback's first frame reaches the speakers.
+	timestamp: Arc<PlaybackTimestamp>,
+	// The stereo correlation the output callback last measured, as f32 bits.
+	correlation: Arc<AtomicU32>,
+	// How long the output callback takes.
+	timer: Arc<CallbackTimer>,
+	// What each output stream's safe output stage starts from.
+	limiter: Option<Limiter>,
+	underrun_wait: Duration,
+	realtime_priority: bool,
+	// Created by the first call to fill.
+	pull: Option<OutputStage>,
+	// What fill_samples converts a pulled output to, and its f32 scratch block.
+	pull_format: cpal::SampleFormat,
+	pull_block: Vec<f32>,
+	// Owns the stream instead of the consumer while it follows the default device.
+	follower: Option<DefaultDeviceFollower>,
+	// Pulls the output into a socket for a cable built with AudioCableBuilder::socket_output.
+	socket: Option<SocketOutput>,
+	// Drains the output for a consumer new_or_null found no device for.
+	null: Option<NullOutput>,
+	// Set by close, once the device has been let go.
+	closed: bool,
+}
+
+// Settings shared with the output callback. Gains are stored as f32 bits so the callback can read
+// them without locking.
+struct ConsumerControls {
+	volume: AtomicU32,
+	// One linear gain per output channel, applied on top of the master volume.
+	channel_gains: Vec<AtomicU32>,
+	// The output channel each channel of the audio is played on, identity unless remapped.
+	channel_map: Vec<AtomicUsize>,
+	// While set the callback outputs silence and leaves the data channel untouched.
+	paused: AtomicBool,
+	// While set the callback keeps draining the data channel but outputs silence.
+	muted: AtomicBool,
+	// Whether the master volume and mute come before the custom effects (VolumeStage::PreEffects).
+	volume_pre_effects: AtomicBool,
+	// Set by flush until the next callback has emptied the data channel.
+	flush: AtomicBool,
+}
+
+impl ConsumerControls {
+	fn new(channels: usize) -> ConsumerControls {
+		ConsumerControls {
+			volume: AtomicU32::new(1.0f32.to_bits()),
+			channel_gains: (0..channels).map(|_| AtomicU32::new(1.0f32.to_bits())).collect(),
+			channel_map: (0..channels).map(AtomicUsize::new).collect(),
+			paused: AtomicBool::new(false),
+			muted: AtomicBool::new(false),
+			volume_pre_effects: AtomicBool::new(false),
+			flush: AtomicBool::new(false),
+		}
+	}
+
+	fn set_volume(&self, volume: f32) {
+		self.volume.store(volume.max(0.0).to_bits(), Ordering::Relaxed);
+	}
+
+	fn volume(&self) -> f32 {
+		f32::from_bits(self.volume.load(Ordering::Relaxed))
+	}
+
+	fn set_paused(&self, paused: bool) {
+		self.paused.store(paused, Ordering::Relaxed);
+	}
+
+	fn paused(&self) -> bool {
+		self.paused.load(Ordering::Relaxed)
+	}
+
+	fn set_muted(&self, muted: bool) {
+		self.muted.store(muted, Ordering::Relaxed);
+	}
+
+	fn muted(&self) -> bool {
+		self.muted.load(Ordering::Relaxed)
+	}
+
+	fn set_volume_stage(&self, stage: VolumeStage) {
+		self.volume_pre_effects.store(stage == VolumeStage::PreEffects, Ordering::Relaxed);
+	}
+
+	fn volume_stage(&self) -> VolumeStage {
+		if self.volume_pre_effects.load(Ordering::Relaxed) { VolumeStage::PreEffects } else { VolumeStage::PostEffects }
+	}
+
+	fn flush(&self) {
+		self.flush.store(true, Ordering::Relaxed);
+	}
 }
 
 fn err_fn<T>(err: T) where T: std::fmt::Display {
 	eprintln!("an error occurred on the output audio stream: {}", err);
 }
 
+// Picks the device called `name` out of `devices`; `kind` says which sort for the error.
+fn find_device(mut devices: impl Iterator<Item = cpal::Device>, name: &str, kind: &str) -> Result<cpal::Device, AudioError> {
+	devices.find(|device| device.name().is_ok_and(|device_name| device_name == name))
+		.ok_or_else(|| AudioError::Device(format!("no {} device named \"{}\"", kind, name)))
+}
+
 impl AudioConsumer {
-	fn new(data_channel: mpsc::Receiver<f32>) -> AudioConsumer {
-		let host = cpal::default_host();
+	// Opens the requested or default output device and creates the data channel, sized in frames
+	// of the device's channel count. Returns the consumer with the sending half for the producer.
+	fn with_options(options: &ConsumerOptions) -> Result<(AudioConsumer, crossbeam_channel::Sender<f32>), AudioError> {
+		if let Some((sample_rate, channels)) = options.pull_output {
+			let config = cpal::StreamConfig { channels, sample_rate: cpal::SampleRate(sample_rate), buffer_size: cpal::BufferSize::Default };
+			return Ok(AudioConsumer::with_device(AudioDevice::VIRUTAL(config), channels as usize, options));
+		}
 
-		let device = host.default_output_device()
-			.expect("No default audio device.");
-		let mut supported_configs_range = device.supported_output_configs()
-			.expect("Error querying devices.");
-		let supported_config = supported_configs_range.next()
-			.expect("No supported stream configuration.")
-			.with_max_sample_rate();
+		let (physical_device, channels) = AudioConsumer::open_physical(options)?;
+		let (mut ac, data_sender) = AudioConsumer::with_device(AudioDevice::PHYSICAL(physical_device), channels, options);
+		// The stream is started by play once the producer has buffered some audio.
+		ac.build_stream()?;
+		Ok((ac, data_sender))
+	}
 
-		let mut ac = AudioConsumer {
-			data_channel: Arc::new(Mutex::new(data_channel)),
-			audio_device: AudioDevice::PHYSICAL(PhyiscalAudioDevice {
-				host,
-				device,
-				supported_configs_range,
-				sample_format: supported_config.sample_format(),
-				config: supported_config.into(),
-				stream: None
-			}),
-		};
-		ac.connect();
-		ac
+	// Plays what comes through `data_channel` on the default output device, in its default
+	// configuration, or if there is none to open, e.g. on a headless machine or in CI, on a null
+	// output that drains the channel in real time and discards the audio, with a warning; see
+	// NullOutput. Returns which was picked. The producer has to send frames of the layout
+	// active_config reports, 48 kHz stereo for the null output. The output starts with play.
+	pub fn new_or_null(data_channel: crossbeam_channel::Receiver<f32>) -> (AudioConsumer, OutputMode) {
+		let options = ConsumerOptions::default();
+		let capacity = data_channel.capacity().unwrap_or(0);
+		match AudioConsumer::open_physical(&options) {
+			Ok((physical_device, channels)) => {
+				let mut ac = AudioConsumer::with_receiver(AudioDevice::PHYSICAL(physical_device), channels, &options, data_channel, capacity);
+				match ac.build_stream() {
+					Ok(()) => (ac, OutputMode::Hardware),
+					Err(err) => ac.fall_back_to_null(err)
+				}
+			},
+			Err(err) => AudioConsumer::with_receiver(AudioDevice::NONE, NULL_CHANNELS as usize, &options, data_channel, capacity).fall_back_to_null(err)
+		}
 	}
 
-	fn connect(&mut self) -> () {
-		match &mut self.audio_device {
-			AudioDevice::PHYSICAL(physical_device) => {
-				let data_channel_arc = Arc::clone( & self.data_channel);
+	// Switches a consumer new_or_null couldn't open a device for, because of `err`, to the null
+	// output.
+	fn fall_back_to_null(mut self, err: AudioError) -> (AudioConsumer, OutputMode) {
+		eprintln!("no output device could be opened, playing to a null output instead: {}", err);
+		self.audio_device = AudioDevice::VIRUTAL(cpal::StreamConfig {
+			channels: NULL_CHANNELS,
+			sample_rate: cpal::SampleRate(NULL_SAMPLE_RATE),
+			buffer_size: cpal::BufferSize::Default
+		});
+		self.controls = Arc::new(ConsumerControls::new(NULL_CHANNELS as usize));
+		self.data_channel.lock().unwrap_or_else(PoisonError::into_inner).set_channels(NULL_CHANNELS as usize);
+		match NullOutput::start(self.output_stage(NULL_SAMPLE_RATE), Arc::clone(&self.timestamp)) {
+			Ok(null) => self.null = Some(null),
+			Err(err) => eprintln!("failed to start the null output: {}", err)
+		}
+		(self, OutputMode::Null)
+	}
+
+	// Opens the requested or default output device in its preferred configuration. Returns it with
+	// its channel count.
+	fn open_physical(options: &ConsumerOptions) -> Result<(PhyiscalAudioDevice, usize), AudioError> {
+		let (host, device) = open_output_device(options)?;
+		let supported_configs: Vec<_> = device.supported_output_configs()
+			.map_err(|err| AudioError::Device(format!("error querying device configurations: {}", err)))?
+			.collect();
+		let supported_config = preferred_config(&supported_configs, &options.sample_formats)
+			.ok_or_else(|| if supported_configs.is_empty() {
+				AudioError::Device(String::from("no supported stream configuration"))
+			} else if supported_configs.iter().any(|config| options.sample_formats.contains(&config.sample_format())) {
+				AudioError::InvalidConfig(format!("the output device only offers unusable configurations: {:?}", supported_configs))
+			} else {
+				AudioError::Device(format!("the output device supports none of the sample formats {:?}", options.sample_formats))
+			})?;
 
-				let clu = move | data: & mut [f32],
-								 _: & cpal::OutputCallbackInfo | {
-					let lock = match ( * data_channel_arc).lock() {
-						Ok(lock) => lock,
-						Err(_) => panic ! ("other thread panicked") // other thread panicked
-					};
+		let mut config: cpal::StreamConfig = supported_config.config();
+		apply_share_mode(options.share_mode, &device, &supported_config, &mut config)?;
+		let channels = config.channels as usize;
+		let physical_device = PhyiscalAudioDevice {
+			host,
+			device,
+			supported_configs,
+			sample_format: supported_config.sample_format(),
+			config,
+			stream: None
+		};
+		Ok((physical_device, channels))
+	}
 
-					let data_channel = & * lock;
+	// Creates the data channel, sized in frames of `channels`, for a consumer playing to
+	// `audio_device`.
+	fn with_device(audio_device: AudioDevice, channels: usize, options: &ConsumerOptions) -> (AudioConsumer, crossbeam_channel::Sender<f32>) {
+		let capacity = options.capacity_frames * channels.max(1);
+		let (data_sender, data_channel) = crossbeam_channel::bounded(capacity);
+		(AudioConsumer::with_receiver(audio_device, channels, options, data_channel, capacity), data_sender)
+	}
 
-					for sample in data.iter_mut() {
-						let s = data_channel.recv_timeout(std::time::Duration::from_millis(1)).unwrap_or(0.0f32);
-						//println!("{}", s);
-						* sample = cpal::Sample::from( & s);
-					}
-				};
+	// A consumer playing what comes through `data_channel`, bounded to `capacity` samples, to
+	// `audio_device`.
+	fn with_receiver(audio_device: AudioDevice, channels: usize, options: &ConsumerOptions, data_channel: crossbeam_channel::Receiver<f32>, capacity: usize) -> AudioConsumer {
+		let (reporter, reports) = RealtimeReporter::new();
+		let sent_samples = Arc::new(AtomicU64::new(0));
+
+		AudioConsumer {
+			data_channel: Arc::new(Mutex::new(FrameReceiver::new(data_channel, channels, Arc::clone(&sent_samples)))),
+			capacity,
+			audio_device,
+			controls: Arc::new(ConsumerControls::new(channels)),
+			played_samples: Arc::new(AtomicU64::new(0)),
+			sent_samples,
+			effects: Arc::new(Mutex::new(Vec::new())),
+			reporter,
+			reports: Some(reports),
+			timestamp: Arc::new(PlaybackTimestamp::new()),
+			correlation: Arc::new(AtomicU32::new(0.0f32.to_bits())),
+			timer: Arc::new(CallbackTimer::new()),
+			limiter: options.limiter.clone(),
+			underrun_wait: options.underrun_wait,
+			realtime_priority: options.realtime_priority,
+			pull: None,
+			pull_format: options.sample_formats.first().copied().unwrap_or(cpal::SampleFormat::F32),
+			pull_block: Vec::new(),
+			follower: None,
+			socket: None,
+			null: None,
+			closed: false,
+		}
+	}
+
+	fn stale_sample_drain(&self) -> StaleSampleDrain {
+		StaleSampleDrain(Arc::clone(&self.data_channel), Arc::clone(&self.sent_samples))
+	}
+
+	// Empties the buffered audio in the next callback, which plays silence; see AudioCable::flush.
+	pub fn flush(&self) {
+		self.controls.flush();
+	}
+
+	// Whether samples sent to this output are still waiting to be played. Together with the
+	// source having finished this tells when playback is truly over; see AudioCable::is_finished.
+	// Stays true while paused. The few milliseconds the device itself buffers aren't counted.
+	pub fn is_draining(&self) -> bool {
+		// Less than a frame left over is the start of one the producer gave up on when it stopped,
+		// which never plays.
+		let played = self.played_samples.load(Ordering::Relaxed);
+		self.sent_samples.load(Ordering::Relaxed).saturating_sub(played) >= self.channels().max(1) as u64
+	}
+
+	fn sent_samples(&self) -> Arc<AtomicU64> {
+		Arc::clone(&self.sent_samples)
+	}
+
+	// Frames the producer has sent to this output, less any a seek or a new source threw away
+	// unplayed. Along with frames_played this gives the exact number buffered between the two.
+	// Lock-free, so it can be polled from anywhere.
+	pub fn frames_emitted(&self) -> u64 {
+		self.sent_samples.load(Ordering::Relaxed) / self.channels().max(1) as u64
+	}
+
+	// Frames the output callback has taken for this output, silence played during underruns
+	// excluded.
+	pub fn frames_played(&self) -> u64 {
+		self.played_samples.load(Ordering::Relaxed) / self.channels().max(1) as u64
+	}
+
+	// How alike the two channels of a stereo output sound over the last few hundred
+	// milliseconds, from -1 (one is the other inverted, so they cancel when summed to mono) through
+	// 0 (unrelated, or silent) to +1 (mono). Persistently negative values point at a wiring or
+	// polarity problem that only shows on mono playback. Measured on what is sent to the device,
+	// after the effects and volume; it holds while paused. NaN unless the output has two channels.
+	pub fn phase_correlation(&self) -> f32 {
+		if self.controls.channel_gains.len() != 2 {
+			return f32::NAN;
+		}
+		f32::from_bits(self.correlation.load(Ordering::Relaxed))
+	}
+
+	// The priority the output callback's thread runs at, as found on its first callback, to judge
+	// the risk of dropouts alongside callback_timing: a Normal thread can be held up by anything
+	// else busy on the machine. AudioCableBuilder::realtime_priority asks for Realtime. Unknown
+	// until the output first plays, where the platform can't tell, and for pulled and null
+	// outputs, which have no callback thread of their own.
+	pub fn is_realtime(&self) -> CallbackPriority {
+		self.reporter.priority()
+	}
+
+	// How long the output callback took, on average and at most, since the previous call (or
+	// since the output started), e.g. to spot effects too slow for realtime: a callback that
+	// takes longer than the audio it produces (block frames / sample rate) is a dropout. Each
+	// call starts a new window.
+	pub fn callback_timing(&self) -> CallbackTiming {
+		self.timer.take()
+	}
+
+	// Calls `hook` with the duration of every output callback from now on, replacing any hook
+	// set before. It runs on a thread of its own, never in the callback; durations it can't keep
+	// up with are dropped, though callback_timing still counts them.
+	pub fn set_callback_timing_hook<F: Fn(Duration) + Send + 'static>(&self, hook: F) -> Result<(), AudioError> {
+		self.timer.set_hook(Box::new(hook))
+	}
+
+	// The frame, counted from the first one this output played, that was due at the speakers at
+	// the returned instant, e.g. to time video frames to the audio. Updated once per device
+	// callback; in between, the frame playing now is that frame plus the time elapsed since the
+	// instant times the sample rate, unless paused. Seeks don't reset the count. None until the
+	// stream has started.
+	pub fn playback_timestamp(&self) -> Option<(u64, std::time::Instant)> {
+		self.timestamp.load()
+	}
+
+	fn played_samples(&self) -> Arc<AtomicU64> {
+		Arc::clone(&self.played_samples)
+	}
+
+	fn device_name(&self) -> Option<String> {
+		if let Some(follower) = &self.follower {
+			return follower.device_name();
+		}
+		match &self.audio_device {
+			AudioDevice::PHYSICAL(physical_device) => physical_device.device.name().ok(),
+			_ => None
+		}
+	}
+
+	// Makes this consumer follow another's volume, gains, pause and mute, e.g. for a mirror
+	// output. Both must have the same channel count.
+	fn share_controls(&mut self, controls: Arc<ConsumerControls>) -> Result<(), AudioError> {
+		self.controls = controls;
+		self.build_stream()
+	}
+
+	fn sample_rate(&self) -> Option<u32> {
+		self.active_config().map(|config| config.sample_rate.0)
+	}
+
+	// The rate nearest `rate` the output device supports in its current channel count and sample
+	// format, for DeviceRatePolicy::MatchSource. None without a physical device.
+	fn closest_sample_rate(&self, rate: u32) -> Option<u32> {
+		match &self.audio_device {
+			AudioDevice::PHYSICAL(physical_device) => closest_rate(physical_device.supported_configs.iter()
+				.filter(|range| range.channels() == physical_device.config.channels && range.sample_format() == physical_device.sample_format)
+				.map(|range| (range.min_sample_rate().0, range.max_sample_rate().0)), rate),
+			_ => None
+		}
+	}
+
+	// The configuration the output stream is currently running with, or the layout a pulled
+	// output was built with.
+	pub fn active_config(&self) -> Option<cpal::StreamConfig> {
+		if let Some(follower) = &self.follower {
+			return follower.config();
+		}
+		match &self.audio_device {
+			AudioDevice::PHYSICAL(physical_device) => Some(physical_device.config.clone()),
+			AudioDevice::VIRUTAL(config) => Some(config.clone()),
+			_ => None
+		}
+	}
 
-				physical_device.stream = Option::Some( match physical_device.sample_format {
-					cpal::SampleFormat::F32 => physical_device.device.build_output_stream( &physical_device.config, clu, err_fn),
-					cpal::SampleFormat::I16 => physical_device.device.build_output_stream( &physical_device.config, clu, err_fn),
-					cpal::SampleFormat::U16 => physical_device.device.build_output_stream( &physical_device.config, clu, err_fn),
-				}.expect("Stream died (ooof)."));
+	// Rebuilds the output stream at `sample_rate`, keeping the channel count and sample format.
+	// The rate must fall within one of the device's supported configurations; otherwise, or if the
+	// new stream can't be started, the previous stream keeps playing and an error is returned.
+	// AudioCable::set_sample_rate also retargets the producer's resampler.
+	pub fn set_sample_rate(&mut self, sample_rate: u32) -> Result<(), AudioError> {
+		if self.closed {
+			return Err(AudioError::Stopped);
+		}
+		if !SAMPLE_RATE_RANGE.contains(&sample_rate) {
+			return Err(AudioError::InvalidArgument(format!(
+				"sample rates must be between {} and {} Hz, got {}", SAMPLE_RATE_RANGE.start(), SAMPLE_RATE_RANGE.end(), sample_rate
+			)));
+		}
+		if let Some(follower) = &self.follower {
+			return follower.set_sample_rate(sample_rate);
+		}
+		let previous_rate = match &mut self.audio_device {
+			AudioDevice::PHYSICAL(physical_device) => {
+				check_rate_supported(&physical_device.device, &physical_device.config, physical_device.sample_format, sample_rate)?;
 
-				physical_device.stream.as_ref().unwrap().play().unwrap();
+				let previous_rate = physical_device.config.sample_rate;
+				physical_device.stream = None;
+				physical_device.config.sample_rate = cpal::SampleRate(sample_rate);
+				previous_rate
 			},
-			_ => {}
+			// A pulled output has no device to agree with; the caller's backend takes the new rate.
+			AudioDevice::VIRUTAL(config) => {
+				config.sample_rate = cpal::SampleRate(sample_rate);
+				if let Some(stage) = &mut self.pull {
+					stage.sample_rate = sample_rate;
+				}
+				return Ok(());
+			},
+			_ => return Err(AudioError::Unsupported(String::from("there is no physical output device to reconfigure")))
+		};
+
+		if let Err(err) = self.connect() {
+			if let AudioDevice::PHYSICAL(physical_device) = &mut self.audio_device {
+				physical_device.config.sample_rate = previous_rate;
+			}
+			self.connect()?;
+			return Err(err);
 		}
+		Ok(())
 	}
-}
 
-impl AudioSink for AudioConsumer {
-	fn new(rx_channel: mpsc::Receiver<f32>) -> Self {
-		let mut ac = AudioConsumer {
-			data_channel: Arc::new(Mutex::new(rx_channel)),
-			audio_device: AudioDevice::NONE
+	// Walks `formats`, most preferred first, for the first the device can play at the current
+	// rate and channel count, and rebuilds the stream in it if that isn't the format playing
+	// already. Fails with InvalidArgument, leaving the stream alone, if there is none. A pulled
+	// output switches to the first of them.
+	pub fn set_output_format_priority(&mut self, formats: &[cpal::SampleFormat]) -> Result<(), AudioError> {
+		if self.closed {
+			return Err(AudioError::Stopped);
+		}
+		if formats.is_empty() {
+			return Err(AudioError::InvalidArgument(String::from("at least one output sample format is needed")));
+		}
+		if self.follower.is_some() {
+			return Err(AudioError::Unsupported(String::from("a cable following the default device picks its format as it moves")));
+		}
+		let previous_format = match &mut self.audio_device {
+			AudioDevice::PHYSICAL(physical_device) => {
+				let config = &physical_device.config;
+				let format = formats.iter().copied().find(|&format| physical_device.supported_configs.iter().any(|range| {
+					range.sample_format() == format
+						&& range.channels() == config.channels
+						&& range.min_sample_rate() <= config.sample_rate
+						&& config.sample_rate <= range.max_sample_rate()
+				})).ok_or_else(|| AudioError::InvalidArgument(format!(
+					"the output device supports none of the sample formats {:?} at {} Hz with {} channels", formats, config.sample_rate.0, config.channels
+				)))?;
+				if format == physical_device.sample_format {
+					return Ok(());
+				}
+				physical_device.stream = None;
+				std::mem::replace(&mut physical_device.sample_format, format)
+			},
+			AudioDevice::VIRUTAL(_) => {
+				self.pull_format = formats[0];
+				return Ok(());
+			},
+			_ => return Err(AudioError::Unsupported(String::from("there is no physical output device to reconfigure")))
 		};
-		ac.connect();
-		ac
+
+		if let Err(err) = self.connect() {
+			if let AudioDevice::PHYSICAL(physical_device) = &mut self.audio_device {
+				physical_device.sample_format = previous_format;
+			}
+			self.connect()?;
+			return Err(err);
+		}
+		Ok(())
 	}
 
-	fn connect(&mut self) -> () {
+	fn set_volume(&self, volume: f32) {
+		self.controls.set_volume(volume);
+	}
 
+	fn volume(&self) -> f32 {
+		self.controls.volume()
 	}
-}
 
-/*
- * AudioProducer: Representation of object responsible for producing audio to a given internal audio channel.
- */
-struct AudioProducer {
-	data_channel: Arc<Mutex<mpsc::Sender<f32>>>,
-	source_type: Arc<SourceType>,
-	thread: Option<std::thread::JoinHandle<()>>,
-}
+	// Sets an independent linear gain for each output channel, e.g. to calibrate a multi-speaker
+	// setup. The slice must hold exactly one gain per device channel.
+	pub fn set_channel_gains(&self, gains: &[f32]) -> Result<(), AudioError> {
+		if gains.len() != self.controls.channel_gains.len() {
+			return Err(AudioError::InvalidArgument(format!(
+				"expected {} channel gains, got {}", self.controls.channel_gains.len(), gains.len()
+			)));
+		}
+		for (gain, value) in self.controls.channel_gains.iter().zip(gains) {
+			gain.store(value.max(0.0).to_bits(), Ordering::Relaxed);
+		}
+		Ok(())
+	}
 
-/* LocalAudioProducer: Representation of an AudioProducer that gets its audio data from a file.
- */
-trait LocalAudioProducer {
-	fn new(_: String, _: mpsc::Sender<f32>) -> AudioProducer;
+	// Appends a custom effect to the output chain. Effects run in the order they were added, on
+	// the audio about to be played, before the channel map, volume and channel gains are applied;
+	// set_volume_stage can move the volume ahead of them. See Effect for what implementations may
+	// do on the audio thread.
+	pub fn add_effect(&self, effect: Box<dyn Effect>) {
+		self.effects.lock().unwrap_or_else(PoisonError::into_inner).push(effect);
+	}
 
-	fn connect(&mut self) -> ();
-}
+	// Replaces the whole chain of custom effects with `effects` at once, e.g. to switch presets,
+	// and hands back the chain it replaced, to switch back to later. The callback runs each block
+	// through the chain under one lock, so every block goes through all of the old chain or all of
+	// the new one, never a mix or neither. The swap takes that same lock, so it waits for a block
+	// the callback is in the middle of, and a callback that comes in during the swap waits for it;
+	// only the two chains trade places under the lock, and the old one is dropped by the caller.
+	pub fn set_effects(&self, effects: Vec<Box<dyn Effect>>) -> Vec<Box<dyn Effect>> {
+		std::mem::replace(&mut *self.effects.lock().unwrap_or_else(PoisonError::into_inner), effects)
+	}
 
-/* StreamAudioProducer: Representation of a AudioProducer that gets its audio data from a network stream.
- */
-trait StreamAudioProducer {
-	fn new() -> AudioProducer;
-}
+	pub fn channel_gains(&self) -> Vec<f32> {
+		self.controls.channel_gains.iter().map(|gain| f32::from_bits(gain.load(Ordering::Relaxed))).collect()
+	}
 
-/* DeviceAudioProducer: Representation of a AudioProducer that gets its audio data from a device on the system.
- */
-trait DeviceAudioProducer {
-	fn new() -> AudioProducer;
-}
+	// Plays channel i of the audio on output channel map[i], e.g. [1, 0] swaps left and right.
+	// Channels several map to are summed, and those none map to are silent, so [0, 0] plays both
+	// channels on the left speaker only. The map must hold one index below the device channel
+	// count per channel. Applied after the custom effects, which still see the audio's own
+	// channel order, and before the channel gains, which stay with the output channels.
+	pub fn set_channel_map(&self, map: &[usize]) -> Result<(), AudioError> {
+		let channels = self.controls.channel_map.len();
+		if map.len() != channels {
+			return Err(AudioError::InvalidArgument(format!("expected a channel map of {} entries, got {}", channels, map.len())));
+		}
+		if let Some(index) = map.iter().find(|&&index| index >= channels) {
+			return Err(AudioError::InvalidArgument(format!("output channel {} is out of range, the device has {}", index, channels)));
+		}
+		for (entry, &index) in self.controls.channel_map.iter().zip(map) {
+			entry.store(index, Ordering::Relaxed);
+		}
+		Ok(())
+	}
 
-impl LocalAudioProducer for AudioProducer {
-	fn new(file: String, data_channel: mpsc::Sender<f32>) -> AudioProducer {
-		let mut ap = AudioProducer {
-			data_channel: Arc::new(Mutex::new(data_channel)),
-			source_type: Arc::new(SourceType::from_local(file)),
-			thread: None,
-		};
-		ap.connect();
-		ap
+	pub fn channel_map(&self) -> Vec<usize> {
+		self.controls.channel_map.iter().map(|entry| entry.load(Ordering::Relaxed)).collect()
 	}
 
-	fn connect(&mut self) -> () {
-		// Grab a shared access to data_channel and source_type to use in the thread.
-		let tx_channel = Arc::clone(&self.data_channel);
-		let source_type = Arc::clone(&self.source_type);
+	fn channels(&self) -> usize {
+		self.controls.channel_gains.len()
+	}
+
+	fn capacity(&self) -> usize {
+		self.capacity
+	}
 
-		let source_type_2 = Arc::clone(&self.source_type);
+	fn connect(&mut self) -> Result<(), AudioError> {
+		self.build_stream()?;
+		self.play()
+	}
 
-		self.thread = match &*source_type {
-			SourceType::FLAC(_) => Some(std::thread::spawn(move || {
-				let flac_file = match &*source_type_2 { SourceType::FLAC(flac_file) => flac_file, _ => panic!("unreachable") };
+	fn play(&self) -> Result<(), AudioError> {
+		if self.closed {
+			return Err(AudioError::Stopped);
+		}
+		if let Some(follower) = &self.follower {
+			return follower.play();
+		}
+		if let Some(socket) = &self.socket {
+			socket.play();
+		}
+		if let Some(null) = &self.null {
+			null.play();
+		}
+		if let AudioDevice::PHYSICAL(PhyiscalAudioDevice { stream: Some(stream), .. }) = &self.audio_device {
+			stream.play()
+				.map_err(|err| AudioError::Device(format!("failed to start output stream: {}", err)))?;
+		}
+		Ok(())
+	}
 
-				let mut reader = claxon::FlacReader::open(flac_file).expect("no file.");
-				let samples = reader.samples();
+	// Stops the output stream and lets go of the device now, rather than whenever the consumer
+	// happens to be dropped, for apps that open and close playback over and over. An error pausing
+	// the stream is returned, though the device is released all the same.
+	pub fn close(mut self) -> Result<(), AudioError> {
+		self.release()
+	}
 
-				let lock = match (*tx_channel).lock() {
-					Ok(lock) => lock,
-					Err(_) => panic!("other thread panicked") // other thread panicked
-				};
+	// close for a consumer that lives on, e.g. in a cable: afterwards play, set_sample_rate and
+	// set_output_format_priority fail with Stopped and a pulled output is filled with silence.
+	fn release(&mut self) -> Result<(), AudioError> {
+		self.closed = true;
+		// Dropping the follower ends its thread, and the stream it owns with it; the socket and null
+		// outputs' likewise.
+		self.follower = None;
+		self.socket = None;
+		self.null = None;
+		self.pull = None;
+		match std::mem::replace(&mut self.audio_device, AudioDevice::NONE) {
+			AudioDevice::PHYSICAL(PhyiscalAudioDevice { stream: Some(stream), .. }) => stream.pause()
+				.map_err(|err| AudioError::Device(format!("failed to pause output stream: {}", err))),
+			_ => Ok(())
+		}
+	}
 
-				let data_channel = &*lock;
+	fn build_stream(&mut self) -> Result<(), AudioError> {
+		let sample_rate = match &self.audio_device {
+			AudioDevice::PHYSICAL(physical_device) => physical_device.config.sample_rate.0,
+			_ => return Ok(())
+		};
+		let stage = self.output_stage(sample_rate);
+		let timestamp = &self.timestamp;
+		if let AudioDevice::PHYSICAL(physical_device) = &mut self.audio_device {
+			physical_device.stream = Some(build_output_stream(&physical_device.device, &physical_device.config, physical_device.sample_format, stage, timestamp)?);
+		}
+		Ok(())
+	}
 
-				for sample in samples {
-					let s = (sample.unwrap_or(0) as f32) / (std::i32::MAX as f32) * 160.0;
-					data_channel.send(s).unwrap();
-				}
-			})),
-			SourceType::SOURCELESS => None,
-			SourceType::UNSUPPORTED => None
+	// Hands the stream over to a DefaultDeviceFollower, which rebuilds it on whichever device
+	// becomes the default. `producer` takes the new rate when a device can't keep the old one.
+	fn follow_default_device(&mut self, options: &ConsumerOptions, producer: CommandSender, events: crossbeam_channel::Sender<PlaybackEvent>) -> Result<(), AudioError> {
+		let stage = match self.sample_rate() {
+			Some(sample_rate) => self.output_stage(sample_rate),
+			None => return Ok(())
 		};
+		if let AudioDevice::PHYSICAL(physical_device) = &mut self.audio_device {
+			physical_device.stream = None;
+			let follower = DefaultDeviceFollower::start(
+				physical_device.device.name().ok(), physical_device.config.clone(), physical_device.sample_format,
+				stage, Arc::clone(&self.timestamp), options.clone(), producer, events
+			)?;
+			self.follower = Some(follower);
+		}
+		Ok(())
 	}
-}
-/*
-impl StreamAudioProducer for AudioProducer {
-    fn new() -> AudioProducer {
-        AudioProducer {
-            data_channel: Arc::<mpsc::Sender<f32>>::new_uninit(),
-            source_type: std::sync::Arc::new(SourceType::SOURCELESS),
-            thread: None
-        }
-    }
-}*/
 
-impl AudioProducer {
+	// Starts pulling a pulled output into the socket at `path`; see SocketOutput.
+	fn output_to_socket(&mut self, path: &Path, format: WavSampleFormat) -> Result<(), AudioError> {
+		let sample_rate = self.sample_rate().unwrap_or(0);
+		self.socket = Some(SocketOutput::start(path, format, self.output_stage(sample_rate), Arc::clone(&self.timestamp))?);
+		Ok(())
+	}
 
-}
+	fn output_stage(&self, sample_rate: u32) -> OutputStage {
+		OutputStage {
+			data_channel: Arc::clone(&self.data_channel),
+			controls: Arc::clone(&self.controls),
+			played: Arc::clone(&self.played_samples),
+			effects: Arc::clone(&self.effects),
+			reporter: Arc::clone(&self.reporter),
+			gain: Gain::new(Arc::clone(&self.controls)),
+			limiter: self.limiter.clone(),
+			phase: PhaseMeter::new(Arc::clone(&self.correlation)),
+			timer: Arc::clone(&self.timer),
+			frame: Vec::new(),
+			sample_rate,
+			underrun_wait: self.underrun_wait,
+			realtime_priority: self.realtime_priority,
+		}
+	}
+
+	// Pulls the next `out.len()` samples, interleaved at the rate and channel count given to
+	// AudioCableBuilder::pull_output, through the effects and volume, for applications that drive
+	// their own audio backend (SDL, oboe, a game engine) instead of a cpal device. Call it from the
+	// backend's callback as the device would: samples that aren't ready come out as silence at once,
+	// or after AudioCableBuilder::underrun_wait, and count as an underrun, and while paused the
+	// output is silent and nothing is taken from the buffer. `out` should hold whole frames, as a
+	// device's buffers do. Only cables built with pull_output should be pulled; on any other the
+	// device and the caller would split the audio between them.
+	pub fn fill(&mut self, out: &mut [f32]) {
+		if self.closed {
+			out.fill(0.0);
+			return;
+		}
+		if self.pull.is_none() {
+			let sample_rate = self.sample_rate().unwrap_or(0);
+			self.pull = Some(self.output_stage(sample_rate));
+		}
+		if let Some(stage) = &mut self.pull {
+			let frame = stage.played.load(Ordering::Relaxed) / stage.channels() as u64;
+			self.timestamp.store(frame, std::time::Instant::now());
+			stage.fill(out);
+		}
+	}
 
+	// The sample format the output is written in: the device stream's, or for a pulled output the
+	// first of AudioCableBuilder::sample_formats, F32 unless another was forced.
+	pub fn sample_format(&self) -> Option<cpal::SampleFormat> {
+		match &self.audio_device {
+			AudioDevice::PHYSICAL(physical_device) => Some(physical_device.sample_format),
+			AudioDevice::VIRUTAL(_) => Some(self.pull_format),
+			_ => None
+		}
+	}
 
-/* AudioCable - Digital representation of a physical connection between a source and a destination.
+	// Pulls like fill, then converts the samples to `T` exactly as the output callback does for a
+	// device stream of that format, e.g. for a backend that wants i16. `T` has to be the pulled
+	// output's sample_format; anything else fails with InvalidArgument.
+	pub fn fill_samples<T: cpal::Sample>(&mut self, out: &mut [T]) -> Result<(), AudioError> {
+		if T::FORMAT != self.pull_format {
+			return Err(AudioError::InvalidArgument(format!("the pulled output is {:?}, not {:?}", self.pull_format, T::FORMAT)));
+		}
+		let mut block = std::mem::take(&mut self.pull_block);
+		block.resize(out.len(), 0.0);
+		self.fill(&mut block);
+		write_samples(&block, out);
+		self.pull_block = block;
+		Ok(())
+	}
+}
 
+/* OutputStage: The consumer's processing between the data channel and the output. Takes samples
+ * from the channel, then runs the custom effects and the built-in Gain over them.
+ *
+ * The cpal callback and AudioConsumer::fill both go through it, so a pulled output sounds the
+ * same as a device.
  */
-pub struct AudioCable {
-	data_source: AudioProducer,
-	data_destination: AudioConsumer,
+struct OutputStage {
+	data_channel: Arc<Mutex<FrameReceiver>>,
+	controls: Arc<ConsumerControls>,
+	played: Arc<AtomicU64>,
+	effects: Arc<Mutex<Vec<Box<dyn Effect>>>>,
+	reporter: Arc<RealtimeReporter>,
+	gain: Gain,
+	limiter: Option<Limiter>,
+	phase: PhaseMeter,
+	timer: Arc<CallbackTimer>,
+	// One frame as it was before remapping.
+	frame: Vec<f32>,
+	sample_rate: u32,
+	underrun_wait: Duration,
+	// Only acted on by output_callback; fill and the socket output run on the caller's threads.
+	realtime_priority: bool,
 }
 
-impl AudioCable {
-	pub fn new(audio_source: String) -> Self {
-		let (tx, rx): (std::sync::mpsc::Sender<f32>, std::sync::mpsc::Receiver<f32>) = std::sync::mpsc::channel();
+impl OutputStage {
+	fn channels(&self) -> u16 {
+		self.controls.channel_gains.len().max(1) as u16
+	}
+
+	// A stage for another stream of the same output at `sample_rate`, sharing everything but the
+	// per-stream state, which starts over.
+	fn renewed(&self, sample_rate: u32) -> OutputStage {
+		OutputStage {
+			data_channel: Arc::clone(&self.data_channel),
+			controls: Arc::clone(&self.controls),
+			played: Arc::clone(&self.played),
+			effects: Arc::clone(&self.effects),
+			reporter: Arc::clone(&self.reporter),
+			gain: Gain::new(Arc::clone(&self.controls)),
+			limiter: self.limiter.clone(),
+			phase: self.phase.renewed(),
+			timer: Arc::clone(&self.timer),
+			frame: Vec::new(),
+			sample_rate,
+			underrun_wait: self.underrun_wait,
+			realtime_priority: self.realtime_priority,
+		}
+	}
+
+	fn fill(&mut self, out: &mut [f32]) {
+		let started = std::time::Instant::now();
+		let _flush = FlushToZero::enable();
+		self.process(out);
+		self.timer.record(started.elapsed());
+	}
+
+	fn process(&mut self, out: &mut [f32]) {
+		// Poisoning only says some other thread panicked while it held the lock; the receiver is
+		// left as it was, and panicking here too would take the device's callback down with it.
+		let mut data_channel = self.data_channel.lock().unwrap_or_else(PoisonError::into_inner);
+
+		// A flush throws the buffered audio away, paused or not, and this callback stays silent
+		// rather than playing whatever the producer sends meanwhile.
+		if self.controls.flush.swap(false, Ordering::Relaxed) {
+			data_channel.clear();
+			for sample in out.iter_mut() {
+				*sample = 0.0;
+			}
+			return;
+		}
+
+		if self.controls.paused() {
+			for sample in out.iter_mut() {
+				*sample = 0.0;
+			}
+			return;
+		}
+
+		// Whatever is buffered is taken straight away. Only once the channel runs dry, and only with
+		// an underrun wait set, is there a wait for the producer, and all of it together may take up
+		// half of the buffer's length, so however small the device's buffers are a late producer
+		// can't make them overrun: a 2-frame buffer at 48 kHz waits 21 µs at most.
+		let deadline = match self.underrun_wait {
+			Duration::ZERO => None,
+			wait => {
+				let frames = out.len() / self.channels() as usize;
+				let half_buffer = Duration::from_secs_f64(frames as f64 / self.sample_rate.max(1) as f64 / 2.0);
+				Some(std::time::Instant::now() + wait.min(half_buffer))
+			}
+		};
+		// Taken a frame at a time, so a frame that's still being sent is silence as a whole and
+		// every channel stays in its place.
+		let channels = self.channels();
+		let mut received = 0;
+		for frame in out.chunks_mut(channels as usize) {
+			if data_channel.recv_frame(frame, deadline) {
+				received += frame.len() as u64;
+			} else {
+				frame.fill(0.0);
+			}
+		}
+		drop(data_channel);
+		self.played.fetch_add(received, Ordering::Relaxed);
+		self.reporter.report_block(out.len() as u64 - received);
+
+		self.gain.process_pre_effects(out);
+		for effect in self.effects.lock().unwrap_or_else(PoisonError::into_inner).iter_mut() {
+			effect.process(out, channels, self.sample_rate);
+		}
+		remap(&self.controls.channel_map, &mut self.frame, out);
+		self.gain.process(out, channels, self.sample_rate);
+		if let Some(limiter) = &mut self.limiter {
+			limiter.process(out, channels, self.sample_rate);
+		}
+		if channels == 2 {
+			self.phase.process(out, self.sample_rate);
+		}
+	}
+
+}
+
+// Moves every channel of `out` to the output channel `map` gives for it, through the scratch
+// `original`.
+fn remap(map: &[AtomicUsize], original: &mut Vec<f32>, out: &mut [f32]) {
+	if map.iter().enumerate().all(|(channel, entry)| entry.load(Ordering::Relaxed) == channel) {
+		return;
+	}
+	original.resize(map.len(), 0.0);
+	for frame in out.chunks_exact_mut(map.len()) {
+		original.copy_from_slice(frame);
+		for sample in frame.iter_mut() {
+			*sample = 0.0;
+		}
+		for (entry, &sample) in map.iter().zip(original.iter()) {
+			frame[entry.load(Ordering::Relaxed)] += sample;
+		}
+	}
+}
+
+// Fills the device's buffers through `stage`, converting from f32 to the stream's format.
+fn output_callback<T: cpal::Sample>(mut stage: OutputStage, timestamp: &Arc<PlaybackTimestamp>) -> impl FnMut(&mut [T], &cpal::OutputCallbackInfo) + Send + 'static {
+	let timestamp = Arc::clone(timestamp);
+	// Only grows until it fits the device's largest callback.
+	let mut block = Vec::new();
+	// A rebuilt stream gets a new callback thread, and with it a new output_callback to raise it
+	// and report its priority.
+	let mut first_callback = true;
+
+	move | data: & mut [T],
+		   info: & cpal::OutputCallbackInfo | {
+		if std::mem::take(&mut first_callback) {
+			if stage.realtime_priority {
+				if let Err(err) = priority::raise_current_thread() {
+					stage.reporter.report_priority_denied(err.raw_os_error());
+				}
+			}
+			stage.reporter.report_priority(priority::current_thread_priority());
+		}
+		let channels = stage.channels();
+		// The first frame of this buffer is heard once the device's output latency has passed.
+		let stream_time = info.timestamp();
+		let latency = stream_time.playback.duration_since(&stream_time.callback).unwrap_or_default();
+		timestamp.store(stage.played.load(Ordering::Relaxed) / channels as u64, std::time::Instant::now() + latency);
+
+		block.resize(data.len(), 0.0);
+		stage.fill(&mut block);
+		write_samples(&block, data);
+	}
+}
+
+// Builds a stream playing `stage` on `device`, paused until played.
+fn build_output_stream(device: &cpal::Device, config: &cpal::StreamConfig, sample_format: cpal::SampleFormat, stage: OutputStage, timestamp: &Arc<PlaybackTimestamp>) -> Result<cpal::Stream, AudioError> {
+	match sample_format {
+		cpal::SampleFormat::F32 => device.build_output_stream(config, output_callback::<f32>(stage, timestamp), err_fn),
+		cpal::SampleFormat::I16 => device.build_output_stream(config, output_callback::<i16>(stage, timestamp), err_fn),
+		cpal::SampleFormat::U16 => device.build_output_stream(config, output_callback::<u16>(stage, timestamp), err_fn),
+	}.map_err(|err| AudioError::Device(format!("failed to build output stream: {}", err)))
+}
+
+// Fails with InvalidArgument unless `device` can play `config`'s channels in `sample_format` at
+// `sample_rate`.
+fn check_rate_supported(device: &cpal::Device, config: &cpal::StreamConfig, sample_format: cpal::SampleFormat, sample_rate: u32) -> Result<(), AudioError> {
+	let supported = device.supported_output_configs()
+		.map_err(|err| AudioError::Device(format!("error querying device configurations: {}", err)))?
+		.any(|range| range.channels() == config.channels
+			&& range.sample_format() == sample_format
+			&& range.min_sample_rate().0 <= sample_rate
+			&& sample_rate <= range.max_sample_rate().0);
+	if !supported {
+		return Err(AudioError::InvalidArgument(format!("the output device doesn't support {} Hz", sample_rate)));
+	}
+	Ok(())
+}
+
+// Converts the output stage's f32 samples to the stream's format.
+fn write_samples<T: cpal::Sample>(block: &[f32], data: &mut [T]) {
+	for (sample, s) in data.iter_mut().zip(block) {
+		* sample = cpal::Sample::from(s);
+	}
+}
+
+// The host and output device `options` ask for: a JACK client when one is named and a JACK
+// server is running, otherwise the named or default device of the platform's default host.
+fn open_output_device(options: &ConsumerOptions) -> Result<(cpal::Host, cpal::Device), AudioError> {
+	#[cfg(all(feature = "jack", target_os = "linux"))]
+	if let Some(client_name) = &options.jack_client {
+		match open_jack_output(client_name) {
+			Some(output) => return Ok(output),
+			None => eprintln!("couldn't connect to a JACK server, playing through ALSA instead")
+		}
+	}
+
+	let host = cpal::default_host();
+	let device = match &options.output_device {
+		Some(name) => {
+			let devices = host.output_devices()
+				.map_err(|err| AudioError::Device(format!("error listing output devices: {}", err)))?;
+			find_device(devices, name, "output")?
+		},
+		None => host.default_output_device()
+			.ok_or_else(|| AudioError::Device(String::from("no default output device")))?
+	};
+	Ok((host, device))
+}
+
+// Registers the JACK client "<client_name>_out". None when no JACK server is running; cpal
+// doesn't start one.
+#[cfg(all(feature = "jack", target_os = "linux"))]
+fn open_jack_output(client_name: &str) -> Option<(cpal::Host, cpal::Device)> {
+	let host = cpal::platform::JackHost::new().ok()?;
+	let device = cpal::platform::JackDevice::default_output_device(client_name, true, false).ok()?;
+	Some((host.into(), device.into()))
+}
+
+// The first usable configuration, in the device's own order, of the most preferred sample format
+// it supports, at its highest usable rate. Formats missing from `preference` are never picked.
+fn preferred_config(configs: &[cpal::SupportedStreamConfigRange], preference: &[cpal::SampleFormat]) -> Option<cpal::SupportedStreamConfig> {
+	preference.iter()
+		.find_map(|&format| configs.iter().filter(|config| config.sample_format() == format).find_map(usable_config))
+}
+
+// Picks the highest rate in `range` that is within SAMPLE_RATE_RANGE. None for ranges with no
+// channels or no such rate, which some virtual and broken devices advertise.
+fn usable_config(range: &cpal::SupportedStreamConfigRange) -> Option<cpal::SupportedStreamConfig> {
+	let rate = range.max_sample_rate().0.min(*SAMPLE_RATE_RANGE.end());
+	if range.channels() == 0 || rate < range.min_sample_rate().0 || !SAMPLE_RATE_RANGE.contains(&rate) {
+		return None;
+	}
+	Some(range.clone().with_sample_rate(cpal::SampleRate(rate)))
+}
+
+// The rate nearest `rate` within any of the (min, max) `ranges` and SAMPLE_RATE_RANGE, `rate`
+// itself when one holds it, and the higher of two equally near, so less is thrown away.
+fn closest_rate(ranges: impl Iterator<Item = (u32, u32)>, rate: u32) -> Option<u32> {
+	ranges.filter_map(|(min, max)| {
+		let (min, max) = (min.max(*SAMPLE_RATE_RANGE.start()), max.min(*SAMPLE_RATE_RANGE.end()));
+		(min <= max).then(|| rate.clamp(min, max))
+	})
+		.min_by_key(|&candidate| (candidate.abs_diff(rate), std::cmp::Reverse(candidate)))
+}
+
+// Adjusts the stream configuration for the requested ShareMode. cpal only opens WASAPI streams in
+// shared mode, so on Windows LowLatency asks for the smallest advertised buffer and Exclusive is
+// reported as unavailable rather than silently downgraded.
+#[cfg(target_os = "windows")]
+fn apply_share_mode(share_mode: ShareMode, device: &cpal::Device, supported_config: &cpal::SupportedStreamConfig, config: &mut cpal::StreamConfig) -> Result<(), AudioError> {
+	match share_mode {
+		ShareMode::Shared => Ok(()),
+		ShareMode::LowLatency => {
+			if let cpal::SupportedBufferSize::Range { min, .. } = supported_config.buffer_size() {
+				config.buffer_size = cpal::BufferSize::Fixed(*min);
+			}
+			Ok(())
+		},
+		ShareMode::Exclusive => Err(AudioError::Unsupported(format!(
+			"exclusive mode is unavailable for device \"{}\" with format {:?}; only shared-mode WASAPI streams can be opened, use ShareMode::LowLatency instead",
+			device.name().unwrap_or_else(|_| String::from("<unknown>")),
+			supported_config.sample_format()
+		))),
+	}
+}
+
+// Share modes only exist on Windows; everywhere else every mode is plain shared mode.
+#[cfg(not(target_os = "windows"))]
+fn apply_share_mode(_: ShareMode, _: &cpal::Device, _: &cpal::SupportedStreamConfig, _: &mut cpal::StreamConfig) -> Result<(), AudioError> {
+	Ok(())
+}
+
+impl AudioSink for AudioConsumer {
+	fn new(rx_channel: crossbeam_channel::Receiver<f32>) -> Self {
+		let sent_samples = Arc::new(AtomicU64::new(0));
+		let mut ac = AudioConsumer {
+			data_channel: Arc::new(Mutex::new(FrameReceiver::new(rx_channel, 0, Arc::clone(&sent_samples)))),
+			// The sender's bound isn't known from here.
+			capacity: 0,
+			audio_device: AudioDevice::NONE,
+			controls: Arc::new(ConsumerControls::new(0)),
+			played_samples: Arc::new(AtomicU64::new(0)),
+			sent_samples,
+			effects: Arc::new(Mutex::new(Vec::new())),
+			reporter: RealtimeReporter::new().0,
+			reports: None,
+			timestamp: Arc::new(PlaybackTimestamp::new()),
+			correlation: Arc::new(AtomicU32::new(0.0f32.to_bits())),
+			timer: Arc::new(CallbackTimer::new()),
+			limiter: None,
+			underrun_wait: Duration::ZERO,
+			realtime_priority: false,
+			pull: None,
+			pull_format: cpal::SampleFormat::F32,
+			pull_block: Vec::new(),
+			follower: None,
+			socket: None,
+			null: None,
+			closed: false,
+		};
+		AudioSink::connect(&mut ac);
+		ac
+	}
+
+	fn connect(&mut self) {
+
+	}
+}
+
+/*
+ * AudioProducer: Representation of object responsible for producing audio to a given internal audio channel.
+ */
+struct AudioProducer {
+	data_channel: Arc<Mutex<crossbeam_channel::Sender<f32>>>,
+	source_type: Arc<SourceType>,
+	thread: Option<std::thread::JoinHandle<()>>,
+	events: crossbeam_channel::Sender<PlaybackEvent>,
+	options: ProducerOptions,
+	// Commands for the decode thread, handled between decoded blocks.
+	comm_chan: (CommandSender, crossbeam_channel::Receiver<AudioChannelMessage>),
+	drain: StaleSampleDrain,
+	// Cached when the source is opened; the source itself moves to the decode thread.
+	cover_art: Option<CoverArt>,
+	chapters: Vec<Chapter>,
+	audio_tracks: Vec<AudioTrackInfo>,
+	// Kept up to date by an internet radio stream's IcyReader. Subscribers stay with the feed
+	// when the source is replaced.
+	metadata: Arc<MetadataFeed>,
+	progress: Arc<ProducerProgress>,
+	// How the watchdog opens again a source that isn't a file.
+	reopen: Option<Reopen>,
+}
+
+// Updated by the decode thread so other threads can tell how far it has got without a channel.
+struct ProducerProgress {
+	// Samples handed to the data channel since the producer started.
+	sent_samples: AtomicU64,
+	// Set while everything up to the end of the source has been sent.
+	finished: AtomicBool,
+	// Set while no decode thread runs: before the first is spawned, and by each just before it
+	// returns.
+	exited: AtomicBool,
+	// Set once the thread has been told to start. Sources that follow, queued or replacing this
+	// one, start straight away too.
+	started: AtomicBool,
+	// Whether the source being played can seek; it changes as queued tracks take over.
+	seekable: AtomicBool,
+	// The sample rate of the source being played, set as each track starts.
+	source_rate: AtomicU32,
+	// The last tempo the thread was set to (f32 bits), carried over to every following track.
+	tempo: AtomicU32,
+	// Set while AudioCable::pause_source holds the decode thread, across tracks too.
+	source_paused: AtomicBool,
+	// What the decoder panicked with, when the thread caught a panic.
+	panic: Mutex<Option<String>>,
+	// Largest absolute sample decoded from the current track (f32 bits). A non-negative f32's bits
+	// order the same way it does, so fetch_max keeps the peak.
+	source_peak: AtomicU32,
+	// Encoded bytes the sources have read, over every track played so far.
+	bytes_read: AtomicU64,
+	// Resampler::latency of the thread's resamplers after the latest block, in nanoseconds.
+	resampler_latency: AtomicU64,
+	// How far into the current track the decoder has got, in nanoseconds, for a restart to
+	// carry on from.
+	source_position: AtomicU64,
+	// The decode error that ended the track, for the watchdog to restart it after.
+	failure: Mutex<Option<String>>,
+	// Run over each block before it's sent; see AudioCable::set_source_filter.
+	source_filter: Mutex<Option<SourceFilter>>,
+}
+
+type SourceFilter = Box<dyn FnMut(&mut [f32]) + Send>;
+
+impl Default for ProducerProgress {
+	fn default() -> Self {
+		ProducerProgress {
+			sent_samples: AtomicU64::new(0),
+			finished: AtomicBool::new(false),
+			exited: AtomicBool::new(true),
+			started: AtomicBool::new(false),
+			seekable: AtomicBool::new(false),
+			source_rate: AtomicU32::new(0),
+			tempo: AtomicU32::new(1.0f32.to_bits()),
+			source_paused: AtomicBool::new(false),
+			panic: Mutex::new(None),
+			source_peak: AtomicU32::new(0),
+			bytes_read: AtomicU64::new(0),
+			resampler_latency: AtomicU64::new(0),
+			source_position: AtomicU64::new(0),
+			failure: Mutex::new(None),
+			source_filter: Mutex::new(None),
+		}
+	}
+}
+
+// Source-side settings collected by the AudioCableBuilder.
+#[derive(Clone, Debug, Default)]
+struct ProducerOptions {
+	// Sample rate the consumer plays at. None leaves the source at its native rate.
+	output_rate: Option<u32>,
+	// Channels the consumer plays, and how a source with fewer fills them.
+	output_channels: Option<u16>,
+	channel_fill: ChannelFill,
+	resample_quality: ResampleQuality,
+	// Portion of the source to play, as offsets from its start.
+	start_at: Option<Duration>,
+	end_at: Option<Duration>,
+	// Silence sent ahead of the source.
+	start_padding: Option<Duration>,
+	// Whether a source ending short of its declared length is padded out to it with silence.
+	pad_truncated: bool,
+	// Brings the source's peak to a target level.
+	normalize_peak: Option<PeakNormalizer>,
+	// Which of a track's ReplayGain tags it plays at.
+	replay_gain: ReplayGainMode,
+	// Which of a multi-track file's audio tracks to play.
+	audio_track: Option<TrackChoice>,
+	// Extra output devices fed the same audio as the consumer.
+	mirrors: Option<Arc<MirrorTap>>,
+	// Level in dBFS at or below which leading and trailing audio is trimmed as silence.
+	trim_silence: Option<f32>,
+	// Tracks to play once the source ends.
+	queue: Option<Arc<TrackQueue>>,
+	// Asked for the next track once the queue has run out.
+	playlist: Option<PlaylistCursor>,
+	// Whether a panic in the decode thread is caught and reported rather than ending it silently.
+	catch_panics: bool,
+	// Whether tracks must play at the output rate as they are, never resampled.
+	strict_rate: bool,
+	// Bound of the decode thread's command channel, and what happens to commands while it's full.
+	control_channel: ControlChannel,
+	// How many restarts in a row the watchdog allows a failing source.
+	watchdog: Option<u32>,
+	// Where a restarted track picks up; only set by the watchdog.
+	resume_at: Option<Duration>,
+}
+
+// Decode threads started so far, numbering their names.
+static PRODUCER_THREADS: AtomicUsize = AtomicUsize::new(0);
+
+// The frame playing at `duration` in: the last to have started by then.
+fn duration_to_frames(duration: Duration, sample_rate: u32) -> u64 {
+	(duration.as_nanos() * sample_rate as u128 / 1_000_000_000) as u64
+}
+
+// When frame `frames` starts. Rounded up, so converting it back lands on this frame rather than
+// the one before.
+fn frames_to_duration(frames: u64, sample_rate: u32) -> Duration {
+	Duration::from_nanos((frames as u128 * 1_000_000_000).div_ceil(sample_rate.max(1) as u128) as u64)
+}
+
+/* LocalAudioProducer: Representation of an AudioProducer that gets its audio data from a file.
+ *
+ * The file is decoded incrementally: each AudioSource only reads and decodes one block (e.g. a
+ * FLAC block) at a time, and the next block isn't decoded until the previous one fits in the
+ * bounded data channel. The decode thread therefore never runs more than one block plus
+ * the channel's capacity ahead of the consumer, no matter how large the file or how
+ * slowly the consumer drains.
+ */
+trait LocalAudioProducer {
+	fn new(_: String, _: crossbeam_channel::Sender<f32>, _: StaleSampleDrain, _: crossbeam_channel::Sender<PlaybackEvent>, _: ProducerOptions) -> Result<AudioProducer, AudioError>;
+
+	fn connect(&mut self) -> Result<(), AudioError>;
+}
+
+/* StreamAudioProducer: Representation of a AudioProducer that gets its audio data from a network stream.
+ *
+ * The stream is played as a pipe, so it can't seek. Icecast and SHOUTcast metadata is taken out
+ * of it on the decode thread, which keeps the stream title in the metadata up to date.
+ */
+trait StreamAudioProducer {
+	fn new(_: &str, _: crossbeam_channel::Sender<f32>, _: StaleSampleDrain, _: crossbeam_channel::Sender<PlaybackEvent>, _: ProducerOptions) -> Result<AudioProducer, AudioError>;
+}
+
+/* DeviceAudioProducer: Representation of a AudioProducer that gets its audio data from a device on the system.
+ *
+ * The device itself is opened by an InputCapture, which must stay alive (and be played) for the
+ * source to receive anything.
+ */
+trait DeviceAudioProducer {
+	fn new(_: DeviceSource, _: crossbeam_channel::Sender<f32>, _: StaleSampleDrain, _: crossbeam_channel::Sender<PlaybackEvent>, _: ProducerOptions) -> Result<AudioProducer, AudioError>;
+}
+
+impl DeviceAudioProducer for AudioProducer {
+	fn new(source: DeviceSource, data_channel: crossbeam_channel::Sender<f32>, drain: StaleSampleDrain, events: crossbeam_channel::Sender<PlaybackEvent>, options: ProducerOptions) -> Result<AudioProducer, AudioError> {
+		let mut ap = AudioProducer::unconnected(SourceType::DEVICE, data_channel, drain, events, options);
+		ap.spawn(Box::new(source))?;
+		Ok(ap)
+	}
+}
+
+impl LocalAudioProducer for AudioProducer {
+	fn new(file: String, data_channel: crossbeam_channel::Sender<f32>, drain: StaleSampleDrain, events: crossbeam_channel::Sender<PlaybackEvent>, options: ProducerOptions) -> Result<AudioProducer, AudioError> {
+		let mut ap = AudioProducer::unconnected(SourceType::from_local(file)?, data_channel, drain, events, options);
+		ap.connect()?;
+		Ok(ap)
+	}
+
+	fn connect(&mut self) -> Result<(), AudioError> {
+		// The reader was opened by from_reader and the device by DeviceAudioProducer; there's
+		// nothing to reopen them from.
+		if let SourceType::READER | SourceType::DEVICE = &*self.source_type {
+			return Ok(());
+		}
+		match self.source_type.open()? {
+			Some(source) => self.spawn(source)?,
+			None => self.thread = None
+		}
+		Ok(())
+	}
+}
+
+// Decodes `source` into the data channel, trimmed to the requested offsets and resampled to the
+// consumer's rate, then time-stretched once a tempo other than 1.0 has been set. Returns the next
+// queued track once this one has been sent, or None when the thread should exit.
+fn run_source(track: Track, tx_channel: &Mutex<crossbeam_channel::Sender<f32>>, control: &crossbeam_channel::Receiver<AudioChannelMessage>, drain: &StaleSampleDrain, events: &crossbeam_channel::Sender<PlaybackEvent>, progress: &ProducerProgress, options: &mut ProducerOptions) -> Option<Track> {
+	let Track { mut source, path } = track;
+	let sample_rate = source.sample_rate();
+	let channels = source.channels().max(1) as usize;
+	let mut output_rate = options.output_rate.unwrap_or(sample_rate);
+	let mut resampler = Resampler::new(sample_rate, output_rate, channels as u16, options.resample_quality);
+	let start_frame = options.start_at.map_or(0, |start| duration_to_frames(start, sample_rate));
+	let end_frame = options.end_at.map(|end| duration_to_frames(end, sample_rate));
+	// Counted at the output rate, so it is exactly as long whatever the source's rate or tempo.
+	let mut padding_frames = options.start_padding.map_or(0, |padding| duration_to_frames(padding, output_rate));
+	// Set once a truncated source is being padded out: the frame the silence runs up to.
+	let mut truncated_end: Option<u64> = None;
+	// Set once the shortfall has been reported, so seeking back past it doesn't repeat it.
+	let mut truncation_reported = false;
+	// The layout the stream was last decoded in, for reporting it changing.
+	let mut stream_channels = source.channels();
+	if let Some(warning) = source.take_open_warning() {
+		let _ = events.send(PlaybackEvent::DecodeWarning(format!("{}: {}", path.as_deref().unwrap_or("the track"), warning)));
+	}
+	// A track's ReplayGain tags take the place of peak normalisation.
+	let replay_gain = source.replay_gain().and_then(|tags| tags.gain(options.replay_gain));
+	let mut normalizer = options.normalize_peak.filter(|_| replay_gain.is_none());
+	let seekable = source.is_seekable();
+	progress.seekable.store(seekable, Ordering::Relaxed);
+	progress.source_rate.store(sample_rate, Ordering::Relaxed);
+	progress.source_peak.store(0, Ordering::Relaxed);
+	// What earlier tracks read, so this one's count can be added as it grows.
+	let bytes_before = progress.bytes_read.load(Ordering::Relaxed);
+	progress.bytes_read.store(bytes_before + source.bytes_read(), Ordering::Relaxed);
+
+	// A decoder that panicked while this thread held the lock poisoned it, but left the sender as
+	// good as ever, so a source replacing it can carry on with it.
+	let lock = tx_channel.lock().unwrap_or_else(PoisonError::into_inner);
+
+	let data_channel = &*lock;
+
+	let mut decoded = Vec::new();
+	// Only allocated once a tempo is set; until then the resampler writes straight to `pending`.
+	// Every track carries on at the tempo the one before it played at: queued, requeued, playlist,
+	// replaced and restarted tracks alike.
+	let tempo = f32::from_bits(progress.tempo.load(Ordering::Relaxed));
+	let mut stretch = Some(tempo).filter(|&tempo| tempo != 1.0)
+		.map(|tempo| TimeStretch::new(channels as u16, output_rate, tempo));
+	let mut resampled = Vec::new();
+	let mut trim = options.trim_silence.map(|threshold| SilenceTrim::new(threshold, sample_rate, channels));
+	let mut trimmed = Vec::new();
+	// With mirrors, the consumer's output passes through its own drift correction so it can follow
+	// a mirror's clock.
+	let mut drift = Resampler::new(output_rate, output_rate, channels as u16, options.resample_quality);
+	let mut mirrored = Vec::new();
+	// The device's channel count, when the source's channels are duplicated up to it.
+	let fill_to = options.output_channels.map(usize::from)
+		.filter(|&output_channels| options.channel_fill == ChannelFill::Duplicate && output_channels > channels);
+	let mut filled = Vec::new();
+	// Processed samples waiting for room in the data channel, and how many of them already went.
+	let mut pending = Vec::new();
+	let mut sent = 0;
+	let mut position = 0u64;
+	if let Some(resume_at) = options.resume_at.take().filter(|_| seekable) {
+		let frame = duration_to_frames(resume_at, sample_rate);
+		match source.seek(frame) {
+			Ok(()) => position = frame,
+			Err(err) => eprintln!("failed to seek the restarted audio source back to where it was: {}", err)
+		}
+	}
+	// Set once the source (or its end_at offset) is exhausted.
+	let mut exhausted = false;
+	// Set once RepeatMode::All has put this track back in the queue.
+	let mut requeued = false;
+	// The frames set_loop_points loops between; they apply to this track only.
+	let mut loop_points: Option<(u64, u64)> = None;
+	// Passes of this track still to play after the current one, as set_loop_count asked; None to
+	// play it endlessly.
+	let mut repeats_left = Some(0u32);
+	// Nothing is decoded before the cable is played, so a cable built ahead of time doesn't sit
+	// on a full buffer of audio.
+	let mut started = progress.started.load(Ordering::Relaxed);
+	// Set by a Cue until the data channel is full.
+	let mut cueing = false;
+	let mut paused = progress.source_paused.load(Ordering::Relaxed);
+	loop {
+		// Once everything has been sent the thread moves on to the next queued track, or stays
+		// around waiting for a command such as a seek back into the file. It exits when stopped or
+		// when the cable is dropped. A paused source only waits for commands.
+		let idle = !started || paused || (exhausted && sent == pending.len());
+		let message = if idle {
+			// The playlist is only asked once the queue has nothing left, and asked again about
+			// every track, so it follows the cursor wherever the app has moved it meanwhile.
+			if started && !paused && exhausted && options.queue.as_deref().is_none_or(TrackQueue::is_empty) {
+				if let Some(next) = options.playlist.as_ref().and_then(|playlist| next_playlist_track(playlist, options.queue.as_deref())) {
+					progress.finished.store(false, Ordering::Relaxed);
+					let _ = events.send(PlaybackEvent::TrackChanged);
+					return Some(next);
+				}
+			}
+			match options.queue.as_deref().filter(|_| started && !paused) {
+				Some(queue) => match wait_for_track(control, queue, progress) {
+					Ok(message) => message,
+					Err(Some(next)) => {
+						progress.finished.store(false, Ordering::Relaxed);
+						let _ = events.send(PlaybackEvent::TrackChanged);
+						return Some(next);
+					},
+					Err(None) => return None
+				},
+				None => match control.recv() {
+					Ok(message) => Some(message),
+					Err(_) => return None
+				}
+			}
+		} else {
+			match control.try_recv() {
+				Ok(message) => Some(message),
+				Err(crossbeam_channel::TryRecvError::Empty) => None,
+				Err(crossbeam_channel::TryRecvError::Disconnected) => return None
+			}
+		};
+
+		match message {
+			// Seeks from a PlaybackControl reach sources that can't seek too; they're ignored just as
+			// AudioCable::seek ignores them.
+			Some(AudioChannelMessage::Seek(_)) if !seekable => continue,
+			Some(AudioChannelMessage::Seek(offset)) => {
+				// Nothing before the start_at offset is played, so a seek to before it goes straight
+				// there rather than decoding its way up to it.
+				let frame = duration_to_frames(offset, sample_rate).max(start_frame);
+				match source.seek(frame) {
+					Ok(()) => {
+						// Nothing is being sent right now, so everything still queued predates the
+						// seek and must not be heard.
+						drain.drain();
+						pending.clear();
+						sent = 0;
+						resampler = Resampler::new(sample_rate, output_rate, channels as u16, options.resample_quality);
+						stretch = stretch.map(|stretch| TimeStretch::new(channels as u16, output_rate, stretch.tempo()));
+						drift = Resampler::new(output_rate, output_rate, channels as u16, options.resample_quality);
+						if let Some(mirrors) = &options.mirrors {
+							mirrors.seek();
+						}
+						if let Some(trim) = &mut trim {
+							trim.seek(frame <= start_frame);
+						}
+						position = frame;
+						padding_frames = 0;
+						truncated_end = None;
+						exhausted = false;
+						progress.finished.store(false, Ordering::Relaxed);
+						let _ = events.send(PlaybackEvent::Seeked(offset));
+					},
+					Err(err) => eprintln!("failed to seek the audio source: {}", err)
+				}
+				continue;
+			},
+			Some(AudioChannelMessage::SetOutputRate(rate)) => {
+				// Samples already resampled at the old rate still go out; everything decoded from
+				// here on targets the new one, queued tracks included.
+				output_rate = rate;
+				options.output_rate = Some(rate);
+				resampler = Resampler::new(sample_rate, output_rate, channels as u16, options.resample_quality);
+				stretch = stretch.map(|stretch| TimeStretch::new(channels as u16, output_rate, stretch.tempo()));
+				drift = Resampler::new(output_rate, output_rate, channels as u16, options.resample_quality);
+				if let Some(mirrors) = &options.mirrors {
+					mirrors.sync.set_nominal_rate(output_rate);
+				}
+				continue;
+			},
+			Some(AudioChannelMessage::SetTempo(tempo)) => {
+				progress.tempo.store(tempo.to_bits(), Ordering::Relaxed);
+				match &mut stretch {
+					Some(stretch) => stretch.set_tempo(tempo),
+					None if tempo != 1.0 => stretch = Some(TimeStretch::new(channels as u16, output_rate, tempo)),
+					None => {}
+				}
+				continue;
+			},
+			Some(AudioChannelMessage::SetLoopPoints(points)) => {
+				loop_points = points;
+				continue;
+			},
+			Some(AudioChannelMessage::SetLoopCount(count)) => {
+				repeats_left = count.checked_sub(1);
+				continue;
+			},
+			Some(AudioChannelMessage::Start) => {
+				started = true;
+				progress.started.store(true, Ordering::Relaxed);
+				continue;
+			},
+			Some(AudioChannelMessage::Cue) => {
+				started = true;
+				cueing = true;
+				progress.started.store(true, Ordering::Relaxed);
+				continue;
+			},
+			Some(AudioChannelMessage::SetSourcePaused(pause)) => {
+				paused = pause;
+				continue;
+			},
+			Some(AudioChannelMessage::Stop) => {
+				drain.drain();
+				return None;
+			},
+			Some(AudioChannelMessage::StopSending) => return None,
+			Some(_) => continue,
+			None => {}
+		}
+
+		if sent < pending.len() {
+			let previously_sent = sent;
+			let result = send_pending(data_channel, drain.sent_samples(), &pending, &mut sent);
+			progress.sent_samples.fetch_add((sent - previously_sent) as u64, Ordering::Relaxed);
+			match result {
+				SendProgress::Done => {
+					pending.clear();
+					sent = 0;
+					if exhausted {
+						// A track too short to fill the data channel is cued once it's all in.
+						if std::mem::take(&mut cueing) {
+							let _ = events.send(PlaybackEvent::Cued);
+						}
+						finish_track(events, progress, options);
+					}
+				},
+				// Wait for room without blocking on the send, so a command arriving meanwhile is
+				// picked up as soon as it's sent.
+				SendProgress::Full => {
+					if std::mem::take(&mut cueing) {
+						let _ = events.send(PlaybackEvent::Cued);
+					}
+					wait_for_room(data_channel, control)
+				},
+				SendProgress::Disconnected => return None
+			}
+			continue;
+		}
+
+		// With sample rate conversion disabled, a track at another rate is skipped unplayed. This is
+		// only checked once the cable plays, after the builder has matched the output to the first.
+		if options.strict_rate && sample_rate != output_rate {
+			eprintln!("skipping {}: it is at {} Hz, the output at {} Hz, and sample rate conversion is disabled", path.as_deref().unwrap_or("the track"), sample_rate, output_rate);
+			exhausted = true;
+			finish_track(events, progress, options);
+			continue;
+		}
+
+		// The padding goes out ahead of the first block, a block's worth at a time, to the mirrors
+		// too so they stay aligned.
+		if padding_frames > 0 {
+			let frames = padding_frames.min(PADDING_BLOCK_FRAMES);
+			padding_frames -= frames;
+			pending.resize(frames as usize * fill_to.unwrap_or(channels), 0.0);
+			if let Some(mirrors) = &options.mirrors {
+				mirrors.push(&pending);
+			}
+			continue;
+		}
+
+		decoded.clear();
+		let mut more = match truncated_end {
+			Some(end) => {
+				let frames = end.saturating_sub(position).min(PADDING_BLOCK_FRAMES);
+				decoded.resize(frames as usize * channels, 0.0);
+				position + frames < end
+			},
+			None => match source.read_block(&mut decoded) {
+				Ok(more) => more,
+				// The watchdog opens the track again and carries on from where it got to.
+				Err(err) if options.watchdog.is_some() => {
+					*progress.failure.lock().unwrap_or_else(PoisonError::into_inner) = Some(err.to_string());
+					return None;
+				},
+				Err(err) => {
+					eprintln!("an error occurred while decoding the audio source: {}", err);
+					false
+				}
+			}
+		};
+		progress.bytes_read.store(bytes_before + source.bytes_read(), Ordering::Relaxed);
+		if source.decoded_channels() != stream_channels {
+			stream_channels = source.decoded_channels();
+			let _ = events.send(PlaybackEvent::ChannelsChanged(stream_channels));
+		}
+
+		// A source that ends short of the length its header declares is truncated or corrupt.
+		// Padding it out keeps anything timed against the declared length, such as a video or the
+		// next track of a playlist, where it should be.
+		let decoded_to = position + (decoded.len() / channels) as u64;
+		if !more && truncated_end.is_none() {
+			if let Some(declared) = source.declared_frames().filter(|&declared| decoded_to < declared) {
+				if !truncation_reported {
+					truncation_reported = true;
+					let message = format!(
+						"{} ends after {} of the {} frames its header declares{}",
+						path.as_deref().unwrap_or("the track"), decoded_to, declared,
+						if options.pad_truncated { "; padding the rest with silence" } else { "" }
+					);
+					eprintln!("{}", message);
+					let _ = events.send(PlaybackEvent::DecodeWarning(message));
+				}
+				if options.pad_truncated {
+					truncated_end = Some(declared);
+					more = true;
+				}
+			}
+		}
+
+		// Only the frames within [start_frame, end_frame) are played; anything before the start
+		// offset is decoded and discarded.
+		let block_frames = (decoded.len() / channels) as u64;
+		let from = start_frame.saturating_sub(position).min(block_frames) as usize;
+		let mut to = end_frame.map_or(block_frames, |end| end.saturating_sub(position).min(block_frames)) as usize;
+		if let Some((_, loop_end)) = loop_points {
+			to = to.min(loop_end.saturating_sub(position).min(block_frames) as usize);
+		}
+		position += block_frames;
+		progress.source_position.store(frames_to_duration(position, sample_rate).as_nanos() as u64, Ordering::Relaxed);
+
+		if from < to {
+			let peak = decoded[from * channels..to * channels].iter().fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+			progress.source_peak.fetch_max(peak.to_bits(), Ordering::Relaxed);
+			if let Some(gain) = replay_gain {
+				for sample in &mut decoded[from * channels..to * channels] {
+					*sample *= gain;
+				}
+			}
+			if let Some(normalizer) = &mut normalizer {
+				normalizer.process(&mut decoded[from * channels..to * channels]);
+			}
+			let mut input = &decoded[from * channels..to * channels];
+			if let Some(trim) = &mut trim {
+				trimmed.clear();
+				trim.process(input, &mut trimmed);
+				input = &trimmed;
+			}
+			match &mut stretch {
+				Some(stretch) => {
+					resampled.clear();
+					resampler.process(input, &mut resampled);
+					stretch.process(&resampled, &mut pending);
+				},
+				None => resampler.process(input, &mut pending)
+			}
+		}
+
+		// A loop goes back to its start from the exact frame it ends on, or from the end of the
+		// track if that comes first, carrying the resampler on into it so there's no gap.
+		if let Some((loop_start, _)) = loop_points.filter(|&(_, loop_end)| position >= loop_end || !more) {
+			match source.seek(loop_start) {
+				Ok(()) => {
+					position = loop_start;
+					truncated_end = None;
+					more = true;
+				},
+				Err(err) => {
+					eprintln!("failed to seek the audio source back to the loop start: {}", err);
+					loop_points = None;
+				}
+			}
+		}
+		let mut finishing = !more || end_frame.is_some_and(|end| position >= end);
+		// A repeated track is rewound straight away rather than flushed, so the resampler carries
+		// on into its start without a gap. Each pass a loop count asks for is a repeat too.
+		if finishing && seekable && (repeats_left != Some(0) || options.queue.as_deref().is_some_and(TrackQueue::rewinds)) {
+			match source.seek(start_frame) {
+				Ok(()) => {
+					if let Some(left) = &mut repeats_left {
+						*left = left.saturating_sub(1);
+					}
+					position = start_frame;
+					truncated_end = None;
+					if let Some(trim) = &mut trim {
+						trim.seek(true);
+					}
+					let _ = events.send(PlaybackEvent::Finished);
+					let _ = events.send(PlaybackEvent::TrackChanged);
+					finishing = false;
+				},
+				Err(err) => eprintln!("failed to rewind the audio source to repeat it: {}", err)
+			}
+		}
+		if finishing {
+			match &mut stretch {
+				Some(stretch) => {
+					resampled.clear();
+					resampler.flush(&mut resampled);
+					stretch.process(&resampled, &mut pending);
+					stretch.flush(&mut pending);
+				},
+				None => resampler.flush(&mut pending)
+			}
+		}
+
+		if let Some(output_channels) = fill_to {
+			filled.clear();
+			duplicate_channels(&pending, channels, output_channels, &mut filled);
+			std::mem::swap(&mut pending, &mut filled);
+		}
+
+		if !pending.is_empty() {
+			if let Some(filter) = &mut *progress.source_filter.lock().unwrap_or_else(PoisonError::into_inner) {
+				filter(&mut pending);
+			}
+		}
+
+		if let Some(mirrors) = &options.mirrors {
+			mirrors.push(&pending);
+			mirrored.clear();
+			mirrored.append(&mut pending);
+			drift.set_drift_correction(mirrors.sync.primary_correction());
+			drift.process(&mirrored, &mut pending);
+			if finishing {
+				drift.flush(&mut pending);
+			}
+		}
+		let latency = resampler.latency() + drift.latency();
+		progress.resampler_latency.store(latency.as_nanos() as u64, Ordering::Relaxed);
+
+		if finishing {
+			exhausted = true;
+			if let (Some(queue), Some(path)) = (options.queue.as_deref(), &path) {
+				// A playlist loops by starting over instead.
+				if queue.repeat_mode() == RepeatMode::All && !requeued && options.playlist.is_none() {
+					requeued = true;
+					if let Err(err) = queue.enqueue(path.clone()) {
+						eprintln!("failed to queue the track again: {}", err);
+					}
+				}
+			}
+			if pending.is_empty() {
+				finish_track(events, progress, options);
+			}
+		}
+	}
+}
+
+// Repeats the `channels` channels of each frame of `input` in order until there are
+// `output_channels` of them, appending the frames to `output`.
+fn duplicate_channels(input: &[f32], channels: usize, output_channels: usize, output: &mut Vec<f32>) {
+	output.reserve(input.len() / channels * output_channels);
+	for frame in input.chunks_exact(channels) {
+		output.extend(frame.iter().cycle().take(output_channels));
+	}
+}
+
+// Reports that the last of a track has been sent. The producer is only finished once no queued
+// track follows.
+fn finish_track(events: &crossbeam_channel::Sender<PlaybackEvent>, progress: &ProducerProgress, options: &ProducerOptions) {
+	let queue = options.queue.as_deref();
+	let playlist_continues = options.playlist.as_ref().is_some_and(|playlist| {
+		playlist.has_next() || (!playlist.is_empty() && queue.is_some_and(|queue| queue.repeat_mode() == RepeatMode::All))
+	});
+	let last = queue.is_none_or(TrackQueue::is_empty) && !playlist_continues;
+	progress.finished.store(last, Ordering::Relaxed);
+	let _ = events.send(PlaybackEvent::Finished);
+	if last {
+		let _ = events.send(PlaybackEvent::QueueEmpty);
+	}
+}
+
+// How restart_track ended when it didn't get the track going again.
+enum RestartEnd {
+	// The watchdog is off, or out of restarts, or there is nothing to open the track again from.
+	GaveUp,
+	// The thread was told to stop while it waited.
+	Stopped,
+}
+
+// Opens the track that failed with `reason` again, from its file or through `reopen`, once the
+// watchdog allows it, trying again for as long as it does. The track carries on from where the
+// decoder had got to.
+#[allow(clippy::too_many_arguments)]
+fn restart_track(watchdog: Option<&mut Watchdog>, path: Option<String>, reopen: Option<&Reopen>, reason: &str, control: &crossbeam_channel::Receiver<AudioChannelMessage>, drain: &StaleSampleDrain, events: &crossbeam_channel::Sender<PlaybackEvent>, progress: &ProducerProgress, options: &mut ProducerOptions) -> Result<Track, RestartEnd> {
+	let watchdog = watchdog.ok_or(RestartEnd::GaveUp)?;
+	if path.is_none() && reopen.is_none() {
+		return Err(RestartEnd::GaveUp);
+	}
+	options.start_padding = None;
+	options.resume_at = Some(Duration::from_nanos(progress.source_position.load(Ordering::Relaxed)));
+	let mut reason = String::from(reason);
+	loop {
+		let attempt = watchdog.restart().ok_or(RestartEnd::GaveUp)?;
+		eprintln!("the audio source failed, restarting it (attempt {}): {}", attempt, reason);
+		let _ = events.send(PlaybackEvent::SourceRestarted(reason.clone()));
+		wait_to_restart(control, drain, progress, options)?;
+		let source = match (&path, reopen) {
+			(Some(path), _) => SourceType::from_local(path.clone())
+				.and_then(|source_type| source_type.open())
+				.and_then(|source| source.ok_or_else(|| AudioError::Decode(format!("{} has nothing to decode", path)))),
+			(None, Some(reopen)) => reopen(),
+			(None, None) => return Err(RestartEnd::GaveUp)
+		};
+		let source = source.and_then(|mut source| {
+			if let Some(choice) = &options.audio_track {
+				source.select_audio_track(choice.pick(&source.audio_tracks())?)?;
+			}
+			Ok(source)
+		});
+		match source {
+			Ok(source) => return Ok(Track { source, path }),
+			Err(err) => reason = err.to_string()
+		}
+	}
+}
+
+// Sits out RESTART_DELAY, keeping what the commands that arrive meanwhile ask for so the
+// restarted track starts out with it; a seek moves where it resumes.
+fn wait_to_restart(control: &crossbeam_channel::Receiver<AudioChannelMessage>, drain: &StaleSampleDrain, progress: &ProducerProgress, options: &mut ProducerOptions) -> Result<(), RestartEnd> {
+	let deadline = std::time::Instant::now() + RESTART_DELAY;
+	loop {
+		let message = match control.recv_deadline(deadline) {
+			Ok(message) => message,
+			Err(crossbeam_channel::RecvTimeoutError::Timeout) => return Ok(()),
+			Err(crossbeam_channel::RecvTimeoutError::Disconnected) => return Err(RestartEnd::Stopped)
+		};
+		match message {
+			AudioChannelMessage::Stop => {
+				drain.drain();
+				return Err(RestartEnd::Stopped);
+			},
+			AudioChannelMessage::StopSending => return Err(RestartEnd::Stopped),
+			AudioChannelMessage::Seek(offset) => options.resume_at = Some(offset),
+			AudioChannelMessage::SetOutputRate(rate) => options.output_rate = Some(rate),
+			AudioChannelMessage::SetTempo(tempo) => progress.tempo.store(tempo.to_bits(), Ordering::Relaxed),
+			AudioChannelMessage::Start | AudioChannelMessage::Cue => progress.started.store(true, Ordering::Relaxed),
+			_ => {}
+		}
+	}
+}
+
+// Moves `playlist` on and opens the track it lands on, skipping those that can't be opened. With
+// RepeatMode::All it starts over once, from the first track, when it reaches the end.
+fn next_playlist_track(playlist: &PlaylistCursor, queue: Option<&TrackQueue>) -> Option<Track> {
+	let mut wrapped = false;
+	loop {
+		let path = match playlist.next() {
+			Some(path) => path,
+			None if !wrapped && !playlist.is_empty() && queue.is_some_and(|queue| queue.repeat_mode() == RepeatMode::All) => {
+				wrapped = true;
+				playlist.rewind();
+				continue;
+			},
+			None => return None
+		};
+		let path = match path.to_str() {
+			Some(path) => String::from(path),
+			None => {
+				eprintln!("skipping the playlist track {}: its path isn't valid UTF-8", path.display());
+				continue;
+			}
+		};
+		match SourceType::from_local(path.clone()).and_then(|source_type| source_type.open()) {
+			Ok(Some(source)) => return Some(Track { source, path: Some(path) }),
+			Ok(None) => eprintln!("skipping the playlist track {}: there is nothing to decode", path),
+			Err(err) => eprintln!("failed to open the playlist track {}, skipping it: {}", path, err)
+		}
+	}
+}
+
+// Waits for a command or, once the current track has been sent, the next queued one. Err holds
+// the next track, or None when the thread should exit.
+fn wait_for_track(control: &crossbeam_channel::Receiver<AudioChannelMessage>, queue: &TrackQueue, progress: &ProducerProgress) -> Result<Option<AudioChannelMessage>, Option<Track>> {
+	let mut select = crossbeam_channel::Select::new();
+	let commands = select.recv(control);
+	select.recv(queue.staged());
+	// Blocking is only safe once nothing is pending, since a queued file that fails to open is
+	// skipped without staging anything.
+	let ready = if queue.is_empty() {
+		progress.finished.store(true, Ordering::Relaxed);
+		select.select()
+	} else {
+		match select.select_timeout(QUEUE_POLL_INTERVAL) {
+			Ok(ready) => ready,
+			Err(_) => return Ok(None)
+		}
+	};
+	if ready.index() == commands {
+		return ready.recv(control).map(Some).map_err(|_| None);
+	}
+	match ready.recv(queue.staged()) {
+		Ok(track) => Err(Some(queue.take(track))),
+		Err(_) => Err(None)
+	}
+}
+
+enum SendProgress {
+	Done,
+	Full,
+	Disconnected,
+}
+
+// Blocks until the data channel has room or a command has arrived. Neither is taken: the caller's
+// loop handles the command, or sends, on its next pass.
+fn wait_for_room<T>(data_channel: &crossbeam_channel::Sender<f32>, control: &crossbeam_channel::Receiver<T>) {
+	let mut select = crossbeam_channel::Select::new();
+	select.send(data_channel);
+	select.recv(control);
+	select.ready();
+}
+
+// Pushes as much of `pending` into the data channel as fits without blocking, counting each
+// sample in the consumer's `sent_samples` before it goes.
+fn send_pending(data_channel: &crossbeam_channel::Sender<f32>, sent_samples: &AtomicU64, pending: &[f32], sent: &mut usize) -> SendProgress {
+	while *sent < pending.len() {
+		sent_samples.fetch_add(1, Ordering::Relaxed);
+		match data_channel.try_send(pending[*sent]) {
+			Ok(()) => *sent += 1,
+			Err(err) => {
+				sent_samples.fetch_sub(1, Ordering::Relaxed);
+				return match err {
+					crossbeam_channel::TrySendError::Full(_) => SendProgress::Full,
+					crossbeam_channel::TrySendError::Disconnected(_) => SendProgress::Disconnected
+				};
+			}
+		}
+	}
+	SendProgress::Done
+}
+
+impl StreamAudioProducer for AudioProducer {
+	fn new(url: &str, data_channel: crossbeam_channel::Sender<f32>, drain: StaleSampleDrain, events: crossbeam_channel::Sender<PlaybackEvent>, options: ProducerOptions) -> Result<AudioProducer, AudioError> {
+		let feed = Arc::new(MetadataFeed::new());
+		let source = open_stream(url, &feed, &events)?;
+		let mut ap = AudioProducer::unconnected(SourceType::READER, data_channel, drain, events, options);
+		let (url, reopen_feed, reopen_events) = (String::from(url), Arc::clone(&feed), ap.events.clone());
+		ap.reopen = Some(Arc::new(move || open_stream(&url, &reopen_feed, &reopen_events)));
+		ap.metadata = feed;
+		ap.spawn(source)?;
+		Ok(ap)
+	}
+}
+
+// Connects to the internet radio stream at `url`, keeping `feed` up to date with its metadata.
+fn open_stream(url: &str, feed: &Arc<MetadataFeed>, events: &crossbeam_channel::Sender<PlaybackEvent>) -> Result<Box<dyn AudioSource>, AudioError> {
+	let stream = IcyStream::connect(url)?;
+	let reader: Box<dyn Read + Send> = match stream.metaint {
+		Some(metaint) => Box::new(IcyReader::new(stream.reader, metaint, Arc::clone(feed), events.clone())),
+		None => Box::new(stream.reader)
+	};
+	// The stream's own bytes are trusted over its Content-Type, which stations often get wrong.
+	let mut reader = PipeReader::new(reader);
+	let format = Format::sniff(reader.peek(SNIFF_LEN)?).or(stream.format)
+		.ok_or_else(|| AudioError::Decode(format!("the format of {} wasn't recognised", url)))?;
+	open_reader(reader, format)
+}
+
+impl AudioProducer {
+	fn unconnected(source_type: SourceType, data_channel: crossbeam_channel::Sender<f32>, drain: StaleSampleDrain, events: crossbeam_channel::Sender<PlaybackEvent>, options: ProducerOptions) -> AudioProducer {
+		let progress = Arc::new(ProducerProgress::default());
+		let comm_chan = CommandSender::channel(options.control_channel, Arc::clone(&progress));
+		AudioProducer {
+			data_channel: Arc::new(Mutex::new(data_channel)),
+			source_type: Arc::new(source_type),
+			thread: None,
+			events,
+			options,
+			comm_chan,
+			drain,
+			cover_art: None,
+			chapters: Vec::new(),
+			audio_tracks: Vec::new(),
+			metadata: Arc::new(MetadataFeed::new()),
+			progress,
+			reopen: None,
+		}
+	}
+
+	// Decodes `format` from any reader instead of a file, e.g. a decrypted stream or an entry in
+	// an archive. Seeking backwards needs the reader to seek; the source reports Unsupported when
+	// it can't.
+	fn from_reader<R: Read + Seek + Send + 'static>(reader: R, format: Format, data_channel: crossbeam_channel::Sender<f32>, drain: StaleSampleDrain, events: crossbeam_channel::Sender<PlaybackEvent>, options: ProducerOptions) -> Result<AudioProducer, AudioError> {
+		let source = open_reader(reader, format)?;
+		let mut ap = AudioProducer::unconnected(SourceType::READER, data_channel, drain, events, options);
+		ap.spawn(source)?;
+		Ok(ap)
+	}
+
+	// Decodes a stream that can only be read once, front to back, such as standard input. Without
+	// `format` it's identified from the first bytes, which are replayed to the decoder. Seeking
+	// reports Unsupported.
+	fn from_pipe<R: Read + Send + 'static>(reader: R, format: Option<Format>, data_channel: crossbeam_channel::Sender<f32>, drain: StaleSampleDrain, events: crossbeam_channel::Sender<PlaybackEvent>, options: ProducerOptions) -> Result<AudioProducer, AudioError> {
+		let mut reader = PipeReader::new(reader);
+		let format = match format {
+			Some(format) => format,
+			None => Format::sniff(reader.peek(SNIFF_LEN)?)
+				.ok_or_else(|| AudioError::Decode(String::from("the stream's format wasn't recognised; name it instead")))?
+		};
+		AudioProducer::from_reader(reader, format, data_channel, drain, events, options)
+	}
+
+	// Plays a source opened by the caller, e.g. raw PCM whose layout the builder was given.
+	fn from_source(source: Box<dyn AudioSource>, data_channel: crossbeam_channel::Sender<f32>, drain: StaleSampleDrain, events: crossbeam_channel::Sender<PlaybackEvent>, options: ProducerOptions) -> Result<AudioProducer, AudioError> {
+		let mut ap = AudioProducer::unconnected(SourceType::READER, data_channel, drain, events, options);
+		ap.spawn(source)?;
+		Ok(ap)
+	}
+
+	// Starts the decode thread. It's named after the file where there is one, so it can be told
+	// apart from other cables' in debuggers, profilers and panic messages.
+	fn spawn(&mut self, mut source: Box<dyn AudioSource>) -> Result<(), AudioError> {
+		self.audio_tracks = source.audio_tracks();
+		if let Some(choice) = &self.options.audio_track {
+			source.select_audio_track(choice.pick(&self.audio_tracks)?)?;
+		}
+		self.check_channel_fill(&*source)?;
+		self.cover_art = source.cover_art().cloned();
+		self.chapters = source.chapters().to_vec();
+		let broadcast = source.broadcast_info().cloned();
+		self.metadata.update(|metadata| metadata.broadcast = broadcast);
+		self.progress.source_rate.store(source.sample_rate(), Ordering::Relaxed);
+		self.progress.seekable.store(source.is_seekable(), Ordering::Relaxed);
+
+		// Grab a shared access to data_channel to use in the thread.
+		let tx_channel = Arc::clone(&self.data_channel);
+		let events = self.events.clone();
+		let control = self.comm_chan.1.clone();
+		let drain = self.drain.clone();
+		let mut options = self.options.clone();
+		let progress = Arc::clone(&self.progress);
+		progress.exited.store(false, Ordering::Relaxed);
+
+		let path = self.source_type.path().map(String::from);
+		let index = PRODUCER_THREADS.fetch_add(1, Ordering::Relaxed);
+		let name = match path.as_deref().and_then(|path| Path::new(path).file_name()) {
+			Some(file_name) => format!("audios-producer-{} ({})", index, file_name.to_string_lossy()),
+			None => format!("audios-producer-{}", index)
+		};
+
+		let reopen = self.reopen.clone();
+		self.thread = Some(std::thread::Builder::new().name(name).spawn(move || {
+			let _flush = FlushToZero::enable();
+			// Panics are caught for the watchdog to restart the track after, too.
+			let catch_panics = options.catch_panics || options.watchdog.is_some();
+			let mut watchdog = options.watchdog.map(Watchdog::new);
+			let mut track = Some(Track { source, path });
+			while let Some(first) = track.take() {
+				// The track being played, to open again should it fail.
+				let mut playing = first.path.clone();
+				let run = || {
+					let mut track = first;
+					loop {
+						playing = track.path.clone();
+						match run_source(track, &tx_channel, &control, &drain, &events, &progress, &mut options) {
+							Some(next) => track = next,
+							None => break
+						}
+						// The offsets, padding, scanned peak and audio track only apply to the track
+						// the cable was built with.
+						options.start_at = None;
+						options.end_at = None;
+						options.start_padding = None;
+						options.normalize_peak = options.normalize_peak.map(PeakNormalizer::running);
+						options.audio_track = None;
+					}
+				};
+				let panic = if !catch_panics {
+					run();
+					None
+				} else {
+					panic::catch_unwind(AssertUnwindSafe(run)).err().map(|payload| {
+						match (payload.downcast_ref::<&str>(), payload.downcast_ref::<String>()) {
+							(Some(message), _) => String::from(*message),
+							(_, Some(message)) => message.clone(),
+							_ => String::from("unknown panic")
+						}
+					})
+				};
+				let failure = panic.clone().or_else(|| progress.failure.lock().unwrap_or_else(PoisonError::into_inner).take());
+				let message = match failure {
+					Some(message) => message,
+					None => break
+				};
+				match restart_track(watchdog.as_mut(), playing, reopen.as_ref(), &message, &control, &drain, &events, &progress, &mut options) {
+					Ok(restarted) => track = Some(restarted),
+					Err(RestartEnd::Stopped) => {},
+					Err(RestartEnd::GaveUp) => {
+						progress.finished.store(true, Ordering::Relaxed);
+						match panic {
+							Some(message) => {
+								*progress.panic.lock().unwrap_or_else(PoisonError::into_inner) = Some(message.clone());
+								let _ = events.send(PlaybackEvent::DecoderPanicked(message));
+							},
+							None => {
+								eprintln!("giving up on the audio source: {}", message);
+								let _ = events.send(PlaybackEvent::SourceFailed(message));
+							}
+						}
+					}
+				}
+			}
+			progress.exited.store(true, Ordering::Relaxed);
+		})?);
+		Ok(())
+	}
+
+	// Whether the source has nothing more to send: everything up to its end has gone into the data
+	// channel, or the decode thread has exited (or never started, for a sourceless producer). A
+	// seek back into the source makes it unfinished again.
+	fn is_finished(&self) -> bool {
+		self.thread.is_none() || self.progress.exited.load(Ordering::Relaxed) || self.progress.finished.load(Ordering::Relaxed)
+	}
+
+	// Fails with DecoderPanicked once the decode thread has caught a panic, as commands for it
+	// would go unheard.
+	fn check_panic(&self) -> Result<(), AudioError> {
+		match self.progress.panic.lock().unwrap_or_else(PoisonError::into_inner).clone() {
+			Some(message) => Err(AudioError::DecoderPanicked(message)),
+			None => Ok(())
+		}
+	}
+
+	fn seek(&self, offset: Duration) -> Result<(), AudioError> {
+		self.check_panic()?;
+		if !self.progress.seekable.load(Ordering::Relaxed) {
+			return Ok(());
+		}
+		self.comm_chan.0.send(AudioChannelMessage::Seek(offset))
+	}
+
+	fn set_source_filter(&self, filter: Option<SourceFilter>) {
+		*self.progress.source_filter.lock().unwrap_or_else(PoisonError::into_inner) = filter;
+	}
+
+	fn set_loop_count(&self, count: u32) -> Result<(), AudioError> {
+		self.check_panic()?;
+		if count != 1 && !self.progress.seekable.load(Ordering::Relaxed) {
+			return Err(AudioError::Unsupported(String::from("the source can't seek back to its start to play it again")));
+		}
+		self.comm_chan.0.send(AudioChannelMessage::SetLoopCount(count))
+	}
+
+	fn set_loop_points(&self, points: Option<(u64, u64)>) -> Result<(), AudioError> {
+		self.check_panic()?;
+		if points.is_some() && !self.progress.seekable.load(Ordering::Relaxed) {
+			return Err(AudioError::Unsupported(String::from("the source can't seek back to a loop start")));
+		}
+		self.comm_chan.0.send(AudioChannelMessage::SetLoopPoints(points))
+	}
+
+	// Blocks until `samples` have been sent, the source is exhausted or `timeout` has passed.
+	// Returns whether the samples were sent.
+	fn wait_for_samples(&self, samples: u64, timeout: Duration) -> bool {
+		if self.thread.is_none() {
+			return false;
+		}
+		let deadline = std::time::Instant::now() + timeout;
+		loop {
+			if self.progress.sent_samples.load(Ordering::Relaxed) >= samples {
+				return true;
+			}
+			if self.progress.finished.load(Ordering::Relaxed) || std::time::Instant::now() >= deadline {
+				return false;
+			}
+			std::thread::sleep(PRODUCER_POLL_INTERVAL);
+		}
+	}
+
+	fn start(&self) -> Result<(), AudioError> {
+		self.comm_chan.0.send(AudioChannelMessage::Start)
+	}
+
+	fn cue(&self) -> Result<(), AudioError> {
+		self.comm_chan.0.send(AudioChannelMessage::Cue)
+	}
+
+	fn set_output_rate(&mut self, sample_rate: u32) {
+		// Kept for the thread of a replaced source.
+		self.options.output_rate = Some(sample_rate);
+		let _ = self.comm_chan.0.send(AudioChannelMessage::SetOutputRate(sample_rate));
+	}
+
+	// With ChannelFill::Duplicate, a source can't have more channels than the output it's
+	// duplicated onto.
+	fn check_channel_fill(&self, source: &dyn AudioSource) -> Result<(), AudioError> {
+		match self.options.output_channels {
+			Some(output_channels) if self.options.channel_fill == ChannelFill::Duplicate && source.channels() > output_channels => {
+				Err(AudioError::InvalidConfig(format!(
+					"a source with {} channels can't be duplicated onto {} output channels", source.channels(), output_channels
+				)))
+			},
+			_ => Ok(())
+		}
+	}
+
+	// With sample rate conversion disabled, a source must match the output rate.
+	fn check_strict_rate(&self, source_rate: u32) -> Result<(), AudioError> {
+		match self.options.output_rate {
+			Some(output_rate) if self.options.strict_rate && source_rate != output_rate => {
+				Err(AudioError::RateMismatch { source_rate, output_rate })
+			},
+			_ => Ok(())
+		}
+	}
+
+	// Stops decoding the current source and starts on the file `file`, into the same data
+	// channel. The file is opened first, so a bad one leaves the current source playing. The new
+	// thread starts straight away if the old one had been started, and otherwise waits for start
+	// like the first one. The queue carries on after the new file.
+	fn replace_file(&mut self, file: String) -> Result<(), AudioError> {
+		let source_type = SourceType::from_local(file)?;
+		let source = source_type.open()?;
+		if let Some(source) = &source {
+			self.check_channel_fill(&**source)?;
+			self.check_strict_rate(source.sample_rate())?;
+		}
+
+		self.stop();
+		// Commands meant for the old source, e.g. a seek sent while it was stopping, don't apply
+		// to the new one.
+		while self.comm_chan.1.try_recv().is_ok() {}
+		if let Some(mirrors) = &self.options.mirrors {
+			mirrors.seek();
+		}
+		self.progress.finished.store(false, Ordering::Relaxed);
+		// Only the cable's start is padded; a replacement follows on at once, and its peak hasn't
+		// been scanned.
+		self.options.start_padding = None;
+		self.options.normalize_peak = self.options.normalize_peak.map(PeakNormalizer::running);
+		self.options.audio_track = None;
+		self.source_type = Arc::new(source_type);
+		self.reopen = None;
+		self.cover_art = None;
+		self.chapters.clear();
+		self.metadata.update(|metadata| metadata.stream_title = None);
+		self.progress.seekable.store(false, Ordering::Relaxed);
+		self.progress.source_peak.store(0, Ordering::Relaxed);
+		*self.progress.panic.lock().unwrap_or_else(PoisonError::into_inner) = None;
+
+		if let Some(source) = source {
+			self.spawn(source)?;
+		}
+		let _ = self.events.send(PlaybackEvent::TrackChanged);
+		Ok(())
+	}
+
+	fn set_tempo(&self, tempo: f32) -> Result<(), AudioError> {
+		self.check_panic()?;
+		set_tempo(&self.comm_chan.0, tempo)
+	}
+
+	fn set_paused(&self, paused: bool) {
+		self.progress.source_paused.store(paused, Ordering::Relaxed);
+		let _ = self.comm_chan.0.send(AudioChannelMessage::SetSourcePaused(paused));
+	}
+
+	fn is_paused(&self) -> bool {
+		self.progress.source_paused.load(Ordering::Relaxed)
+	}
+
+	fn source_peak(&self) -> f32 {
+		f32::from_bits(self.progress.source_peak.load(Ordering::Relaxed))
+	}
+
+	fn bytes_read(&self) -> u64 {
+		self.progress.bytes_read.load(Ordering::Relaxed)
+	}
+
+	// The sample rate of the track playing now, which changes as queued tracks take over.
+	fn source_rate(&self) -> u32 {
+		self.progress.source_rate.load(Ordering::Relaxed)
+	}
+
+	// Frames, in the output's layout, the decode thread has sent since the producer started.
+	fn decode_position(&self) -> u64 {
+		self.progress.sent_samples.load(Ordering::Relaxed) / self.options.output_channels.unwrap_or(1).max(1) as u64
+	}
+
+	fn resampler_latency(&self) -> Duration {
+		Duration::from_nanos(self.progress.resampler_latency.load(Ordering::Relaxed))
+	}
+
+	fn stop(&mut self) {
+		let _ = self.comm_chan.0.send(AudioChannelMessage::Stop);
+		if let Some(thread) = self.thread.take() {
+			let _ = thread.join();
+		}
+		// The thread may have finished decoding before it saw the Stop.
+		self.drain.drain();
+	}
+
+	// Ends the thread without draining the data channel, so the consumer can play out what it was
+	// sent. Whatever the thread had decoded but not yet sent is dropped.
+	fn stop_sending(&mut self) {
+		let _ = self.comm_chan.0.send(AudioChannelMessage::StopSending);
+		if let Some(thread) = self.thread.take() {
+			let _ = thread.join();
+		}
+	}
+}
+
+
+// Shared by AudioProducer and PlaybackControl so both validate the ratio the same way.
+fn set_tempo(commands: &CommandSender, tempo: f32) -> Result<(), AudioError> {
+	if !TEMPO_RANGE.contains(&tempo) {
+		return Err(AudioError::InvalidArgument(format!(
+			"tempo must be between {} and {}, got {}", TEMPO_RANGE.start(), TEMPO_RANGE.end(), tempo
+		)));
+	}
+	commands.send(AudioChannelMessage::SetTempo(tempo))
+}
+
+
+/* AudioCable - Digital representation of a physical connection between a source and a destination.
+
+ */
+#[allow(dead_code)]
+pub struct AudioCable {
+	data_source: AudioProducer,
+	data_destination: AudioConsumer,
+	events: crossbeam_channel::Receiver<PlaybackEvent>,
+	// Samples to buffer before the output stream is first started.
+	warmup_samples: u64,
+	started: AtomicBool,
+	// The input device of a cable playing from one, e.g. AudioCable::monitor.
+	capture: Option<InputCapture>,
+	// Extra outputs added with AudioCableBuilder::mirror_to, and the clock they follow.
+	mirrors: Vec<MirrorOutput>,
+	clock: Option<Arc<ClockSync>>,
+	id: CableId,
+	// The registry the cable was added to with AudioCableBuilder::registry, left again on drop.
+	registry: Option<CableRegistry>,
+}
+
+impl AudioCable {
+	// Panics if the cable can't be built; see try_new for the fallible version.
+	pub fn new(audio_source: String) -> Self {
+		AudioCable::try_new(audio_source).unwrap()
+	}
+
+	// Opens the default output device and starts playing `audio_source`, reporting device,
+	// file-open and format errors instead of panicking.
+	pub fn try_new(audio_source: String) -> Result<Self, AudioError> {
+		AudioCable::builder(audio_source).build()
+	}
+
+	// Like try_new, but lets the producer decode at most `frames` ahead of the output device
+	// instead of the default 8192. See AudioCableBuilder::capacity_frames for the trade-off.
+	pub fn with_capacity(audio_source: String, frames: usize) -> Result<Self, AudioError> {
+		AudioCable::builder(audio_source).capacity_frames(frames).build()
+	}
+
+	pub fn builder(audio_source: String) -> AudioCableBuilder {
+		AudioCableBuilder::new(audio_source)
+	}
+
+	// Plays `format` decoded from `reader` rather than a file. See AudioCableBuilder::from_reader.
+	pub fn from_reader<R: Read + Seek + Send + 'static>(reader: R, format: Format) -> Result<Self, AudioError> {
+		AudioCableBuilder::from_reader(reader, format).build()
+	}
+
+	// Plays an internet radio station or other HTTP stream. See AudioCableBuilder::from_url.
+	pub fn from_url(url: String) -> Result<Self, AudioError> {
+		AudioCableBuilder::from_url(url).build()
+	}
+
+	// Plays encoded audio piped to standard input. See AudioCableBuilder::from_stdin.
+	pub fn from_stdin(format: Option<Format>) -> Result<Self, AudioError> {
+		AudioCableBuilder::from_stdin(format).build()
+	}
+
+	// Plays headerless PCM from `reader`. See AudioCableBuilder::from_raw_pcm.
+	pub fn from_raw_pcm<R: Read + Seek + Send + 'static>(reader: R, format: PcmFormat, sample_rate: u32, channels: u16) -> Result<Self, AudioError> {
+		AudioCableBuilder::from_raw_pcm(reader, format, sample_rate, channels).build()
+	}
+
+	// Plays the samples pushed through the returned sink. See AudioCableBuilder::manual.
+	pub fn manual(sample_rate: u32, channels: u16) -> Result<(Self, SampleSink), AudioError> {
+		let (builder, sink) = AudioCableBuilder::manual(sample_rate, channels);
+		Ok((builder.build()?, sink))
+	}
+
+	// Plays the input device `input_device` (None for the default) on the output device
+	// `output_device` while recording it to a 16-bit WAV file at `record_path`, e.g. to hear
+	// yourself through headphones while recording a microphone.
+	//
+	// Playing a microphone through speakers next to it will feed back. Use headphones, or mute the
+	// output leg with set_muted: the recording carries on unaffected. The speakers lag the input by
+	// up to 1024 frames plus the devices' own buffers; use AudioCableBuilder::from_device with
+	// max_input_latency to keep that lag from growing when the two devices' clocks drift apart.
+	pub fn monitor<P: AsRef<Path>>(input_device: Option<&str>, output_device: Option<&str>, record_path: P) -> Result<Self, AudioError> {
+		let mut builder = AudioCableBuilder::from_device(input_device.map(String::from))
+			.record_to(record_path)
+			.capacity_frames(MONITOR_CAPACITY_FRAMES)
+			.warmup_frames(0);
+		if let Some(output_device) = output_device {
+			builder = builder.output_device(String::from(output_device));
+		}
+		builder.build()
+	}
+
+	pub fn consumer(&self) -> &AudioConsumer {
+		&self.data_destination
+	}
+
+	// Pulls the next samples of a cable built with AudioCableBuilder::pull_output; see
+	// AudioConsumer::fill.
+	pub fn fill(&mut self, out: &mut [f32]) {
+		self.data_destination.fill(out);
+	}
+
+	// Pulls the next samples converted to the pulled output's sample format; see
+	// AudioConsumer::fill_samples.
+	pub fn fill_samples<T: cpal::Sample>(&mut self, out: &mut [T]) -> Result<(), AudioError> {
+		self.data_destination.fill_samples(out)
+	}
+
+	// The picture embedded in the source (FLAC PICTURE block, ID3 APIC frame, ...), preferring the
+	// front cover. None when the file has no art.
+	pub fn cover_art(&self) -> Option<Vec<u8>> {
+		self.data_source.cover_art.as_ref().map(|cover_art| cover_art.data.clone())
+	}
+
+	// MIME type of the cover_art bytes, e.g. "image/jpeg".
+	pub fn cover_art_mime_type(&self) -> Option<&str> {
+		self.data_source.cover_art.as_ref().map(|cover_art| cover_art.mime_type.as_str())
+	}
+
+	// The tracks of a CUE sheet embedded in the source, e.g. an album ripped to one FLAC file, or
+	// of one loaded with load_cue. Empty when there is no CUE sheet.
+	pub fn chapters(&self) -> Vec<Chapter> {
+		self.data_source.chapters.clone()
+	}
+
+	// The audio tracks of the file the cable was built with, or of the source that replaced it;
+	// see available_audio_tracks.
+	pub fn audio_tracks(&self) -> Vec<AudioTrackInfo> {
+		self.data_source.audio_tracks.clone()
+	}
+
+	// Takes the chapters from the standalone .cue file at `cue_path`, replacing any the source
+	// carries, e.g. for an album ripped to one large file next to its cue sheet. The sheet is
+	// read as UTF-8, with anything else replaced rather than rejected; only the tracks of its
+	// first FILE are used. replace_source drops them again, along with the old source's own
+	// chapters.
+	pub fn load_cue(&mut self, cue_path: &Path) -> Result<(), AudioError> {
+		let text = std::fs::read(cue_path)?;
+		let chapters = chapter::from_cue_sheet(&String::from_utf8_lossy(&text));
+		if chapters.is_empty() {
+			return Err(AudioError::Decode(format!("{} lists no tracks with an INDEX", cue_path.display())));
+		}
+		self.data_source.chapters = chapters;
+		Ok(())
+	}
+
+	// What else the source says about itself, e.g. the origination details of a Broadcast Wave
+	// file or the title a radio stream is playing. Fields the source doesn't carry are None.
+	pub fn metadata(&self) -> TrackMetadata {
+		self.data_source.metadata.current()
+	}
+
+	// Returns a receiver that gets the metadata again every time it changes, e.g. as an internet
+	// radio station announces a new song or replace_source opens another file. Metadata already
+	// known is delivered straight away. Subscribe as often as needed; a receiver that is dropped
+	// is simply no longer sent anything.
+	pub fn subscribe_metadata(&self) -> crossbeam_channel::Receiver<TrackMetadata> {
+		self.data_source.metadata.subscribe()
+	}
+
+	// The title an internet radio station says is playing, e.g. "Artist - Song", updated as the
+	// station sends new metadata (each change is also announced with TrackChanged). None for
+	// anything but a stream built with from_url, and until the station has sent a title.
+	pub fn stream_title(&self) -> Option<String> {
+		self.data_source.metadata.current().stream_title
+	}
+
+	// Seeks to the start of chapters()[index].
+	pub fn seek_to_chapter(&self, index: usize) -> Result<(), AudioError> {
+		let chapter = self.data_source.chapters.get(index).ok_or_else(|| AudioError::InvalidArgument(format!(
+			"chapter {} is out of range, the source has {}", index, self.data_source.chapters.len()
+		)))?;
+		self.seek(chapter.start)
+	}
+
+	// This cable's id, unique within the process, e.g. to find it again in a CableRegistry.
+	pub fn id(&self) -> CableId {
+		self.id
+	}
+
+	// Returns a handle that can pause, resume, seek and change the volume of this cable from any
+	// thread. Clone it freely; every clone controls the same cable.
+	pub fn control(&self) -> PlaybackControl {
+		PlaybackControl::new(Arc::clone(&self.data_destination.controls), self.data_source.comm_chan.0.clone())
+	}
+
+	// Returns a handle that pauses and ducks this cable as the app's Android audio focus changes;
+	// see AudioFocusHandler.
+	#[cfg(target_os = "android")]
+	pub fn audio_focus_handler(&self) -> AudioFocusHandler {
+		AudioFocusHandler::new(self.control())
+	}
+
+	// Starts a cable built with autoplay(false): decoding begins, and once the warmup is buffered
+	// the output stream starts, straight away if cue has already filled the buffer. Afterwards it
+	// just resumes a paused cable.
+	pub fn play(&self) -> Result<(), AudioError> {
+		if self.data_destination.closed {
+			return Err(AudioError::Stopped);
+		}
+		self.data_destination.controls.set_paused(false);
+		if self.started.swap(true, Ordering::Relaxed) {
+			return Ok(());
+		}
+
+		self.data_source.start()?;
+		if let Some(capture) = &self.capture {
+			capture.play()?;
+		}
+		if self.warmup_samples > 0 {
+			self.data_source.wait_for_samples(self.warmup_samples, WARMUP_TIMEOUT);
+		}
+		self.data_destination.play()?;
+		for mirror in &self.mirrors {
+			mirror.consumer.play()?;
+		}
+		Ok(())
+	}
+
+	// Gets a cable built with autoplay(false) ready to play without delay, e.g. the next track of a
+	// DJ or broadcast playout: decoding begins and fills the buffer, up to capacity_frames, while
+	// the output holds at the first frame. PlaybackEvent::Cued is sent once the buffer is full, or
+	// holds the whole track if it's shorter, and play then starts the output at once rather than
+	// waiting for the warmup. Returns straight away; a seek meanwhile refills the buffer from the new
+	// offset.
+	// Does nothing on a cable that's already playing, and cables playing from an input device,
+	// which only captures once played, can't be cued.
+	pub fn cue(&self) -> Result<(), AudioError> {
+		if self.data_destination.closed {
+			return Err(AudioError::Stopped);
+		}
+		if self.capture.is_some() {
+			return Err(AudioError::Unsupported(String::from("a cable playing from an input device can't be cued")));
+		}
+		if self.started.load(Ordering::Relaxed) {
+			return Ok(());
+		}
+		self.data_source.cue()
+	}
+
+	// Blocks until the producer has sent at least `frames` frames to the output since the cable
+	// was built, counted at the output's sample rate and channel count. Returns false if `timeout`
+	// passes first or the source ends short of them.
+	pub fn wait_for_frames(&self, frames: u64, timeout: Duration) -> bool {
+		let channels = self.data_destination.channels().max(1) as u64;
+		self.data_source.wait_for_samples(frames.saturating_mul(channels), timeout)
+	}
+
+	// Silences the output on the spot while keeping everything buffered, so resume carries on
+	// from exactly where it stopped. The device stream keeps running, playing silence.
+	pub fn pause(&self) {
+		self.data_destination.controls.set_paused(true);
+	}
+
+	pub fn resume(&self) {
+		self.data_destination.controls.set_paused(false);
+	}
+
+	// Throws away the audio buffered for the output and silences the next callback, for a sound
+	// that has to stop at once, e.g. on a game event, without tearing the cable down as stop does.
+	// Unlike pause nothing is held back: the source carries on from where it had been decoded to,
+	// so unless it has finished, or is paused with pause_source or replaced too, playback resumes
+	// with whatever it sends next. Only a flag is set here; the output callback does the rest, so
+	// it's safe from any thread. Mirror outputs play what they had buffered.
+	pub fn flush(&self) {
+		self.data_destination.flush();
+	}
+
+	// Halts decoding instead of the output, unlike pause: what is already buffered still plays,
+	// up to capacity_frames, and the output then plays silence until resume_source. That silence
+	// is counted by underruns() but not sent as PlaybackEvent::Underrun. Useful to stop reading a
+	// source without touching the device stream. Audio from an input device queues up meanwhile,
+	// bounded by max_input_latency if set.
+	pub fn pause_source(&self) {
+		self.data_source.set_paused(true);
+	}
+
+	pub fn resume_source(&self) {
+		self.data_source.set_paused(false);
+	}
+
+	pub fn is_source_paused(&self) -> bool {
+		self.data_source.is_paused()
+	}
+
+	// Whether playback is over: the source is finished and the output has played everything it
+	// was sent. Polling this is an alternative to waiting for PlaybackEvent::Finished, which is
+	// sent as soon as the last samples are buffered.
+	pub fn is_finished(&self) -> bool {
+		self.data_source.is_finished() && !self.data_destination.is_draining()
+	}
+
+	pub fn is_paused(&self) -> bool {
+		self.data_destination.controls.paused()
+	}
+
+	// Silences the output while the producer keeps running, unlike pause. For a cable playing
+	// from an input device this also keeps its recording going.
+	pub fn set_muted(&self, muted: bool) {
+		self.data_destination.controls.set_muted(muted);
+	}
+
+	pub fn is_muted(&self) -> bool {
+		self.data_destination.controls.muted()
+	}
+
+	// Moves playback to `offset` from the start of the source, or to the start_at offset if it
+	// comes before that. Audio that was already buffered from before the seek is discarded rather
+	// than played. Does nothing for sources that can't seek; see is_seekable.
+	pub fn seek(&self, offset: Duration) -> Result<(), AudioError> {
+		self.data_source.seek(offset)
+	}
+
+	// Starts the source over from its beginning, or its start_at offset, dropping whatever was
+	// buffered: the "restart track" action. The same as seek(Duration::ZERO), except that a source
+	// that can't seek fails with Unsupported instead of carrying on. Nothing is decoded on the
+	// way back, so it's just as quick at the end of a long file. Sends Seeked.
+	pub fn reset(&self) -> Result<(), AudioError> {
+		if !self.is_seekable() {
+			return Err(AudioError::Unsupported(String::from("the source can't seek back to its start")));
+		}
+		self.seek(Duration::ZERO)
+	}
+
+	// Loops the current track from `end_frame` back to `start_frame`, both counted in frames of
+	// the source at its own sample rate, e.g. for game music whose author chose the loop points
+	// in an editor. end_frame is the first frame not played; one beyond the end of the track loops
+	// from the end. The jump is sample-exact, with nothing dropped or repeated, and takes effect as
+	// the decoder gets to end_frame, which runs up to a buffer ahead of what is heard; if it's
+	// already past, it goes back straight away, and a seek past end_frame lands at start_frame. The
+	// loop holds until clear_loop_points or another track takes over. Sources that can't seek fail
+	// with Unsupported.
+	pub fn set_loop_points(&self, start_frame: u64, end_frame: u64) -> Result<(), AudioError> {
+		if start_frame >= end_frame {
+			return Err(AudioError::InvalidArgument(format!(
+				"the loop must end after it starts, got frames {} to {}", start_frame, end_frame
+			)));
+		}
+		self.data_source.set_loop_points(Some((start_frame, end_frame)))
+	}
+
+	// Converts a count of frames of the current source, at its own sample rate as set_loop_points
+	// takes them, to how long they play for, e.g. to show a loop point as a time. It comes out
+	// rounded up to the nanosecond, so duration_to_frames gives back the same count.
+	pub fn frames_to_duration(&self, frames: u64) -> Duration {
+		frames_to_duration(frames, self.data_source.source_rate())
+	}
+
+	// Converts a time into the current source, such as a seek or start_at offset, to the frame
+	// of it, at its own sample rate, playing then, the same way seek and the trims do.
+	pub fn duration_to_frames(&self, duration: Duration) -> u64 {
+		duration_to_frames(duration, self.data_source.source_rate())
+	}
+
+	// Stops looping; the track plays on from wherever it is to its end.
+	pub fn clear_loop_points(&self) -> Result<(), AudioError> {
+		self.data_source.set_loop_points(None)
+	}
+
+	// Plays the current track `count` times in all, counting the pass under way, then finishes
+	// as it would have after one, e.g. for an alarm that rings three times. 0 plays it endlessly
+	// and 1 goes back to playing it once. Every pass but the last ends as a RepeatMode::One
+	// repeat does, with Finished then TrackChanged, gaplessly, between start_at and end_at; the
+	// count is for this track only, and is checked when each pass's end is decoded, which runs up
+	// to a buffer ahead of what is heard. Sources that can't seek fail with Unsupported.
+	pub fn set_loop_count(&self, count: u32) -> Result<(), AudioError> {
+		self.data_source.set_loop_count(count)
+	}
+
+	// Runs `filter` over every block the decode thread sends from now on, replacing any filter
+	// set before, e.g. a notch filter taking mains hum out of a capture before it's buffered. The
+	// blocks are interleaved at the output rate and channel count, after resampling and tempo, so
+	// the mirrors get the filtered audio too; unlike the consumer's effects it runs on the decode
+	// thread, a buffer ahead of what is heard, and carries on across queued and replaced tracks.
+	// Blocks already decoded aren't filtered; AudioCableBuilder::source_filter covers the first.
+	pub fn set_source_filter<F: FnMut(&mut [f32]) + Send + 'static>(&self, filter: F) {
+		self.data_source.set_source_filter(Some(Box::new(filter)));
+	}
+
+	// Removes the filter set_source_filter installed.
+	pub fn clear_source_filter(&self) {
+		self.data_source.set_source_filter(None);
+	}
+
+	// Whether seek can move playback, e.g. to enable a scrub bar. True for files and seekable
+	// readers, false for input devices and readers that can't seek.
+	pub fn is_seekable(&self) -> bool {
+		self.data_source.progress.seekable.load(Ordering::Relaxed)
+	}
+
+	// Switches the output device to `sample_rate` without recreating the cable, e.g. to play a
+	// new track at its native rate and skip resampling. The source is resampled to the new rate
+	// from then on.
+	pub fn set_sample_rate(&mut self, sample_rate: u32) -> Result<(), AudioError> {
+		self.data_destination.set_sample_rate(sample_rate)?;
+		self.data_source.set_output_rate(sample_rate);
+		Ok(())
+	}
+
+	// Changes the output sample formats to try, most preferred first, without recreating the
+	// cable, e.g. [I16, F32] for a device whose float path is known to be poor; see
+	// AudioConsumer::set_output_format_priority. Mirrors keep the format they were built with.
+	pub fn set_output_format_priority(&mut self, formats: &[cpal::SampleFormat]) -> Result<(), AudioError> {
+		self.data_destination.set_output_format_priority(formats)
+	}
+
+	// Adds the file at `path` to the end of the queue, to be played without a gap once everything
+	// before it has. A cable whose queue has run out picks up again with it. Only the extension is
+	// checked here; a file that can't be opened is reported and skipped when its turn comes.
+	// Cables playing from an input device have no queue.
+	pub fn enqueue(&self, path: String) -> Result<(), AudioError> {
+		match &self.data_source.options.queue {
+			Some(queue) => queue.enqueue(path),
+			None => Err(AudioError::Unsupported(String::from("a cable playing from an input device has no queue")))
+		}
+	}
+
+	// Sets what plays once the current track ends; see RepeatMode. The decision is made when a
+	// track's end is decoded, which runs up to a buffer ahead of what is heard. A repeated track
+	// starts over at start_at and ends again at end_at, and sources that can't seek play only
+	// once. Cables playing from an input device have no tracks to repeat.
+	pub fn set_repeat_mode(&self, mode: RepeatMode) -> Result<(), AudioError> {
+		match &self.data_source.options.queue {
+			Some(queue) => {
+				queue.set_repeat_mode(mode);
+				Ok(())
+			},
+			None => Err(AudioError::Unsupported(String::from("a cable playing from an input device can't repeat")))
+		}
+	}
+
+	pub fn repeat_mode(&self) -> RepeatMode {
+		self.data_source.options.queue.as_deref().map_or(RepeatMode::Off, TrackQueue::repeat_mode)
+	}
+
+	// Switches the cable to the file at `path` while keeping the output stream open, so there's no
+	// gap or click from rebuilding it between tracks. Whatever is still buffered from the current
+	// source is dropped, and a TrackChanged event is sent. Options such as tempo and the sample
+	// rate carry over, and start_at/end_at apply to the new file too. A file that can't be opened
+	// is reported and leaves the current source playing. Cables playing from an input device
+	// can't change their source.
+	pub fn replace_source(&mut self, path: String) -> Result<(), AudioError> {
+		if self.capture.is_some() {
+			return Err(AudioError::Unsupported(String::from("a cable playing from an input device can't replace its source")));
+		}
+		self.data_source.replace_file(path)?;
+		if self.started.load(Ordering::Relaxed) {
+			self.data_source.start()?;
+		}
+		Ok(())
+	}
+
+	// Speeds playback up (> 1.0) or slows it down (< 1.0) while keeping the pitch, e.g. for
+	// audiobooks and podcasts. Applied after resampling and only switched on by the first ratio
+	// other than 1.0. Audio already buffered plays at the previous tempo.
+	pub fn set_tempo(&self, ratio: f32) -> Result<(), AudioError> {
+		self.data_source.set_tempo(ratio)
+	}
+
+	// Stops decoding and silences the output immediately, throwing away whatever is buffered;
+	// stop_draining lets it play out first. The cable can't be restarted. A cable playing from an
+	// input device closes it, finishing any recording first.
+	pub fn stop(&mut self) {
+		if let Some(capture) = &mut self.capture {
+			capture.stop();
+		}
+		self.data_source.stop();
+		for mirror in &mut self.mirrors {
+			mirror.stop();
+		}
+	}
+
+	// Stops decoding but lets the output play what is already buffered, for up to `timeout`,
+	// before stopping as stop does, so the end of a playlist fades out naturally instead of being
+	// cut off. Returns whether everything buffered was played in time. A paused cable plays
+	// nothing meanwhile and waits out the whole timeout, as does a pulled output that isn't
+	// filled from another thread. A cable playing from an input device closes it first.
+	pub fn stop_draining(&mut self, timeout: Duration) -> bool {
+		if let Some(capture) = &mut self.capture {
+			capture.stop();
+		}
+		self.data_source.stop_sending();
+		let deadline = std::time::Instant::now() + timeout;
+		let drained = loop {
+			if !self.data_destination.is_draining() {
+				break true;
+			}
+			if std::time::Instant::now() >= deadline {
+				break false;
+			}
+			std::thread::sleep(DRAIN_POLL_INTERVAL);
+		};
+		self.stop();
+		drained
+	}
+
+	// Stops the cable as stop does, then stops and drops its output streams, mirrors included, so
+	// the devices are free again by the time this returns rather than once the cable is dropped.
+	// Afterwards play, set_sample_rate and the like fail with Stopped. The first error pausing a
+	// stream is returned; every device is released regardless.
+	pub fn close(&mut self) -> Result<(), AudioError> {
+		self.stop();
+		let mut result = self.data_destination.release();
+		for mirror in &mut self.mirrors {
+			let released = mirror.consumer.release();
+			result = result.and(released);
+		}
+		result
+	}
+
+	// Makes the output device called `device_name` the master clock of a cable with mirrors: it
+	// plays at its own pace and every other output is resampled to keep in step with it. With
+	// the default master, the primary output, mirrors drift by whatever their crystals differ
+	// from it; see AudioCableBuilder::mirror_to. Fails with InvalidArgument for devices the cable
+	// doesn't play on.
+	pub fn set_clock_master(&self, device_name: &str) -> Result<(), AudioError> {
+		let leg = if self.data_destination.device_name().as_deref() == Some(device_name) {
+			Some(0)
+		} else {
+			self.mirrors.iter().position(|mirror| mirror.name == device_name).map(|index| index + 1)
+		};
+		match (leg, &self.clock) {
+			(Some(leg), Some(clock)) => {
+				clock.set_master(leg);
+				Ok(())
+			},
+			// Without mirrors the primary output is trivially the master.
+			(Some(_), None) => Ok(()),
+			(None, _) => Err(AudioError::InvalidArgument(format!("the cable doesn't play on a device named \"{}\"", device_name)))
+		}
+	}
+
+	// Name of the output device the cable's outputs follow, if the device reports one.
+	pub fn clock_master(&self) -> Option<String> {
+		match self.clock.as_ref().map(|clock| clock.master()) {
+			Some(leg) if leg > 0 => self.mirrors.get(leg - 1).map(|mirror| mirror.name.clone()),
+			_ => self.data_destination.device_name()
+		}
+	}
+
+	// Returns a receiver for the cable's PlaybackEvents. Every clone sees each event once, so
+	// hold on to a single receiver per listener.
+	pub fn events(&self) -> crossbeam_channel::Receiver<PlaybackEvent> {
+		self.events.clone()
+	}
+
+	// Input devices only: turns the automatic gain control on or off, aiming for an RMS level of
+	// `target_db` dBFS, e.g. -20 for speech. A quiet microphone is boosted towards it by up to
+	// 30 dB and a loud one turned down; near-silence holds the gain rather than raising the noise.
+	// See AudioCableBuilder::agc_timing for how fast it reacts. Off by default; a recording made
+	// with record_to keeps the input as captured.
+	pub fn set_agc(&self, target_db: f32, enabled: bool) -> Result<(), AudioError> {
+		let capture = self.capture.as_ref()
+			.ok_or_else(|| AudioError::Unsupported(String::from("automatic gain control only applies to input devices")))?;
+		if !target_db.is_finite() || target_db > 0.0 {
+			return Err(AudioError::InvalidArgument(format!("the gain control target must be at most 0 dBFS, got {}", target_db)));
+		}
+		capture.agc().set(target_db, enabled);
+		Ok(())
+	}
+
+	// Priority of the primary output's callback thread; see AudioConsumer::is_realtime.
+	pub fn is_realtime(&self) -> CallbackPriority {
+		self.data_destination.is_realtime()
+	}
+
+	// Callback timing of the primary output; see AudioConsumer::callback_timing.
+	pub fn callback_timing(&self) -> CallbackTiming {
+		self.data_destination.callback_timing()
+	}
+
+	pub fn set_callback_timing_hook<F: Fn(Duration) + Send + 'static>(&self, hook: F) -> Result<(), AudioError> {
+		self.data_destination.set_callback_timing_hook(hook)
+	}
+
+	// Stereo phase correlation of the primary output; see AudioConsumer::phase_correlation.
+	pub fn phase_correlation(&self) -> f32 {
+		self.data_destination.phase_correlation()
+	}
+
+	// Frames decoded for the primary output and frames it has played, for diagnosing latency and
+	// underruns; see AudioConsumer::frames_emitted.
+	pub fn frames_emitted(&self) -> u64 {
+		self.data_destination.frames_emitted()
+	}
+
+	pub fn frames_played(&self) -> u64 {
+		self.data_destination.frames_played()
+	}
+
+	// Frames the decoder has decoded and sent to the output since the cable was built, at the
+	// output rate and over every track, for telling a decoder racing ahead from an output starved
+	// of audio: compared with frames_played it shows how far decoding has got ahead of playback.
+	// It follows the decoder, not what is heard as playback_timestamp does, and only counts up, so
+	// audio a seek or flush throws away unplayed stays counted; frames_emitted leaves that out.
+	// Lock-free, so it can be polled from anywhere.
+	pub fn decode_position(&self) -> u64 {
+		self.data_source.decode_position()
+	}
+
+	// The largest absolute sample the decoder has produced for the current track so far, before
+	// the tempo, effects, volume and safe output stage touch it. 1.0 is full scale, so a file
+	// reading 1.0 or more is already clipped or over before the cable's gain is applied. It
+	// covers what has been decoded, which runs ahead of what has been heard, and starts again
+	// from 0.0 with each new or replaced track.
+	pub fn source_peak(&self) -> f32 {
+		self.data_source.source_peak()
+	}
+
+	// Encoded bytes read from the file or stream so far, e.g. for a download progress bar on an
+	// internet radio stream whose duration is unknown. Includes the headers and anything read
+	// again after a seek, and adds up over queued tracks. It runs ahead of what has been heard,
+	// as decoding does, and stays 0 for an input device or a CachedSource.
+	pub fn bytes_read(&self) -> u64 {
+		self.data_source.bytes_read()
+	}
+
+	// How long audio waits in the resamplers between being decoded and reaching the output, when
+	// the source is resampled to the output rate or a mirror's clock is followed; zero while it
+	// plays at its own rate. The resamplers interpolate around each output frame rather than
+	// behind it, so they shift no audio in time: frames_played and playback_timestamp already
+	// give the source position exactly, and need no correcting by this. It is how much further
+	// the decode side, e.g. source_peak and bytes_read, has got than frames_emitted shows.
+	pub fn resampler_latency(&self) -> Duration {
+		self.data_source.resampler_latency()
+	}
+
+	// Number of device buffers the primary output couldn't fill from the decoded audio, and how
+	// many samples it played as silence in their place. Both only ever grow.
+	pub fn underruns(&self) -> (u64, u64) {
+		let reporter = &self.data_destination.reporter;
+		(reporter.underruns(), reporter.missing_samples())
+	}
+
+	// Sets the linear output gain, where 1.0 leaves the source untouched.
+	pub fn set_volume(&self, volume: f32) {
+		self.data_destination.set_volume(volume);
+	}
+
+	pub fn volume(&self) -> f32 {
+		self.data_destination.volume()
+	}
+
+	// Applies the volume and mute before or after the custom effects; PostEffects, the default,
+	// makes the volume the final gain ahead of the safe output stage. Takes effect from the next
+	// output callback, on mirrors too.
+	pub fn set_volume_stage(&self, stage: VolumeStage) {
+		self.data_destination.controls.set_volume_stage(stage);
+	}
+
+	pub fn volume_stage(&self) -> VolumeStage {
+		self.data_destination.controls.volume_stage()
+	}
+
+	// Sets the output gain in decibels. Anything at or below MIN_OUTPUT_GAIN_DB is silence.
+	pub fn set_output_gain_db(&self, db: f32) {
+		if db <= MIN_OUTPUT_GAIN_DB {
+			self.set_volume(0.0);
+		} else {
+			self.set_volume(10.0f32.powf(db / 20.0));
+		}
+	}
+
+	// Returns the output gain in decibels, clamped to MIN_OUTPUT_GAIN_DB for silence.
+	pub fn output_gain_db(&self) -> f32 {
+		(20.0 * self.volume().log10()).max(MIN_OUTPUT_GAIN_DB)
+	}
+}
+
+impl Drop for AudioCable {
+	fn drop(&mut self) {
+		if let Some(registry) = &self.registry {
+			registry.remove(self.id);
+		}
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::{AudioCableBuilder, AudioError, PcmFormat, SourceType};
+
+	use std::io::Cursor;
+	use std::time::Duration;
+
+	#[test]
+	fn dot_paths_are_invalid() {
+		for path in ["", ".", "..."] {
+			assert!(matches!(SourceType::from_local(String::from(path)), Err(AudioError::InvalidPath(invalid)) if invalid == path), "{:?}", path);
+		}
+	}
 
-		let comm_chan = crossbeam_channel::unbounded::<i32>();
+	#[test]
+	fn loop_passes_are_identical() {
+		// Each frame holds its own index, on the left channel and negated on the right.
+		let pcm: Vec<u8> = (0..10000i16).flat_map(|n| [n, -n]).flat_map(i16::to_le_bytes).collect();
+		let mut cable = AudioCableBuilder::from_raw_pcm(Cursor::new(pcm), PcmFormat::S16LE, 48000, 2)
+			.pull_output(48000, 2).autoplay(false).build().unwrap();
+		cable.set_loop_points(1000, 5000).unwrap();
+		cable.play().unwrap();
 
-		AudioCable {
-			data_source: <AudioProducer as LocalAudioProducer>::new(audio_source, tx),
-			data_destination: AudioConsumer::new(rx),
+		let mut frames = Vec::new();
+		let mut buffer = vec![0.0f32; 2 * 1000];
+		while frames.len() < 5000 + 3 * 4000 {
+			assert!(cable.wait_for_frames(cable.frames_played() + 1000, Duration::from_secs(2)));
+			cable.fill(&mut buffer);
+			frames.extend(buffer.chunks(2).map(|frame| [(frame[0] * 32768.0).round() as i16, (frame[1] * 32768.0).round() as i16]));
 		}
+		let expected: Vec<[i16; 2]> = (0..5000).chain((0..3).flat_map(|_| 1000..5000)).map(|n| [n, -n]).collect();
+		assert_eq!(frames, expected);
 	}
 }
\ No newline at end of file
@@ -0,0 +1,84 @@
+use super::denormal;
+
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::time::Duration;
+
+// Default time constants: the gain drops quickly when the input gets louder and climbs back
+// slowly, so speech pauses don't pump the background noise up.
+pub(super) const DEFAULT_AGC_ATTACK: Duration = Duration::from_millis(50);
+pub(super) const DEFAULT_AGC_RELEASE: Duration = Duration::from_secs(2);
+// The input can be boosted by at most this much, however quiet it is.
+const MAX_AGC_GAIN_DB: f32 = 30.0;
+// Input below this level (dBFS RMS) is taken for silence and holds the gain where it is, rather
+// than boosting noise towards the target.
+const AGC_GATE_DB: f32 = -60.0;
+// Time constant of the level measurement the gain follows.
+const LEVEL_WINDOW_SECS: f32 = 0.1;
+
+/* AgcControl: Whether the automatic gain control of an input device is on and the level it aims
+ * for, shared between the cable and the decode thread. Off by default.
+ */
+pub(super) struct AgcControl {
+	enabled: AtomicBool,
+	// Target RMS level in dBFS, as f32 bits.
+	target_db: AtomicU32,
+}
+
+impl AgcControl {
+	pub(super) fn new() -> AgcControl {
+		AgcControl { enabled: AtomicBool::new(false), target_db: AtomicU32::new((-20.0f32).to_bits()) }
+	}
+
+	pub(super) fn set(&self, target_db: f32, enabled: bool) {
+		self.target_db.store(target_db.to_bits(), Ordering::Relaxed);
+		self.enabled.store(enabled, Ordering::Relaxed);
+	}
+}
+
+/* Agc: Automatic gain control applied to captured audio on the decode thread.
+ *
+ * The RMS level of the input, over about 100 ms, is compared with the target and the gain moves
+ * towards closing the gap: within `attack` when it has to come down, within `release` when it
+ * has to go up. All channels share the gain. While disabled the audio passes untouched and the
+ * gain resets to unity.
+ */
+pub(super) struct Agc {
+	attack: Duration,
+	release: Duration,
+	// Mean power per sample over the level window, and the linear gain applied.
+	power: f32,
+	gain: f32,
+}
+
+impl Agc {
+	pub(super) fn new(attack: Duration, release: Duration) -> Agc {
+		Agc { attack, release, power: 0.0, gain: 1.0 }
+	}
+
+	pub(super) fn process(&mut self, control: &AgcControl, buffer: &mut [f32], channels: usize, sample_rate: u32) {
+		if !control.enabled.load(Ordering::Relaxed) {
+			self.gain = 1.0;
+			return;
+		}
+		let target = 10.0f32.powf(f32::from_bits(control.target_db.load(Ordering::Relaxed)) / 20.0);
+		let max_gain = 10.0f32.powf(MAX_AGC_GAIN_DB / 20.0);
+		let gate = 10.0f32.powf(AGC_GATE_DB / 10.0);
+		let rate = sample_rate.max(1) as f32;
+		let smoothing = (-1.0 / (LEVEL_WINDOW_SECS * rate)).exp();
+		let attack = (-1.0 / (self.attack.as_secs_f32() * rate).max(1.0)).exp();
+		let release = (-1.0 / (self.release.as_secs_f32() * rate).max(1.0)).exp();
+
+		for frame in buffer.chunks_mut(channels.max(1)) {
+			let power = frame.iter().map(|sample| sample * sample).sum::<f32>() / frame.len() as f32;
+			self.power = denormal::flush(power + (self.power - power) * smoothing);
+			if self.power > gate {
+				let wanted = (target / self.power.sqrt()).min(max_gain);
+				let coefficient = if wanted < self.gain { attack } else { release };
+				self.gain = wanted + (self.gain - wanted) * coefficient;
+			}
+			for sample in frame.iter_mut() {
+				*sample *= self.gain;
+			}
+		}
+	}
+}
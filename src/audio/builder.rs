@@ -0,0 +1,844 @@
+use super::agc::{Agc, DEFAULT_AGC_ATTACK, DEFAULT_AGC_RELEASE};
+use super::safe::{Limiter, MAX_NORMALIZE_GAIN_DB};
+#[cfg(feature = "tokio")]
+use super::AsyncReadBridge;
+use super::{measure_loudness, report, scan_peak, AudioCable, CableId, CableRegistry, CachedSource, SafeOutput, AudioConsumer, AudioError, AudioProducer, AudioRecorder, AudioSource, ClockSync, ConsumerOptions, ControlChannel, DeviceAudioProducer, DeviceSource, Format, InputCapture, LocalAudioProducer, ManualSource, MirrorOutput, StreamAudioProducer, MirrorTap, PcmFormat, PeakNormalizer, PlaylistCursor, PcmSource, ProducerOptions, ReadSeek, ReplayGainMode, ResampleQuality, SampleSink, SourceFilter, SourceType, Splitter, TrackChoice, TrackQueue, WavSampleFormat, DEFAULT_PREFETCH_TRACKS, MIRROR_QUEUE_BLOCKS, RECORDER_QUEUE, SAMPLE_RATE_RANGE};
+
+use std::fs::File;
+use std::io::{Read, Seek};
+use std::path::{Path, PathBuf};
+
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::Duration;
+
+/* ShareMode: How the output device is shared with the rest of the system.
+ *
+ * Only the Windows (WASAPI) host distinguishes between these; on every other platform all modes
+ * fall back to Shared.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShareMode {
+	// The device's default shared-mode configuration.
+	Shared,
+	// Shared mode with the smallest buffer the device advertises.
+	LowLatency,
+	// Exclusive access to the device, bypassing the system mixer.
+	Exclusive,
+}
+
+/* ChannelFill: What plays on the output device's channels when the source has fewer of its own.
+ * Only sources with fewer channels than the device are affected.
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ChannelFill {
+	// The source's samples go out as they are; the device should have as many channels.
+	#[default]
+	Direct,
+	// The source's channels repeat in order over the device's, e.g. L R L R on four speakers
+	// from a stereo file. Each device channel gets a full-level copy; nothing is mixed.
+	Duplicate,
+}
+
+/* DeviceRatePolicy: Which of the output device's supported sample rates a cable opens it at.
+ * Pulled outputs play at the rate given to pull_output whatever the policy.
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DeviceRatePolicy {
+	// The highest rate the device offers. Nothing is lost, but a 44.1 kHz file on a device that
+	// runs at 192 kHz is resampled, and the output callback run, at four times the work it needs.
+	#[default]
+	MaxRate,
+	// The rate closest to the source's, the source's own wherever the device can play it, so
+	// there is as little to resample as there can be. Only the first track is matched; queued
+	// tracks are resampled to it.
+	MatchSource,
+	// This rate, which the device has to support.
+	Fixed(u32),
+}
+
+// Where the builder's audio comes from.
+enum AudioInput {
+	Path(String),
+	Reader(Box<dyn ReadSeek>, Format),
+	// A stream that can't seek, in the given format or one detected from its first bytes.
+	Pipe(Box<dyn Read + Send>, Option<Format>),
+	// A tokio stream, read into a pipe once the cable is built.
+	#[cfg(feature = "tokio")]
+	AsyncRead(Box<dyn tokio::io::AsyncRead + Send + Unpin>, Option<Format>),
+	// An HTTP stream such as an internet radio station.
+	Url(String),
+	// Headerless PCM with its encoding, sample rate and channel count.
+	RawPcm(Box<dyn ReadSeek>, PcmFormat, u32, u16),
+	// An input device by name, or the default one.
+	Device(Option<String>),
+	// Samples decoded ahead of time.
+	Cached(CachedSource),
+	// Samples pushed through a SampleSink.
+	Manual(ManualSource),
+}
+
+/* AudioCableBuilder: Configures an AudioCable before any device or file is opened.
+ */
+pub struct AudioCableBuilder {
+	audio_source: AudioInput,
+	consumer_options: ConsumerOptions,
+	producer_options: ProducerOptions,
+	autoplay: bool,
+	// Input devices only: where to record the captured audio, and how far playback may lag it.
+	record_path: Option<PathBuf>,
+	socket_output: Option<(PathBuf, WavSampleFormat)>,
+	record_format: WavSampleFormat,
+	max_input_latency: Option<Duration>,
+	// Attack and release of the input's automatic gain control.
+	agc_timing: Option<(Duration, Duration)>,
+	// Names of extra output devices to play on.
+	mirrors: Vec<String>,
+	// Files to play after the source, and how many of them to open ahead of time.
+	queue: Vec<String>,
+	prefetch_tracks: usize,
+	// Decoder to use for a file regardless of its extension.
+	format: Option<Format>,
+	safe_output: Option<SafeOutput>,
+	device_rate: DeviceRatePolicy,
+	// Peak level in dBFS to normalise the source to.
+	normalize_peak: Option<f32>,
+	// Installed on the producer before it decodes anything.
+	source_filter: Option<SourceFilter>,
+	registry: Option<CableRegistry>,
+}
+
+// The defaults every builder starts from, and so what AudioCable::new plays with: the default
+// output device in shared mode at its highest rate, trying F32, I16 then U16; a data channel of
+// 8192 frames that must hold 2048 before the stream starts; Cubic resampling; one queued track
+// prefetched; autoplay on; no trimming, mirrors or safe output stage. Every cable starts unmuted
+// at full volume and reports underruns as PlaybackEvent::Underrun. There is no source yet: set
+// one with source, or build fails with InvalidPath.
+impl Default for AudioCableBuilder {
+	fn default() -> Self {
+		AudioCableBuilder {
+			audio_source: AudioInput::Path(String::new()),
+			consumer_options: ConsumerOptions::default(),
+			producer_options: ProducerOptions::default(),
+			autoplay: true,
+			record_path: None,
+			socket_output: None,
+			record_format: WavSampleFormat::I16,
+			max_input_latency: None,
+			agc_timing: None,
+			mirrors: Vec::new(),
+			queue: Vec::new(),
+			prefetch_tracks: DEFAULT_PREFETCH_TRACKS,
+			format: None,
+			safe_output: None,
+			device_rate: DeviceRatePolicy::MaxRate,
+			normalize_peak: None,
+			source_filter: None,
+			registry: None,
+		}
+	}
+}
+
+impl AudioCableBuilder {
+	pub fn new(audio_source: String) -> Self {
+		AudioCableBuilder::with_input(AudioInput::Path(audio_source))
+	}
+
+	// Decodes `format` from `reader` instead of opening a file, e.g. a decrypted stream or a
+	// memory-mapped file. The reader is read from its current position. Seeking backwards needs
+	// a reader that can seek; if it can't, the decode thread reports Unsupported and keeps
+	// playing from where it was.
+	pub fn from_reader<R: Read + Seek + Send + 'static>(reader: R, format: Format) -> Self {
+		AudioCableBuilder::with_input(AudioInput::Reader(Box::new(reader), format))
+	}
+
+	// Decodes a stream that can only be read front to back, e.g. the stdout of a child process.
+	// Without `format` the format is detected from the stream's first bytes. The cable can't seek.
+	pub fn from_pipe<R: Read + Send + 'static>(reader: R, format: Option<Format>) -> Self {
+		AudioCableBuilder::with_input(AudioInput::Pipe(Box::new(reader), format))
+	}
+
+	// Decodes a tokio stream, e.g. an async download being decrypted, like from_pipe. A task
+	// reads it ahead of the decoder on a thread of its own. build reads the stream's header and
+	// blocks until it arrives, so call it through tokio::task::spawn_blocking from async code.
+	// The cable can't seek.
+	#[cfg(feature = "tokio")]
+	pub fn from_async_reader<R: tokio::io::AsyncRead + Send + Unpin + 'static>(reader: R, format: Option<Format>) -> Self {
+		AudioCableBuilder::with_input(AudioInput::AsyncRead(Box::new(reader), format))
+	}
+
+	// Plays the HTTP stream at `url`, e.g. an Icecast or SHOUTcast internet radio station. Only
+	// plain http:// is supported. The format is detected from the stream, falling back to its
+	// Content-Type. When the station sends ICY metadata, AudioCable::stream_title follows its
+	// now-playing title and TrackChanged is sent whenever it changes. The cable can't seek, and
+	// build waits for the server to answer.
+	pub fn from_url(url: String) -> Self {
+		AudioCableBuilder::with_input(AudioInput::Url(url))
+	}
+
+	// Decodes standard input, for `somecmd | player` pipelines; see from_pipe.
+	pub fn from_stdin(format: Option<Format>) -> Self {
+		AudioCableBuilder::from_pipe(std::io::stdin(), format)
+	}
+
+	// Reads headerless interleaved PCM from `reader`, e.g. a capture dump or an RTP payload
+	// stream, which has to be described since there is no header to read it from. The reader is
+	// read from its current position, and seeking needs a reader that can seek.
+	pub fn from_raw_pcm<R: Read + Seek + Send + 'static>(reader: R, format: PcmFormat, sample_rate: u32, channels: u16) -> Self {
+		AudioCableBuilder::with_input(AudioInput::RawPcm(Box::new(reader), format, sample_rate, channels))
+	}
+
+	// Replays the samples of `cache`; see CachedSource::cable.
+	pub(super) fn from_cache(cache: &CachedSource) -> Self {
+		AudioCableBuilder::with_input(AudioInput::Cached(cache.clone()))
+	}
+
+	// Plays live audio from the input device called `name`, or the default input device for None.
+	// See AudioCable::monitor for the feedback risk of playing a microphone through speakers.
+	pub fn from_device(name: Option<String>) -> Self {
+		AudioCableBuilder::with_input(AudioInput::Device(name))
+	}
+
+	// Plays samples the application pushes through the returned sink, interleaved f32 frames at
+	// `sample_rate` with `channels` channels, e.g. from a synthesizer. The cable finishes once
+	// every clone of the sink has been dropped. It can't seek.
+	pub fn manual(sample_rate: u32, channels: u16) -> (Self, SampleSink) {
+		let (sink, source) = SampleSink::new(sample_rate, channels);
+		(AudioCableBuilder::with_input(AudioInput::Manual(source)), sink)
+	}
+
+	fn with_input(audio_source: AudioInput) -> Self {
+		AudioCableBuilder { audio_source, ..AudioCableBuilder::default() }
+	}
+
+	// Plays the file at `audio_source`, replacing whatever source the builder had; mostly useful
+	// with a builder started from AudioCableBuilder::default().
+	pub fn source(mut self, audio_source: String) -> Self {
+		self.audio_source = AudioInput::Path(audio_source);
+		self
+	}
+
+	// Decodes the file as `format` whatever its extension says, e.g. for a tool whose user forces
+	// the format with `--format flac` (see Format's FromStr) or for files with a wrong or missing
+	// extension. Only applies to files.
+	pub fn format(mut self, format: Format) -> Self {
+		self.format = Some(format);
+		self
+	}
+
+	// Plays on the output device called `name` instead of the default one.
+	pub fn output_device(mut self, name: String) -> Self {
+		self.consumer_options.output_device = Some(name);
+		self
+	}
+
+	// Keeps playing on whichever device the OS makes the default output, e.g. moving to Bluetooth
+	// headphones as they connect, and sends PlaybackEvent::DeviceChanged each time. The default is
+	// checked twice a second. The new device has to offer the cable's channel count, or the cable
+	// stays where it is; its sample rate is kept if the device can play it and resampled to
+	// otherwise. Off by default, and can't be combined with output_device, pull_output or
+	// jack_client.
+	//
+	// Only hosts that name their default device can be followed, such as WASAPI and CoreAudio. On
+	// Linux ALSA's default is always the "default" PCM, which PulseAudio and PipeWire already move
+	// between devices on their own, so the cable never sees a change there.
+	pub fn follow_default_device(mut self, follow: bool) -> Self {
+		self.consumer_options.follow_default_device = follow;
+		self
+	}
+
+	// Plays through JACK as a client called `client_name`, so the cable shows up in patchbays
+	// such as qjackctl or Carla as "<client_name>_out" with one port per channel, "out_0",
+	// "out_1" and so on. Its ports are connected to the system playback ports, and output_device
+	// is ignored. When no JACK server is running the cable falls back to the default ALSA output.
+	#[cfg(all(feature = "jack", target_os = "linux"))]
+	pub fn jack_client(mut self, client_name: String) -> Self {
+		self.consumer_options.jack_client = Some(client_name);
+		self
+	}
+
+	// Doesn't open an output device: the application pulls the audio itself with AudioCable::fill,
+	// interleaved f32 at `sample_rate` with `channels` channels, e.g. to play through SDL, oboe or
+	// a game engine's mixer. The source is resampled to that rate as it would be to a device's.
+	// Everything else, such as effects, volume and underrun reports, works as with a device.
+	pub fn pull_output(mut self, sample_rate: u32, channels: u16) -> Self {
+		self.consumer_options.pull_output = Some((sample_rate, channels));
+		self
+	}
+
+	// Plays into a Unix domain socket created at `path`, or on Windows the named pipe there, for
+	// another process to read, e.g. an encoder: raw `format` samples at `sample_rate` with
+	// `channels` channels, after a short header giving the layout; see SocketOutput. The output is
+	// pulled like pull_output's, which this takes the place of, so the cable isn't to be filled
+	// by the application too. Nothing plays while no reader is connected.
+	pub fn socket_output<P: AsRef<Path>>(mut self, path: P, sample_rate: u32, channels: u16, format: WavSampleFormat) -> Self {
+		self.consumer_options.pull_output = Some((sample_rate, channels));
+		self.socket_output = Some((path.as_ref().to_path_buf(), format));
+		self
+	}
+
+	// Ends the output path with a limiter, and optionally loudness normalisation, so the device
+	// never gets full-scale or clipped audio however high the volume or effects push it; see
+	// SafeOutput for the order things happen in. Normalising measures the file's loudness while
+	// building, which decodes it once in full before playback starts, and boosts quiet files by at
+	// most 12 dB. The target only applies to files: other sources, and tracks queued or replaced
+	// later, play with the gain of the file the cable was built with, or none.
+	pub fn safe_output(mut self, safe_output: SafeOutput) -> Self {
+		self.safe_output = Some(safe_output);
+		self
+	}
+
+	// Plays the audio track at `index` of a file with several, e.g. one language of a film's
+	// soundtrack in an MP4 or Matroska file; available_audio_tracks lists them. By default the
+	// container's default track plays, or else the first. Building fails when there's no such
+	// track. Only the source the cable is built with is affected, not tracks queued or replaced.
+	pub fn audio_track(mut self, index: usize) -> Self {
+		self.producer_options.audio_track = Some(TrackChoice::Index(index));
+		self
+	}
+
+	// Plays the first audio track tagged with `language`, e.g. "eng", like audio_track.
+	pub fn audio_track_language(mut self, language: &str) -> Self {
+		self.producer_options.audio_track = Some(TrackChoice::Language(String::from(language)));
+		self
+	}
+
+	// Brings the source's peak to `target_db` dBFS with a gain applied as it's decoded, for files
+	// without ReplayGain tags; -1 leaves a little headroom. A file's peak is found while building,
+	// which decodes it once in full before playback starts, and the gain holds for the whole file.
+	// Sources that can't be scanned ahead, such as streams, readers and input devices, as well as
+	// tracks queued or replaced later, are normalised as they play instead: the gain follows the
+	// loudest sample so far and steps down whenever a louder one comes, so early quiet passages
+	// play louder than they would with a scan. Quiet files are boosted by at most 30 dB.
+	pub fn normalize_peak(mut self, target_db: f32) -> Self {
+		self.normalize_peak = Some(target_db);
+		self
+	}
+
+	// Plays each track at the gain its ReplayGain tags give for `mode` (default Off), read from
+	// the FLAC or other file as it's opened, so queued tracks change level as they start. Album
+	// mode gives every track of an album the same gain, keeping their levels relative to each
+	// other. The gain is held under what would clip the tagged peak. Tracks with ReplayGain tags
+	// play at their gain in place of normalize_peak; untagged ones are still normalised.
+	pub fn replay_gain(mut self, mode: ReplayGainMode) -> Self {
+		self.producer_options.replay_gain = mode;
+		self
+	}
+
+	// Runs `filter` over every block the decode thread sends, from the first one on; see
+	// AudioCable::set_source_filter, which would miss whatever was decoded before it's called.
+	pub fn source_filter<F: FnMut(&mut [f32]) + Send + 'static>(mut self, filter: F) -> Self {
+		self.source_filter = Some(Box::new(filter));
+		self
+	}
+
+	// Output sample formats to try, most preferred first (default F32, I16, U16). Formats left out
+	// are never used; build fails if the device supports none of those given. The list can be
+	// changed later with AudioCable::set_output_format_priority.
+	pub fn sample_formats(mut self, formats: &[cpal::SampleFormat]) -> Self {
+		self.consumer_options.sample_formats = formats.to_vec();
+		self
+	}
+
+	// Writes the output in `format` and nothing else, whatever the device would pick, e.g. to
+	// exercise the I16 or U16 conversion. On a device build fails if it doesn't support the
+	// format; a pulled output delivers it through AudioCable::fill_samples.
+	pub fn sample_format(self, format: cpal::SampleFormat) -> Self {
+		self.sample_formats(&[format])
+	}
+
+	// Also plays everything on the output device called `name`, e.g. to fill two rooms from one
+	// cable. Can be called once per extra device; each must have as many channels as the primary
+	// output. Volume, pause and mute apply to all of them. The primary output is the clock master
+	// until AudioCable::set_clock_master picks another; the others are resampled to stay in step
+	// with it, adjusting their ratio every block by up to 0.2% from how full their buffers run.
+	pub fn mirror_to(mut self, name: String) -> Self {
+		self.mirrors.push(name);
+		self
+	}
+
+	// How a source with fewer channels than the output device fills the remaining ones. Defaults
+	// to Direct. With Duplicate, build and AudioCable::replace_source fail with InvalidConfig for a
+	// source with more channels than the device.
+	pub fn channel_fill(mut self, channel_fill: ChannelFill) -> Self {
+		self.producer_options.channel_fill = channel_fill;
+		self
+	}
+
+	pub fn share_mode(mut self, share_mode: ShareMode) -> Self {
+		self.consumer_options.share_mode = share_mode;
+		self
+	}
+
+	// Interpolation used when the source rate differs from the device rate. Defaults to Cubic; see
+	// ResampleQuality for the CPU cost of each setting.
+	pub fn resample_quality(mut self, quality: ResampleQuality) -> Self {
+		self.producer_options.resample_quality = quality;
+		self
+	}
+
+	// Skips everything before `start` in the source, e.g. to jump past an intro.
+	pub fn start_at(mut self, start: Duration) -> Self {
+		self.producer_options.start_at = Some(start);
+		self
+	}
+
+	// Stops the source at `end` (measured from the start of the file) and emits Finished there.
+	pub fn end_at(mut self, end: Duration) -> Self {
+		self.producer_options.end_at = Some(end);
+		self
+	}
+
+	// Pads a track that ends short of the length its header declares, e.g. a FLAC file cut off
+	// mid-download, with silence up to that length, so the timing of everything after it holds.
+	// Either way a PlaybackEvent::DecodeWarning reports the shortfall. Off by default.
+	pub fn pad_truncated(mut self, pad: bool) -> Self {
+		self.producer_options.pad_truncated = pad;
+		self
+	}
+
+	// Plays `padding` of silence before the source, e.g. to hold back one of several cables
+	// started together so their audio lines up with a video's lip-sync. It comes ahead of the
+	// start_at offset, at the output rate, and neither tempo nor a seek within the source
+	// stretches it; a seek before it has all played skips the rest. Queued tracks and replaced
+	// sources aren't padded.
+	pub fn start_padding(mut self, padding: Duration) -> Self {
+		self.producer_options.start_padding = Some(padding);
+		self
+	}
+
+	// Skips the silence a source starts with and stops at the silence it ends with, e.g. for
+	// badly cut files. Audio at or below `threshold_db` dBFS on every channel counts as silence;
+	// -60 suits most recordings, while digital silence is caught by any threshold. Off by default.
+	// Trailing silence is trimmed up to 10 seconds of it.
+	pub fn trim_silence(mut self, threshold_db: f32) -> Self {
+		self.producer_options.trim_silence = Some(threshold_db);
+		self
+	}
+
+	// Plays the file at `path` once the source ends, without a gap; call it again to queue more.
+	// Later tracks can be added with AudioCable::enqueue. start_at and end_at only apply to the
+	// source itself. Input devices can't have a queue.
+	pub fn enqueue(mut self, path: String) -> Self {
+		self.queue.push(path);
+		self
+	}
+
+	// Plays the tracks of `playlist` after the source and anything queued, asking the cursor for
+	// the next one each time a track ends; see PlaylistCursor. Its tracks are opened as their turn
+	// comes rather than prefetched, and one that can't be opened is reported and skipped. With
+	// RepeatMode::All the cursor starts over from its first track once it reaches the end.
+	// Input devices can't have a playlist.
+	pub fn playlist(mut self, playlist: &PlaylistCursor) -> Self {
+		self.producer_options.playlist = Some(playlist.clone());
+		self
+	}
+
+	// How many queued tracks are opened and have their first half second decoded ahead of time
+	// (default 1), so moving on to them is instant. Each one staged holds that much audio in
+	// memory; more help when files open slowly, e.g. from a network share.
+	pub fn prefetch_tracks(mut self, tracks: usize) -> Self {
+		self.prefetch_tracks = tracks;
+		self
+	}
+
+	// How many frames the producer may decode ahead of the output device (default 8192). The
+	// buffered audio plays out before anything applied on the decode side, such as a new tempo,
+	// becomes audible, so those lag by up to frames / sample rate seconds (about 170 ms at 48 kHz
+	// by default). Smaller capacities react faster but underrun sooner when the decode thread is
+	// starved of CPU.
+	pub fn capacity_frames(mut self, frames: usize) -> Self {
+		self.consumer_options.capacity_frames = frames;
+		self
+	}
+
+	// How long the output callback may wait for a late producer once the buffered audio runs out,
+	// before playing silence and counting an underrun; capped at half the callback's buffer. The
+	// default of zero never blocks the device's realtime thread. A small wait can cover a producer
+	// that is only just behind, e.g. on a loaded machine, but the callback spends it blocked, which
+	// on a tight schedule can make the device itself glitch.
+	pub fn underrun_wait(mut self, wait: Duration) -> Self {
+		self.consumer_options.underrun_wait = wait;
+		self
+	}
+
+	// Raises the thread that runs the output device's callbacks to realtime priority (default
+	// off), so other busy processes are less likely to make it miss a deadline. cpal creates that
+	// thread itself, so this is best effort, done from the first callback: on Linux and Android it
+	// asks for SCHED_FIFO, which needs an RLIMIT_RTPRIO allowance (e.g. an audio group set up in
+	// /etc/security/limits.conf) or CAP_SYS_NICE, and otherwise settles for a lower niceness; on
+	// Windows it makes the thread time critical; macOS and iOS already run callbacks at realtime
+	// priority. Where it can't be raised the cable plays on as before and sends
+	// PlaybackEvent::PriorityNotRaised. A pulled output and a socket output have no device
+	// callback, so it does nothing for them.
+	pub fn realtime_priority(mut self, realtime: bool) -> Self {
+		self.consumer_options.realtime_priority = realtime;
+		self
+	}
+
+	// Number of frames the producer must buffer before the output stream starts (default 2048),
+	// capped at the data channel's capacity. The stream starts anyway if the source is shorter or
+	// hasn't produced them within half a second. 0 starts it straight away.
+	pub fn warmup_frames(mut self, frames: usize) -> Self {
+		self.consumer_options.warmup_frames = frames;
+		self
+	}
+
+	// With autoplay off (it's on by default) build opens the device and the source but leaves
+	// both idle until AudioCable::play, so cables can be prepared ahead of time and started on cue.
+	pub fn autoplay(mut self, autoplay: bool) -> Self {
+		self.autoplay = autoplay;
+		self
+	}
+
+	// Adds the built cable to `registry` under its id, so it can be paused or stopped through the
+	// registry from anywhere. It leaves the registry when dropped.
+	pub fn registry(mut self, registry: &CableRegistry) -> Self {
+		self.registry = Some(registry.clone());
+		self
+	}
+
+	// Input devices only: also writes everything captured to a WAV file at `path`, at the
+	// device's own rate and channel count, 16-bit unless record_format says otherwise. The file is
+	// finished when the cable is stopped or dropped. Recording carries on while the cable is paused
+	// or muted.
+	pub fn record_to<P: AsRef<Path>>(mut self, path: P) -> Self {
+		self.record_path = Some(path.as_ref().to_path_buf());
+		self
+	}
+
+	// How record_to stores samples; WavSampleFormat::F32 keeps the capture exactly as the device
+	// delivered it.
+	pub fn record_format(mut self, format: WavSampleFormat) -> Self {
+		self.record_format = format;
+		self
+	}
+
+	// Input devices only: when more than `latency` of captured audio is waiting to be played, the
+	// oldest of it is skipped so the output catches up with the input. This keeps a monitor's lag
+	// bounded when the input and output devices run at slightly different speeds. Off by
+	// default, in which case nothing is skipped until the capture queue is full.
+	pub fn max_input_latency(mut self, latency: Duration) -> Self {
+		self.max_input_latency = Some(latency);
+		self
+	}
+
+	// Input devices only: how fast the automatic gain control, once turned on with
+	// AudioCable::set_agc, turns a loud input down (`attack`, default 50 ms) and a quiet one up
+	// (`release`, default 2 s).
+	pub fn agc_timing(mut self, attack: Duration, release: Duration) -> Self {
+		self.agc_timing = Some((attack, release));
+		self
+	}
+
+	// Catches a panic in the decode thread, e.g. from a decoder tripping over a malformed or
+	// malicious file, and reports it with PlaybackEvent::DecoderPanicked instead of leaving the
+	// output playing silence with nothing said. The cable is then finished. Off by default, so
+	// the panic runs its course as anywhere else.
+	pub fn catch_decoder_panics(mut self, catch: bool) -> Self {
+		self.producer_options.catch_panics = catch;
+		self
+	}
+
+	// Opens the source again when it fails, by panicking or with a decode error such as a dropped
+	// connection, and carries on from where the decoder had got to, for players that must keep
+	// going, e.g. on an internet radio stream left running for days. Up to `max_restarts` are
+	// made in a row, a second apart, each reported with PlaybackEvent::SourceRestarted; a failure
+	// a minute or more after the last restart starts the count over. Once they're used up the
+	// cable finishes with SourceFailed, or DecoderPanicked for a panic. Files, queued tracks and
+	// URLs can be restarted; readers and pipes can't be opened again and fail as they would
+	// without it. After a decode error the track carries on from the exact frame; a panic may
+	// skip what was decoded but not yet sent. Loop points are lost. Off by default, when a decode
+	// error ends the track.
+	pub fn watchdog(mut self, max_restarts: u32) -> Self {
+		self.producer_options.watchdog = Some(max_restarts);
+		self
+	}
+
+	// Which sample rate the output device is opened at; see DeviceRatePolicy. MaxRate by default.
+	// strict_sample_rate overrides it, since it has to play at the source's rate anyway; a Fixed
+	// rate the device doesn't support fails the build with InvalidArgument.
+	pub fn device_rate(mut self, policy: DeviceRatePolicy) -> Self {
+		self.device_rate = policy;
+		self
+	}
+
+	// Refuses to resample, for output that must be bit-exact, e.g. when archiving or verifying.
+	// The output device is switched to the source's rate; when it doesn't support that rate, or
+	// a pulled output was asked for another, build fails with RateMismatch. replace_source fails
+	// the same way for a file at another rate, and queued tracks at another rate are skipped.
+	// AudioCable::set_sample_rate still resamples on request. Off by default, so any source plays
+	// on any device.
+	pub fn strict_sample_rate(mut self, strict: bool) -> Self {
+		self.producer_options.strict_rate = strict;
+		self
+	}
+
+	// How commands reach the decode thread: queued without limit by default, or through a bounded
+	// channel that makes senders wait, or drops seeks and tempo changes, while it's full. Stop and
+	// the other commands that must arrive are never dropped. See ControlChannel.
+	pub fn control_channel(mut self, control_channel: ControlChannel) -> Self {
+		self.producer_options.control_channel = control_channel;
+		self
+	}
+
+	pub fn build(mut self) -> Result<AudioCable, AudioError> {
+		if self.consumer_options.capacity_frames == 0 {
+			return Err(AudioError::InvalidArgument(String::from("the channel capacity must be at least one frame")));
+		}
+		if let ControlChannel::Bounded(0) | ControlChannel::BoundedDropping(0) = self.producer_options.control_channel {
+			return Err(AudioError::InvalidArgument(String::from("a bounded control channel must hold at least one command")));
+		}
+		if let (Some(start), Some(end)) = (self.producer_options.start_at, self.producer_options.end_at) {
+			if end <= start {
+				return Err(AudioError::InvalidArgument(format!("end_at ({:?}) must come after start_at ({:?})", end, start)));
+			}
+		}
+
+		if let Some(threshold) = self.producer_options.trim_silence {
+			if threshold.is_nan() || threshold > 0.0 {
+				return Err(AudioError::InvalidArgument(format!("the silence threshold must be at most 0 dBFS, got {}", threshold)));
+			}
+		}
+
+		if let Some((sample_rate, channels)) = self.consumer_options.pull_output {
+			if !SAMPLE_RATE_RANGE.contains(&sample_rate) || channels == 0 {
+				return Err(AudioError::InvalidArgument(format!("a pulled output needs a sample rate and channels, got {} Hz and {} channels", sample_rate, channels)));
+			}
+			if !self.mirrors.is_empty() {
+				return Err(AudioError::InvalidArgument(String::from("a pulled output can't have mirrors")));
+			}
+		}
+		if self.consumer_options.follow_default_device && (self.consumer_options.output_device.is_some() || self.consumer_options.pull_output.is_some()) {
+			return Err(AudioError::InvalidArgument(String::from("follow_default_device can't be combined with output_device or pull_output")));
+		}
+		#[cfg(all(feature = "jack", target_os = "linux"))]
+		if self.consumer_options.follow_default_device && self.consumer_options.jack_client.is_some() {
+			return Err(AudioError::InvalidArgument(String::from("follow_default_device can't be combined with jack_client")));
+		}
+		if self.consumer_options.sample_formats.is_empty() {
+			return Err(AudioError::InvalidArgument(String::from("at least one output sample format is needed")));
+		}
+		let from_device = matches!(self.audio_source, AudioInput::Device(_));
+		if !from_device && (self.record_path.is_some() || self.max_input_latency.is_some() || self.agc_timing.is_some()) {
+			return Err(AudioError::InvalidArgument(String::from("record_to, max_input_latency and agc_timing only apply to input devices")));
+		}
+
+		if self.prefetch_tracks == 0 {
+			return Err(AudioError::InvalidArgument(String::from("at least one track must be prefetched")));
+		}
+		if from_device && (!self.queue.is_empty() || self.producer_options.playlist.is_some()) {
+			return Err(AudioError::InvalidArgument(String::from("a cable playing from an input device can't have a queue or playlist")));
+		}
+
+		// Report unknown or compiled-out formats before opening any device.
+		match (&self.audio_source, self.format) {
+			(AudioInput::Path(_), Some(format)) if !format.is_enabled() => {
+				return Err(AudioError::UnsupportedFormat { extension: String::from(format.extension()), feature: Some(format.feature()) });
+			},
+			(AudioInput::Path(_), Some(_)) => {},
+			(AudioInput::Path(path), None) => {
+				SourceType::from_local(path.clone())?;
+			},
+			(_, Some(_)) => return Err(AudioError::InvalidArgument(String::from("format only applies to files"))),
+			(_, None) => {}
+		}
+		for path in &self.queue {
+			SourceType::from_local(path.clone())?;
+		}
+		if let AudioInput::RawPcm(_, _, sample_rate, channels) = self.audio_source {
+			if sample_rate == 0 || channels == 0 {
+				return Err(AudioError::InvalidArgument(format!("raw PCM needs a sample rate and channel count, got {} Hz and {} channels", sample_rate, channels)));
+			}
+		}
+		if let AudioInput::Manual(source) = &self.audio_source {
+			if source.sample_rate() == 0 || source.channels() == 0 {
+				return Err(AudioError::InvalidArgument(format!("pushed samples need a sample rate and channel count, got {} Hz and {} channels", source.sample_rate(), source.channels())));
+			}
+		}
+
+		if let Some(safe_output) = self.safe_output {
+			if !safe_output.headroom_db.is_finite() || safe_output.headroom_db < 0.0 {
+				return Err(AudioError::InvalidArgument(format!("the headroom must be at least 0 dB, got {}", safe_output.headroom_db)));
+			}
+			if safe_output.true_peak && !cfg!(feature = "true-peak") {
+				return Err(AudioError::Unsupported(String::from("true-peak limiting needs the `true-peak` cargo feature")));
+			}
+			let normalize_gain_db = match (safe_output.target_lufs, &self.audio_source) {
+				(Some(target), _) if !target.is_finite() || target >= 0.0 => {
+					return Err(AudioError::InvalidArgument(format!("the loudness target must be below 0 LUFS, got {}", target)));
+				},
+				(Some(target), AudioInput::Path(path)) => {
+					let loudness = measure_loudness(Path::new(path))?;
+					// Silence has no loudness to correct.
+					if loudness.is_finite() { (target - loudness).min(MAX_NORMALIZE_GAIN_DB) } else { 0.0 }
+				},
+				_ => 0.0
+			};
+			self.consumer_options.limiter = Some(Limiter::new(safe_output, normalize_gain_db));
+		}
+
+		if let Some(target_db) = self.normalize_peak {
+			if !target_db.is_finite() || target_db > 0.0 {
+				return Err(AudioError::InvalidArgument(format!("the peak target must be at most 0 dBFS, got {}", target_db)));
+			}
+			let normalizer = PeakNormalizer::new(target_db);
+			self.producer_options.normalize_peak = Some(match &self.audio_source {
+				AudioInput::Path(path) => normalizer.with_peak(scan_peak(Path::new(path))?),
+				AudioInput::Cached(cache) => normalizer.with_peak(cache.peak()),
+				_ => normalizer
+			});
+		}
+
+		let (events_tx, events_rx) = crossbeam_channel::unbounded();
+
+		let (mut data_destination, tx) = AudioConsumer::with_options(&self.consumer_options)?;
+		self.producer_options.output_rate = data_destination.sample_rate();
+		self.producer_options.output_channels = Some(data_destination.channels() as u16);
+
+		let (mirrors, clock) = self.open_mirrors(&data_destination)?;
+		if !from_device {
+			let queue = Arc::new(TrackQueue::new(self.prefetch_tracks));
+			for path in self.queue.drain(..) {
+				queue.enqueue(path)?;
+			}
+			self.producer_options.queue = Some(queue);
+		}
+
+		let drain = data_destination.stale_sample_drain();
+		let report_events = events_tx.clone();
+		let follow_events = events_tx.clone();
+		let mut capture = None;
+		let strict_rate = self.producer_options.strict_rate;
+		let mut data_source = match self.audio_source {
+			AudioInput::Path(path) => match self.format {
+				Some(format) => AudioProducer::from_reader(File::open(path)?, format, tx, drain, events_tx, self.producer_options)?,
+				None => <AudioProducer as LocalAudioProducer>::new(path, tx, drain, events_tx, self.producer_options)?
+			},
+			AudioInput::Reader(reader, format) => AudioProducer::from_reader(reader, format, tx, drain, events_tx, self.producer_options)?,
+			AudioInput::Pipe(reader, format) => AudioProducer::from_pipe(reader, format, tx, drain, events_tx, self.producer_options)?,
+			#[cfg(feature = "tokio")]
+			AudioInput::AsyncRead(reader, format) => {
+				let reader = AsyncReadBridge::new(reader)?;
+				AudioProducer::from_pipe(reader, format, tx, drain, events_tx, self.producer_options)?
+			},
+			AudioInput::Url(url) => <AudioProducer as StreamAudioProducer>::new(&url, tx, drain, events_tx, self.producer_options)?,
+			AudioInput::Cached(cache) => AudioProducer::from_source(Box::new(cache.source()), tx, drain, events_tx, self.producer_options)?,
+			AudioInput::RawPcm(reader, format, sample_rate, channels) => {
+				let source = PcmSource::from_reader(reader, format, sample_rate, channels)?;
+				AudioProducer::from_source(Box::new(source), tx, drain, events_tx, self.producer_options)?
+			},
+			AudioInput::Manual(source) => AudioProducer::from_source(Box::new(source), tx, drain, events_tx, self.producer_options)?,
+			AudioInput::Device(name) => {
+				let mut input = InputCapture::open(name.as_deref())?;
+				let (sample_rate, channels) = (input.sample_rate(), input.channels());
+
+				let mut splitter = Splitter::new();
+				let playback = splitter.add_output(self.consumer_options.capacity_frames * channels as usize);
+				if let Some(path) = &self.record_path {
+					let recorder = AudioRecorder::create_with_format(path, sample_rate, channels, self.record_format)?;
+					let queue = (RECORDER_QUEUE.as_secs_f64() * sample_rate as f64) as usize * channels as usize;
+					input.record(recorder, splitter.add_output(queue))?;
+				}
+				input.build_stream(splitter)?;
+
+				let (attack, release) = self.agc_timing.unwrap_or((DEFAULT_AGC_ATTACK, DEFAULT_AGC_RELEASE));
+				let source = DeviceSource::new(playback, sample_rate, channels, data_destination.channels() as u16, self.max_input_latency, Agc::new(attack, release), input.agc());
+				capture = Some(input);
+				<AudioProducer as DeviceAudioProducer>::new(source, tx, drain, events_tx, self.producer_options)?
+			}
+		};
+
+		if let Some(filter) = self.source_filter.take() {
+			data_source.set_source_filter(Some(filter));
+		}
+
+		// The device opens at its highest rate, and moves to the one the policy picks once the
+		// source's rate is known.
+		if !strict_rate && self.consumer_options.pull_output.is_none() {
+			let rate = match self.device_rate {
+				DeviceRatePolicy::MaxRate => None,
+				DeviceRatePolicy::MatchSource => data_destination.closest_sample_rate(data_source.source_rate()),
+				DeviceRatePolicy::Fixed(rate) => Some(rate)
+			};
+			if let Some(rate) = rate.filter(|&rate| Some(rate) != data_destination.sample_rate()) {
+				data_destination.set_sample_rate(rate)?;
+				data_source.set_output_rate(rate);
+			}
+		}
+
+		if strict_rate {
+			let (source_rate, output_rate) = (data_source.source_rate(), data_destination.sample_rate().unwrap_or(0));
+			if source_rate != output_rate {
+				if self.consumer_options.pull_output.is_some() || data_destination.set_sample_rate(source_rate).is_err() {
+					return Err(AudioError::RateMismatch { source_rate, output_rate });
+				}
+				data_source.set_output_rate(source_rate);
+			}
+		}
+
+		if self.consumer_options.follow_default_device {
+			data_destination.follow_default_device(&self.consumer_options, data_source.comm_chan.0.clone(), follow_events)?;
+		}
+		if let Some((path, format)) = &self.socket_output {
+			data_destination.output_to_socket(path, *format)?;
+		}
+
+		if let Some(reports) = data_destination.reports.take() {
+			report::forward(reports, report_events, Arc::clone(&data_source.progress))?;
+		}
+
+		let warmup_samples = (self.consumer_options.warmup_frames * data_destination.channels()).min(data_destination.capacity());
+
+		let cable = AudioCable {
+			data_source,
+			data_destination,
+			events: events_rx,
+			warmup_samples: warmup_samples as u64,
+			started: AtomicBool::new(false),
+			capture,
+			mirrors,
+			clock,
+			id: CableId::next(),
+			registry: self.registry.take(),
+		};
+		if let Some(registry) = &cable.registry {
+			registry.insert(cable.id, cable.control());
+		}
+		if self.autoplay {
+			cable.play()?;
+		}
+		Ok(cable)
+	}
+
+	// Opens the mirror devices and hands the decode thread their tap through the producer options.
+	fn open_mirrors(&mut self, primary: &AudioConsumer) -> Result<(Vec<MirrorOutput>, Option<Arc<ClockSync>>), AudioError> {
+		if self.mirrors.is_empty() {
+			return Ok((Vec::new(), None));
+		}
+		let sync = Arc::new(ClockSync::new(primary.sample_rate().unwrap_or(0)));
+		let mut splitter = Splitter::new();
+		let mut mirrors = Vec::with_capacity(self.mirrors.len());
+		let mut drains = Vec::with_capacity(self.mirrors.len());
+
+		for (index, name) in self.mirrors.iter().enumerate() {
+			let mut options = self.consumer_options.clone();
+			options.output_device = Some(name.clone());
+			options.capacity_frames *= 2;
+			let (mut consumer, tx) = AudioConsumer::with_options(&options)?;
+			if consumer.channels() != primary.channels() {
+				return Err(AudioError::InvalidArgument(format!(
+					"mirror device \"{}\" has {} channels, the primary output {}", name, consumer.channels(), primary.channels()
+				)));
+			}
+			consumer.share_controls(Arc::clone(&primary.controls))?;
+
+			drains.push(consumer.stale_sample_drain());
+			let blocks = splitter.add_output(MIRROR_QUEUE_BLOCKS);
+			mirrors.push(MirrorOutput::new(name.clone(), consumer, tx, blocks, index + 1, Arc::clone(&sync), self.producer_options.resample_quality)?);
+		}
+
+		self.producer_options.mirrors = Some(Arc::new(MirrorTap { splitter, sync: Arc::clone(&sync), drains }));
+		Ok((mirrors, Some(sync)))
+	}
+}
@@ -0,0 +1,91 @@
+use super::render::DecodedBlocks;
+use super::source::{MemorySamples, MemorySource};
+use super::{AudioCable, AudioCableBuilder, AudioError};
+
+use std::path::Path;
+use std::sync::Arc;
+
+/* CachedSource: A file decoded once into memory, e.g. a short sound effect played over and
+ * over.
+ *
+ * Each cable made from the cache replays the shared samples without opening or decoding the file
+ * again, so starting one is cheap. The whole file is held in memory as f32 samples, or with
+ * load_i16 as 16-bit ones in half the room, so this only suits short sounds. Clones share the
+ * same samples.
+ */
+#[derive(Clone)]
+pub struct CachedSource {
+	samples: MemorySamples,
+	sample_rate: u32,
+	channels: u16,
+}
+
+impl CachedSource {
+	// Decodes the whole file at `path` into memory.
+	pub fn load(path: &Path) -> Result<CachedSource, AudioError> {
+		let blocks = DecodedBlocks::open(path)?;
+		let (sample_rate, channels) = (blocks.sample_rate(), blocks.channels());
+		let mut samples = Vec::new();
+		for block in blocks {
+			samples.extend_from_slice(&block?);
+		}
+		Ok(CachedSource { samples: MemorySamples::F32(Arc::new(samples)), sample_rate, channels })
+	}
+
+	// Decodes the whole file at `path` into memory as load does, but keeps it as 16-bit samples,
+	// e.g. for a game's bank of hundreds of sound effects. That takes half the memory of f32 and
+	// loses nothing from a 16-bit file; deeper ones are rounded to 16 bits, and float samples past
+	// full scale clipped, as a 16-bit WAV recording would be. Samples are converted back to f32 as
+	// they're played.
+	pub fn load_i16(path: &Path) -> Result<CachedSource, AudioError> {
+		let blocks = DecodedBlocks::open(path)?;
+		let (sample_rate, channels) = (blocks.sample_rate(), blocks.channels());
+		let mut samples = Vec::new();
+		for block in blocks {
+			samples.extend(block?.iter().map(|&sample| (sample * 32768.0).round().clamp(i16::MIN as f32, i16::MAX as f32) as i16));
+		}
+		Ok(CachedSource { samples: MemorySamples::I16(Arc::new(samples)), sample_rate, channels })
+	}
+
+	pub fn sample_rate(&self) -> u32 {
+		self.sample_rate
+	}
+
+	pub fn channels(&self) -> u16 {
+		self.channels
+	}
+
+	// A builder for a cable replaying the cached samples, to be configured like any other.
+	pub fn cable(&self) -> AudioCableBuilder {
+		AudioCableBuilder::from_cache(self)
+	}
+
+	// Plays the cached samples once on the default output; the returned cable must be kept
+	// alive until it has finished.
+	pub fn play(&self) -> Result<AudioCable, AudioError> {
+		self.cable().build()
+	}
+
+	// The largest absolute sample, for peak normalisation.
+	pub(super) fn peak(&self) -> f32 {
+		match &self.samples {
+			MemorySamples::F32(samples) => samples.iter().fold(0.0f32, |peak, sample| peak.max(sample.abs())),
+			MemorySamples::I16(samples) => samples.iter().fold(0.0f32, |peak, &sample| peak.max((sample as f32 / 32768.0).abs())),
+		}
+	}
+
+	pub(super) fn source(&self) -> MemorySource {
+		MemorySource::new(self.samples.clone(), self.sample_rate, self.channels)
+	}
+}
+
+// Leaves the samples out; there may be millions of them.
+impl std::fmt::Debug for CachedSource {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("CachedSource")
+			.field("sample_rate", &self.sample_rate)
+			.field("channels", &self.channels)
+			.field("samples", &self.samples.len())
+			.finish()
+	}
+}
@@ -0,0 +1,134 @@
+use super::agc::AgcControl;
+use super::{find_device, AudioError, AudioRecorder, Splitter, SAMPLE_RATE_RANGE};
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+/* InputCapture: An open input device (microphone, line in, ...) whose samples go to a Splitter.
+ *
+ * The stream is built up front but only started by play, so capture begins together with the
+ * cable. An attached recorder runs on its own thread and finishes its file once capture stops.
+ */
+pub(crate) struct InputCapture {
+	device: cpal::Device,
+	config: cpal::StreamConfig,
+	sample_format: cpal::SampleFormat,
+	stream: Option<cpal::Stream>,
+	recorder: Option<JoinHandle<()>>,
+	// The automatic gain control settings the DeviceSource playing this input follows.
+	agc: Arc<AgcControl>,
+}
+
+fn err_fn<T>(err: T) where T: std::fmt::Display {
+	eprintln!("an error occurred on the input audio stream: {}", err);
+}
+
+impl InputCapture {
+	// Opens the input device called `name`, or the default one, in its default configuration.
+	pub(crate) fn open(name: Option<&str>) -> Result<InputCapture, AudioError> {
+		let host = cpal::default_host();
+		let device = match name {
+			Some(name) => {
+				let devices = host.input_devices()
+					.map_err(|err| AudioError::Device(format!("error listing input devices: {}", err)))?;
+				find_device(devices, name, "input")?
+			},
+			None => host.default_input_device()
+				.ok_or_else(|| AudioError::Device(String::from("no default input device")))?
+		};
+		let supported_config = device.default_input_config()
+			.map_err(|err| AudioError::Device(format!("error querying the input configuration: {}", err)))?;
+		if supported_config.channels() == 0 || !SAMPLE_RATE_RANGE.contains(&supported_config.sample_rate().0) {
+			return Err(AudioError::InvalidConfig(format!("the input device's default configuration is unusable: {:?}", supported_config)));
+		}
+
+		Ok(InputCapture {
+			device,
+			config: supported_config.config(),
+			sample_format: supported_config.sample_format(),
+			stream: None,
+			recorder: None,
+			agc: Arc::new(AgcControl::new()),
+		})
+	}
+
+	pub(crate) fn sample_rate(&self) -> u32 {
+		self.config.sample_rate.0
+	}
+
+	pub(crate) fn channels(&self) -> u16 {
+		self.config.channels
+	}
+
+	pub(crate) fn agc(&self) -> Arc<AgcControl> {
+		Arc::clone(&self.agc)
+	}
+
+	// Writes everything arriving on `samples` to `recorder` until the splitter feeding it is gone.
+	pub(crate) fn record(&mut self, mut recorder: AudioRecorder, samples: crossbeam_channel::Receiver<f32>) -> Result<(), AudioError> {
+		self.recorder = Some(std::thread::Builder::new().name(String::from("audios-recorder")).spawn(move || {
+			let mut block = Vec::new();
+			while let Ok(sample) = samples.recv() {
+				block.clear();
+				block.push(sample);
+				block.extend(samples.try_iter());
+				if let Err(err) = recorder.write(&block) {
+					eprintln!("failed to write the recording: {}", err);
+					return;
+				}
+			}
+			if let Err(err) = recorder.finish() {
+				eprintln!("failed to finish the recording: {}", err);
+			}
+		})?);
+		Ok(())
+	}
+
+	// Builds the input stream, converting every callback's samples to f32 for `splitter`.
+	pub(crate) fn build_stream(&mut self, splitter: Splitter) -> Result<(), AudioError> {
+		let stream = match self.sample_format {
+			cpal::SampleFormat::F32 => self.device.build_input_stream(&self.config, move |data: &[f32], _: &cpal::InputCallbackInfo| {
+				splitter.push(data);
+			}, err_fn),
+			cpal::SampleFormat::I16 => self.device.build_input_stream(&self.config, converting_callback::<i16>(splitter), err_fn),
+			cpal::SampleFormat::U16 => self.device.build_input_stream(&self.config, converting_callback::<u16>(splitter), err_fn),
+		}.map_err(|err| AudioError::Device(format!("failed to build input stream: {}", err)))?;
+		self.stream = Some(stream);
+		Ok(())
+	}
+
+	pub(crate) fn play(&self) -> Result<(), AudioError> {
+		if let Some(stream) = &self.stream {
+			stream.play()
+				.map_err(|err| AudioError::Device(format!("failed to start input stream: {}", err)))?;
+		}
+		Ok(())
+	}
+
+	// Closes the input stream and waits for the recorder to finish its file.
+	pub(crate) fn stop(&mut self) {
+		// Dropping the stream drops the splitter, which disconnects the recorder's queue.
+		self.stream = None;
+		if let Some(recorder) = self.recorder.take() {
+			let _ = recorder.join();
+		}
+	}
+}
+
+impl Drop for InputCapture {
+	fn drop(&mut self) {
+		self.stop();
+	}
+}
+
+// The conversion buffer only grows until it fits the device's largest callback.
+fn converting_callback<T: cpal::Sample>(splitter: Splitter) -> impl FnMut(&[T], &cpal::InputCallbackInfo) + Send + 'static {
+	let mut converted = Vec::new();
+	move |data: &[T], _: &cpal::InputCallbackInfo| {
+		converted.clear();
+		converted.extend(data.iter().map(cpal::Sample::to_f32));
+		splitter.push(&converted);
+	}
+}
@@ -0,0 +1,127 @@
+use super::frames_to_duration;
+
+use std::time::Duration;
+
+// Track numbers FLAC reserves for the lead-out of CD-DA and other CUE sheets.
+#[cfg(any(feature = "flac", feature = "aac", feature = "symphonia"))]
+const LEAD_OUT_TRACKS: [u8; 2] = [170, 255];
+
+// CUE sheet INDEX times count frames of a CD, 75 to the second.
+const CUE_FRAMES_PER_SECOND: u64 = 75;
+
+/* Chapter: One track of a CUE sheet, e.g. a song of an album stored as a single FLAC file. The
+ * sheet is either embedded in the source or loaded with AudioCable::load_cue. Returned by
+ * AudioCable::chapters().
+ */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Chapter {
+	// Track number as written in the CUE sheet.
+	pub number: u8,
+	// Offset from the start of the source, usable with AudioCable::seek.
+	pub start: Duration,
+	// From the TITLE line of a text CUE sheet (a CUESHEET tag or a .cue file), when there is one.
+	pub title: Option<String>,
+	// From the track's PERFORMER line, or else the sheet's own.
+	pub performer: Option<String>,
+}
+
+// One TRACK of a text CUE sheet. `start` is its INDEX 01, or INDEX 00 without one.
+struct CueTrack {
+	number: u8,
+	title: Option<String>,
+	performer: Option<String>,
+	start: Option<Duration>,
+}
+
+// Builds chapters from CUE sheet tracks given as (number, start frame), skipping the lead-out.
+// Titles and performers are looked up in the text cue sheet some encoders embed alongside the
+// binary block.
+#[cfg(any(feature = "flac", feature = "aac", feature = "symphonia"))]
+pub(super) fn from_cue_tracks(tracks: impl IntoIterator<Item = (u8, u64)>, sample_rate: u32, cue_text: Option<&str>) -> Vec<Chapter> {
+	let text_tracks = cue_text.map(parse_cue_sheet).unwrap_or_default();
+	tracks.into_iter()
+		.filter(|(number, _)| !LEAD_OUT_TRACKS.contains(number))
+		.map(|(number, frame)| {
+			let text_track = text_tracks.iter().find(|track| track.number == number);
+			Chapter {
+				number,
+				// Zero for a sample rate of 0, which a malformed header can report.
+				start: if sample_rate == 0 { Duration::ZERO } else { frames_to_duration(frame, sample_rate) },
+				title: text_track.and_then(|track| track.title.clone()),
+				performer: text_track.and_then(|track| track.performer.clone()),
+			}
+		})
+		.collect()
+}
+
+// Builds chapters from a standalone .cue file describing one audio file. Tracks without an INDEX
+// are left out, as are the tracks of any FILE after the first, whose times count from the start
+// of a different file.
+pub(super) fn from_cue_sheet(text: &str) -> Vec<Chapter> {
+	parse_cue_sheet(text).into_iter()
+		.filter_map(|track| Some(Chapter { number: track.number, start: track.start?, title: track.title, performer: track.performer }))
+		.collect()
+}
+
+// Reads the TRACK, TITLE, PERFORMER and INDEX lines of a text CUE sheet, tolerating any case,
+// unquoted values and lines it doesn't know. The sheet's own TITLE before the first TRACK is
+// ignored; its PERFORMER stands in for tracks that have none.
+fn parse_cue_sheet(text: &str) -> Vec<CueTrack> {
+	let mut tracks: Vec<CueTrack> = Vec::new();
+	let mut sheet_performer = None;
+	let mut files = 0;
+	for line in text.trim_start_matches('\u{feff}').lines() {
+		let line = line.trim();
+		let (command, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+		let rest = rest.trim();
+		match command.to_ascii_uppercase().as_str() {
+			"FILE" => files += 1,
+			_ if files > 1 => {},
+			"TRACK" => {
+				if let Some(number) = rest.split_whitespace().next().and_then(|number| number.parse::<u8>().ok()) {
+					tracks.push(CueTrack { number, title: None, performer: None, start: None });
+				}
+			},
+			"TITLE" => if let Some(track) = tracks.last_mut() {
+				track.title = Some(unquote(rest));
+			},
+			"PERFORMER" => match tracks.last_mut() {
+				Some(track) => track.performer = Some(unquote(rest)),
+				None => sheet_performer = Some(unquote(rest))
+			},
+			"INDEX" => {
+				let mut fields = rest.split_whitespace();
+				let index = fields.next().and_then(|index| index.parse::<u8>().ok());
+				let time = fields.next().and_then(cue_time);
+				if let (Some(track), Some(index @ (0 | 1)), Some(time)) = (tracks.last_mut(), index, time) {
+					if index == 1 || track.start.is_none() {
+						track.start = Some(time);
+					}
+				}
+			},
+			_ => {}
+		}
+	}
+	for track in &mut tracks {
+		if track.performer.is_none() {
+			track.performer = sheet_performer.clone();
+		}
+	}
+	tracks
+}
+
+fn unquote(value: &str) -> String {
+	let value = value.strip_prefix('"').map_or(value, |value| value.strip_suffix('"').unwrap_or(value));
+	String::from(value)
+}
+
+// Parses an mm:ss:ff INDEX time.
+fn cue_time(time: &str) -> Option<Duration> {
+	let mut fields = time.split(':').map(|field| field.parse::<u64>().ok());
+	let (minutes, seconds, frames) = (fields.next()??, fields.next()??, fields.next()??);
+	if fields.next().is_some() || seconds >= 60 || frames >= CUE_FRAMES_PER_SECOND {
+		return None;
+	}
+	let frames = (minutes * 60 + seconds) * CUE_FRAMES_PER_SECOND + frames;
+	Some(frames_to_duration(frames, CUE_FRAMES_PER_SECOND as u32))
+}
@@ -0,0 +1,80 @@
+use super::{AudioChannelMessage, AudioError, ProducerProgress};
+
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+
+// How long a command waiting for room in a full control channel goes between checks that a
+// decode thread is still there to make some.
+const COMMAND_RETRY: Duration = Duration::from_millis(10);
+
+/* ControlChannel: How the commands behind seek, set_tempo, pause_source, stop and the like reach
+ * the decode thread, which picks them up between decoded blocks.
+ *
+ * Stop, the other commands that end or start the thread, and those that change state a later
+ * command doesn't put right (the output rate, pausing the source) are never dropped: on a full
+ * channel they wait for room whatever the setting. A thread held up by a slow source can keep a
+ * bounded channel full for a while, so a sender may wait that long too.
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ControlChannel {
+	// Every command is queued, however many the thread has yet to pick up.
+	#[default]
+	Unbounded,
+	// At most this many commands wait; a sender waits for room while it's full.
+	Bounded(usize),
+	// At most this many commands wait. While it's full, seeks and tempo changes are dropped rather
+	// than waited on, for an app that sends them continuously, e.g. from a scrub bar or a slider,
+	// and would rather the thread catch up with the latest one.
+	BoundedDropping(usize),
+}
+
+impl AudioChannelMessage {
+	// Whether the command can be left out under ControlChannel::BoundedDropping.
+	fn droppable(&self) -> bool {
+		matches!(self, AudioChannelMessage::Seek(_) | AudioChannelMessage::SetTempo(_) | AudioChannelMessage::NOP)
+	}
+}
+
+/* CommandSender: The sending half of the decode thread's control channel, shared by the producer,
+ * PlaybackControl handles and a default device follower.
+ */
+#[derive(Clone)]
+pub(super) struct CommandSender {
+	sender: crossbeam_channel::Sender<AudioChannelMessage>,
+	dropping: bool,
+	// Whether a decode thread is around to take what's sent; a bounded channel nobody reads stays
+	// full for good.
+	progress: Arc<ProducerProgress>,
+}
+
+impl CommandSender {
+	pub(super) fn channel(kind: ControlChannel, progress: Arc<ProducerProgress>) -> (CommandSender, crossbeam_channel::Receiver<AudioChannelMessage>) {
+		let (sender, receiver) = match kind {
+			ControlChannel::Unbounded => crossbeam_channel::unbounded(),
+			ControlChannel::Bounded(capacity) | ControlChannel::BoundedDropping(capacity) => crossbeam_channel::bounded(capacity)
+		};
+		(CommandSender { sender, dropping: matches!(kind, ControlChannel::BoundedDropping(_)), progress }, receiver)
+	}
+
+	// Queues `message` for the decode thread. Stopped is returned once the producer is gone, or,
+	// when it has to wait for room, once there's no thread left to make any.
+	pub(super) fn send(&self, message: AudioChannelMessage) -> Result<(), AudioError> {
+		let mut message = match self.sender.try_send(message) {
+			Ok(()) => return Ok(()),
+			Err(crossbeam_channel::TrySendError::Disconnected(_)) => return Err(AudioError::Stopped),
+			Err(crossbeam_channel::TrySendError::Full(message)) if self.dropping && message.droppable() => return Ok(()),
+			Err(crossbeam_channel::TrySendError::Full(message)) => message
+		};
+		loop {
+			message = match self.sender.send_timeout(message, COMMAND_RETRY) {
+				Ok(()) => return Ok(()),
+				Err(crossbeam_channel::SendTimeoutError::Disconnected(_)) => return Err(AudioError::Stopped),
+				Err(crossbeam_channel::SendTimeoutError::Timeout(message)) => message
+			};
+			if self.progress.exited.load(Ordering::Relaxed) {
+				return Err(AudioError::Stopped);
+			}
+		}
+	}
+}
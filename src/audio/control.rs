@@ -0,0 +1,74 @@
+use super::{set_tempo, AudioChannelMessage, AudioError, CommandSender, ConsumerControls};
+
+use std::sync::Arc;
+use std::time::Duration;
+
+/* PlaybackControl: A cheap, cloneable handle for steering an AudioCable from other threads.
+ *
+ * It only holds the output callback's shared settings and the sender half of the producer's
+ * control channel, never the output stream itself, so clones can be sent to any thread. Obtained
+ * through AudioCable::control().
+ */
+#[derive(Clone)]
+pub struct PlaybackControl {
+	controls: Arc<ConsumerControls>,
+	commands: CommandSender,
+}
+
+impl PlaybackControl {
+	pub(super) fn new(controls: Arc<ConsumerControls>, commands: CommandSender) -> Self {
+		PlaybackControl { controls, commands }
+	}
+
+	// Outputs silence and holds the buffered audio until resume.
+	pub fn pause(&self) {
+		self.controls.set_paused(true);
+	}
+
+	pub fn resume(&self) {
+		self.controls.set_paused(false);
+	}
+
+	pub fn is_paused(&self) -> bool {
+		self.controls.paused()
+	}
+
+	// Ends decoding and drops what was buffered, like AudioCable::stop, except that an input device
+	// or mirror outputs stay open until the cable itself is stopped or dropped.
+	pub fn stop(&self) -> Result<(), AudioError> {
+		self.commands.send(AudioChannelMessage::Stop)
+	}
+
+	// Same as AudioCable::flush.
+	pub fn flush(&self) {
+		self.controls.flush();
+	}
+
+	// Same as AudioCable::set_muted.
+	pub fn set_muted(&self, muted: bool) {
+		self.controls.set_muted(muted);
+	}
+
+	pub fn is_muted(&self) -> bool {
+		self.controls.muted()
+	}
+
+	// Sets the linear output gain, where 1.0 leaves the source untouched.
+	pub fn set_volume(&self, volume: f32) {
+		self.controls.set_volume(volume);
+	}
+
+	pub fn volume(&self) -> f32 {
+		self.controls.volume()
+	}
+
+	// Same as AudioCable::seek. Fails with AudioError::Stopped once the cable's producer is gone.
+	pub fn seek(&self, offset: Duration) -> Result<(), AudioError> {
+		self.commands.send(AudioChannelMessage::Seek(offset))
+	}
+
+	// Same as AudioCable::set_tempo.
+	pub fn set_tempo(&self, ratio: f32) -> Result<(), AudioError> {
+		set_tempo(&self.commands, ratio)
+	}
+}
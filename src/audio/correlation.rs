@@ -0,0 +1,63 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+// How far back the correlation reaches: older audio fades out with this time constant, about
+// the integration time of a hardware correlation meter.
+const CORRELATION_WINDOW_SECS: f64 = 0.3;
+// Below this mean power per channel (about -100 dBFS) the output counts as silent.
+const SILENCE_POWER: f64 = 1e-10;
+
+/* PhaseMeter: Correlation between the two channels of a stereo output, updated by the output
+ * callback.
+ *
+ * The callback keeps exponentially decaying sums of L*R, L*L and R*R and after every block
+ * publishes L*R / sqrt(L*L * R*R) as f32 bits, so readers never lock. The result is +1 for mono,
+ * 0 for unrelated channels and -1 when one channel is the other inverted, which cancels out when
+ * the output is summed to mono. Silence reads as 0.
+ */
+pub(super) struct PhaseMeter {
+	correlation: Arc<AtomicU32>,
+	left_right: f64,
+	left: f64,
+	right: f64,
+}
+
+impl PhaseMeter {
+	pub(super) fn new(correlation: Arc<AtomicU32>) -> PhaseMeter {
+		PhaseMeter { correlation, left_right: 0.0, left: 0.0, right: 0.0 }
+	}
+
+	// A meter publishing to the same place, starting from silence.
+	pub(super) fn renewed(&self) -> PhaseMeter {
+		PhaseMeter::new(Arc::clone(&self.correlation))
+	}
+
+	// Takes one block of interleaved stereo samples as they are sent to the device.
+	pub(super) fn process(&mut self, block: &[f32], sample_rate: u32) {
+		let frames = block.len() / 2;
+		if frames == 0 {
+			return;
+		}
+		let decay = (-(frames as f64) / (CORRELATION_WINDOW_SECS * sample_rate.max(1) as f64)).exp();
+		let (mut left_right, mut left, mut right) = (0.0, 0.0, 0.0);
+		for frame in block.chunks_exact(2) {
+			let (l, r) = (frame[0] as f64, frame[1] as f64);
+			left_right += l * r;
+			left += l * l;
+			right += r * r;
+		}
+		// The block's sums are scaled to a per-frame mean so the silence threshold doesn't depend
+		// on the block size.
+		let weight = (1.0 - decay) / frames as f64;
+		self.left_right = self.left_right * decay + left_right * weight;
+		self.left = self.left * decay + left * weight;
+		self.right = self.right * decay + right * weight;
+
+		let correlation = if self.left < SILENCE_POWER || self.right < SILENCE_POWER {
+			0.0
+		} else {
+			(self.left_right / (self.left * self.right).sqrt()).clamp(-1.0, 1.0)
+		};
+		self.correlation.store((correlation as f32).to_bits(), Ordering::Relaxed);
+	}
+}
@@ -0,0 +1,102 @@
+use super::{AudioCable, AudioError};
+
+/* CrossfadeCurve: How Crossfader::set_crossfade shares the level between the two decks.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum CrossfadeCurve {
+	// Each deck's gain follows the fader in a straight line. Two decks playing unrelated music
+	// sound about 3 dB quieter together in the middle than either does alone.
+	Linear,
+	// The gains follow a quarter sine and cosine, so the total power, and with unrelated music the
+	// loudness, stays the same across the fader: both decks play at -3 dB in the middle.
+	#[default]
+	EqualPower,
+}
+
+/* Crossfader: Two cables, deck A and deck B, under one DJ-style crossfader that sets each deck's
+ * volume from a single position, 0.0 for all A and 1.0 for all B.
+ *
+ * The decks keep playing through their own output streams, so the OS mixes them, and the
+ * crossfader only drives their volumes: setting one directly with AudioCable::set_volume holds
+ * until the next set_crossfade. Everything else, playing, seeking, tempo and so on, is done on
+ * the decks themselves.
+ */
+pub struct Crossfader {
+	decks: [AudioCable; 2],
+	position: f32,
+	curve: CrossfadeCurve,
+}
+
+impl Crossfader {
+	// Puts `deck_a` and `deck_b` under a crossfader that starts all the way over at A, with the
+	// equal-power curve.
+	pub fn new(deck_a: AudioCable, deck_b: AudioCable) -> Crossfader {
+		let crossfader = Crossfader { decks: [deck_a, deck_b], position: 0.0, curve: CrossfadeCurve::default() };
+		crossfader.apply();
+		crossfader
+	}
+
+	// Moves the fader to `position`, between 0.0 (only deck A heard) and 1.0 (only deck B), and
+	// sets both decks' volumes to match at once.
+	pub fn set_crossfade(&mut self, position: f32) -> Result<(), AudioError> {
+		if !(0.0..=1.0).contains(&position) {
+			return Err(AudioError::InvalidArgument(format!("the crossfade position must be between 0 and 1, got {}", position)));
+		}
+		self.position = position;
+		self.apply();
+		Ok(())
+	}
+
+	pub fn crossfade(&self) -> f32 {
+		self.position
+	}
+
+	// Switches to `curve`, applying it to the fader's current position.
+	pub fn set_curve(&mut self, curve: CrossfadeCurve) {
+		self.curve = curve;
+		self.apply();
+	}
+
+	pub fn curve(&self) -> CrossfadeCurve {
+		self.curve
+	}
+
+	// The linear gains of deck A and deck B at the current position.
+	pub fn gains(&self) -> (f32, f32) {
+		match self.curve {
+			CrossfadeCurve::Linear => (1.0 - self.position, self.position),
+			CrossfadeCurve::EqualPower => {
+				let angle = self.position * std::f32::consts::FRAC_PI_2;
+				(angle.cos(), angle.sin())
+			}
+		}
+	}
+
+	pub fn deck_a(&self) -> &AudioCable {
+		&self.decks[0]
+	}
+
+	pub fn deck_a_mut(&mut self) -> &mut AudioCable {
+		&mut self.decks[0]
+	}
+
+	pub fn deck_b(&self) -> &AudioCable {
+		&self.decks[1]
+	}
+
+	pub fn deck_b_mut(&mut self) -> &mut AudioCable {
+		&mut self.decks[1]
+	}
+
+	// Hands the decks back, A first, at the volumes the crossfader last gave them.
+	pub fn into_decks(self) -> (AudioCable, AudioCable) {
+		let [deck_a, deck_b] = self.decks;
+		(deck_a, deck_b)
+	}
+
+	fn apply(&self) {
+		let (gain_a, gain_b) = self.gains();
+		self.decks[0].set_volume(gain_a);
+		self.decks[1].set_volume(gain_b);
+	}
+}
@@ -0,0 +1,81 @@
+use super::ConsumerControls;
+
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+/* Effect: Custom processing applied to the audio in the output callback, added with
+ * AudioConsumer::add_effect or set_effects.
+ *
+ * process runs on the device's realtime thread once per callback, with the interleaved samples
+ * about to be played. The buffer's length changes from callback to callback. Implementations
+ * must not block, lock, allocate, do I/O or otherwise take unbounded time there: anything slow
+ * makes the device underrun and click. Do allocations up front and reach settings changed from
+ * other threads through atomics.
+ *
+ * On x86-64 and AArch64 the CPU flushes denormal floats to zero while process runs, so a filter's
+ * decaying tail can't slow the callback down. Elsewhere, flush the state a filter feeds back into
+ * itself to zero once it drops below f32::MIN_POSITIVE.
+ */
+pub trait Effect: Send {
+	fn process(&mut self, buffer: &mut [f32], channels: u16, sample_rate: u32);
+}
+
+/* VolumeStage: Where the master volume and mute are applied in the output chain. Effects whose
+ * behaviour depends on the level they're given, such as a compressor or a distortion, sound
+ * different with each. The channel gains always come last either way.
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum VolumeStage {
+	// Ahead of the custom effects, which see the audio at the volume it's played at.
+	PreEffects,
+	// After the custom effects, as the final gain before the device; they see the audio at its
+	// own level.
+	#[default]
+	PostEffects,
+}
+
+/* Gain: The built-in volume stage, run after the custom effects. Applies the master volume and
+ * mute, unless the VolumeStage moves them ahead of the effects, then each channel's own gain.
+ */
+pub(super) struct Gain {
+	controls: Arc<ConsumerControls>,
+	// The stage read for the current block, so a change can't apply the volume twice or skip it.
+	stage: VolumeStage,
+}
+
+impl Gain {
+	pub(super) fn new(controls: Arc<ConsumerControls>) -> Gain {
+		Gain { controls, stage: VolumeStage::PostEffects }
+	}
+
+	// Run ahead of the custom effects for every block: picks up the stage and, for PreEffects,
+	// applies the master volume and mute.
+	pub(super) fn process_pre_effects(&mut self, buffer: &mut [f32]) {
+		self.stage = self.controls.volume_stage();
+		if self.stage == VolumeStage::PreEffects {
+			let volume = self.master_volume();
+			for sample in buffer.iter_mut() {
+				*sample *= volume;
+			}
+		}
+	}
+
+	fn master_volume(&self) -> f32 {
+		if self.controls.muted() { 0.0 } else { self.controls.volume() }
+	}
+}
+
+impl Effect for Gain {
+	fn process(&mut self, buffer: &mut [f32], channels: u16, _: u32) {
+		let volume = match self.stage {
+			VolumeStage::PreEffects => 1.0,
+			VolumeStage::PostEffects => self.master_volume()
+		};
+		let channels = channels.max(1) as usize;
+		for (index, sample) in buffer.iter_mut().enumerate() {
+			let channel_gain = self.controls.channel_gains.get(index % channels)
+				.map_or(1.0, |gain| f32::from_bits(gain.load(Ordering::Relaxed)));
+			*sample *= volume * channel_gain;
+		}
+	}
+}
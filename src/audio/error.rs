@@ -0,0 +1,105 @@
+use std::fmt;
+
+/* AudioError: Everything that can go wrong while wiring up or driving an AudioCable.
+ */
+#[derive(Debug)]
+pub enum AudioError {
+	// No usable output device, or the device rejected the stream.
+	Device(String),
+	// Reading the source failed.
+	Io(std::io::Error),
+	// The source is corrupt or couldn't be decoded.
+	Decode(String),
+	// The source path is empty or has no usable filename/extension.
+	InvalidPath(String),
+	// The source's format isn't recognised, or its decoder was compiled out. `feature` names the
+	// cargo feature that enables it, if there is one.
+	UnsupportedFormat { extension: String, feature: Option<&'static str> },
+	// An argument was out of range or didn't match the device/source layout.
+	InvalidArgument(String),
+	// The device only offers configurations that can't be played, e.g. with no channels or a
+	// nonsensical sample rate.
+	InvalidConfig(String),
+	// The cable was stopped and can no longer be controlled.
+	Stopped,
+	// The requested feature isn't available for this device, source or platform.
+	Unsupported(String),
+	// The decoder panicked, e.g. on a malformed file, with this message. Only caught with
+	// AudioCableBuilder::catch_decoder_panics.
+	DecoderPanicked(String),
+	// The source's sample rate can't be played as it is, and sample rate conversion was disabled
+	// with AudioCableBuilder::strict_sample_rate.
+	RateMismatch { source_rate: u32, output_rate: u32 },
+	// The decoded audio doesn't hash to the MD5 the file declares, so the file is corrupt; see
+	// verify_integrity.
+	IntegrityMismatch { declared: [u8; 16], decoded: [u8; 16] },
+}
+
+impl fmt::Display for AudioError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			AudioError::Device(reason) => write!(f, "audio device error: {}", reason),
+			AudioError::Io(err) => write!(f, "i/o error: {}", err),
+			AudioError::Decode(reason) => write!(f, "decode error: {}", reason),
+			AudioError::InvalidPath(path) => write!(f, "invalid audio source path: \"{}\"", path),
+			AudioError::UnsupportedFormat { extension, feature: Some(feature) } =>
+				write!(f, "unsupported audio format \".{}\": enable the `{}` cargo feature to decode it", extension, feature),
+			AudioError::UnsupportedFormat { extension, feature: None } => write!(f, "unsupported audio format \".{}\"", extension),
+			AudioError::InvalidArgument(reason) => write!(f, "invalid argument: {}", reason),
+			AudioError::InvalidConfig(reason) => write!(f, "invalid device configuration: {}", reason),
+			AudioError::Stopped => write!(f, "the audio cable has been stopped"),
+			AudioError::Unsupported(reason) => write!(f, "unsupported: {}", reason),
+			AudioError::DecoderPanicked(message) => write!(f, "the decoder panicked: {}", message),
+			AudioError::RateMismatch { source_rate, output_rate } => write!(
+				f, "the source is at {} Hz but the output plays at {} Hz, and sample rate conversion is disabled", source_rate, output_rate
+			),
+			AudioError::IntegrityMismatch { declared, decoded } => write!(
+				f, "the decoded audio's MD5 is {} but the file declares {}", hex(decoded), hex(declared)
+			),
+		}
+	}
+}
+
+fn hex(bytes: &[u8]) -> String {
+	bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+impl std::error::Error for AudioError {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			AudioError::Io(err) => Some(err),
+			_ => None
+		}
+	}
+}
+
+impl From<std::io::Error> for AudioError {
+	fn from(err: std::io::Error) -> Self {
+		AudioError::Io(err)
+	}
+}
+
+#[cfg(feature = "flac")]
+impl From<claxon::Error> for AudioError {
+	fn from(err: claxon::Error) -> Self {
+		match err {
+			claxon::Error::IoError(err) => AudioError::Io(err),
+			claxon::Error::FormatError(reason) => AudioError::Decode(String::from(reason)),
+			claxon::Error::Unsupported(reason) => AudioError::Unsupported(String::from(reason)),
+		}
+	}
+}
+
+
+#[cfg(any(feature = "aac", feature = "symphonia"))]
+impl From<symphonia::core::errors::Error> for AudioError {
+	fn from(err: symphonia::core::errors::Error) -> Self {
+		use symphonia::core::errors::Error;
+
+		match err {
+			Error::IoError(err) => AudioError::Io(err),
+			Error::Unsupported(reason) => AudioError::Unsupported(String::from(reason)),
+			err => AudioError::Decode(err.to_string()),
+		}
+	}
+}
\ No newline at end of file
@@ -0,0 +1,56 @@
+use std::time::Duration;
+
+/* PlaybackEvent: Notifications emitted by an AudioCable while it plays. Received through
+ * AudioCable::events().
+ */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PlaybackEvent {
+	// The producer has sent the last sample of its source, or reached its end_at offset. Sent
+	// again at the end of every queued or repeated track.
+	Finished,
+	// The track that just sent Finished was the last: nothing is queued after it, no playlist
+	// carries on and the repeat mode doesn't start anything over, so the cable has run out of
+	// audio, e.g. for an app to queue more from its library or show playback as stopped. Sent once
+	// each time that happens, straight after the last track's Finished.
+	QueueEmpty,
+	// The cable moved on to the next queued track, started a track over for its RepeatMode,
+	// AudioCable::replace_source switched to a new file, or an internet radio station announced a
+	// new AudioCable::stream_title.
+	TrackChanged,
+	// The output ran out of audio and is playing silence, e.g. because decoding can't keep up.
+	// Sent once when it starts, not for every device buffer it lasts.
+	Underrun,
+	// The output is playing audio again after an Underrun.
+	Recovered,
+	// AudioCableBuilder::realtime_priority couldn't raise the output callback's thread, for this
+	// reason, e.g. a Linux user without an RLIMIT_RTPRIO allowance. Playback carries on at the
+	// thread's ordinary priority.
+	PriorityNotRaised(String),
+	// The source moved to this offset from its start for a seek or AudioCable::reset; what plays
+	// next comes from there. Seeks that fail aren't reported.
+	Seeked(Duration),
+	// The decoder panicked with this message and the source is over, e.g. on a malformed file.
+	// The output plays out what was buffered; commands for the source then fail with
+	// AudioError::DecoderPanicked. Only sent with AudioCableBuilder::catch_decoder_panics.
+	DecoderPanicked(String),
+	// The source failed with this message, by panicking or with a decode error, and the watchdog
+	// is opening it again, a moment from now, to carry on where it got to. Sent for every attempt.
+	// Only sent with AudioCableBuilder::watchdog.
+	SourceRestarted(String),
+	// The watchdog ran out of restarts for a source that failed with a decode error, last with this
+	// message, and the cable is finished. A panic is reported as DecoderPanicked instead.
+	SourceFailed(String),
+	// The source decoded with a problem it played on past, e.g. a FLAC file ending short of the
+	// length its header declares or with ID3v2 tags in front of it. The message says what happened.
+	DecodeWarning(String),
+	// The output moved to the new OS default output device with this name. Only sent with
+	// AudioCableBuilder::follow_default_device.
+	DeviceChanged(String),
+	// The stream switched to this many channels midway, e.g. a radio station going from stereo
+	// to mono for a talk segment, or back again. The cable carries on with the channels it started
+	// with, mixing the new layout to them.
+	ChannelsChanged(u16),
+	// AudioCable::cue has filled the buffer, or buffered the whole track if it's shorter, so play
+	// starts the output at once.
+	Cued,
+}
@@ -0,0 +1,248 @@
+use super::md5::Md5;
+use super::{AudioError, FlacOptions};
+
+use flacenc::bitsink::ByteSink;
+use flacenc::component::{BitRepr, StreamInfo};
+use flacenc::error::{Verified, Verify};
+use flacenc::source::{Fill, FrameBuf};
+
+use std::io::{Seek, SeekFrom, Write};
+
+// Size of the STREAMINFO block's body.
+const STREAMINFO_LEN: usize = 34;
+// Frames in flacenc's smallest Rice partition. Its predictors overflow on a block shorter than
+// that, so a last block this short is stored verbatim.
+const MIN_PREDICTED_BLOCK: usize = 64;
+
+/* FlacEncoder: The FLAC side of an AudioRecorder, encoding a fixed-size block at a time with
+ * flacenc and writing each frame out as soon as it's encoded, so a long recording is never held
+ * in memory.
+ *
+ * The STREAMINFO block goes first with the frame count and MD5 still unknown; finish writes it
+ * again with them once the last, shorter block is out.
+ */
+pub(super) struct FlacEncoder {
+	config: Verified<flacenc::config::Encoder>,
+	// The config with prediction turned off, for a last block under MIN_PREDICTED_BLOCK.
+	verbatim_config: Verified<flacenc::config::Encoder>,
+	stream_info: StreamInfo,
+	channels: usize,
+	bits_per_sample: u8,
+	block_size: usize,
+	// Interleaved samples of the block being collected.
+	block: Vec<i32>,
+	frame_buf: FrameBuf,
+	frame_number: usize,
+	md5: Md5,
+	frames: u64,
+	sink: ByteSink,
+}
+
+impl FlacEncoder {
+	pub(super) fn new(sample_rate: u32, channels: u16, options: FlacOptions) -> Result<FlacEncoder, AudioError> {
+		if ![16, 24].contains(&options.bits_per_sample) {
+			return Err(AudioError::InvalidArgument(format!("FLAC recordings are 16 or 24-bit, got {}", options.bits_per_sample)));
+		}
+		if options.compression_level > 8 {
+			return Err(AudioError::InvalidArgument(format!("FLAC compression levels go from 0 to 8, got {}", options.compression_level)));
+		}
+		if channels > 8 {
+			return Err(AudioError::InvalidArgument(format!("FLAC holds at most 8 channels, got {}", channels)));
+		}
+		// Roughly the reference encoder's presets: short blocks and fixed predictors only at the
+		// fast end, then longer blocks and higher LPC orders as the level goes up.
+		let level = options.compression_level;
+		let mut config = flacenc::config::Encoder::default();
+		config.block_size = if level <= 2 { 1152 } else { 4096 };
+		config.stereo_coding.use_leftside = level > 0;
+		config.stereo_coding.use_rightside = level > 0;
+		config.stereo_coding.use_midside = level > 0;
+		config.subframe_coding.use_lpc = level > 2;
+		config.subframe_coding.qlpc.lpc_order = match level {
+			0..=3 => 6,
+			4..=6 => 8,
+			_ => 12
+		};
+		let mut verbatim_config = config.clone();
+		verbatim_config.subframe_coding.use_fixed = false;
+		verbatim_config.subframe_coding.use_lpc = false;
+		let config = config.into_verified()
+			.map_err(|(_, err)| AudioError::InvalidArgument(format!("invalid FLAC encoder settings: {}", err)))?;
+		let verbatim_config = verbatim_config.into_verified()
+			.map_err(|(_, err)| AudioError::InvalidArgument(format!("invalid FLAC encoder settings: {}", err)))?;
+		let block_size = config.block_size;
+		let stream_info = StreamInfo::new(sample_rate as usize, channels as usize, options.bits_per_sample as usize)
+			.map_err(|err| AudioError::InvalidArgument(format!("can't record FLAC at {} Hz: {}", sample_rate, err)))?;
+		let frame_buf = FrameBuf::with_size(channels as usize, block_size)
+			.map_err(|err| AudioError::InvalidArgument(format!("can't record FLAC with {} channels: {}", channels, err)))?;
+		Ok(FlacEncoder {
+			config,
+			verbatim_config,
+			stream_info,
+			channels: channels as usize,
+			bits_per_sample: options.bits_per_sample,
+			block_size,
+			block: Vec::with_capacity(block_size * channels as usize),
+			frame_buf,
+			frame_number: 0,
+			md5: Md5::new(),
+			frames: 0,
+			sink: ByteSink::new(),
+		})
+	}
+
+	// Moves the file to `sample_rate` before anything is encoded, for
+	// AudioRecorder::with_target_rate.
+	pub(super) fn set_sample_rate(&mut self, sample_rate: u32) -> Result<(), AudioError> {
+		if self.frames > 0 || !self.block.is_empty() {
+			return Err(AudioError::InvalidArgument(String::from("the target rate must be set before anything is recorded")));
+		}
+		self.stream_info = StreamInfo::new(sample_rate as usize, self.channels, self.bits_per_sample as usize)
+			.map_err(|err| AudioError::InvalidArgument(format!("can't record FLAC at {} Hz: {}", sample_rate, err)))?;
+		Ok(())
+	}
+
+	// Frames encoded and written so far; the block being collected isn't counted until it's full.
+	pub(super) fn frames(&self) -> u64 {
+		self.frames
+	}
+
+	// Writes the fLaC marker and the STREAMINFO block, the only metadata block.
+	pub(super) fn write_header(&mut self, writer: &mut impl Write) -> Result<(), AudioError> {
+		let mut info = self.stream_info.clone();
+		// STREAMINFO gives the nominal block size, which the last, shorter block doesn't change,
+		// and 0 for frame sizes not known yet.
+		info.set_block_sizes(self.block_size, self.block_size).map_err(encode_error)?;
+		if self.frame_number == 0 {
+			info.set_frame_sizes(0, 0).map_err(encode_error)?;
+		}
+		self.sink.clear();
+		info.write(&mut self.sink).map_err(encode_error)?;
+		writer.write_all(b"fLaC")?;
+		// The last metadata block, of type 0 (STREAMINFO).
+		writer.write_all(&[0x80])?;
+		writer.write_all(&(STREAMINFO_LEN as u32).to_be_bytes()[1..])?;
+		writer.write_all(self.sink.as_slice())?;
+		Ok(())
+	}
+
+	// Encodes interleaved samples, writing every block they fill.
+	pub(super) fn write(&mut self, writer: &mut impl Write, samples: &[f32]) -> Result<(), AudioError> {
+		let scale = (1i32 << (self.bits_per_sample - 1)) as f32;
+		let (min, max) = (-scale, scale - 1.0);
+		for &sample in samples {
+			// Scaled the inverse of how the decoders read FLAC, so decoded sources at the same bit
+			// depth are written back unchanged.
+			self.block.push((sample * scale).round().clamp(min, max) as i32);
+			if self.block.len() == self.block_size * self.channels {
+				self.encode_block(writer)?;
+			}
+		}
+		Ok(())
+	}
+
+	// Encodes what's left, then writes the header again with the length and MD5 of the audio.
+	pub(super) fn finish<W: Write + Seek>(&mut self, writer: &mut W) -> Result<(), AudioError> {
+		// What's left of the block, whole frames only.
+		self.block.truncate(self.block.len() / self.channels * self.channels);
+		if !self.block.is_empty() {
+			self.encode_block(writer)?;
+		}
+		let md5 = std::mem::replace(&mut self.md5, Md5::new());
+		self.stream_info.set_md5_digest(&md5.finish());
+		self.stream_info.set_total_samples(self.frames as usize);
+		writer.seek(SeekFrom::Start(0))?;
+		self.write_header(writer)
+	}
+
+	fn encode_block(&mut self, writer: &mut impl Write) -> Result<(), AudioError> {
+		let frames = self.block.len() / self.channels;
+		if frames != self.frame_buf.size() {
+			self.frame_buf.resize(frames);
+		}
+		self.frame_buf.fill_interleaved(&self.block).map_err(encode_error)?;
+		let bytes = self.bits_per_sample as usize / 8;
+		for sample in &self.block {
+			self.md5.update(&sample.to_le_bytes()[..bytes]);
+		}
+		let config = if frames < MIN_PREDICTED_BLOCK { &self.verbatim_config } else { &self.config };
+		let frame = flacenc::encode_fixed_size_frame(config, &self.frame_buf, self.frame_number, &self.stream_info)
+			.map_err(|err| encode_error(format!("{:?}", err)))?;
+		self.stream_info.update_frame_info(&frame);
+		self.sink.clear();
+		frame.write(&mut self.sink).map_err(encode_error)?;
+		writer.write_all(self.sink.as_slice())?;
+		self.frame_number += 1;
+		self.frames += frames as u64;
+		self.block.clear();
+		Ok(())
+	}
+}
+
+fn encode_error(err: impl std::fmt::Display) -> AudioError {
+	AudioError::Io(std::io::Error::other(format!("FLAC encoding failed: {}", err)))
+}
+
+#[cfg(all(test, feature = "flac"))]
+mod tests {
+	use super::{FlacEncoder, FlacOptions, Md5};
+
+	use std::io::Cursor;
+
+	// Encodes `samples` and decodes them again with claxon, checking the STREAMINFO written last.
+	fn round_trip(samples: &[i32], channels: u16, options: FlacOptions) {
+		let scale = (1i32 << (options.bits_per_sample - 1)) as f32;
+		let interleaved: Vec<f32> = samples.iter().map(|&sample| sample as f32 / scale).collect();
+		let mut encoder = FlacEncoder::new(44100, channels, options).unwrap();
+		let mut file = Cursor::new(Vec::new());
+		encoder.write_header(&mut file).unwrap();
+		// Writes that don't line up with the blocks.
+		for piece in interleaved.chunks(999) {
+			encoder.write(&mut file, piece).unwrap();
+		}
+		encoder.finish(&mut file).unwrap();
+
+		let mut reader = claxon::FlacReader::new(Cursor::new(file.into_inner())).unwrap();
+		let info = reader.streaminfo();
+		assert_eq!((info.sample_rate, info.channels, info.bits_per_sample), (44100, channels as u32, options.bits_per_sample as u32));
+		assert_eq!(info.samples, Some((samples.len() / channels as usize) as u64));
+		let decoded: Vec<i32> = reader.samples().map(Result::unwrap).collect();
+		assert_eq!(decoded, samples);
+	}
+
+	// Noise from a linear congruential generator, `bits` wide.
+	fn noise(len: usize, bits: u8) -> Vec<i32> {
+		let mut state = 1u32;
+		(0..len).map(|_| {
+			state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+			state as i32 >> (32 - bits)
+		}).collect()
+	}
+
+	#[test]
+	fn round_trips() {
+		// Two full 4096-frame blocks and a short one at the end.
+		let frames = 4096 * 2 + 13;
+		for level in [0, 5, 8] {
+			round_trip(&noise(frames * 2, 16), 2, FlacOptions { bits_per_sample: 16, compression_level: level });
+		}
+		round_trip(&noise(frames, 24), 1, FlacOptions { bits_per_sample: 24, compression_level: 5 });
+	}
+
+	#[test]
+	fn md5_of_the_audio() {
+		let samples = noise(5000 * 2, 16);
+		let mut encoder = FlacEncoder::new(48000, 2, FlacOptions::default()).unwrap();
+		let mut file = Cursor::new(Vec::new());
+		encoder.write_header(&mut file).unwrap();
+		encoder.write(&mut file, &samples.iter().map(|&sample| sample as f32 / 32768.0).collect::<Vec<_>>()).unwrap();
+		encoder.finish(&mut file).unwrap();
+
+		let mut md5 = Md5::new();
+		for sample in &samples {
+			md5.update(&(*sample as i16).to_le_bytes());
+		}
+		let reader = claxon::FlacReader::new(Cursor::new(file.into_inner())).unwrap();
+		assert_eq!(reader.streaminfo().md5sum, md5.finish());
+	}
+}
@@ -0,0 +1,93 @@
+use super::PlaybackControl;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+
+// Fraction of the volume kept while another app ducks this one, about -14 dB.
+const DUCK_VOLUME: f32 = 0.2;
+
+/* AudioFocus: A change of Android audio focus, as AudioManager.OnAudioFocusChangeListener
+ * reports it to onAudioFocusChange.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AudioFocus {
+	// AUDIOFOCUS_GAIN: the focus is back, or was granted.
+	Gain,
+	// AUDIOFOCUS_LOSS: another app took the focus for good, e.g. started playing music.
+	Loss,
+	// AUDIOFOCUS_LOSS_TRANSIENT: another app needs the focus for a while, e.g. a phone call.
+	LossTransient,
+	// AUDIOFOCUS_LOSS_TRANSIENT_CAN_DUCK: another app plays something short, e.g. a navigation
+	// prompt, and this one may carry on quietly.
+	LossTransientCanDuck,
+}
+
+impl AudioFocus {
+	// The AudioFocus for one of the AUDIOFOCUS_* values Android passes to onAudioFocusChange, e.g.
+	// forwarded unchanged through JNI. None for values Android doesn't send to listeners.
+	pub fn from_android(focus_change: i32) -> Option<AudioFocus> {
+		match focus_change {
+			1 => Some(AudioFocus::Gain),
+			-1 => Some(AudioFocus::Loss),
+			-2 => Some(AudioFocus::LossTransient),
+			-3 => Some(AudioFocus::LossTransientCanDuck),
+			_ => None
+		}
+	}
+}
+
+/* AudioFocusHandler: Pauses and ducks an AudioCable the way Android expects as the audio focus
+ * comes and goes. Obtained through AudioCable::audio_focus_handler; clones share their state and
+ * can be moved to whichever thread the app's focus listener calls into.
+ *
+ * Requesting the focus is still up to the app (AudioManager.requestAudioFocus); the handler only
+ * reacts to the changes the app hands it.
+ */
+#[derive(Clone)]
+pub struct AudioFocusHandler {
+	control: PlaybackControl,
+	// Set while a transient loss holds a cable that was playing, which the next Gain resumes.
+	paused_by_focus: Arc<AtomicBool>,
+	// The volume from before ducking, restored by the next Gain.
+	ducked_from: Arc<Mutex<Option<f32>>>,
+}
+
+impl AudioFocusHandler {
+	pub(super) fn new(control: PlaybackControl) -> AudioFocusHandler {
+		AudioFocusHandler { control, paused_by_focus: Arc::new(AtomicBool::new(false)), ducked_from: Arc::new(Mutex::new(None)) }
+	}
+
+	// Reacts to `focus`: a permanent loss pauses until the app resumes the cable itself; a
+	// transient loss pauses until the focus comes back; a loss that allows ducking plays on at a
+	// fifth of the volume. Gain undoes whichever of these is in effect.
+	pub fn handle(&self, focus: AudioFocus) {
+		match focus {
+			AudioFocus::Gain => {
+				if let Some(volume) = self.ducked_from.lock().unwrap_or_else(PoisonError::into_inner).take() {
+					self.control.set_volume(volume);
+				}
+				if self.paused_by_focus.swap(false, Ordering::Relaxed) {
+					self.control.resume();
+				}
+			},
+			AudioFocus::Loss => {
+				self.paused_by_focus.store(false, Ordering::Relaxed);
+				self.control.pause();
+			},
+			AudioFocus::LossTransient => {
+				if !self.control.is_paused() {
+					self.paused_by_focus.store(true, Ordering::Relaxed);
+					self.control.pause();
+				}
+			},
+			AudioFocus::LossTransientCanDuck => {
+				let mut ducked_from = self.ducked_from.lock().unwrap_or_else(PoisonError::into_inner);
+				if ducked_from.is_none() {
+					let volume = self.control.volume();
+					*ducked_from = Some(volume);
+					self.control.set_volume(volume * DUCK_VOLUME);
+				}
+			}
+		}
+	}
+}
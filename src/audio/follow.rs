@@ -0,0 +1,247 @@
+use super::{apply_share_mode, build_output_stream, check_rate_supported, find_device, usable_config, AudioChannelMessage, AudioError, CommandSender, ConsumerOptions, OutputStage, PlaybackEvent, PlaybackTimestamp};
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+// How often the OS default output device is checked for a change.
+const DEFAULT_DEVICE_POLL: Duration = Duration::from_millis(500);
+
+enum FollowCommand {
+	Play(crossbeam_channel::Sender<Result<(), AudioError>>),
+	SetSampleRate(u32, crossbeam_channel::Sender<Result<(), AudioError>>),
+}
+
+// The device a followed output plays on, as the cable sees it.
+#[derive(Clone)]
+struct PlayingOn {
+	name: Option<String>,
+	config: cpal::StreamConfig,
+}
+
+/* DefaultDeviceFollower: Keeps a consumer's output on the OS default output device, enabled with
+ * AudioCableBuilder::follow_default_device.
+ *
+ * cpal streams can't leave the thread that built them, so the stream lives on a thread of the
+ * follower's own, which also polls the default device twice a second. When it changes the stream
+ * is rebuilt on the new device with the same channel count, keeping the sample rate where the
+ * device allows it and otherwise retargeting the producer's resampler, and DeviceChanged is sent.
+ * The consumer's play and set_sample_rate are handed to the thread too. It ends when the follower
+ * is dropped.
+ */
+pub(super) struct DefaultDeviceFollower {
+	commands: Option<crossbeam_channel::Sender<FollowCommand>>,
+	playing_on: Arc<Mutex<PlayingOn>>,
+	thread: Option<JoinHandle<()>>,
+}
+
+impl DefaultDeviceFollower {
+	// Starts the thread, which opens the default device again, expecting it to be the one called
+	// `name`, and builds its stream at `config` straight away. The stream stays paused until play.
+	#[allow(clippy::too_many_arguments)]
+	pub(super) fn start(name: Option<String>, config: cpal::StreamConfig, sample_format: cpal::SampleFormat, stage: OutputStage, timestamp: Arc<PlaybackTimestamp>, options: ConsumerOptions, producer: CommandSender, events: crossbeam_channel::Sender<PlaybackEvent>) -> Result<DefaultDeviceFollower, AudioError> {
+		let playing_on = Arc::new(Mutex::new(PlayingOn { name: name.clone(), config: config.clone() }));
+		let (commands, received) = crossbeam_channel::unbounded();
+		let (started, start_result) = crossbeam_channel::bounded(1);
+		let shared = Arc::clone(&playing_on);
+		// Streams, and so FollowedOutput, can't be sent to the thread; it's put together there.
+		let thread = std::thread::Builder::new().name(String::from("audios-default-device")).spawn(move || {
+			let host = cpal::default_host();
+			let device = match &name {
+				Some(name) => host.output_devices().ok().and_then(|devices| find_device(devices, name, "output").ok()),
+				None => host.default_output_device()
+			};
+			let device = match device {
+				Some(device) => device,
+				None => {
+					let _ = started.send(Err(AudioError::Device(String::from("the default output device went away"))));
+					return;
+				}
+			};
+			let mut output = FollowedOutput {
+				options,
+				device,
+				config,
+				sample_format,
+				stream: None,
+				playing: false,
+				default_name: name,
+				stage,
+				timestamp,
+				producer,
+				events,
+				playing_on: shared,
+			};
+			let result = output.build();
+			let failed = result.is_err();
+			let _ = started.send(result);
+			if !failed {
+				output.run(&host, received);
+			}
+		})?;
+		start_result.recv().map_err(|_| AudioError::Device(String::from("the default device thread exited")))??;
+		Ok(DefaultDeviceFollower { commands: Some(commands), playing_on, thread: Some(thread) })
+	}
+
+	pub(super) fn play(&self) -> Result<(), AudioError> {
+		self.request(FollowCommand::Play)
+	}
+
+	// Rebuilds the stream at `sample_rate` on the device it currently plays on.
+	pub(super) fn set_sample_rate(&self, sample_rate: u32) -> Result<(), AudioError> {
+		self.request(|reply| FollowCommand::SetSampleRate(sample_rate, reply))
+	}
+
+	pub(super) fn config(&self) -> Option<cpal::StreamConfig> {
+		Some(self.playing_on.lock().unwrap_or_else(PoisonError::into_inner).config.clone())
+	}
+
+	pub(super) fn device_name(&self) -> Option<String> {
+		self.playing_on.lock().unwrap_or_else(PoisonError::into_inner).name.clone()
+	}
+
+	fn request(&self, command: impl FnOnce(crossbeam_channel::Sender<Result<(), AudioError>>) -> FollowCommand) -> Result<(), AudioError> {
+		let (reply, result) = crossbeam_channel::bounded(1);
+		self.commands.as_ref().ok_or(AudioError::Stopped)?.send(command(reply)).map_err(|_| AudioError::Stopped)?;
+		result.recv().map_err(|_| AudioError::Stopped)?
+	}
+}
+
+impl Drop for DefaultDeviceFollower {
+	fn drop(&mut self) {
+		// Disconnecting the commands ends the thread, and its stream with it.
+		self.commands = None;
+		if let Some(thread) = self.thread.take() {
+			let _ = thread.join();
+		}
+	}
+}
+
+// The follower thread's side: the stream and the device it plays on.
+struct FollowedOutput {
+	options: ConsumerOptions,
+	device: cpal::Device,
+	config: cpal::StreamConfig,
+	sample_format: cpal::SampleFormat,
+	stream: Option<cpal::Stream>,
+	playing: bool,
+	// The default device as last seen, which may be one that couldn't be followed.
+	default_name: Option<String>,
+	// Copied for every stream built.
+	stage: OutputStage,
+	timestamp: Arc<PlaybackTimestamp>,
+	producer: CommandSender,
+	events: crossbeam_channel::Sender<PlaybackEvent>,
+	playing_on: Arc<Mutex<PlayingOn>>,
+}
+
+impl FollowedOutput {
+	fn run(&mut self, host: &cpal::Host, commands: crossbeam_channel::Receiver<FollowCommand>) {
+		loop {
+			match commands.recv_timeout(DEFAULT_DEVICE_POLL) {
+				Ok(FollowCommand::Play(reply)) => {
+					let _ = reply.send(self.play());
+				},
+				Ok(FollowCommand::SetSampleRate(sample_rate, reply)) => {
+					let _ = reply.send(self.set_sample_rate(sample_rate));
+				},
+				Err(crossbeam_channel::RecvTimeoutError::Timeout) => self.follow(host),
+				Err(crossbeam_channel::RecvTimeoutError::Disconnected) => return
+			}
+		}
+	}
+
+	// Replaces the stream with a new one for the current device and configuration. The old one is
+	// closed first, since some devices only take one stream at a time.
+	fn build(&mut self) -> Result<(), AudioError> {
+		self.stream = None;
+		let stage = self.stage.renewed(self.config.sample_rate.0);
+		let stream = build_output_stream(&self.device, &self.config, self.sample_format, stage, &self.timestamp)?;
+		if self.playing {
+			stream.play().map_err(|err| AudioError::Device(format!("failed to start output stream: {}", err)))?;
+		}
+		self.stream = Some(stream);
+		*self.playing_on.lock().unwrap_or_else(PoisonError::into_inner) = PlayingOn { name: self.device.name().ok(), config: self.config.clone() };
+		Ok(())
+	}
+
+	fn play(&mut self) -> Result<(), AudioError> {
+		self.playing = true;
+		match &self.stream {
+			Some(stream) => stream.play().map_err(|err| AudioError::Device(format!("failed to start output stream: {}", err))),
+			None => Ok(())
+		}
+	}
+
+	// Like AudioConsumer::set_sample_rate: the previous rate is restored if the new stream fails.
+	fn set_sample_rate(&mut self, sample_rate: u32) -> Result<(), AudioError> {
+		check_rate_supported(&self.device, &self.config, self.sample_format, sample_rate)?;
+		let previous_rate = self.config.sample_rate;
+		self.config.sample_rate = cpal::SampleRate(sample_rate);
+		if let Err(err) = self.build() {
+			self.config.sample_rate = previous_rate;
+			self.build()?;
+			return Err(err);
+		}
+		Ok(())
+	}
+
+	// Moves the stream to the default device if that has changed since the last poll.
+	fn follow(&mut self, host: &cpal::Host) {
+		let device = match host.default_output_device() {
+			Some(device) => device,
+			None => return
+		};
+		let name = device.name().ok();
+		if name == self.default_name {
+			return;
+		}
+		self.default_name = name.clone();
+		let label = name.as_deref().unwrap_or("<unknown>");
+
+		let (config, sample_format) = match self.config_for(&device) {
+			Some(config) => config,
+			None => {
+				eprintln!("the new default output device \"{}\" can't play {} channels; staying on the previous one", label, self.config.channels);
+				return;
+			}
+		};
+		let previous = (std::mem::replace(&mut self.device, device), std::mem::replace(&mut self.config, config), self.sample_format);
+		let previous_rate = previous.1.sample_rate;
+		self.sample_format = sample_format;
+		if let Err(err) = self.build() {
+			eprintln!("couldn't move the output to the new default device \"{}\": {}", label, err);
+			(self.device, self.config, self.sample_format) = previous;
+			// The previous device may be gone too, e.g. unplugged; then there's nothing to play on
+			// until the next change.
+			if let Err(err) = self.build() {
+				eprintln!("couldn't reopen the previous output device: {}", err);
+			}
+			return;
+		}
+		if self.config.sample_rate != previous_rate {
+			let _ = self.producer.send(AudioChannelMessage::SetOutputRate(self.config.sample_rate.0));
+		}
+		let _ = self.events.send(PlaybackEvent::DeviceChanged(String::from(label)));
+	}
+
+	// A configuration of `device` with the current channel count, in the most preferred sample
+	// format it offers, at the current rate if it can do it and its highest usable one otherwise.
+	fn config_for(&self, device: &cpal::Device) -> Option<(cpal::StreamConfig, cpal::SampleFormat)> {
+		let ranges: Vec<_> = device.supported_output_configs().ok()?
+			.filter(|range| range.channels() == self.config.channels)
+			.collect();
+		let rate = self.config.sample_rate;
+		let supported = self.options.sample_formats.iter().find_map(|&format| {
+			let mut ranges = ranges.iter().filter(|range| range.sample_format() == format);
+			ranges.clone().find(|range| range.min_sample_rate() <= rate && rate <= range.max_sample_rate())
+				.map(|range| range.clone().with_sample_rate(rate))
+				.or_else(|| ranges.find_map(usable_config))
+		})?;
+		let mut config = supported.config();
+		apply_share_mode(self.options.share_mode, device, &supported, &mut config).ok()?;
+		Some((config, supported.sample_format()))
+	}
+}
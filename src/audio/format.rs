@@ -0,0 +1,369 @@
+use super::AudioError;
+
+use std::convert::TryFrom;
+use std::path::Path;
+use std::str::FromStr;
+
+// How many bytes Format::sniff needs from the start of a stream.
+pub(super) const SNIFF_LEN: usize = 12;
+
+// The GUID a Sony Wave64 file starts with, where RIFF has its four letters.
+pub(super) const WAVE64_RIFF: [u8; 16] = [0x72, 0x69, 0x66, 0x66, 0x2e, 0x91, 0xcf, 0x11, 0xa5, 0xd6, 0x28, 0xdb, 0x04, 0xc1, 0x00, 0x00];
+
+// Every extension Format::from_path recognises, in lower case, with its format.
+const EXTENSIONS: &[(&str, Format)] = &[
+	("flac", Format::FLAC),
+	("aac", Format::AAC),
+	("m4a", Format::AAC),
+	("mp1", Format::MP3),
+	("mp2", Format::MP3),
+	("mp3", Format::MP3),
+	("mp4", Format::MP4),
+	("wav", Format::WAV),
+	("wave", Format::WAV),
+	("w64", Format::WAV),
+	("aif", Format::AIFF),
+	("aiff", Format::AIFF),
+	("caf", Format::CAF),
+	("ogg", Format::OGG),
+	("oga", Format::OGG),
+	("mka", Format::MKV),
+	("mkv", Format::MKV),
+	("webm", Format::MKV),
+];
+
+/* Format: Audio file formats recognised by extension.
+ *
+ * Every format is always listed, but only those whose decoder was compiled in convert
+ * successfully; the others report the cargo feature that would enable them. WavPack (.wv) isn't
+ * listed, as neither claxon nor symphonia decodes it; a .wv file fails with Unsupported.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+	// .flac
+	FLAC,
+	// .aac (ADTS) and .m4a. With the symphonia feature .m4a files may also hold ALAC.
+	AAC,
+	// The formats below need the symphonia feature.
+	// .mp1, .mp2 and .mp3
+	MP3,
+	// .mp4
+	MP4,
+	// .wav, .wave and .w64, in plain RIFF, RF64, BW64 or Sony Wave64 containers
+	WAV,
+	// .aif and .aiff
+	AIFF,
+	// .caf
+	CAF,
+	// .ogg and .oga (Vorbis)
+	OGG,
+	// .mka, .mkv and .webm
+	MKV,
+}
+
+impl Format {
+	// Identifies the format of `path` from its extension, ignoring case. Fails with InvalidPath
+	// when there is no extension and UnsupportedFormat when the extension is unknown or its
+	// decoder wasn't built.
+	pub fn from_path(path: &Path) -> Result<Format, AudioError> {
+		let extension = match path.extension() {
+			Some(extension) if !extension.is_empty() => extension.to_string_lossy().to_lowercase(),
+			_ => return Err(AudioError::InvalidPath(path.display().to_string()))
+		};
+
+		let format = match Format::from_extension(&extension) {
+			Some(format) => format,
+			None => return Err(AudioError::UnsupportedFormat { extension, feature: None })
+		};
+
+		if !format.is_enabled() {
+			return Err(AudioError::UnsupportedFormat { extension, feature: Some(format.feature()) });
+		}
+		Ok(format)
+	}
+
+	// Extensions are matched in lower case.
+	fn from_extension(extension: &str) -> Option<Format> {
+		EXTENSIONS.iter().find(|&&(known, _)| known == extension).map(|&(_, format)| format)
+	}
+
+	// Identifies the format from the first bytes of a stream, for sources without a file name
+	// such as a pipe. SNIFF_LEN bytes are enough for every signature checked, though a shorter
+	// stream is still matched as far as it goes. MP3 files starting with an ID3 tag are recognised
+	// by the tag, and headerless MP3 and ADTS AAC by their frame sync.
+	pub(super) fn sniff(head: &[u8]) -> Option<Format> {
+		let at = |offset: usize, magic: &[u8]| head.get(offset..offset + magic.len()) == Some(magic);
+		if at(0, b"fLaC") {
+			Some(Format::FLAC)
+		} else if (at(0, b"RIFF") || at(0, b"RF64") || at(0, b"BW64")) && at(8, b"WAVE") {
+			Some(Format::WAV)
+		} else if at(0, &WAVE64_RIFF[..SNIFF_LEN]) {
+			// Wave64 starts with a GUID, whose first 12 bytes are as good a signature.
+			Some(Format::WAV)
+		} else if at(0, b"FORM") && (at(8, b"AIFF") || at(8, b"AIFC")) {
+			Some(Format::AIFF)
+		} else if at(0, b"caff") {
+			Some(Format::CAF)
+		} else if at(0, b"OggS") {
+			Some(Format::OGG)
+		} else if at(0, &[0x1a, 0x45, 0xdf, 0xa3]) {
+			Some(Format::MKV)
+		} else if at(4, b"ftyp") {
+			// Audio-only MPEG-4 files say so in their major brand.
+			if at(8, b"M4A ") || at(8, b"M4B ") { Some(Format::AAC) } else { Some(Format::MP4) }
+		} else if at(0, b"ID3") {
+			Some(Format::MP3)
+		} else if head.len() >= 2 && head[0] == 0xff && head[1] & 0xe0 == 0xe0 {
+			// ADTS is the MPEG frame sync with the layer bits set to 0.
+			if head[1] & 0x06 == 0 { Some(Format::AAC) } else { Some(Format::MP3) }
+		} else {
+			None
+		}
+	}
+
+	// Identifies the format from a Content-Type header, as sent by internet radio servers.
+	// Parameters such as "; charset=..." are ignored.
+	pub(super) fn from_mime_type(mime_type: &str) -> Option<Format> {
+		let mime_type = mime_type.split(';').next().unwrap_or_default().trim().to_lowercase();
+		match mime_type.as_str() {
+			"audio/flac" | "audio/x-flac" => Some(Format::FLAC),
+			"audio/aac" | "audio/aacp" | "audio/x-aac" | "audio/mp4" => Some(Format::AAC),
+			"audio/mpeg" | "audio/mp3" => Some(Format::MP3),
+			"audio/wav" | "audio/x-wav" | "audio/wave" => Some(Format::WAV),
+			"audio/ogg" | "application/ogg" => Some(Format::OGG),
+			"audio/webm" | "audio/x-matroska" => Some(Format::MKV),
+			_ => None
+		}
+	}
+
+	// The usual extension of the format, used where there is no file name to take it from.
+	pub(super) fn extension(self) -> &'static str {
+		match self {
+			Format::FLAC => "flac",
+			Format::AAC => "m4a",
+			Format::MP3 => "mp3",
+			Format::MP4 => "mp4",
+			Format::WAV => "wav",
+			Format::AIFF => "aiff",
+			Format::CAF => "caf",
+			Format::OGG => "ogg",
+			Format::MKV => "mka",
+		}
+	}
+
+	// The cargo feature that adds a decoder for this format.
+	pub(super) fn feature(self) -> &'static str {
+		match self {
+			Format::FLAC => "flac",
+			Format::AAC => "aac",
+			_ => "symphonia"
+		}
+	}
+
+	pub(super) fn is_enabled(self) -> bool {
+		cfg!(feature = "symphonia")
+			|| (self == Format::FLAC && cfg!(feature = "flac"))
+			|| (self == Format::AAC && cfg!(feature = "aac"))
+	}
+}
+
+// The extensions, without the dot and in lower case, of the files this build can play: those of
+// every format whose decoder was compiled in, e.g. just "flac" with the default features. Meant
+// for file dialog filters and the like; Format::from_path accepts exactly these.
+pub fn supported_extensions() -> Vec<&'static str> {
+	EXTENSIONS.iter().filter(|(_, format)| format.is_enabled()).map(|&(extension, _)| extension).collect()
+}
+
+impl TryFrom<&Path> for Format {
+	type Error = AudioError;
+
+	fn try_from(path: &Path) -> Result<Self, Self::Error> {
+		Format::from_path(path)
+	}
+}
+
+// Parses any extension from_path knows, with or without the leading dot and ignoring case, e.g.
+// for a command line option forcing the format. Unlike from_path this doesn't check that the
+// format's decoder was built; a disabled format is reported once a cable tries to decode it.
+impl FromStr for Format {
+	type Err = AudioError;
+
+	fn from_str(name: &str) -> Result<Self, Self::Err> {
+		let extension = name.strip_prefix('.').unwrap_or(name).to_lowercase();
+		Format::from_extension(&extension).ok_or_else(|| AudioError::InvalidArgument(format!(
+			"unknown audio format \"{}\", expected one of flac, aac, m4a, mp3, mp4, wav, aiff, caf, ogg or mka", name
+		)))
+	}
+}
+
+/* PcmFormat: Sample encodings of raw, headerless PCM, for AudioCableBuilder::from_raw_pcm.
+ *
+ * Raw dumps carry no header saying how they were written, so the byte order is part of the
+ * format rather than assumed to be the machine's own: network protocols such as RTP L16 send
+ * big-endian samples. 24-bit samples are packed into three bytes.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PcmFormat {
+	// Unsigned 8-bit, centred on 128.
+	U8,
+	S16LE,
+	S16BE,
+	S24LE,
+	S24BE,
+	S32LE,
+	S32BE,
+	F32LE,
+	F32BE,
+}
+
+impl PcmFormat {
+	pub fn bytes_per_sample(self) -> usize {
+		match self {
+			PcmFormat::U8 => 1,
+			PcmFormat::S16LE | PcmFormat::S16BE => 2,
+			PcmFormat::S24LE | PcmFormat::S24BE => 3,
+			PcmFormat::S32LE | PcmFormat::S32BE | PcmFormat::F32LE | PcmFormat::F32BE => 4,
+		}
+	}
+
+	fn is_big_endian(self) -> bool {
+		matches!(self, PcmFormat::S16BE | PcmFormat::S24BE | PcmFormat::S32BE | PcmFormat::F32BE)
+	}
+
+	// Converts the encoded samples in `bytes`, a whole number of them, to f32 in [-1.0, 1.0) and
+	// appends them to `output`. Every encoding goes through the same loop; the match only picks
+	// which PcmSample reads it.
+	pub(super) fn decode_into(self, bytes: &[u8], output: &mut Vec<f32>) {
+		let big_endian = self.is_big_endian();
+		match self {
+			PcmFormat::U8 => decode_samples::<u8>(bytes, big_endian, output),
+			PcmFormat::S16LE | PcmFormat::S16BE => decode_samples::<i16>(bytes, big_endian, output),
+			PcmFormat::S24LE | PcmFormat::S24BE => decode_samples::<Packed24>(bytes, big_endian, output),
+			PcmFormat::S32LE | PcmFormat::S32BE => decode_samples::<i32>(bytes, big_endian, output),
+			PcmFormat::F32LE | PcmFormat::F32BE => decode_samples::<f32>(bytes, big_endian, output),
+		}
+	}
+}
+
+/* PcmSample: A type PCM samples are stored as, one per bit depth, for decode_samples. Each only
+ * says how its bytes are read and how it scales to f32.
+ */
+trait PcmSample: Copy {
+	// Bytes per stored sample.
+	const BYTES: usize;
+
+	// Reads a sample from exactly BYTES bytes.
+	fn read(bytes: &[u8], big_endian: bool) -> Self;
+
+	fn to_f32(self) -> f32;
+}
+
+// Unsigned 8-bit, centred on 128; there's no byte order to speak of.
+impl PcmSample for u8 {
+	const BYTES: usize = 1;
+
+	fn read(bytes: &[u8], _: bool) -> u8 {
+		bytes[0]
+	}
+
+	fn to_f32(self) -> f32 {
+		(self as f32 - 128.0) / 128.0
+	}
+}
+
+impl PcmSample for i16 {
+	const BYTES: usize = 2;
+
+	fn read(bytes: &[u8], big_endian: bool) -> i16 {
+		let bytes = [bytes[0], bytes[1]];
+		if big_endian { i16::from_be_bytes(bytes) } else { i16::from_le_bytes(bytes) }
+	}
+
+	fn to_f32(self) -> f32 {
+		self as f32 / 32768.0
+	}
+}
+
+// A 24-bit sample packed into three bytes, held sign-extended.
+#[derive(Clone, Copy)]
+struct Packed24(i32);
+
+impl PcmSample for Packed24 {
+	const BYTES: usize = 3;
+
+	fn read(bytes: &[u8], big_endian: bool) -> Packed24 {
+		Packed24(unpack_s24([bytes[0], bytes[1], bytes[2]], big_endian))
+	}
+
+	fn to_f32(self) -> f32 {
+		self.0 as f32 / 8388608.0
+	}
+}
+
+impl PcmSample for i32 {
+	const BYTES: usize = 4;
+
+	fn read(bytes: &[u8], big_endian: bool) -> i32 {
+		let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+		if big_endian { i32::from_be_bytes(bytes) } else { i32::from_le_bytes(bytes) }
+	}
+
+	fn to_f32(self) -> f32 {
+		self as f32 / 2147483648.0
+	}
+}
+
+impl PcmSample for f32 {
+	const BYTES: usize = 4;
+
+	fn read(bytes: &[u8], big_endian: bool) -> f32 {
+		let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+		if big_endian { f32::from_be_bytes(bytes) } else { f32::from_le_bytes(bytes) }
+	}
+
+	fn to_f32(self) -> f32 {
+		self
+	}
+}
+
+// The one read loop behind PcmFormat::decode_into: `bytes` as a run of S, converted to f32.
+fn decode_samples<S: PcmSample>(bytes: &[u8], big_endian: bool, output: &mut Vec<f32>) {
+	output.reserve(bytes.len() / S::BYTES);
+	output.extend(bytes.chunks_exact(S::BYTES).map(|sample| S::read(sample, big_endian).to_f32()));
+}
+
+// Reads a packed 24-bit two's complement sample, as stored in 24-bit WAV (little endian) and AIFF
+// (big endian) files. The three bytes go into the top of an i32 and are shifted back down, so the
+// arithmetic shift carries the sign bit: [0xff, 0xff, 0xff] is -1, not 16777215.
+pub(super) fn unpack_s24(bytes: [u8; 3], big_endian: bool) -> i32 {
+	let [high, middle, low] = if big_endian { bytes } else { [bytes[2], bytes[1], bytes[0]] };
+	i32::from_be_bytes([high, middle, low, 0]) >> 8
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{unpack_s24, PcmFormat};
+
+	#[test]
+	fn s16_byte_order() {
+		// 0x0100 read big endian, 0x0001 read little endian.
+		let bytes = [0x01, 0x00];
+		let mut big_endian = Vec::new();
+		PcmFormat::S16BE.decode_into(&bytes, &mut big_endian);
+		let mut little_endian = Vec::new();
+		PcmFormat::S16LE.decode_into(&bytes, &mut little_endian);
+		assert_eq!(big_endian, [256.0 / 32768.0]);
+		assert_eq!(little_endian, [1.0 / 32768.0]);
+	}
+
+	#[test]
+	fn s24_sign_and_bounds() {
+		for big_endian in [false, true] {
+			// The bytes are written big endian and reversed for little endian.
+			let order = |bytes: [u8; 3]| if big_endian { bytes } else { [bytes[2], bytes[1], bytes[0]] };
+			assert_eq!(unpack_s24(order([0xff, 0xff, 0xff]), big_endian), -1);
+			assert_eq!(unpack_s24(order([0x7f, 0xff, 0xff]), big_endian), 0x7fffff);
+			assert_eq!(unpack_s24(order([0x80, 0x00, 0x00]), big_endian), -0x800000);
+		}
+	}
+}
@@ -0,0 +1,162 @@
+use super::render::DecodedBlocks;
+use super::AudioError;
+
+use std::path::Path;
+
+// Parameters of the two K-weighting stages from ITU-R BS.1770, given as analog prototypes so
+// they can be matched at any sample rate: a high shelf modelling the head, then a high-pass.
+const SHELF_FREQUENCY: f64 = 1681.974450955533;
+const SHELF_GAIN_DB: f64 = 3.999843853973347;
+const SHELF_Q: f64 = 0.7071752369554196;
+const HIGH_PASS_FREQUENCY: f64 = 38.13547087602444;
+const HIGH_PASS_Q: f64 = 0.5003270373238773;
+
+// Gating blocks are 400 ms long and start every 100 ms, i.e. overlap by 75%.
+const BLOCK_SEGMENTS: usize = 4;
+const SEGMENT_SECONDS: f64 = 0.1;
+// Blocks quieter than this are left out of the measurement altogether.
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+// Blocks this far below the loudness of the blocks passing the absolute gate are left out too.
+const RELATIVE_GATE_LU: f64 = -10.0;
+
+// Direct form I biquad, run in f64 so the low high-pass corner stays accurate.
+#[derive(Clone, Copy)]
+struct Biquad {
+	b: [f64; 3],
+	a: [f64; 2],
+	x: [f64; 2],
+	y: [f64; 2],
+}
+
+impl Biquad {
+	fn new(b: [f64; 3], a: [f64; 2]) -> Biquad {
+		Biquad { b, a, x: [0.0; 2], y: [0.0; 2] }
+	}
+
+	fn shelf(sample_rate: u32) -> Biquad {
+		let k = (std::f64::consts::PI * SHELF_FREQUENCY / sample_rate as f64).tan();
+		let vh = 10f64.powf(SHELF_GAIN_DB / 20.0);
+		let vb = vh.powf(0.4996667741545416);
+		let a0 = 1.0 + k / SHELF_Q + k * k;
+		Biquad::new(
+			[(vh + vb * k / SHELF_Q + k * k) / a0, 2.0 * (k * k - vh) / a0, (vh - vb * k / SHELF_Q + k * k) / a0],
+			[2.0 * (k * k - 1.0) / a0, (1.0 - k / SHELF_Q + k * k) / a0],
+		)
+	}
+
+	fn high_pass(sample_rate: u32) -> Biquad {
+		let k = (std::f64::consts::PI * HIGH_PASS_FREQUENCY / sample_rate as f64).tan();
+		let a0 = 1.0 + k / HIGH_PASS_Q + k * k;
+		Biquad::new([1.0, -2.0, 1.0], [2.0 * (k * k - 1.0) / a0, (1.0 - k / HIGH_PASS_Q + k * k) / a0])
+	}
+
+	fn process(&mut self, x: f64) -> f64 {
+		let y = self.b[0] * x + self.b[1] * self.x[0] + self.b[2] * self.x[1] - self.a[0] * self.y[0] - self.a[1] * self.y[1];
+		self.x = [x, self.x[0]];
+		self.y = [y, self.y[0]];
+		y
+	}
+}
+
+/* LoudnessMeter: Integrated loudness of interleaved audio per ITU-R BS.1770-4, fed block by
+ * block.
+ *
+ * Each channel is K-weighted and its mean square taken over 400 ms gating blocks, weighted by
+ * its position: 1.0 for front channels and 1.41 for the surrounds of a 6-channel (5.1) stream,
+ * whose LFE channel doesn't count. Every other layout weights all channels equally.
+ */
+struct LoudnessMeter {
+	channels: usize,
+	weights: Vec<f64>,
+	filters: Vec<[Biquad; 2]>,
+	segment_frames: usize,
+	// Frames and weighted sum of squares of the segment being filled.
+	frames: usize,
+	energy: f64,
+	// Weighted energy of the latest segments, oldest first, for the block that ends with them.
+	segments: Vec<f64>,
+	// Mean weighted square of every gating block so far.
+	blocks: Vec<f64>,
+}
+
+impl LoudnessMeter {
+	fn new(sample_rate: u32, channels: u16) -> LoudnessMeter {
+		let channels = channels.max(1) as usize;
+		let weights = match channels {
+			6 => vec![1.0, 1.0, 1.0, 0.0, 1.41, 1.41],
+			_ => vec![1.0; channels]
+		};
+		LoudnessMeter {
+			channels,
+			weights,
+			filters: vec![[Biquad::shelf(sample_rate), Biquad::high_pass(sample_rate)]; channels],
+			segment_frames: ((sample_rate as f64 * SEGMENT_SECONDS).round() as usize).max(1),
+			frames: 0,
+			energy: 0.0,
+			segments: Vec::with_capacity(BLOCK_SEGMENTS),
+			blocks: Vec::new(),
+		}
+	}
+
+	fn process(&mut self, samples: &[f32]) {
+		for frame in samples.chunks_exact(self.channels) {
+			for (channel, &sample) in frame.iter().enumerate() {
+				let [shelf, high_pass] = &mut self.filters[channel];
+				let weighted = high_pass.process(shelf.process(sample as f64));
+				self.energy += self.weights[channel] * weighted * weighted;
+			}
+			self.frames += 1;
+
+			if self.frames == self.segment_frames {
+				if self.segments.len() == BLOCK_SEGMENTS {
+					self.segments.remove(0);
+				}
+				self.segments.push(self.energy);
+				if self.segments.len() == BLOCK_SEGMENTS {
+					let block_frames = (BLOCK_SEGMENTS * self.segment_frames) as f64;
+					self.blocks.push(self.segments.iter().sum::<f64>() / block_frames);
+				}
+				self.frames = 0;
+				self.energy = 0.0;
+			}
+		}
+	}
+
+	// Gated loudness in LUFS, or negative infinity when no block passes the gates, e.g. for
+	// silence or audio shorter than one 400 ms block.
+	fn integrated(&self) -> f32 {
+		let absolute_gate = power(ABSOLUTE_GATE_LUFS);
+		let relative_gate = match mean(self.blocks.iter().copied().filter(|&block| block > absolute_gate)) {
+			Some(ungated) => power(loudness(ungated) + RELATIVE_GATE_LU).max(absolute_gate),
+			None => return f32::NEG_INFINITY
+		};
+		mean(self.blocks.iter().copied().filter(|&block| block > relative_gate))
+			.map_or(f32::NEG_INFINITY, |gated| loudness(gated) as f32)
+	}
+}
+
+fn loudness(power: f64) -> f64 {
+	-0.691 + 10.0 * power.log10()
+}
+
+fn power(loudness: f64) -> f64 {
+	10f64.powf((loudness + 0.691) / 10.0)
+}
+
+fn mean(values: impl Iterator<Item = f64>) -> Option<f64> {
+	let (sum, count) = values.fold((0.0, 0usize), |(sum, count), value| (sum + value, count + 1));
+	if count == 0 { None } else { Some(sum / count as f64) }
+}
+
+// Measures the integrated loudness of the file at `path` in LUFS per ITU-R BS.1770, e.g. to
+// normalise a library to a target such as -23 LUFS (EBU R 128) or -14 LUFS. The whole file is
+// decoded without an output device, one block at a time. Returns negative infinity for files
+// that are silent or shorter than 400 ms.
+pub fn measure_loudness(path: &Path) -> Result<f32, AudioError> {
+	let blocks = DecodedBlocks::open(path)?;
+	let mut meter = LoudnessMeter::new(blocks.sample_rate(), blocks.channels());
+	for block in blocks {
+		meter.process(&block?);
+	}
+	Ok(meter.integrated())
+}
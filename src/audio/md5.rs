@@ -0,0 +1,137 @@
+// Per-round left rotations and additive constants of RFC 1321.
+const SHIFTS: [u32; 64] = [
+	7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22,
+	5, 9, 14, 20, 5, 9, 14, 20, 5, 9, 14, 20, 5, 9, 14, 20,
+	4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23,
+	6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21,
+];
+const CONSTANTS: [u32; 64] = [
+	0xd76aa478, 0xe8c7b756, 0x242070db, 0xc1bdceee, 0xf57c0faf, 0x4787c62a, 0xa8304613, 0xfd469501,
+	0x698098d8, 0x8b44f7af, 0xffff5bb1, 0x895cd7be, 0x6b901122, 0xfd987193, 0xa679438e, 0x49b40821,
+	0xf61e2562, 0xc040b340, 0x265e5a51, 0xe9b6c7aa, 0xd62f105d, 0x02441453, 0xd8a1e681, 0xe7d3fbc8,
+	0x21e1cde6, 0xc33707d6, 0xf4d50d87, 0x455a14ed, 0xa9e3e905, 0xfcefa3f8, 0x676f02d9, 0x8d2a4c8a,
+	0xfffa3942, 0x8771f681, 0x6d9d6122, 0xfde5380c, 0xa4beea44, 0x4bdecfa9, 0xf6bb4b60, 0xbebfbc70,
+	0x289b7ec6, 0xeaa127fa, 0xd4ef3085, 0x04881d05, 0xd9d4d039, 0xe6db99e5, 0x1fa27cf8, 0xc4ac5665,
+	0xf4292244, 0x432aff97, 0xab9423a7, 0xfc93a039, 0x655b59c3, 0x8f0ccc92, 0xffeff47d, 0x85845dd1,
+	0x6fa87e4f, 0xfe2ce6e0, 0xa3014314, 0x4e0811a1, 0xf7537e82, 0xbd3af235, 0x2ad7d2bb, 0xeb86d391,
+];
+const BLOCK_LEN: usize = 64;
+
+/* Md5: The MD5 digest of RFC 1321, for checking a FLAC file's audio against the MD5 its
+ * STREAMINFO declares. Not for anything security-related: MD5 collisions are easy to make.
+ */
+pub(crate) struct Md5 {
+	state: [u32; 4],
+	// The start of a block yet to be filled.
+	block: [u8; BLOCK_LEN],
+	// Bytes hashed so far.
+	len: u64,
+}
+
+impl Md5 {
+	pub(crate) fn new() -> Md5 {
+		Md5 { state: [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476], block: [0; BLOCK_LEN], len: 0 }
+	}
+
+	pub(crate) fn update(&mut self, mut bytes: &[u8]) {
+		let filled = (self.len % BLOCK_LEN as u64) as usize;
+		self.len += bytes.len() as u64;
+		if filled > 0 {
+			let take = bytes.len().min(BLOCK_LEN - filled);
+			self.block[filled..filled + take].copy_from_slice(&bytes[..take]);
+			bytes = &bytes[take..];
+			if filled + take < BLOCK_LEN {
+				return;
+			}
+			let block = self.block;
+			self.compress(&block);
+		}
+		let mut blocks = bytes.chunks_exact(BLOCK_LEN);
+		for block in &mut blocks {
+			self.compress(block);
+		}
+		let rest = blocks.remainder();
+		self.block[..rest.len()].copy_from_slice(rest);
+	}
+
+	pub(crate) fn finish(mut self) -> [u8; 16] {
+		let bits = self.len.wrapping_mul(8);
+		// A 1 bit, zeros up to 8 bytes short of a block, then the length in bits.
+		let padding = (BLOCK_LEN * 2 - 8 - (self.len % BLOCK_LEN as u64) as usize - 1) % BLOCK_LEN + 1;
+		let mut tail = [0u8; BLOCK_LEN + 8];
+		tail[0] = 0x80;
+		tail[padding..padding + 8].copy_from_slice(&bits.to_le_bytes());
+		self.update(&tail[..padding + 8]);
+
+		let mut digest = [0u8; 16];
+		for (bytes, word) in digest.chunks_exact_mut(4).zip(self.state) {
+			bytes.copy_from_slice(&word.to_le_bytes());
+		}
+		digest
+	}
+
+	fn compress(&mut self, block: &[u8]) {
+		let mut words = [0u32; 16];
+		for (word, bytes) in words.iter_mut().zip(block.chunks_exact(4)) {
+			*word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+		}
+		let [mut a, mut b, mut c, mut d] = self.state;
+		for round in 0..64 {
+			let (f, word) = match round / 16 {
+				0 => ((b & c) | (!b & d), round),
+				1 => ((d & b) | (!d & c), (5 * round + 1) % 16),
+				2 => (b ^ c ^ d, (3 * round + 5) % 16),
+				_ => (c ^ (b | !d), (7 * round) % 16)
+			};
+			let rotated = a.wrapping_add(f).wrapping_add(CONSTANTS[round]).wrapping_add(words[word]).rotate_left(SHIFTS[round]);
+			a = d;
+			d = c;
+			c = b;
+			b = b.wrapping_add(rotated);
+		}
+		for (state, value) in self.state.iter_mut().zip([a, b, c, d]) {
+			*state = state.wrapping_add(value);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::Md5;
+
+	// The test suite of RFC 1321, appendix A.5.
+	const VECTORS: [(&str, &str); 7] = [
+		("", "d41d8cd98f00b204e9800998ecf8427e"),
+		("a", "0cc175b9c0f1b6a831c399e269772661"),
+		("abc", "900150983cd24fb0d6963f7d28e17f72"),
+		("message digest", "f96b697d7cb7938d525a2f31aaf161d0"),
+		("abcdefghijklmnopqrstuvwxyz", "c3fcd3d76192e4007dfb496cca67e13b"),
+		("ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789", "d174ab98d277d9f5a5611c2c9f419d9f"),
+		("12345678901234567890123456789012345678901234567890123456789012345678901234567890", "57edf4a22be3c955ac49da2e2107b67a"),
+	];
+
+	fn hex(digest: [u8; 16]) -> String {
+		digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+	}
+
+	#[test]
+	fn rfc_1321_vectors() {
+		for (message, digest) in VECTORS {
+			let mut md5 = Md5::new();
+			md5.update(message.as_bytes());
+			assert_eq!(hex(md5.finish()), digest, "{:?}", message);
+		}
+	}
+
+	#[test]
+	fn split_updates() {
+		// Pieces that straddle the 64-byte blocks hash the same as the message in one go.
+		for (message, digest) in VECTORS {
+			let mut md5 = Md5::new();
+			for piece in message.as_bytes().chunks(7) {
+				md5.update(piece);
+			}
+			assert_eq!(hex(md5.finish()), digest, "{:?}", message);
+		}
+	}
+}
@@ -0,0 +1,126 @@
+use super::{AudioError, SourceType};
+
+use std::path::Path;
+use std::sync::{Mutex, PoisonError};
+
+/* TrackMetadata: Information about the source beyond its audio, read while it was opened or, for
+ * live streams, as it arrives. Returned by AudioCable::metadata() and pushed to the receivers of
+ * AudioCable::subscribe_metadata().
+ */
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TrackMetadata {
+	// The bext chunk of a Broadcast Wave file; None for any other file, plain WAVs included.
+	pub broadcast: Option<BroadcastInfo>,
+	// The now-playing title of an internet radio stream, as its ICY metadata last gave it.
+	pub stream_title: Option<String>,
+}
+
+/* MetadataFeed: The metadata of the source being played, shared between the cable and whatever
+ * updates it mid-stream, and the subscribers it's pushed to whenever it changes.
+ */
+pub(super) struct MetadataFeed {
+	state: Mutex<FeedState>,
+}
+
+struct FeedState {
+	current: TrackMetadata,
+	subscribers: Vec<crossbeam_channel::Sender<TrackMetadata>>,
+}
+
+impl MetadataFeed {
+	pub(super) fn new() -> MetadataFeed {
+		MetadataFeed { state: Mutex::new(FeedState { current: TrackMetadata::default(), subscribers: Vec::new() }) }
+	}
+
+	pub(super) fn current(&self) -> TrackMetadata {
+		self.state.lock().unwrap_or_else(PoisonError::into_inner).current.clone()
+	}
+
+	// Applies `update` and, if that changed anything, sends the result to every subscriber still
+	// listening. Returns whether it changed.
+	pub(super) fn update(&self, update: impl FnOnce(&mut TrackMetadata)) -> bool {
+		let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+		let mut metadata = state.current.clone();
+		update(&mut metadata);
+		if metadata == state.current {
+			return false;
+		}
+		state.subscribers.retain(|subscriber| subscriber.send(metadata.clone()).is_ok());
+		state.current = metadata;
+		true
+	}
+
+	// A receiver for every change from now on, starting with the current metadata unless there
+	// is none yet.
+	pub(super) fn subscribe(&self) -> crossbeam_channel::Receiver<TrackMetadata> {
+		let (sender, receiver) = crossbeam_channel::unbounded();
+		let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+		if state.current != TrackMetadata::default() {
+			let _ = sender.send(state.current.clone());
+		}
+		state.subscribers.push(sender);
+		receiver
+	}
+}
+
+/* BroadcastInfo: The origination details of a Broadcast Wave (BWF, EBU Tech 3285) file. Text
+ * fields are empty when the file leaves them blank.
+ */
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BroadcastInfo {
+	pub description: String,
+	// Who made the recording, and their reference for it.
+	pub originator: String,
+	pub originator_reference: String,
+	// As written in the file, yyyy-mm-dd and hh:mm:ss.
+	pub origination_date: String,
+	pub origination_time: String,
+	// First sample of the file counted from midnight, at the file's sample rate.
+	pub time_reference: u64,
+	// Version of the bext chunk; loudness is only defined from version 2.
+	pub version: u16,
+	pub loudness: Option<BroadcastLoudness>,
+	// One line per process the audio went through, e.g. "A=PCM,F=48000,W=24,M=stereo".
+	pub coding_history: String,
+}
+
+/* BroadcastLoudness: The EBU R 128 measurements of a version 2 bext chunk. Each is None when the
+ * file marks it as not measured.
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BroadcastLoudness {
+	// Integrated loudness in LUFS.
+	pub integrated: Option<f32>,
+	// Loudness range in LU.
+	pub range: Option<f32>,
+	// Maximum true peak in dBTP.
+	pub max_true_peak: Option<f32>,
+	// Highest momentary and short-term loudness in LUFS.
+	pub max_momentary: Option<f32>,
+	pub max_short_term: Option<f32>,
+}
+
+/* AudioTrackInfo: One of the audio tracks in a file, e.g. a language of a film's soundtrack in an
+ * MP4 or Matroska file. Listed by available_audio_tracks and AudioCable::audio_tracks, and picked
+ * with AudioCableBuilder::audio_track or audio_track_language. Files of other formats list their
+ * only track.
+ */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AudioTrackInfo {
+	// Position among the file's audio tracks, counting from 0.
+	pub index: usize,
+	// The language tag the container gives it, e.g. "eng" or "de".
+	pub language: Option<String>,
+	pub sample_rate: Option<u32>,
+	pub channels: Option<u16>,
+	// Whether the container marks it as the default, which is played unless another is picked.
+	pub default: bool,
+}
+
+// The audio tracks in the file at `path`, read from its header without decoding any audio.
+pub fn available_audio_tracks(path: &Path) -> Result<Vec<AudioTrackInfo>, AudioError> {
+	match SourceType::from_local(path.to_string_lossy().into_owned())?.open()? {
+		Some(source) => Ok(source.audio_tracks()),
+		None => Ok(Vec::new())
+	}
+}
@@ -0,0 +1,277 @@
+use super::{send_pending, AudioConsumer, AudioError, ResampleQuality, Resampler, SendProgress, Splitter, StaleSampleDrain, PRODUCER_POLL_INTERVAL};
+
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+// The largest ratio adjustment drift correction applies, as a fraction of the nominal rate. Real
+// sound card clocks are within a few hundred ppm of each other.
+const MAX_DRIFT_CORRECTION: f64 = 0.002;
+// Proportional and integral gains of the drift controller, per unit of relative fill error.
+const DRIFT_KP: f64 = 0.016;
+const DRIFT_KI: f64 = 0.000008;
+// Weight of each new fill measurement; the fill jumps by a device buffer with every callback.
+const FILL_SMOOTHING: f64 = 0.02;
+// How long a mirror leg waits for audio before checking whether it was stopped.
+const MIRROR_POLL_TIMEOUT: Duration = Duration::from_millis(10);
+// Decoded blocks each mirror may fall behind the decode thread before blocks are dropped.
+pub(super) const MIRROR_QUEUE_BLOCKS: usize = 32;
+
+/* ClockSync: Which output of a cable with mirrors is the master clock, and the state the legs
+ * share to follow it.
+ *
+ * Every output plays from its own crystal, so two devices at a nominal 48 kHz drift apart by up
+ * to a few hundred frames a minute. The master plays at its own rate, and every other output
+ * resamples the audio by a ratio that is adjusted periodically to keep its buffer at a constant
+ * fill: a buffer that grows means the device plays slower than the master, so the ratio is
+ * lowered slightly, and the other way round. When the master is a mirror, the primary output is
+ * corrected from the master's buffer instead, since it sets the pace for everything behind it.
+ */
+pub(super) struct ClockSync {
+	// 0 for the cable's primary output, i + 1 for mirror i.
+	master: AtomicUsize,
+	// Correction (f64 bits) for the primary output, set by the master mirror.
+	primary_correction: AtomicU64,
+	// Rate of the audio handed to the mirrors, i.e. of the primary output.
+	nominal_rate: AtomicU32,
+	// Bumped on every seek so the mirrors drop what they hold from before it.
+	generation: AtomicU64,
+}
+
+impl ClockSync {
+	pub(super) fn new(nominal_rate: u32) -> ClockSync {
+		ClockSync {
+			master: AtomicUsize::new(0),
+			primary_correction: AtomicU64::new(1.0f64.to_bits()),
+			nominal_rate: AtomicU32::new(nominal_rate),
+			generation: AtomicU64::new(0),
+		}
+	}
+
+	pub(super) fn master(&self) -> usize {
+		self.master.load(Ordering::Relaxed)
+	}
+
+	pub(super) fn set_master(&self, leg: usize) {
+		self.master.store(leg, Ordering::Relaxed);
+		if leg == 0 {
+			self.primary_correction.store(1.0f64.to_bits(), Ordering::Relaxed);
+		}
+	}
+
+	pub(super) fn primary_correction(&self) -> f64 {
+		f64::from_bits(self.primary_correction.load(Ordering::Relaxed))
+	}
+
+	pub(super) fn set_nominal_rate(&self, rate: u32) {
+		self.nominal_rate.store(rate, Ordering::Relaxed);
+	}
+}
+
+// A processed block on its way to the mirrors, tagged with the seek it follows.
+#[derive(Clone)]
+pub(super) struct TapBlock {
+	generation: u64,
+	samples: Arc<Vec<f32>>,
+}
+
+/* MirrorTap: The decode thread's end of a cable's mirrors. Everything the producer sends to the
+ * primary output is pushed here too, before the primary's own drift correction.
+ */
+pub(super) struct MirrorTap {
+	pub(super) splitter: Splitter<TapBlock>,
+	pub(super) sync: Arc<ClockSync>,
+	// The mirrors' data channels, drained on seek.
+	pub(super) drains: Vec<StaleSampleDrain>,
+}
+
+impl std::fmt::Debug for MirrorTap {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("MirrorTap").field("mirrors", &self.drains.len()).finish()
+	}
+}
+
+impl MirrorTap {
+	pub(super) fn push(&self, samples: &[f32]) {
+		if samples.is_empty() {
+			return;
+		}
+		let block = TapBlock {
+			generation: self.sync.generation.load(Ordering::Relaxed),
+			samples: Arc::new(samples.to_vec()),
+		};
+		self.splitter.push(&[block]);
+	}
+
+	// Drops everything the mirrors have queued, as the primary output does on a seek.
+	pub(super) fn seek(&self) {
+		self.sync.generation.fetch_add(1, Ordering::Relaxed);
+		self.splitter.clear();
+		for drain in &self.drains {
+			drain.drain();
+		}
+	}
+}
+
+// PI controller turning a buffer's fill error into a resampling correction.
+#[derive(Default)]
+struct DriftController {
+	fill: Option<f64>,
+	integral: f64,
+}
+
+impl DriftController {
+	// Returns how much the buffer is overfull, as a controller output in [-MAX, MAX].
+	fn update(&mut self, fill: f64, target: f64) -> f64 {
+		let smoothed = match self.fill {
+			Some(previous) => previous + (fill - previous) * FILL_SMOOTHING,
+			None => fill
+		};
+		self.fill = Some(smoothed);
+
+		let error = (smoothed - target) / target.max(1.0);
+		self.integral = (self.integral + error).clamp(-MAX_DRIFT_CORRECTION / DRIFT_KI, MAX_DRIFT_CORRECTION / DRIFT_KI);
+		(DRIFT_KP * error + DRIFT_KI * self.integral).clamp(-MAX_DRIFT_CORRECTION, MAX_DRIFT_CORRECTION)
+	}
+
+	fn reset(&mut self) {
+		*self = DriftController::default();
+	}
+}
+
+/* MirrorOutput: An extra output device playing the same audio as the cable's primary one.
+ */
+pub(super) struct MirrorOutput {
+	pub(super) name: String,
+	pub(super) consumer: AudioConsumer,
+	stop: Arc<AtomicBool>,
+	thread: Option<JoinHandle<()>>,
+}
+
+impl MirrorOutput {
+	// Starts the leg thread moving audio from `blocks` into the consumer's data channel, keeping
+	// it half full. The primary output's channel sits full while the decode thread waits on it, so
+	// mirrors get twice its capacity to play with the same delay. `leg` is the mirror's index in
+	// ClockSync, i.e. one more than its index among the mirrors.
+	pub(super) fn new(name: String, consumer: AudioConsumer, data_channel: crossbeam_channel::Sender<f32>, blocks: crossbeam_channel::Receiver<TapBlock>, leg: usize, sync: Arc<ClockSync>, quality: ResampleQuality) -> Result<MirrorOutput, AudioError> {
+		let stop = Arc::new(AtomicBool::new(false));
+		let leg = MirrorLeg {
+			blocks,
+			data_channel,
+			leg,
+			sync,
+			played: consumer.played_samples(),
+			sent_samples: consumer.sent_samples(),
+			output_rate: consumer.sample_rate().unwrap_or(0),
+			channels: consumer.channels().max(1),
+			target_fill: consumer.capacity() as f64 / 2.0,
+			quality,
+			stop: Arc::clone(&stop),
+		};
+		let thread = std::thread::Builder::new().name(format!("audios-mirror-{}", leg.leg)).spawn(move || leg.run())?;
+		Ok(MirrorOutput {
+			name,
+			consumer,
+			stop,
+			thread: Some(thread),
+		})
+	}
+
+	pub(super) fn stop(&mut self) {
+		self.stop.store(true, Ordering::Relaxed);
+		if let Some(thread) = self.thread.take() {
+			let _ = thread.join();
+		}
+	}
+}
+
+impl Drop for MirrorOutput {
+	fn drop(&mut self) {
+		self.stop();
+	}
+}
+
+struct MirrorLeg {
+	blocks: crossbeam_channel::Receiver<TapBlock>,
+	data_channel: crossbeam_channel::Sender<f32>,
+	leg: usize,
+	sync: Arc<ClockSync>,
+	// Samples the mirror's output callback has played.
+	played: Arc<AtomicU64>,
+	// The consumer's count of samples sent to it, kept for AudioConsumer::is_draining.
+	sent_samples: Arc<AtomicU64>,
+	output_rate: u32,
+	channels: usize,
+	target_fill: f64,
+	quality: ResampleQuality,
+	stop: Arc<AtomicBool>,
+}
+
+impl MirrorLeg {
+	fn run(self) {
+		let mut input_rate = self.sync.nominal_rate.load(Ordering::Relaxed);
+		let mut resampler = Resampler::new(input_rate, self.output_rate, self.channels as u16, self.quality);
+		let mut generation = self.sync.generation.load(Ordering::Relaxed);
+		let mut own = DriftController::default();
+		let mut primary = DriftController::default();
+		let mut resampled = Vec::new();
+		let mut sent_samples = 0u64;
+
+		while !self.stop.load(Ordering::Relaxed) {
+			let block = match self.blocks.recv_timeout(MIRROR_POLL_TIMEOUT) {
+				Ok(block) => block,
+				Err(crossbeam_channel::RecvTimeoutError::Timeout) => continue,
+				Err(crossbeam_channel::RecvTimeoutError::Disconnected) => return
+			};
+			// Skip audio from before a seek.
+			if block.generation != self.sync.generation.load(Ordering::Relaxed) {
+				continue;
+			}
+
+			let rate = self.sync.nominal_rate.load(Ordering::Relaxed);
+			if rate != input_rate || block.generation != generation {
+				input_rate = rate;
+				generation = block.generation;
+				resampler = Resampler::new(input_rate, self.output_rate, self.channels as u16, self.quality);
+				// A seek emptied the data channel, so nothing sent before it is still waiting.
+				sent_samples = self.played.load(Ordering::Relaxed);
+				own.reset();
+			}
+
+			let fill = sent_samples.saturating_sub(self.played.load(Ordering::Relaxed)) as f64;
+			if self.sync.master() == self.leg {
+				// The master plays at its own pace; the primary output follows it instead.
+				let correction = 1.0 + primary.update(fill, self.target_fill);
+				self.sync.primary_correction.store(correction.to_bits(), Ordering::Relaxed);
+				resampler.set_drift_correction(1.0);
+				own.reset();
+			} else {
+				resampler.set_drift_correction(1.0 - own.update(fill, self.target_fill));
+				primary.reset();
+			}
+
+			resampled.clear();
+			resampler.process(&block.samples, &mut resampled);
+
+			// Drop the block if a seek came in while it was being resampled.
+			if self.sync.generation.load(Ordering::Relaxed) != generation {
+				continue;
+			}
+			let mut sent = 0;
+			loop {
+				let previously_sent = sent;
+				let progress = send_pending(&self.data_channel, &self.sent_samples, &resampled, &mut sent);
+				sent_samples += (sent - previously_sent) as u64;
+				match progress {
+					SendProgress::Done => break,
+					SendProgress::Full if self.stop.load(Ordering::Relaxed) => return,
+					// What's left of the block predates a seek.
+					SendProgress::Full if self.sync.generation.load(Ordering::Relaxed) != generation => break,
+					SendProgress::Full => std::thread::sleep(PRODUCER_POLL_INTERVAL),
+					SendProgress::Disconnected => return
+				}
+			}
+		}
+	}
+}
@@ -0,0 +1,64 @@
+use super::render::DecodedBlocks;
+use super::AudioError;
+
+use std::path::Path;
+
+// Quiet tracks are boosted by at most this much, so a near-silent one doesn't have its noise
+// floor raised to the target.
+const MAX_PEAK_GAIN_DB: f32 = 30.0;
+
+/* PeakNormalizer: The gain AudioCableBuilder::normalize_peak applies on the decode side, taking a
+ * track's peak to the target level.
+ *
+ * With the peak scanned ahead of time the gain is fixed for the whole track. Without it the
+ * normalizer follows the loudest sample so far, block by block: the gain starts high and only
+ * ever comes down, at the start of a block louder than anything before it, so no sample goes past
+ * the target but the level may step down during the track.
+ */
+#[derive(Clone, Copy, Debug)]
+pub(super) struct PeakNormalizer {
+	// Linear level the peak is brought to.
+	target: f32,
+	peak: f32,
+	// Whether `peak` is the track's own rather than the loudest heard so far.
+	scanned: bool,
+}
+
+impl PeakNormalizer {
+	pub(super) fn new(target_db: f32) -> PeakNormalizer {
+		PeakNormalizer { target: 10.0f32.powf(target_db / 20.0), peak: 0.0, scanned: false }
+	}
+
+	// The normalizer for a track whose peak has been found beforehand.
+	pub(super) fn with_peak(self, peak: f32) -> PeakNormalizer {
+		PeakNormalizer { peak, scanned: true, ..self }
+	}
+
+	// The same target for a track that hasn't been scanned, e.g. the next one queued.
+	pub(super) fn running(self) -> PeakNormalizer {
+		PeakNormalizer { peak: 0.0, scanned: false, ..self }
+	}
+
+	pub(super) fn process(&mut self, block: &mut [f32]) {
+		if !self.scanned {
+			self.peak = block.iter().fold(self.peak, |peak, sample| peak.max(sample.abs()));
+		}
+		// Silence has no peak to bring up.
+		if self.peak <= 0.0 {
+			return;
+		}
+		let gain = (self.target / self.peak).min(10.0f32.powf(MAX_PEAK_GAIN_DB / 20.0));
+		for sample in block.iter_mut() {
+			*sample *= gain;
+		}
+	}
+}
+
+// The largest absolute sample in the file at `path`, found by decoding all of it.
+pub(super) fn scan_peak(path: &Path) -> Result<f32, AudioError> {
+	let mut peak = 0.0f32;
+	for block in DecodedBlocks::open(path)? {
+		peak = block?.iter().fold(peak, |peak, sample| peak.max(sample.abs()));
+	}
+	Ok(peak)
+}
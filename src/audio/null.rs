@@ -0,0 +1,77 @@
+use super::pace::paced_blocks;
+use super::{OutputStage, PlaybackTimestamp};
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+// Layout of the null output AudioConsumer::new_or_null falls back to, the most common device
+// default.
+pub(super) const NULL_SAMPLE_RATE: u32 = 48000;
+pub(super) const NULL_CHANNELS: u16 = 2;
+// Audio thrown away at a time. Blocks go on the output's clock, like a device's callbacks.
+const NULL_BLOCK: Duration = Duration::from_millis(10);
+// How often a thread that hasn't been played yet checks whether it's been played or stopped.
+const NULL_POLL: Duration = Duration::from_millis(20);
+
+/* OutputMode: Where AudioConsumer::new_or_null sends the audio.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputMode {
+	// The default output device.
+	Hardware,
+	// No device could be opened, so a null output plays the audio to nowhere; see NullOutput.
+	Null,
+}
+
+/* NullOutput: Stands in for an output device where there is none, e.g. on a headless server or
+ * in CI, so an app keeps running rather than failing to open one.
+ *
+ * Once played, a thread drains the data channel through the usual output stage, a block at a
+ * time in real time, and discards what comes out, so the source is paced, positions and events
+ * move on and the cable finishes as if it were heard.
+ */
+pub(super) struct NullOutput {
+	playing: Arc<AtomicBool>,
+	stop: Arc<AtomicBool>,
+	thread: Option<JoinHandle<()>>,
+}
+
+impl NullOutput {
+	pub(super) fn start(stage: OutputStage, timestamp: Arc<PlaybackTimestamp>) -> std::io::Result<NullOutput> {
+		let playing = Arc::new(AtomicBool::new(false));
+		let stop = Arc::new(AtomicBool::new(false));
+		let thread_playing = Arc::clone(&playing);
+		let thread_stop = Arc::clone(&stop);
+		let thread = std::thread::Builder::new().name(String::from("audios-null-output"))
+			.spawn(move || discard(stage, timestamp, thread_playing, thread_stop))?;
+		Ok(NullOutput { playing, stop, thread: Some(thread) })
+	}
+
+	// Starts draining; until then the thread only waits.
+	pub(super) fn play(&self) {
+		self.playing.store(true, Ordering::Relaxed);
+	}
+}
+
+impl Drop for NullOutput {
+	fn drop(&mut self) {
+		self.stop.store(true, Ordering::Relaxed);
+		if let Some(thread) = self.thread.take() {
+			let _ = thread.join();
+		}
+	}
+}
+
+fn discard(mut stage: OutputStage, timestamp: Arc<PlaybackTimestamp>, playing: Arc<AtomicBool>, stop: Arc<AtomicBool>) {
+	let channels = stage.channels().max(1) as usize;
+	let frames = ((stage.sample_rate as f64 * NULL_BLOCK.as_secs_f64()) as usize).max(1);
+	let mut block = vec![0.0f32; frames * channels];
+	let _ = paced_blocks(NULL_BLOCK, NULL_POLL, &playing, &stop, || {
+		let frame = stage.played.load(Ordering::Relaxed) / channels as u64;
+		timestamp.store(frame, std::time::Instant::now());
+		stage.fill(&mut block);
+		Ok(())
+	});
+}
@@ -0,0 +1,25 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+// Runs `block` once every `period` on a clock of its own, as a device would its callbacks, for
+// the outputs that have no device: the null output and the output socket. Until `playing` is set
+// it only checks back every `poll`, and the clock starts over once it is. Returns when `stop` is
+// set or with the first error `block` returns.
+pub(super) fn paced_blocks(period: Duration, poll: Duration, playing: &AtomicBool, stop: &AtomicBool, mut block: impl FnMut() -> std::io::Result<()>) -> std::io::Result<()> {
+	let mut next = std::time::Instant::now();
+	while !stop.load(Ordering::Relaxed) {
+		if !playing.load(Ordering::Relaxed) {
+			std::thread::sleep(poll);
+			next = std::time::Instant::now();
+			continue;
+		}
+		block()?;
+
+		// Time lost to a block that held things up isn't caught up on in a burst.
+		next = (next + period).max(std::time::Instant::now());
+		if let Some(wait) = next.checked_duration_since(std::time::Instant::now()) {
+			std::thread::sleep(wait);
+		}
+	}
+	Ok(())
+}
@@ -0,0 +1,92 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+
+/* PlaylistCursor: A position in a fixed list of files, for an app's previous and next buttons.
+ *
+ * The cursor sits on the track that's playing. next, prev and jump move it and return the track
+ * it lands on, and iterating over a `&PlaylistCursor` calls next until the end. A cable handed
+ * the cursor with AudioCableBuilder::playlist calls next itself whenever a track ends and plays
+ * what it returns, so moving the cursor changes what plays after the current track; to switch at
+ * once, pass the track returned to AudioCable::replace_source. Files queued with enqueue play
+ * before the cursor is asked. Clones share the same position.
+ */
+#[derive(Clone, Debug)]
+pub struct PlaylistCursor {
+	tracks: Arc<[PathBuf]>,
+	// The track the cursor is on, or None before the first call to next.
+	current: Arc<Mutex<Option<usize>>>,
+}
+
+impl PlaylistCursor {
+	pub fn new<P: Into<PathBuf>>(tracks: impl IntoIterator<Item = P>) -> PlaylistCursor {
+		PlaylistCursor {
+			tracks: tracks.into_iter().map(Into::into).collect(),
+			current: Arc::new(Mutex::new(None)),
+		}
+	}
+
+	// Moves to the track after the current one, or to the first before any, and returns it. At
+	// the last track the cursor stays and None is returned.
+	#[allow(clippy::should_implement_trait)]
+	pub fn next(&self) -> Option<&Path> {
+		self.step(|current| current.map_or(Some(0), |current| current.checked_add(1)))
+	}
+
+	// Moves to the track before the current one and returns it, or None at the first track.
+	pub fn prev(&self) -> Option<&Path> {
+		self.step(|current| current?.checked_sub(1))
+	}
+
+	// Moves to the track at `index` and returns it, or None if there's no such track, leaving the
+	// cursor where it was.
+	pub fn jump(&self, index: usize) -> Option<&Path> {
+		self.step(|_| Some(index))
+	}
+
+	// Goes back to before the first track, so the next call to next returns it.
+	pub fn rewind(&self) {
+		*self.current.lock().unwrap_or_else(PoisonError::into_inner) = None;
+	}
+
+	// The index of the track the cursor is on, or None before the first call to next.
+	pub fn position(&self) -> Option<usize> {
+		*self.current.lock().unwrap_or_else(PoisonError::into_inner)
+	}
+
+	pub fn current(&self) -> Option<&Path> {
+		self.position().and_then(|index| self.tracks.get(index)).map(PathBuf::as_path)
+	}
+
+	pub fn tracks(&self) -> &[PathBuf] {
+		&self.tracks
+	}
+
+	pub fn len(&self) -> usize {
+		self.tracks.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.tracks.is_empty()
+	}
+
+	// Whether next would return a track.
+	pub(super) fn has_next(&self) -> bool {
+		self.position().map_or(0, |current| current + 1) < self.tracks.len()
+	}
+
+	// Moves to the index `target` picks from the current one, if there's a track there.
+	fn step(&self, target: impl FnOnce(Option<usize>) -> Option<usize>) -> Option<&Path> {
+		let mut current = self.current.lock().unwrap_or_else(PoisonError::into_inner);
+		let index = target(*current).filter(|&index| index < self.tracks.len())?;
+		*current = Some(index);
+		Some(&self.tracks[index])
+	}
+}
+
+impl<'a> Iterator for &'a PlaylistCursor {
+	type Item = &'a Path;
+
+	fn next(&mut self) -> Option<&'a Path> {
+		PlaylistCursor::next(self)
+	}
+}
@@ -0,0 +1,149 @@
+/* CallbackPriority: What AudioConsumer::is_realtime found the output callback's thread running
+ * at, best effort per platform.
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CallbackPriority {
+	// The platform can't tell, or no callback has run yet.
+	#[default]
+	Unknown,
+	// The ordinary priority of any thread, so other busy work on the machine can delay the
+	// callback into a dropout.
+	Normal,
+	// Raised above ordinary threads without realtime scheduling: a lowered niceness on Linux, a
+	// higher priority class on Windows.
+	Elevated,
+	// Realtime scheduling: SCHED_FIFO or SCHED_RR on Linux, time critical on Windows, Core
+	// Audio's realtime thread on macOS and iOS.
+	Realtime,
+}
+
+// SCHED_FIFO priority asked for on Linux: above other applications' ordinary realtime threads at
+// the default of 1, below the kernel's own and JACK's, which runs at 70 and up.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const REALTIME_PRIORITY: i32 = 60;
+// The niceness tried instead when realtime scheduling isn't allowed, which RLIMIT_NICE may permit
+// where RLIMIT_RTPRIO doesn't.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const FALLBACK_NICENESS: i32 = -11;
+
+// Raises the calling thread, a device's callback thread, to realtime priority where the platform
+// lets it, for AudioCableBuilder::realtime_priority.
+//
+// On Linux and Android that's SCHED_FIFO, which needs RLIMIT_RTPRIO (e.g. membership of an audio
+// group given a limit in /etc/security/limits.conf) or CAP_SYS_NICE; without it the thread's
+// niceness is lowered instead, and only if that's refused too does this fail. On Windows the thread
+// is made time critical, which any process may do. macOS and iOS already run callbacks on Core
+// Audio's realtime thread, so there is nothing to do. Elsewhere it fails with Unsupported.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(super) fn raise_current_thread() -> std::io::Result<()> {
+	// SAFETY: plain calls on the current thread with a valid sched_param.
+	unsafe {
+		let priority = REALTIME_PRIORITY.clamp(libc::sched_get_priority_min(libc::SCHED_FIFO), libc::sched_get_priority_max(libc::SCHED_FIFO));
+		let param = libc::sched_param { sched_priority: priority };
+		if libc::pthread_setschedparam(libc::pthread_self(), libc::SCHED_FIFO, &param) == 0 {
+			return Ok(());
+		}
+		// Linux keeps a niceness per thread, so this leaves the rest of the process alone.
+		let thread = libc::syscall(libc::SYS_gettid) as libc::id_t;
+		if libc::setpriority(libc::PRIO_PROCESS, thread, FALLBACK_NICENESS) == 0 {
+			return Ok(());
+		}
+	}
+	Err(std::io::Error::last_os_error())
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+pub(super) fn raise_current_thread() -> std::io::Result<()> {
+	Ok(())
+}
+
+#[cfg(target_os = "windows")]
+pub(super) fn raise_current_thread() -> std::io::Result<()> {
+	use std::ffi::c_void;
+
+	const THREAD_PRIORITY_TIME_CRITICAL: i32 = 15;
+
+	#[link(name = "kernel32")]
+	extern "system" {
+		fn GetCurrentThread() -> *mut c_void;
+		fn SetThreadPriority(thread: *mut c_void, priority: i32) -> i32;
+	}
+
+	// SAFETY: GetCurrentThread returns a pseudo handle to the calling thread that needn't be closed.
+	if unsafe { SetThreadPriority(GetCurrentThread(), THREAD_PRIORITY_TIME_CRITICAL) } == 0 {
+		return Err(std::io::Error::last_os_error());
+	}
+	Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "macos", target_os = "ios", target_os = "windows")))]
+pub(super) fn raise_current_thread() -> std::io::Result<()> {
+	Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "raising the thread's priority isn't supported on this platform"))
+}
+
+// The priority the calling thread runs at, for the output callback to report.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(super) fn current_thread_priority() -> CallbackPriority {
+	// SAFETY: plain calls on the current thread, writing into locals.
+	unsafe {
+		let mut policy = 0;
+		let mut param = libc::sched_param { sched_priority: 0 };
+		if libc::pthread_getschedparam(libc::pthread_self(), &mut policy, &mut param) != 0 {
+			return CallbackPriority::Unknown;
+		}
+		if policy == libc::SCHED_FIFO || policy == libc::SCHED_RR {
+			return CallbackPriority::Realtime;
+		}
+		let thread = libc::syscall(libc::SYS_gettid) as libc::id_t;
+		// -1 is both a niceness and the error return, which only errno tells apart.
+		*errno_location() = 0;
+		match libc::getpriority(libc::PRIO_PROCESS, thread) {
+			-1 if *errno_location() != 0 => CallbackPriority::Unknown,
+			niceness if niceness < 0 => CallbackPriority::Elevated,
+			_ => CallbackPriority::Normal
+		}
+	}
+}
+
+// The calling thread's errno, which glibc and bionic name differently.
+#[cfg(target_os = "linux")]
+unsafe fn errno_location() -> *mut libc::c_int {
+	libc::__errno_location()
+}
+
+#[cfg(target_os = "android")]
+unsafe fn errno_location() -> *mut libc::c_int {
+	libc::__errno()
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+pub(super) fn current_thread_priority() -> CallbackPriority {
+	CallbackPriority::Realtime
+}
+
+#[cfg(target_os = "windows")]
+pub(super) fn current_thread_priority() -> CallbackPriority {
+	use std::ffi::c_void;
+
+	const THREAD_PRIORITY_TIME_CRITICAL: i32 = 15;
+	const THREAD_PRIORITY_ERROR_RETURN: i32 = i32::MAX;
+
+	#[link(name = "kernel32")]
+	extern "system" {
+		fn GetCurrentThread() -> *mut c_void;
+		fn GetThreadPriority(thread: *mut c_void) -> i32;
+	}
+
+	// SAFETY: GetCurrentThread returns a pseudo handle to the calling thread that needn't be closed.
+	match unsafe { GetThreadPriority(GetCurrentThread()) } {
+		THREAD_PRIORITY_ERROR_RETURN => CallbackPriority::Unknown,
+		priority if priority >= THREAD_PRIORITY_TIME_CRITICAL => CallbackPriority::Realtime,
+		priority if priority > 0 => CallbackPriority::Elevated,
+		_ => CallbackPriority::Normal
+	}
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "macos", target_os = "ios", target_os = "windows")))]
+pub(super) fn current_thread_priority() -> CallbackPriority {
+	CallbackPriority::Unknown
+}
@@ -0,0 +1,244 @@
+use super::replay_gain::ReplayGain;
+use super::source::{AudioSource, CoverArt};
+use super::{AudioError, AudioTrackInfo, BroadcastInfo, Chapter, SourceType};
+
+use std::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+// Queued tracks staged ahead of time unless AudioCableBuilder::prefetch_tracks says otherwise.
+pub(super) const DEFAULT_PREFETCH_TRACKS: usize = 1;
+
+// How much of each upcoming track the prefetch thread decodes before handing it over.
+const STAGED_HEAD: Duration = Duration::from_millis(500);
+
+/* RepeatMode: What a cable plays once its current track ends, set with
+ * AudioCable::set_repeat_mode.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RepeatMode {
+	// Each track plays once, then the next queued one follows or the cable finishes.
+	Off,
+	// The current track starts over whenever it ends.
+	One,
+	// Every track goes back to the end of the queue once it ends, so the queue loops. A track
+	// that isn't a file, e.g. one played from a reader, can't be reopened and only loops while
+	// nothing else is queued.
+	All,
+}
+
+impl RepeatMode {
+	fn from_u8(mode: u8) -> RepeatMode {
+		match mode {
+			1 => RepeatMode::One,
+			2 => RepeatMode::All,
+			_ => RepeatMode::Off
+		}
+	}
+}
+
+/* Track: A source for the decode thread, with the file it was opened from so RepeatMode::All can
+ * queue it again.
+ */
+pub(super) struct Track {
+	pub(super) source: Box<dyn AudioSource>,
+	pub(super) path: Option<String>,
+}
+
+/* TrackQueue: Files to play once the current source ends, for gapless playlists.
+ *
+ * A prefetch thread, started on the first enqueue, opens each file and decodes its head ahead of
+ * time, so when a track ends the decode thread carries straight on with the next one while the
+ * data channel is still full. At most `prefetch` tracks are staged at once, which bounds the
+ * memory held to that many heads plus the one the thread is working on; the thread waits for the
+ * decode thread to take one before it starts on the next.
+ */
+pub(super) struct TrackQueue {
+	paths: Mutex<Option<crossbeam_channel::Sender<(SourceType, String)>>>,
+	staged: crossbeam_channel::Receiver<StagedSource>,
+	staging: crossbeam_channel::Sender<StagedSource>,
+	// Tracks enqueued and not yet taken by the decode thread, staged or not.
+	pending: Arc<AtomicUsize>,
+	// A RepeatMode as its discriminant.
+	repeat: AtomicU8,
+}
+
+impl TrackQueue {
+	pub(super) fn new(prefetch: usize) -> TrackQueue {
+		let (staging, staged) = crossbeam_channel::bounded(prefetch);
+		TrackQueue {
+			paths: Mutex::new(None),
+			staged,
+			staging,
+			pending: Arc::new(AtomicUsize::new(0)),
+			repeat: AtomicU8::new(RepeatMode::Off as u8),
+		}
+	}
+
+	// Adds `file` to the end of the queue. Only the extension is checked here; a file that can't
+	// be opened is reported and skipped once the prefetch thread gets to it.
+	pub(super) fn enqueue(&self, file: String) -> Result<(), AudioError> {
+		let source_type = SourceType::from_local(file.clone())?;
+		let mut paths = self.paths.lock().unwrap_or_else(PoisonError::into_inner);
+		if paths.is_none() {
+			let (sender, requests) = crossbeam_channel::unbounded();
+			let staging = self.staging.clone();
+			let pending = Arc::clone(&self.pending);
+			std::thread::Builder::new().name(String::from("audios-prefetch")).spawn(move || prefetch(requests, staging, pending))?;
+			*paths = Some(sender);
+		}
+		let paths = paths.as_ref().ok_or(AudioError::Stopped)?;
+		self.pending.fetch_add(1, Ordering::Relaxed);
+		paths.send((source_type, file)).map_err(|_| AudioError::Stopped)
+	}
+
+	pub(super) fn set_repeat_mode(&self, mode: RepeatMode) {
+		self.repeat.store(mode as u8, Ordering::Relaxed);
+	}
+
+	pub(super) fn repeat_mode(&self) -> RepeatMode {
+		RepeatMode::from_u8(self.repeat.load(Ordering::Relaxed))
+	}
+
+	// Whether a track that just ended should start over rather than make way for the next one:
+	// always with RepeatMode::One, and with RepeatMode::All when it's the only track left.
+	pub(super) fn rewinds(&self) -> bool {
+		match self.repeat_mode() {
+			RepeatMode::Off => false,
+			RepeatMode::One => true,
+			RepeatMode::All => self.is_empty()
+		}
+	}
+
+	// Whether no more tracks are to come, staged or still being opened.
+	pub(super) fn is_empty(&self) -> bool {
+		self.pending.load(Ordering::Relaxed) == 0
+	}
+
+	// Where the decode thread picks up staged tracks; take must be called for each one received.
+	pub(super) fn staged(&self) -> &crossbeam_channel::Receiver<StagedSource> {
+		&self.staged
+	}
+
+	pub(super) fn take(&self, mut track: StagedSource) -> Track {
+		self.pending.fetch_sub(1, Ordering::Relaxed);
+		let path = track.path.take();
+		Track { source: Box::new(track), path }
+	}
+}
+
+impl std::fmt::Debug for TrackQueue {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("TrackQueue")
+			.field("pending", &self.pending.load(Ordering::Relaxed))
+			.field("repeat", &self.repeat_mode())
+			.finish()
+	}
+}
+
+// Runs on the prefetch thread until the queue is dropped.
+fn prefetch(requests: crossbeam_channel::Receiver<(SourceType, String)>, staging: crossbeam_channel::Sender<StagedSource>, pending: Arc<AtomicUsize>) {
+	for (source_type, path) in requests {
+		let staged = match source_type.open() {
+			Ok(Some(source)) => StagedSource::new(source, path),
+			Ok(None) => Err(AudioError::Unsupported(String::from("there is nothing to decode"))),
+			Err(err) => Err(err)
+		};
+		match staged {
+			Ok(staged) => {
+				if staging.send(staged).is_err() {
+					return;
+				}
+			},
+			Err(err) => {
+				eprintln!("failed to open a queued track, skipping it: {}", err);
+				pending.fetch_sub(1, Ordering::Relaxed);
+			}
+		}
+	}
+}
+
+/* StagedSource: A queued source with its head already decoded, which read_block hands out first.
+ */
+pub(super) struct StagedSource {
+	source: Box<dyn AudioSource>,
+	head: Vec<f32>,
+	// What the source's last read said, in case the whole track fit in the head.
+	more: bool,
+	// Handed over separately by TrackQueue::take.
+	path: Option<String>,
+}
+
+impl StagedSource {
+	fn new(mut source: Box<dyn AudioSource>, path: String) -> Result<StagedSource, AudioError> {
+		let head_samples = (STAGED_HEAD.as_secs_f64() * source.sample_rate() as f64) as usize * source.channels().max(1) as usize;
+		let mut head = Vec::with_capacity(head_samples);
+		let mut more = true;
+		while more && head.len() < head_samples {
+			more = source.read_block(&mut head)?;
+		}
+		Ok(StagedSource { source, head, more, path: Some(path) })
+	}
+}
+
+impl AudioSource for StagedSource {
+	fn sample_rate(&self) -> u32 {
+		self.source.sample_rate()
+	}
+
+	fn channels(&self) -> u16 {
+		self.source.channels()
+	}
+
+	fn read_block(&mut self, buffer: &mut Vec<f32>) -> Result<bool, AudioError> {
+		if self.head.is_empty() {
+			return self.source.read_block(buffer);
+		}
+		buffer.append(&mut self.head);
+		self.head = Vec::new();
+		Ok(self.more)
+	}
+
+	fn cover_art(&self) -> Option<&CoverArt> {
+		self.source.cover_art()
+	}
+
+	fn chapters(&self) -> &[Chapter] {
+		self.source.chapters()
+	}
+
+	fn broadcast_info(&self) -> Option<&BroadcastInfo> {
+		self.source.broadcast_info()
+	}
+
+	fn replay_gain(&self) -> Option<&ReplayGain> {
+		self.source.replay_gain()
+	}
+
+	fn audio_tracks(&self) -> Vec<AudioTrackInfo> {
+		self.source.audio_tracks()
+	}
+
+	fn decoded_channels(&self) -> u16 {
+		self.source.decoded_channels()
+	}
+
+	fn declared_frames(&self) -> Option<u64> {
+		self.source.declared_frames()
+	}
+
+	fn bytes_read(&self) -> u64 {
+		self.source.bytes_read()
+	}
+
+	fn is_seekable(&self) -> bool {
+		self.source.is_seekable()
+	}
+
+	fn seek(&mut self, frame: u64) -> Result<(), AudioError> {
+		self.source.seek(frame)?;
+		self.head = Vec::new();
+		self.more = true;
+		Ok(())
+	}
+}
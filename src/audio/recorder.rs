@@ -0,0 +1,328 @@
+#[cfg(feature = "flac-encode")]
+use super::flac_encoder::FlacEncoder;
+use super::resample::{ResampleQuality, Resampler};
+use super::{AudioError, SAMPLE_RATE_RANGE};
+
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+// Where the frame count of the fact chunk sits in a float file's header.
+const FACT_OFFSET: u64 = 46;
+
+/* WavSampleFormat: How an AudioRecorder stores samples.
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WavSampleFormat {
+	// 16-bit integer PCM, the most widely read. Samples are scaled by 32768 and clamped to its
+	// range, the inverse of how the decoders read 16-bit audio, so decoded 16-bit sources are
+	// written back unchanged.
+	#[default]
+	I16,
+	// 32-bit IEEE float (WAVE_FORMAT_IEEE_FLOAT): the samples exactly as the pipeline carries
+	// them, with nothing rounded or clamped.
+	F32,
+}
+
+impl WavSampleFormat {
+	fn bits(self) -> u16 {
+		match self {
+			WavSampleFormat::I16 => 16,
+			WavSampleFormat::F32 => 32,
+		}
+	}
+
+	// Size of the header written in front of the samples. Float files have the longer fmt chunk
+	// and the fact chunk that formats other than PCM need.
+	fn header_len(self) -> u32 {
+		match self {
+			WavSampleFormat::I16 => 44,
+			WavSampleFormat::F32 => 58,
+		}
+	}
+}
+
+/* FlacOptions: How AudioRecorder::create_flac encodes.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FlacOptions {
+	// 16 or 24. Samples are scaled to the depth and clamped like WavSampleFormat::I16's, so a
+	// source decoded at the same depth is recorded losslessly.
+	pub bits_per_sample: u8,
+	// 0 (fastest) to 8 (smallest), as with the reference encoder; 5 by default. Every level is
+	// lossless, and decodes as fast as any other.
+	pub compression_level: u8,
+}
+
+impl Default for FlacOptions {
+	fn default() -> Self {
+		FlacOptions { bits_per_sample: 16, compression_level: 5 }
+	}
+}
+
+/* AudioRecorder: Writes interleaved f32 audio to a WAV file, 16-bit PCM by default; see
+ * WavSampleFormat. With the flac-encode feature, create_flac records to FLAC instead.
+ *
+ * The file is written at the rate the audio comes in at unless with_target_rate picks another,
+ * for sources at rates other software handles badly (e.g. a device running at 44.1 kHz recorded
+ * for a 48 kHz project).
+ *
+ * The header's sizes are only known at the end, so they are patched in by finish, or on drop if
+ * finish was never called.
+ */
+pub struct AudioRecorder {
+	writer: Option<BufWriter<File>>,
+	// The rate samples are passed to write at.
+	input_rate: u32,
+	// The rate the file is written at, input_rate unless with_target_rate changed it.
+	sample_rate: u32,
+	channels: u16,
+	format: WavSampleFormat,
+	// Converts from input_rate to sample_rate, if they differ.
+	resampler: Option<Resampler>,
+	resampled: Vec<f32>,
+	// Bytes of sample data written after the header.
+	data_len: u32,
+	// Set for a FLAC recording, which writes through it rather than as WAV.
+	#[cfg(feature = "flac-encode")]
+	flac: Option<FlacEncoder>,
+}
+
+impl AudioRecorder {
+	// Creates (or truncates) the 16-bit WAV file at `path` for audio of the given layout.
+	pub fn create<P: AsRef<Path>>(path: P, sample_rate: u32, channels: u16) -> Result<AudioRecorder, AudioError> {
+		AudioRecorder::create_with_format(path, sample_rate, channels, WavSampleFormat::I16)
+	}
+
+	// Like create, storing the samples as `format`.
+	pub fn create_with_format<P: AsRef<Path>>(path: P, sample_rate: u32, channels: u16, format: WavSampleFormat) -> Result<AudioRecorder, AudioError> {
+		if sample_rate == 0 || channels == 0 {
+			return Err(AudioError::InvalidArgument(format!(
+				"can't record {} channels at {} Hz", channels, sample_rate
+			)));
+		}
+		let mut writer = BufWriter::new(File::create(path)?);
+		write_header(&mut writer, sample_rate, channels, format)?;
+		Ok(AudioRecorder {
+			writer: Some(writer),
+			input_rate: sample_rate,
+			sample_rate,
+			channels,
+			format,
+			resampler: None,
+			resampled: Vec::new(),
+			data_len: 0,
+			#[cfg(feature = "flac-encode")]
+			flac: None,
+		})
+	}
+
+	// Creates (or truncates) the FLAC file at `path` for audio of the given layout, encoded as
+	// `options` says. It's finished, with its length and MD5 filled in, by finish or on drop.
+	// Needs the flac-encode feature; without it this fails with Unsupported.
+	#[cfg(feature = "flac-encode")]
+	pub fn create_flac<P: AsRef<Path>>(path: P, sample_rate: u32, channels: u16, options: FlacOptions) -> Result<AudioRecorder, AudioError> {
+		if sample_rate == 0 || channels == 0 {
+			return Err(AudioError::InvalidArgument(format!(
+				"can't record {} channels at {} Hz", channels, sample_rate
+			)));
+		}
+		let mut flac = FlacEncoder::new(sample_rate, channels, options)?;
+		let mut writer = BufWriter::new(File::create(path)?);
+		flac.write_header(&mut writer)?;
+		Ok(AudioRecorder {
+			writer: Some(writer),
+			input_rate: sample_rate,
+			sample_rate,
+			channels,
+			format: WavSampleFormat::default(),
+			resampler: None,
+			resampled: Vec::new(),
+			data_len: 0,
+			flac: Some(flac),
+		})
+	}
+
+	#[cfg(not(feature = "flac-encode"))]
+	pub fn create_flac<P: AsRef<Path>>(_path: P, _sample_rate: u32, _channels: u16, _options: FlacOptions) -> Result<AudioRecorder, AudioError> {
+		Err(AudioError::Unsupported(String::from("recording to FLAC needs the flac-encode feature")))
+	}
+
+	// Writes the file at `rate` instead of the rate given to create, resampling everything passed
+	// to write on its way to the file. Must be called before anything is written. A rate equal to
+	// the input's goes back to writing it unchanged.
+	pub fn with_target_rate(mut self, rate: u32) -> Result<AudioRecorder, AudioError> {
+		if !SAMPLE_RATE_RANGE.contains(&rate) {
+			return Err(AudioError::InvalidArgument(format!(
+				"sample rates must be between {} and {} Hz, got {}", SAMPLE_RATE_RANGE.start(), SAMPLE_RATE_RANGE.end(), rate
+			)));
+		}
+		if self.data_len > 0 {
+			return Err(AudioError::InvalidArgument(String::from("the target rate must be set before anything is recorded")));
+		}
+		let writer = self.writer.as_mut().ok_or(AudioError::Stopped)?;
+		writer.seek(SeekFrom::Start(0))?;
+		#[cfg(feature = "flac-encode")]
+		if let Some(flac) = &mut self.flac {
+			flac.set_sample_rate(rate)?;
+			flac.write_header(writer)?;
+		} else {
+			write_header(writer, rate, self.channels, self.format)?;
+		}
+		#[cfg(not(feature = "flac-encode"))]
+		write_header(writer, rate, self.channels, self.format)?;
+		self.sample_rate = rate;
+		self.resampler = if rate == self.input_rate {
+			None
+		} else {
+			Some(Resampler::new(self.input_rate, rate, self.channels, ResampleQuality::Cubic))
+		};
+		Ok(self)
+	}
+
+	pub fn channels(&self) -> u16 {
+		self.channels
+	}
+
+	// The rate the file is written at.
+	pub fn sample_rate(&self) -> u32 {
+		self.sample_rate
+	}
+
+	// The sample format of a WAV recording; I16 for FLAC.
+	pub fn format(&self) -> WavSampleFormat {
+		self.format
+	}
+
+	// Appends interleaved samples at the rate given to create. WAV sizes are 32-bit, so writing
+	// stops with InvalidArgument once a WAV file would pass 4 GiB.
+	pub fn write(&mut self, samples: &[f32]) -> Result<(), AudioError> {
+		if self.writer.is_none() {
+			return Err(AudioError::Stopped);
+		}
+		match self.resampler.as_mut() {
+			Some(resampler) => {
+				let mut resampled = std::mem::take(&mut self.resampled);
+				resampled.clear();
+				resampler.process(samples, &mut resampled);
+				let written = self.write_file(&resampled);
+				self.resampled = resampled;
+				written
+			},
+			None => self.write_file(samples)
+		}
+	}
+
+	// Appends samples already at the file's rate.
+	fn write_file(&mut self, samples: &[f32]) -> Result<(), AudioError> {
+		let writer = self.writer.as_mut().ok_or(AudioError::Stopped)?;
+		#[cfg(feature = "flac-encode")]
+		if let Some(flac) = &mut self.flac {
+			return flac.write(writer, samples);
+		}
+		let bytes = (samples.len() * self.format.bits() as usize / 8) as u64;
+		if self.format.header_len() as u64 + self.data_len as u64 + bytes > u32::MAX as u64 {
+			return Err(AudioError::InvalidArgument(String::from("the WAV file has reached its 4 GiB limit")));
+		}
+		for &sample in samples {
+			match self.format {
+				WavSampleFormat::I16 => {
+					let sample = (sample * 32768.0).round().clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+					writer.write_all(&sample.to_le_bytes())?;
+				},
+				WavSampleFormat::F32 => writer.write_all(&sample.to_le_bytes())?
+			}
+		}
+		self.data_len += bytes as u32;
+		Ok(())
+	}
+
+	// Frames written to the file so far, at its rate. While resampling, the last few frames
+	// passed to write are held back until more come in or the recording is finished, and FLAC
+	// holds back those of a block still being filled.
+	pub fn frames_written(&self) -> u64 {
+		#[cfg(feature = "flac-encode")]
+		if let Some(flac) = &self.flac {
+			return flac.frames();
+		}
+		self.data_len as u64 / (self.format.bits() as u64 / 8 * self.channels as u64)
+	}
+
+	// Completes the header and flushes the file.
+	pub fn finish(mut self) -> Result<(), AudioError> {
+		self.finalize()
+	}
+
+	fn finalize(&mut self) -> Result<(), AudioError> {
+		if self.writer.is_none() {
+			return Ok(());
+		}
+		// The resampler's tail goes out first, so the file ends with the last frame recorded.
+		if let Some(mut resampler) = self.resampler.take() {
+			let mut resampled = std::mem::take(&mut self.resampled);
+			resampled.clear();
+			resampler.flush(&mut resampled);
+			self.write_file(&resampled)?;
+		}
+		let mut writer = match self.writer.take() {
+			Some(writer) => writer,
+			None => return Ok(())
+		};
+		#[cfg(feature = "flac-encode")]
+		if let Some(flac) = &mut self.flac {
+			flac.finish(&mut writer)?;
+			writer.flush()?;
+			return Ok(());
+		}
+		let header_len = self.format.header_len();
+		writer.seek(SeekFrom::Start(4))?;
+		writer.write_all(&(header_len - 8 + self.data_len).to_le_bytes())?;
+		if self.format == WavSampleFormat::F32 {
+			writer.seek(SeekFrom::Start(FACT_OFFSET))?;
+			writer.write_all(&(self.frames_written() as u32).to_le_bytes())?;
+		}
+		writer.seek(SeekFrom::Start(header_len as u64 - 4))?;
+		writer.write_all(&self.data_len.to_le_bytes())?;
+		writer.flush()?;
+		Ok(())
+	}
+}
+
+impl Drop for AudioRecorder {
+	fn drop(&mut self) {
+		if let Err(err) = self.finalize() {
+			eprintln!("failed to finish the recording: {}", err);
+		}
+	}
+}
+
+// Writes the header of an empty file; finalize fills in the sizes.
+fn write_header(writer: &mut impl Write, sample_rate: u32, channels: u16, format: WavSampleFormat) -> std::io::Result<()> {
+	let block_align = channels * format.bits() / 8;
+	writer.write_all(b"RIFF")?;
+	writer.write_all(&(format.header_len() - 8).to_le_bytes())?;
+	writer.write_all(b"WAVEfmt ")?;
+	let tag = match format {
+		WavSampleFormat::I16 => WAVE_FORMAT_PCM,
+		WavSampleFormat::F32 => WAVE_FORMAT_IEEE_FLOAT,
+	};
+	let fmt_len: u32 = if format == WavSampleFormat::F32 { 18 } else { 16 };
+	writer.write_all(&fmt_len.to_le_bytes())?;
+	writer.write_all(&tag.to_le_bytes())?;
+	writer.write_all(&channels.to_le_bytes())?;
+	writer.write_all(&sample_rate.to_le_bytes())?;
+	writer.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+	writer.write_all(&block_align.to_le_bytes())?;
+	writer.write_all(&format.bits().to_le_bytes())?;
+	if format == WavSampleFormat::F32 {
+		// No extension bytes, then the fact chunk with the frame count.
+		writer.write_all(&0u16.to_le_bytes())?;
+		writer.write_all(b"fact")?;
+		writer.write_all(&4u32.to_le_bytes())?;
+		writer.write_all(&0u32.to_le_bytes())?;
+	}
+	writer.write_all(b"data")?;
+	writer.write_all(&0u32.to_le_bytes())
+}
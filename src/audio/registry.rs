@@ -0,0 +1,113 @@
+use super::{AudioError, PlaybackControl};
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+
+// Ids handed out so far; every cable built takes the next one.
+static NEXT_CABLE_ID: AtomicU64 = AtomicU64::new(0);
+
+/* CableId: Identifies one AudioCable for as long as the process runs; ids are never reused.
+ * Returned by AudioCable::id().
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CableId(u64);
+
+impl CableId {
+	pub(super) fn next() -> CableId {
+		CableId(NEXT_CABLE_ID.fetch_add(1, Ordering::Relaxed))
+	}
+}
+
+impl fmt::Display for CableId {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "cable #{}", self.0)
+	}
+}
+
+/* CableRegistry: Finds the cables an app has running by id, e.g. a game with many sounds at
+ * once, to pause, resume or stop them from anywhere.
+ *
+ * Cables only join a registry handed to AudioCableBuilder::registry, and leave it when they're
+ * dropped. The registry holds a PlaybackControl for each rather than the cable itself, so the
+ * app still owns and drops its cables. Clones share the same cables.
+ */
+#[derive(Clone, Default)]
+pub struct CableRegistry {
+	cables: Arc<Mutex<HashMap<CableId, PlaybackControl>>>,
+}
+
+impl CableRegistry {
+	pub fn new() -> CableRegistry {
+		CableRegistry::default()
+	}
+
+	pub(super) fn insert(&self, id: CableId, control: PlaybackControl) {
+		self.cables.lock().unwrap_or_else(PoisonError::into_inner).insert(id, control);
+	}
+
+	pub(super) fn remove(&self, id: CableId) {
+		self.cables.lock().unwrap_or_else(PoisonError::into_inner).remove(&id);
+	}
+
+	// A handle on the cable `id`, or None once it has been dropped.
+	pub fn control(&self, id: CableId) -> Option<PlaybackControl> {
+		self.cables.lock().unwrap_or_else(PoisonError::into_inner).get(&id).cloned()
+	}
+
+	// The ids of the cables still alive, in the order they were built.
+	pub fn ids(&self) -> Vec<CableId> {
+		let mut ids: Vec<_> = self.cables.lock().unwrap_or_else(PoisonError::into_inner).keys().copied().collect();
+		ids.sort();
+		ids
+	}
+
+	pub fn len(&self) -> usize {
+		self.cables.lock().unwrap_or_else(PoisonError::into_inner).len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+
+	pub fn pause(&self, id: CableId) -> Result<(), AudioError> {
+		self.find(id)?.pause();
+		Ok(())
+	}
+
+	pub fn resume(&self, id: CableId) -> Result<(), AudioError> {
+		self.find(id)?.resume();
+		Ok(())
+	}
+
+	// Stops the cable `id`; see PlaybackControl::stop. It stays in the registry until dropped.
+	pub fn stop(&self, id: CableId) -> Result<(), AudioError> {
+		self.find(id)?.stop()
+	}
+
+	pub fn pause_all(&self) {
+		self.for_each(PlaybackControl::pause);
+	}
+
+	pub fn resume_all(&self) {
+		self.for_each(PlaybackControl::resume);
+	}
+
+	// Stops every cable. Those already stopped are skipped.
+	pub fn stop_all(&self) {
+		self.for_each(|control| {
+			let _ = control.stop();
+		});
+	}
+
+	fn find(&self, id: CableId) -> Result<PlaybackControl, AudioError> {
+		self.control(id).ok_or_else(|| AudioError::InvalidArgument(format!("{} isn't in the registry", id)))
+	}
+
+	// Calls `action` outside the lock, so it can't hold up cables being built or dropped.
+	fn for_each(&self, action: impl Fn(&PlaybackControl)) {
+		let controls: Vec<_> = self.cables.lock().unwrap_or_else(PoisonError::into_inner).values().cloned().collect();
+		controls.iter().for_each(action);
+	}
+}
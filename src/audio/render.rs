@@ -0,0 +1,199 @@
+#[cfg(feature = "flac")]
+use super::source::FlacSource;
+use super::source::{open_reader, AudioSource};
+use super::{duration_to_frames, frames_to_duration, AudioError, AudioRecorder, FlacOptions, Format};
+
+use std::fs::File;
+use std::path::Path;
+use std::time::Duration;
+
+/* DecodedBlocks: Iterates over the decoded blocks of a file without an output device, for
+ * offline processing such as render_to_vec and transcode.
+ *
+ * Blocks hold interleaved f32 frames at the file's own sample rate and channel count. Iteration
+ * ends after the last block or the first error.
+ */
+pub(crate) struct DecodedBlocks {
+	source: Box<dyn AudioSource>,
+	done: bool,
+}
+
+impl DecodedBlocks {
+	pub(crate) fn open(path: &Path) -> Result<DecodedBlocks, AudioError> {
+		let format = Format::from_path(path)?;
+		let source = open_reader(File::open(path)?, format)?;
+		Ok(DecodedBlocks { source, done: false })
+	}
+
+	pub(crate) fn sample_rate(&self) -> u32 {
+		self.source.sample_rate()
+	}
+
+	pub(crate) fn channels(&self) -> u16 {
+		self.source.channels()
+	}
+}
+
+impl Iterator for DecodedBlocks {
+	type Item = Result<Vec<f32>, AudioError>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		while !self.done {
+			let mut block = Vec::new();
+			match self.source.read_block(&mut block) {
+				Ok(more) => self.done = !more,
+				Err(err) => {
+					self.done = true;
+					return Some(Err(err));
+				}
+			}
+			if !block.is_empty() {
+				return Some(Ok(block));
+			}
+		}
+		None
+	}
+}
+
+/* RenderedAudio: A whole file decoded by render_to_vec, as interleaved f32 samples at the file's
+ * own sample rate and channel count.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct RenderedAudio {
+	pub samples: Vec<f32>,
+	pub sample_rate: u32,
+	pub channels: u16,
+}
+
+impl RenderedAudio {
+	pub fn frames(&self) -> usize {
+		self.samples.len() / self.channels.max(1) as usize
+	}
+
+	pub fn duration(&self) -> Duration {
+		frames_to_duration(self.frames() as u64, self.sample_rate)
+	}
+}
+
+// Decodes the whole file at `path` into memory.
+pub fn render_to_vec(path: &Path) -> Result<RenderedAudio, AudioError> {
+	let blocks = DecodedBlocks::open(path)?;
+	let (sample_rate, channels) = (blocks.sample_rate(), blocks.channels());
+	let mut samples = Vec::new();
+	for block in blocks {
+		samples.extend_from_slice(&block?);
+	}
+	Ok(RenderedAudio { samples, sample_rate, channels })
+}
+
+// Decodes only the first `duration` of the file at `path`, e.g. for an audition clip, stopping
+// the decoder as soon as it has enough. Samples are interleaved f32 at the file's own sample rate
+// and channel count; a file shorter than `duration` is returned whole.
+pub fn preview(path: &Path, duration: Duration) -> Result<Vec<f32>, AudioError> {
+	let blocks = DecodedBlocks::open(path)?;
+	let len = duration_to_frames(duration, blocks.sample_rate()) as usize * blocks.channels() as usize;
+	let mut samples = Vec::with_capacity(len);
+	for block in blocks {
+		if samples.len() >= len {
+			break;
+		}
+		let block = block?;
+		let take = block.len().min(len - samples.len());
+		samples.extend_from_slice(&block[..take]);
+	}
+	Ok(samples)
+}
+
+// Decodes `input` and writes it to `output` with the same sample rate and channel count, e.g. to
+// convert a FLAC file for a tool that only reads WAV. The container follows `output`'s extension:
+// .wav for a 16-bit WAV file, or .flac for a 16-bit FLAC file at the default compression level,
+// which needs the flac-encode feature. Other extensions, or .flac without the feature, fail with
+// Unsupported before anything is decoded.
+pub fn transcode(input: &Path, output: &Path) -> Result<(), AudioError> {
+	let extension = output.extension().map(|extension| extension.to_string_lossy().to_lowercase());
+	let flac = match extension.as_deref() {
+		Some("wav") | Some("wave") => false,
+		Some("flac") => true,
+		_ => return Err(AudioError::Unsupported(format!("can only transcode to WAV or FLAC, not \"{}\"", output.display())))
+	};
+	if flac && !cfg!(feature = "flac-encode") {
+		return Err(AudioError::Unsupported(String::from("transcoding to FLAC needs the flac-encode feature")));
+	}
+
+	let blocks = DecodedBlocks::open(input)?;
+	let mut recorder = if flac {
+		AudioRecorder::create_flac(output, blocks.sample_rate(), blocks.channels(), FlacOptions::default())?
+	} else {
+		AudioRecorder::create(output, blocks.sample_rate(), blocks.channels())?
+	};
+	for block in blocks {
+		recorder.write(&block?)?;
+	}
+	recorder.finish()
+}
+
+// Decodes `a` and `b` and compares them sample by sample, each within `tolerance` of the other
+// (0.0 for bit-exact), e.g. to check that a transcode or a lossless pipeline kept the audio
+// intact. Files with a different sample rate or channel count never match. first_difference says
+// where they part.
+pub fn samples_equal(a: &Path, b: &Path, tolerance: f32) -> Result<bool, AudioError> {
+	Ok(first_difference(a, b, tolerance)?.is_none())
+}
+
+// Compares `a` and `b` as samples_equal does, returning the index of the first interleaved sample
+// that differs by more than `tolerance`, or None if they match. A file that ends early differs at
+// the first sample it's missing, and files with a different sample rate or channel count at 0.
+// Both are decoded a block at a time, so neither is held in memory whole.
+pub fn first_difference(a: &Path, b: &Path, tolerance: f32) -> Result<Option<u64>, AudioError> {
+	let mut a = DecodedBlocks::open(a)?;
+	let mut b = DecodedBlocks::open(b)?;
+	if (a.sample_rate(), a.channels()) != (b.sample_rate(), b.channels()) {
+		return Ok(Some(0));
+	}
+
+	let (mut block_a, mut block_b) = (Vec::new(), Vec::new());
+	let (mut at_a, mut at_b) = (0, 0);
+	let mut index = 0u64;
+	loop {
+		if at_a == block_a.len() {
+			block_a = a.next().transpose()?.unwrap_or_default();
+			at_a = 0;
+		}
+		if at_b == block_b.len() {
+			block_b = b.next().transpose()?.unwrap_or_default();
+			at_b = 0;
+		}
+		let len = (block_a.len() - at_a).min(block_b.len() - at_b);
+		if len == 0 {
+			// At least one has ended; they match only if both have.
+			return Ok(if block_a.is_empty() && block_b.is_empty() { None } else { Some(index) });
+		}
+		let differs = block_a[at_a..at_a + len].iter().zip(&block_b[at_b..at_b + len])
+			.position(|(&x, &y)| !within(x, y, tolerance));
+		if let Some(offset) = differs {
+			return Ok(Some(index + offset as u64));
+		}
+		at_a += len;
+		at_b += len;
+		index += len as u64;
+	}
+}
+
+// Decodes the whole FLAC file at `path` and checks the audio against the MD5 its STREAMINFO
+// declares, failing with IntegrityMismatch if they differ, e.g. to find files damaged on disk or
+// in transfer before a long playlist reaches them. A damaged frame usually fails to decode
+// first, with a Decode error. Files that leave the MD5 unset, and other formats, which don't
+// declare one, fail with Unsupported. Every sample is decoded, so this takes as long as
+// render_to_vec; it's meant for offline checks rather than as part of playback.
+pub fn verify_integrity(path: &Path) -> Result<(), AudioError> {
+	match Format::from_path(path)? {
+		#[cfg(feature = "flac")]
+		Format::FLAC => FlacSource::from_reader(File::open(path)?)?.verify_md5(),
+		format => Err(AudioError::Unsupported(format!("only FLAC files declare an MD5 of their audio, not {:?}", format)))
+	}
+}
+
+// Identical samples match whatever the tolerance, NaNs included; any other NaN never does.
+fn within(x: f32, y: f32, tolerance: f32) -> bool {
+	x.to_bits() == y.to_bits() || (x - y).abs() <= tolerance
+}
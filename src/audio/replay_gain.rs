@@ -0,0 +1,73 @@
+/* ReplayGainMode: Which of a track's ReplayGain tags AudioCableBuilder::replay_gain plays it at.
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ReplayGainMode {
+	// The tags are ignored and every track plays at the level it was mastered at.
+	#[default]
+	Off,
+	// Each track is brought to the reference loudness on its own (REPLAYGAIN_TRACK_GAIN), so a
+	// shuffled playlist plays at an even level. Falls back to the album gain.
+	Track,
+	// Every track of an album gets the album's gain (REPLAYGAIN_ALBUM_GAIN), so a quiet interlude
+	// stays quieter than the songs around it, as it was mastered, when the album is played in
+	// order. Falls back to the track gain.
+	Album,
+}
+
+/* ReplayGain: A track's ReplayGain tags, read while its source was opened: gains in dB and peaks
+ * as linear sample levels.
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct ReplayGain {
+	pub(crate) track_gain: Option<f32>,
+	pub(crate) track_peak: Option<f32>,
+	pub(crate) album_gain: Option<f32>,
+	pub(crate) album_peak: Option<f32>,
+}
+
+impl ReplayGain {
+	// Picks the ReplayGain fields out of (key, value) tags, such as Vorbis comments, ignoring
+	// the keys' case and values that don't parse. None if none of them is there.
+	#[cfg(any(feature = "flac", feature = "aac", feature = "symphonia"))]
+	pub(crate) fn from_tags<'a>(tags: impl IntoIterator<Item = (&'a str, &'a str)>) -> Option<ReplayGain> {
+		let mut replay_gain = ReplayGain::default();
+		for (key, value) in tags {
+			let field = match key.to_ascii_uppercase().as_str() {
+				"REPLAYGAIN_TRACK_GAIN" => &mut replay_gain.track_gain,
+				"REPLAYGAIN_TRACK_PEAK" => &mut replay_gain.track_peak,
+				"REPLAYGAIN_ALBUM_GAIN" => &mut replay_gain.album_gain,
+				"REPLAYGAIN_ALBUM_PEAK" => &mut replay_gain.album_peak,
+				_ => continue
+			};
+			if let Some(parsed) = parse_value(value) {
+				*field = Some(parsed);
+			}
+		}
+		Some(replay_gain).filter(|replay_gain| *replay_gain != ReplayGain::default())
+	}
+
+	// The linear gain to play the track at in `mode`, None for Off or when neither gain is
+	// tagged. It's held down so the tagged peak that goes with it doesn't clip.
+	pub(crate) fn gain(&self, mode: ReplayGainMode) -> Option<f32> {
+		let (gain_db, peak) = match mode {
+			ReplayGainMode::Off => return None,
+			ReplayGainMode::Track => self.track_gain.map(|gain| (gain, self.track_peak))
+				.or_else(|| self.album_gain.map(|gain| (gain, self.album_peak)))?,
+			ReplayGainMode::Album => self.album_gain.map(|gain| (gain, self.album_peak))
+				.or_else(|| self.track_gain.map(|gain| (gain, self.track_peak)))?
+		};
+		let gain = 10.0f32.powf(gain_db / 20.0);
+		Some(match peak.filter(|&peak| peak > 0.0) {
+			Some(peak) => gain.min(1.0 / peak),
+			None => gain
+		})
+	}
+}
+
+// Reads a tag value such as "-6.54 dB" or "0.988553".
+#[cfg(any(feature = "flac", feature = "aac", feature = "symphonia"))]
+fn parse_value(value: &str) -> Option<f32> {
+	let value = value.trim();
+	let number = value.strip_suffix("dB").or_else(|| value.strip_suffix("db")).unwrap_or(value);
+	number.trim().parse::<f32>().ok().filter(|number| number.is_finite())
+}
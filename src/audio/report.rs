@@ -0,0 +1,130 @@
+use super::{AudioError, CallbackPriority, PlaybackEvent, ProducerProgress};
+
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
+use std::sync::Arc;
+
+// Messages that may be waiting for the forwarding thread at once. The callback only sends on a
+// change of state, so this is plenty; a message that doesn't fit is dropped.
+const REPORT_QUEUE: usize = 16;
+
+// What the output callback tells the forwarding thread.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum RealtimeMessage {
+	// The data channel ran dry and the callback played silence in its place.
+	Underrun,
+	// A callback was filled entirely again after an underrun.
+	Recovered,
+	// The callback thread's priority couldn't be raised, with the OS error code if there was one.
+	PriorityDenied(Option<i32>),
+}
+
+/* RealtimeReporter: Lets the output callback report problems without allocating, locking or
+ * blocking.
+ *
+ * Every callback updates the counters, which other threads can read at any time. Only a change
+ * of state sends a message, through a bounded channel whose slots are allocated up front, so a
+ * device that keeps underrunning sends one message rather than one per callback. The forwarding
+ * thread started with forward turns those messages into PlaybackEvents.
+ */
+pub(super) struct RealtimeReporter {
+	// Callbacks that couldn't be filled from the data channel.
+	underruns: AtomicU64,
+	// Samples played as silence because the data channel was empty.
+	missing_samples: AtomicU64,
+	// Kept here rather than in the callback so rebuilding the stream doesn't repeat a message.
+	underrunning: AtomicBool,
+	// The CallbackPriority the latest stream's callback thread found itself at.
+	priority: AtomicU8,
+	messages: crossbeam_channel::Sender<RealtimeMessage>,
+}
+
+impl RealtimeReporter {
+	pub(super) fn new() -> (Arc<RealtimeReporter>, crossbeam_channel::Receiver<RealtimeMessage>) {
+		let (messages, receiver) = crossbeam_channel::bounded(REPORT_QUEUE);
+		let reporter = RealtimeReporter {
+			underruns: AtomicU64::new(0),
+			missing_samples: AtomicU64::new(0),
+			underrunning: AtomicBool::new(false),
+			priority: AtomicU8::new(CallbackPriority::Unknown as u8),
+			messages,
+		};
+		(Arc::new(reporter), receiver)
+	}
+
+	// Called by the output callback once per block with the number of samples it had to fill
+	// with silence.
+	pub(super) fn report_block(&self, missing: u64) {
+		let underrun = missing > 0;
+		if underrun {
+			self.underruns.fetch_add(1, Ordering::Relaxed);
+			self.missing_samples.fetch_add(missing, Ordering::Relaxed);
+		}
+		if self.underrunning.swap(underrun, Ordering::Relaxed) != underrun {
+			let message = if underrun { RealtimeMessage::Underrun } else { RealtimeMessage::Recovered };
+			// try_send on a bounded channel neither blocks nor allocates.
+			let _ = self.messages.try_send(message);
+		}
+	}
+
+	// Called by the output callback when AudioCableBuilder::realtime_priority couldn't be honoured.
+	pub(super) fn report_priority_denied(&self, os_error: Option<i32>) {
+		let _ = self.messages.try_send(RealtimeMessage::PriorityDenied(os_error));
+	}
+
+	// Called by the output callback on its first block, after any attempt to raise its priority.
+	pub(super) fn report_priority(&self, priority: CallbackPriority) {
+		self.priority.store(priority as u8, Ordering::Relaxed);
+	}
+
+	pub(super) fn priority(&self) -> CallbackPriority {
+		match self.priority.load(Ordering::Relaxed) {
+			priority if priority == CallbackPriority::Normal as u8 => CallbackPriority::Normal,
+			priority if priority == CallbackPriority::Elevated as u8 => CallbackPriority::Elevated,
+			priority if priority == CallbackPriority::Realtime as u8 => CallbackPriority::Realtime,
+			_ => CallbackPriority::Unknown
+		}
+	}
+
+	pub(super) fn underruns(&self) -> u64 {
+		self.underruns.load(Ordering::Relaxed)
+	}
+
+	pub(super) fn missing_samples(&self) -> u64 {
+		self.missing_samples.load(Ordering::Relaxed)
+	}
+}
+
+// Starts the thread turning a reporter's messages into events. It ends once the consumer and its
+// stream are gone. The data channel runs dry once the source has been sent in full, which is the
+// end of playback rather than an underrun, so that one isn't reported, and neither is running dry
+// while the source is paused.
+pub(super) fn forward(messages: crossbeam_channel::Receiver<RealtimeMessage>, events: crossbeam_channel::Sender<PlaybackEvent>, progress: Arc<ProducerProgress>) -> Result<(), AudioError> {
+	std::thread::Builder::new().name(String::from("audios-events")).spawn(move || {
+		let mut reported = false;
+		for message in messages {
+			let event = match message {
+				RealtimeMessage::Underrun if !progress.finished.load(Ordering::Relaxed) && !progress.source_paused.load(Ordering::Relaxed) => {
+					reported = true;
+					PlaybackEvent::Underrun
+				},
+				RealtimeMessage::Recovered if reported => {
+					reported = false;
+					PlaybackEvent::Recovered
+				},
+				RealtimeMessage::PriorityDenied(os_error) => {
+					let reason = match os_error {
+						Some(code) => std::io::Error::from_raw_os_error(code).to_string(),
+						None => String::from("not supported on this platform")
+					};
+					eprintln!("couldn't raise the output callback's thread priority: {}", reason);
+					PlaybackEvent::PriorityNotRaised(reason)
+				},
+				_ => continue
+			};
+			if events.send(event).is_err() {
+				return;
+			}
+		}
+	})?;
+	Ok(())
+}
@@ -0,0 +1,206 @@
+use std::time::Duration;
+
+/* ResampleQuality: Interpolation used to convert the source sample rate to the device's.
+ *
+ * The cost is roughly proportional to the number of input frames read per output sample:
+ *  - Linear reads 2 frames. Cheapest, but dulls high frequencies and aliases on large ratios.
+ *  - Cubic reads 4 frames (Catmull-Rom). A good trade-off for real-time playback.
+ *  - Sinc reads 32 frames through a Blackman-windowed sinc, about 8x the cost of Cubic. Best
+ *    reserved for offline rendering or machines with CPU to spare.
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ResampleQuality {
+	Linear,
+	#[default]
+	Cubic,
+	Sinc,
+}
+
+impl ResampleQuality {
+	// Number of input frames needed on each side of the interpolation point.
+	fn half_width(self) -> usize {
+		match self {
+			ResampleQuality::Linear => 1,
+			ResampleQuality::Cubic => 2,
+			ResampleQuality::Sinc => 16,
+		}
+	}
+}
+
+/* Resampler: Streaming sample rate converter for interleaved f32 audio.
+ *
+ * Input can be pushed in arbitrarily sized blocks of whole frames; output is produced as soon as
+ * enough input frames surround the next interpolation point. `flush` must be called once the
+ * input ends so the tail is emitted and the output length comes out at
+ * ceil(input_frames * output_rate / input_rate).
+ *
+ * When the rates match and no drift correction is set, input is copied straight to the output
+ * without interpolating or waiting for frames ahead, and only the last few frames are kept in
+ * case interpolation starts later.
+ */
+pub(super) struct Resampler {
+	quality: ResampleQuality,
+	channels: usize,
+	// Input frames advanced per output frame.
+	step: f64,
+	// Normalised low-pass cutoff for the sinc kernel, lowered when downsampling to avoid aliasing.
+	cutoff: f64,
+	output_rate: u32,
+	input_rate: u32,
+	// Interleaved input frames that may still be needed for interpolation.
+	buffer: Vec<f32>,
+	// Read position in frames relative to the start of `buffer`.
+	position: f64,
+	frames_in: u64,
+	frames_out: u64,
+}
+
+impl Resampler {
+	pub(super) fn new(input_rate: u32, output_rate: u32, channels: u16, quality: ResampleQuality) -> Resampler {
+		let channels = channels.max(1) as usize;
+		let half = quality.half_width();
+		Resampler {
+			quality,
+			channels,
+			step: input_rate as f64 / output_rate as f64,
+			cutoff: (output_rate as f64 / input_rate as f64).min(1.0),
+			output_rate,
+			input_rate,
+			// Pretend the source was preceded by silence so the first output lands on frame 0.
+			buffer: vec![0.0; (half - 1) * channels],
+			position: (half - 1) as f64,
+			frames_in: 0,
+			frames_out: 0,
+		}
+	}
+
+	// Stretches the output by `correction` (e.g. 1.0001 for 100 ppm more output frames) to follow
+	// a device clock that runs slightly off its nominal rate. Takes effect from the next frame.
+	pub(super) fn set_drift_correction(&mut self, correction: f64) {
+		self.step = self.input_rate as f64 / (self.output_rate as f64 * correction);
+	}
+
+	pub(super) fn process(&mut self, input: &[f32], output: &mut Vec<f32>) {
+		self.frames_in += (input.len() / self.channels) as u64;
+		if self.is_passthrough() {
+			output.extend_from_slice(input);
+			self.frames_out = self.frames_in;
+			// Exactly the frames interpolation would need before the next one, so position stays
+			// where it is.
+			let history = (self.quality.half_width() - 1) * self.channels;
+			if input.len() >= history {
+				self.buffer.clear();
+				self.buffer.extend_from_slice(&input[input.len() - history..]);
+			} else {
+				self.buffer.extend_from_slice(input);
+				self.buffer.drain(..self.buffer.len() - history);
+			}
+			return;
+		}
+		self.buffer.extend_from_slice(input);
+		self.drain(output, None);
+	}
+
+	// How far the output trails the input: frames are only interpolated once the ones ahead of
+	// them have come in, so the last `half_width` frames pushed wait for more. Nothing waits while
+	// the input is copied straight through. The kernels are centred on each output frame, so this
+	// is a delay in getting the audio out only; every frame still lands at its own time.
+	pub(super) fn latency(&self) -> Duration {
+		if self.is_passthrough() {
+			return Duration::ZERO;
+		}
+		Duration::from_secs_f64(self.quality.half_width() as f64 / self.input_rate.max(1) as f64)
+	}
+
+	// Whether the next output frame is exactly the next input frame: the rates match and every
+	// input frame so far has been output, as happens from the start when nothing is resampled.
+	fn is_passthrough(&self) -> bool {
+		let half = self.quality.half_width();
+		self.step == 1.0 && self.position == (half - 1) as f64 && self.buffer.len() == (half - 1) * self.channels
+	}
+
+	pub(super) fn flush(&mut self, output: &mut Vec<f32>) {
+		// Sources are rejected at a rate of 0 when opened, but a bad rate mustn't panic here.
+		let expected = (self.frames_in * self.output_rate as u64).div_ceil(self.input_rate.max(1) as u64);
+		let padding = self.quality.half_width() * self.channels;
+		self.buffer.resize(self.buffer.len() + padding, 0.0);
+		self.drain(output, Some(expected));
+	}
+
+	fn drain(&mut self, output: &mut Vec<f32>, limit: Option<u64>) {
+		let half = self.quality.half_width();
+		let available = self.buffer.len() / self.channels;
+
+		loop {
+			if let Some(limit) = limit {
+				if self.frames_out >= limit {
+					break;
+				}
+			}
+			let index = self.position.floor() as usize;
+			if index + half >= available {
+				break;
+			}
+			let frac = self.position - index as f64;
+			for channel in 0..self.channels {
+				output.push(self.interpolate(index, frac, channel));
+			}
+			self.position += self.step;
+			self.frames_out += 1;
+		}
+
+		// Drop the frames that no future interpolation point can reach.
+		let keep_from = (self.position.floor() as usize + 1).saturating_sub(half).min(available);
+		if keep_from > 0 {
+			self.buffer.drain(..keep_from * self.channels);
+			self.position -= keep_from as f64;
+		}
+	}
+
+	fn frame(&self, index: usize, channel: usize) -> f32 {
+		self.buffer[index * self.channels + channel]
+	}
+
+	fn interpolate(&self, index: usize, frac: f64, channel: usize) -> f32 {
+		match self.quality {
+			ResampleQuality::Linear => {
+				let x0 = self.frame(index, channel);
+				let x1 = self.frame(index + 1, channel);
+				x0 + (x1 - x0) * frac as f32
+			},
+			ResampleQuality::Cubic => {
+				let xm1 = self.frame(index - 1, channel);
+				let x0 = self.frame(index, channel);
+				let x1 = self.frame(index + 1, channel);
+				let x2 = self.frame(index + 2, channel);
+				let t = frac as f32;
+				let a = -0.5 * xm1 + 1.5 * x0 - 1.5 * x1 + 0.5 * x2;
+				let b = xm1 - 2.5 * x0 + 2.0 * x1 - 0.5 * x2;
+				let c = -0.5 * xm1 + 0.5 * x1;
+				((a * t + b) * t + c) * t + x0
+			},
+			ResampleQuality::Sinc => {
+				let half = self.quality.half_width();
+				let mut sum = 0.0f64;
+				for k in (index + 1 - half)..=(index + half) {
+					let distance = frac - (k as f64 - index as f64);
+					sum += self.frame(k, channel) as f64 * self.sinc_kernel(distance, half as f64);
+				}
+				sum as f32
+			},
+		}
+	}
+
+	fn sinc_kernel(&self, distance: f64, half: f64) -> f64 {
+		use std::f64::consts::PI;
+
+		let x = distance / half;
+		if x.abs() >= 1.0 {
+			return 0.0;
+		}
+		let window = 0.42 + 0.5 * (PI * x).cos() + 0.08 * (2.0 * PI * x).cos();
+		let arg = PI * self.cutoff * distance;
+		let sinc = if arg.abs() < 1e-9 { 1.0 } else { arg.sin() / arg };
+		self.cutoff * sinc * window
+	}
+}
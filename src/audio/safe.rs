@@ -0,0 +1,89 @@
+use super::Effect;
+#[cfg(feature = "true-peak")]
+use super::true_peak::TruePeak;
+
+// How long the limiter takes to recover from a peak, as the time constant of its release.
+const LIMITER_RELEASE_SECS: f32 = 0.15;
+// Quiet files are boosted by at most this much to reach the loudness target; the limiter would
+// otherwise squash a very quiet file's peaks flat.
+pub(super) const MAX_NORMALIZE_GAIN_DB: f32 = 12.0;
+
+/* SafeOutput: Settings of the safe output stage, enabled with AudioCableBuilder::safe_output, which
+ * keeps the output from reaching full scale whatever the volume and effects do.
+ *
+ * It runs last in the output callback, after the custom effects and the volume: first the
+ * loudness normalisation gain, if a target is set, then a peak limiter whose ceiling sits
+ * `headroom_db` below full scale. The limiter reacts within the sample that would cross the
+ * ceiling, so nothing louder ever reaches the device, and recovers over about 150 ms.
+ *
+ * Samples under the ceiling can still describe a waveform that peaks above it between them, which
+ * a DAC then plays, or a lossy encoder clips. With `true_peak` the limiter measures those
+ * inter-sample peaks instead, oversampling 4x, so the ceiling becomes a true-peak one in dBTP, as
+ * broadcast specs such as EBU R 128 (-1 dBTP) ask for. That costs about 50 multiplications per
+ * sample and delays the output by 12 frames, needs the true-peak cargo feature, and is off by
+ * default.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SafeOutput {
+	// How far below 0 dBFS the limiter's ceiling is; 1 dB by default.
+	pub headroom_db: f32,
+	// Integrated loudness in LUFS to normalise the file played to, e.g. -23 (EBU R 128) or -14 for
+	// streaming levels. None, the default, leaves the level alone.
+	pub target_lufs: Option<f32>,
+	// Limit inter-sample (true) peaks rather than sample peaks; needs the true-peak feature.
+	pub true_peak: bool,
+}
+
+impl Default for SafeOutput {
+	fn default() -> Self {
+		SafeOutput { headroom_db: 1.0, target_lufs: None, true_peak: false }
+	}
+}
+
+/* Limiter: The safe output stage in the output callback. All channels share one gain, so a peak
+ * in one doesn't shift the stereo image. Cloned for every output stream, so each starts from
+ * unity gain.
+ */
+#[derive(Clone, Debug)]
+pub(super) struct Limiter {
+	// Linear normalisation gain and ceiling.
+	gain: f32,
+	ceiling: f32,
+	// The gain reduction applied to the latest frame, 1.0 when nothing is being limited.
+	reduction: f32,
+	// Set for SafeOutput::true_peak.
+	#[cfg(feature = "true-peak")]
+	true_peak: Option<TruePeak>,
+}
+
+impl Limiter {
+	pub(super) fn new(settings: SafeOutput, normalize_gain_db: f32) -> Limiter {
+		Limiter {
+			gain: 10.0f32.powf(normalize_gain_db / 20.0),
+			ceiling: 10.0f32.powf(-settings.headroom_db / 20.0),
+			reduction: 1.0,
+			#[cfg(feature = "true-peak")]
+			true_peak: if settings.true_peak { Some(TruePeak::new()) } else { None },
+		}
+	}
+}
+
+impl Effect for Limiter {
+	fn process(&mut self, buffer: &mut [f32], channels: u16, sample_rate: u32) {
+		let release = (-1.0 / (LIMITER_RELEASE_SECS * sample_rate.max(1) as f32)).exp();
+		#[cfg(feature = "true-peak")]
+		if let Some(true_peak) = &mut self.true_peak {
+			true_peak.process(buffer, channels, self.gain, self.ceiling, release, &mut self.reduction);
+			return;
+		}
+		for frame in buffer.chunks_mut(channels.max(1) as usize) {
+			let peak = frame.iter().fold(0.0f32, |peak, sample| peak.max((sample * self.gain).abs()));
+			let needed = if peak > self.ceiling { self.ceiling / peak } else { 1.0 };
+			// Down at once, back up gradually.
+			self.reduction = if needed < self.reduction { needed } else { needed + (self.reduction - needed) * release };
+			for sample in frame.iter_mut() {
+				*sample *= self.gain * self.reduction;
+			}
+		}
+	}
+}
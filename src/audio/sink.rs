@@ -0,0 +1,66 @@
+use super::source::ManualSource;
+use super::AudioError;
+
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+// How much pushed audio may wait for the producer before push blocks, on top of the data channel.
+const SINK_QUEUE: Duration = Duration::from_millis(100);
+
+/* SampleSink: The handle an application pushes its own samples through, to a cable built with
+ * AudioCableBuilder::manual.
+ *
+ * Samples are interleaved f32 frames at the sample rate and channel count given to manual, and
+ * are resampled and processed like those of any other source. push blocks while the cable is
+ * full, so a caller generating audio as fast as it can is paced by the output. Clones push into
+ * the same cable one push at a time, so their frames never interleave; it finishes once every
+ * clone has been dropped and what they pushed has played.
+ */
+#[derive(Clone)]
+pub struct SampleSink {
+	samples: crossbeam_channel::Sender<f32>,
+	// Held by a push for as long as it's sending, so pushes from different clones never interleave
+	// within a frame.
+	pushing: Arc<Mutex<()>>,
+	sample_rate: u32,
+	channels: u16,
+}
+
+impl SampleSink {
+	pub(super) fn new(sample_rate: u32, channels: u16) -> (SampleSink, ManualSource) {
+		let capacity = (SINK_QUEUE.as_secs_f64() * sample_rate as f64) as usize * channels.max(1) as usize;
+		let (samples, received) = crossbeam_channel::bounded(capacity.max(channels.max(1) as usize));
+		(SampleSink { samples, pushing: Arc::new(Mutex::new(())), sample_rate, channels }, ManualSource::new(received, sample_rate, channels))
+	}
+
+	// Queues `samples`, blocking until there's room for all of them. They have to be whole frames.
+	// Stopped is returned once the cable is gone, InvalidArgument for a partial frame.
+	pub fn push(&self, samples: &[f32]) -> Result<(), AudioError> {
+		if self.channels == 0 || !samples.len().is_multiple_of(self.channels as usize) {
+			return Err(AudioError::InvalidArgument(format!("{} samples aren't whole frames of {} channels", samples.len(), self.channels)));
+		}
+		let _pushing = self.pushing.lock().unwrap_or_else(PoisonError::into_inner);
+		for &sample in samples {
+			self.samples.send(sample).map_err(|_| AudioError::Stopped)?;
+		}
+		Ok(())
+	}
+
+	pub fn sample_rate(&self) -> u32 {
+		self.sample_rate
+	}
+
+	pub fn channels(&self) -> u16 {
+		self.channels
+	}
+}
+
+impl std::fmt::Debug for SampleSink {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("SampleSink")
+			.field("sample_rate", &self.sample_rate)
+			.field("channels", &self.channels)
+			.field("queued", &self.samples.len())
+			.finish()
+	}
+}
@@ -0,0 +1,221 @@
+use super::pace::paced_blocks;
+use super::{AudioError, OutputStage, PlaybackTimestamp, WavSampleFormat};
+
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+// Audio written to the reader at a time. Blocks go out on the output's clock, like a device's
+// callbacks.
+const SOCKET_BLOCK: Duration = Duration::from_millis(10);
+// How often a thread waiting for a reader, or on one that has stopped reading, checks whether
+// it's been stopped.
+const SOCKET_POLL: Duration = Duration::from_millis(20);
+// Starts every connection, ahead of the header's fields.
+const SOCKET_MAGIC: &[u8; 4] = b"APCM";
+// The WAV format tags, for the header to say how samples are stored.
+const FORMAT_TAG_PCM: u16 = 1;
+const FORMAT_TAG_FLOAT: u16 = 3;
+
+#[cfg(unix)]
+type Listener = std::os::unix::net::UnixListener;
+#[cfg(unix)]
+type Connection = std::os::unix::net::UnixStream;
+// A Windows named pipe is created by the reading process; the path is all there is to keep.
+#[cfg(windows)]
+type Listener = PathBuf;
+#[cfg(windows)]
+type Connection = std::fs::File;
+
+/* SocketOutput: Plays a cable's output into a Unix domain socket, or a named pipe on Windows, for
+ * another process to read, e.g. an encoder streaming it on. Set up with
+ * AudioCableBuilder::socket_output.
+ *
+ * On Unix the output listens on a socket it creates at the path and removes again when closed; on
+ * Windows the path names a pipe the reader has created (\\.\pipe\...), which is opened for
+ * writing. One reader is served at a time, and each is sent a 16-byte header first:
+ *
+ *     "APCM", sample rate (u32), channels (u16), bits per sample (u16), format tag (u16: 1 for
+ *     16-bit integer, 3 for 32-bit float, as in WAV), two zero bytes
+ *
+ * all little-endian, then interleaved little-endian samples for as long as it stays connected.
+ * Blocks go out in real time once the cable plays; a reader that falls behind slows the output
+ * down rather than losing audio. While no reader is connected nothing is pulled, so the source
+ * waits where it is and the next reader carries on from there, less whatever was still in the
+ * socket's buffer when the last one hung up.
+ */
+pub(super) struct SocketOutput {
+	// The socket to remove again.
+	#[cfg(unix)]
+	path: PathBuf,
+	playing: Arc<AtomicBool>,
+	stop: Arc<AtomicBool>,
+	thread: Option<JoinHandle<()>>,
+}
+
+impl SocketOutput {
+	// Creates the socket, so a path in use fails the build, and starts the thread serving readers
+	// `stage`'s output in `format`.
+	pub(super) fn start(path: &Path, format: WavSampleFormat, stage: OutputStage, timestamp: Arc<PlaybackTimestamp>) -> Result<SocketOutput, AudioError> {
+		let listener = listen(path)?;
+		let playing = Arc::new(AtomicBool::new(false));
+		let stop = Arc::new(AtomicBool::new(false));
+		let mut writer = SocketWriter { listener, format, stage, timestamp, playing: Arc::clone(&playing), stop: Arc::clone(&stop) };
+		let thread = std::thread::Builder::new().name(String::from("audios-socket-output")).spawn(move || writer.run())?;
+		Ok(SocketOutput {
+			#[cfg(unix)]
+			path: path.to_path_buf(),
+			playing,
+			stop,
+			thread: Some(thread),
+		})
+	}
+
+	// Lets audio flow to a connected reader; until then the thread only accepts.
+	pub(super) fn play(&self) {
+		self.playing.store(true, Ordering::Relaxed);
+	}
+}
+
+impl Drop for SocketOutput {
+	fn drop(&mut self) {
+		self.stop.store(true, Ordering::Relaxed);
+		if let Some(thread) = self.thread.take() {
+			let _ = thread.join();
+		}
+		#[cfg(unix)]
+		let _ = std::fs::remove_file(&self.path);
+	}
+}
+
+#[cfg(unix)]
+fn listen(path: &Path) -> Result<Listener, AudioError> {
+	let listener = Listener::bind(path)?;
+	// Accepting is polled so the thread notices when it's stopped.
+	listener.set_nonblocking(true)?;
+	Ok(listener)
+}
+
+#[cfg(windows)]
+fn listen(path: &Path) -> Result<Listener, AudioError> {
+	Ok(path.to_path_buf())
+}
+
+// The thread's side: waits for a reader, then writes to it until it goes away.
+struct SocketWriter {
+	listener: Listener,
+	format: WavSampleFormat,
+	stage: OutputStage,
+	timestamp: Arc<PlaybackTimestamp>,
+	playing: Arc<AtomicBool>,
+	stop: Arc<AtomicBool>,
+}
+
+impl SocketWriter {
+	fn run(&mut self) {
+		while let Some(mut connection) = self.accept() {
+			if let Err(err) = self.serve(&mut connection) {
+				if !is_disconnect(&err) {
+					eprintln!("an error occurred writing to the output socket: {}", err);
+				}
+			}
+		}
+	}
+
+	// Waits for the next reader; None once stopped.
+	#[cfg(unix)]
+	fn accept(&self) -> Option<Connection> {
+		while !self.stop.load(Ordering::Relaxed) {
+			match self.listener.accept() {
+				Ok((connection, _)) => {
+					// Writes time out so a reader that stops reading can't hold up stopping.
+					if connection.set_nonblocking(false).and_then(|_| connection.set_write_timeout(Some(SOCKET_POLL))).is_ok() {
+						return Some(connection);
+					}
+				},
+				Err(err) if err.kind() == ErrorKind::WouldBlock => std::thread::sleep(SOCKET_POLL),
+				Err(err) => {
+					eprintln!("an error occurred accepting a reader on the output socket: {}", err);
+					std::thread::sleep(SOCKET_POLL);
+				}
+			}
+		}
+		None
+	}
+
+	// Waits for the reader to create the pipe; None once stopped.
+	#[cfg(windows)]
+	fn accept(&self) -> Option<Connection> {
+		while !self.stop.load(Ordering::Relaxed) {
+			match std::fs::OpenOptions::new().write(true).open(&self.listener) {
+				Ok(connection) => return Some(connection),
+				Err(_) => std::thread::sleep(SOCKET_POLL)
+			}
+		}
+		None
+	}
+
+	fn serve(&mut self, connection: &mut Connection) -> std::io::Result<()> {
+		let channels = self.stage.channels();
+		let (bits, tag) = match self.format {
+			WavSampleFormat::I16 => (16u16, FORMAT_TAG_PCM),
+			WavSampleFormat::F32 => (32u16, FORMAT_TAG_FLOAT),
+		};
+		let mut header = Vec::with_capacity(16);
+		header.extend_from_slice(SOCKET_MAGIC);
+		header.extend_from_slice(&self.stage.sample_rate.to_le_bytes());
+		header.extend_from_slice(&channels.to_le_bytes());
+		header.extend_from_slice(&bits.to_le_bytes());
+		header.extend_from_slice(&tag.to_le_bytes());
+		header.extend_from_slice(&[0, 0]);
+		self.write_all(connection, &header)?;
+
+		let frames = ((self.stage.sample_rate as f64 * SOCKET_BLOCK.as_secs_f64()) as usize).max(1);
+		let mut block = vec![0.0f32; frames * channels as usize];
+		let mut bytes = Vec::with_capacity(block.len() * bits as usize / 8);
+		let (playing, stop) = (Arc::clone(&self.playing), Arc::clone(&self.stop));
+		paced_blocks(SOCKET_BLOCK, SOCKET_POLL, &playing, &stop, || {
+			let frame = self.stage.played.load(Ordering::Relaxed) / channels as u64;
+			self.timestamp.store(frame, std::time::Instant::now());
+			self.stage.fill(&mut block);
+
+			bytes.clear();
+			for &sample in &block {
+				match self.format {
+					WavSampleFormat::I16 => {
+						let sample = (sample * 32768.0).round().clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+						bytes.extend_from_slice(&sample.to_le_bytes());
+					},
+					WavSampleFormat::F32 => bytes.extend_from_slice(&sample.to_le_bytes())
+				}
+			}
+			self.write_all(connection, &bytes)
+		})
+	}
+
+	// write_all that keeps at it through write timeouts until the thread is stopped, so a block is
+	// never cut short while the reader is still there.
+	fn write_all(&self, connection: &mut Connection, mut bytes: &[u8]) -> std::io::Result<()> {
+		while !bytes.is_empty() {
+			match connection.write(bytes) {
+				Ok(0) => return Err(std::io::Error::from(ErrorKind::WriteZero)),
+				Ok(written) => bytes = &bytes[written..],
+				Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted) => {
+					if self.stop.load(Ordering::Relaxed) {
+						return Ok(());
+					}
+				},
+				Err(err) => return Err(err)
+			}
+		}
+		Ok(())
+	}
+}
+
+// Errors that only mean the reader has gone.
+fn is_disconnect(err: &std::io::Error) -> bool {
+	matches!(err.kind(), ErrorKind::BrokenPipe | ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted | ErrorKind::WriteZero)
+}
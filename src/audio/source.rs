@@ -0,0 +1,189 @@
+use super::replay_gain::ReplayGain;
+use super::{AudioError, AudioTrackInfo, BroadcastInfo, Chapter, Format};
+
+use std::io::{Read, Seek};
+
+#[cfg(feature = "tokio")]
+mod async_read;
+#[cfg(any(feature = "aac", feature = "symphonia"))]
+mod bwf;
+mod counting;
+mod device;
+mod icy;
+#[cfg(feature = "symphonia")]
+mod large_wav;
+mod manual;
+mod memory;
+mod pcm;
+mod pipe;
+#[cfg(feature = "flac")]
+mod flac;
+#[cfg(any(feature = "aac", feature = "symphonia"))]
+mod symphonia;
+
+#[cfg(feature = "tokio")]
+pub(crate) use self::async_read::AsyncReadBridge;
+pub(crate) use self::counting::CountingReader;
+pub(crate) use self::device::DeviceSource;
+pub(crate) use self::icy::{IcyReader, IcyStream};
+#[cfg(feature = "symphonia")]
+pub(crate) use self::large_wav::LargeWavSource;
+pub(crate) use self::manual::ManualSource;
+pub(crate) use self::memory::{MemorySamples, MemorySource};
+pub(crate) use self::pcm::PcmSource;
+pub(crate) use self::pipe::PipeReader;
+#[cfg(feature = "flac")]
+pub(crate) use self::flac::FlacSource;
+#[cfg(any(feature = "aac", feature = "symphonia"))]
+pub(crate) use self::symphonia::SymphoniaSource;
+
+/* ReadSeek: Any reader a source can be decoded from, boxed so AudioCableBuilder needn't be generic.
+ */
+pub(crate) trait ReadSeek: Read + Seek + Send {}
+
+impl<T: Read + Seek + Send> ReadSeek for T {}
+
+// Opens `reader` with whichever decoder handles `format` in this build.
+pub(crate) fn open_reader<R: Read + Seek + Send + 'static>(reader: R, format: Format) -> Result<Box<dyn AudioSource>, AudioError> {
+	if !format.is_enabled() {
+		return Err(AudioError::UnsupportedFormat { extension: String::from(format.extension()), feature: Some(format.feature()) });
+	}
+	match format {
+		#[cfg(feature = "flac")]
+		Format::FLAC => Ok(Box::new(FlacSource::from_reader(reader)?)),
+		// RF64 and Wave64, which symphonia doesn't read, are only found in readers that can seek.
+		#[cfg(feature = "symphonia")]
+		Format::WAV => match LargeWavSource::probe(reader)? {
+			Ok(source) => Ok(Box::new(source)),
+			Err(reader) => Ok(Box::new(SymphoniaSource::from_reader(reader, format.extension())?))
+		},
+		#[cfg(any(feature = "aac", feature = "symphonia"))]
+		_ => Ok(Box::new(SymphoniaSource::from_reader(reader, format.extension())?)),
+		#[cfg(not(any(feature = "aac", feature = "symphonia")))]
+		_ => {
+			// Unused when no decoder is compiled in at all.
+			let _ = reader;
+			unreachable!("only FLAC is enabled without symphonia")
+		}
+	}
+}
+
+/* TrackChoice: Which of a file's audio tracks to play, as given to the builder.
+ */
+#[derive(Clone, Debug)]
+pub(crate) enum TrackChoice {
+	Index(usize),
+	// A language tag, matched regardless of case.
+	Language(String),
+}
+
+impl TrackChoice {
+	// The index of the chosen track among `tracks`.
+	pub(crate) fn pick(&self, tracks: &[AudioTrackInfo]) -> Result<usize, AudioError> {
+		let found = match self {
+			TrackChoice::Index(index) => tracks.iter().position(|track| track.index == *index),
+			TrackChoice::Language(language) => tracks.iter()
+				.position(|track| track.language.as_deref().is_some_and(|track_language| track_language.eq_ignore_ascii_case(language)))
+		};
+		found.ok_or_else(|| {
+			let wanted = match self {
+				TrackChoice::Index(index) => format!("{}", index),
+				TrackChoice::Language(language) => format!("in \"{}\"", language)
+			};
+			let languages: Vec<_> = tracks.iter().map(|track| track.language.as_deref().unwrap_or("unknown")).collect();
+			AudioError::InvalidArgument(format!("no audio track {} among the source's {} ({})", wanted, tracks.len(), languages.join(", ")))
+		})
+	}
+}
+
+/* CoverArt: A picture embedded in the source file, e.g. a FLAC PICTURE block or an ID3 APIC frame.
+ */
+#[derive(Clone, Debug)]
+pub(crate) struct CoverArt {
+	pub(crate) mime_type: String,
+	pub(crate) data: Vec<u8>,
+}
+
+/* AudioSource: A decoder that hands interleaved f32 frames to the producer thread.
+ *
+ * Implementations decode lazily, one block per read_block call, so the producer never holds more
+ * than a block plus the bounded data channel in memory.
+ */
+pub(crate) trait AudioSource: Send {
+	fn sample_rate(&self) -> u32;
+
+	fn channels(&self) -> u16;
+
+	// Appends the next decoded block of interleaved samples to `buffer`. Returns Ok(false) once
+	// the source is exhausted.
+	fn read_block(&mut self, buffer: &mut Vec<f32>) -> Result<bool, AudioError>;
+
+	// The embedded picture, read while the source was opened.
+	fn cover_art(&self) -> Option<&CoverArt> {
+		None
+	}
+
+	// Tracks of the CUE sheet embedded in the source, read while it was opened.
+	fn chapters(&self) -> &[Chapter] {
+		&[]
+	}
+
+	// The audio tracks of the file, of which the source plays the one select_audio_track last
+	// picked or the default. Most formats only have the one being played.
+	fn audio_tracks(&self) -> Vec<AudioTrackInfo> {
+		vec![AudioTrackInfo { index: 0, language: None, sample_rate: Some(self.sample_rate()), channels: Some(self.channels()), default: true }]
+	}
+
+	// Switches to the audio track at `index` in audio_tracks, before anything has been read.
+	fn select_audio_track(&mut self, index: usize) -> Result<(), AudioError> {
+		match index {
+			0 => Ok(()),
+			_ => Err(AudioError::InvalidArgument(format!("audio track {} is out of range, the source has one", index)))
+		}
+	}
+
+	// Channels the latest block had as the stream carried it. A live stream may switch layout
+	// midway, e.g. a station going from stereo to mono for a talk segment; read_block still
+	// delivers channels() of them, remixed, so this only tells the change apart.
+	fn decoded_channels(&self) -> u16 {
+		self.channels()
+	}
+
+	// The bext chunk of a Broadcast Wave file, read while the source was opened.
+	fn broadcast_info(&self) -> Option<&BroadcastInfo> {
+		None
+	}
+
+	// The ReplayGain tags, read while the source was opened.
+	fn replay_gain(&self) -> Option<&ReplayGain> {
+		None
+	}
+
+	// How many frames the stream's header says it holds, for sources that declare it, e.g. the
+	// total samples of a FLAC STREAMINFO block. A source that ends short of it is truncated.
+	fn declared_frames(&self) -> Option<u64> {
+		None
+	}
+
+	// Something the source played on past while it was opened, e.g. ID3v2 tags skipped in front
+	// of a FLAC stream, for the producer to report as DecodeWarning. Given out once.
+	fn take_open_warning(&mut self) -> Option<String> {
+		None
+	}
+
+	// Encoded bytes read from the file or stream so far, including headers and anything read
+	// again after a seek. Zero for sources that don't read any, such as an input device.
+	fn bytes_read(&self) -> u64 {
+		0
+	}
+
+	// Whether seek can move the source. Only checked once, when the producer takes the source.
+	fn is_seekable(&self) -> bool {
+		false
+	}
+
+	// Positions the source so the next read_block starts exactly at `frame`.
+	fn seek(&mut self, _frame: u64) -> Result<(), AudioError> {
+		Err(AudioError::Unsupported(String::from("this source can't seek")))
+	}
+}
@@ -0,0 +1,70 @@
+use std::io::{self, Read};
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+// Size of the chunks read from the async stream, and how many may wait for the decoder, so at
+// most 256 KiB is read ahead.
+const ASYNC_CHUNK_LEN: usize = 16 * 1024;
+const ASYNC_CHUNKS: usize = 16;
+
+/* AsyncReadBridge: Turns a tokio AsyncRead into the blocking Read the decoders take.
+ *
+ * A task reads the stream into a bounded queue of chunks, asynchronously and ahead of the
+ * decoder, and the decode thread blocks on the queue. The task runs on a current-thread runtime
+ * on a thread of its own, with nothing else to run, so it waits for room in the queue by
+ * blocking too. It ends with the stream, on its first error, which the decoder then reads, or
+ * once the bridge is dropped.
+ */
+pub(crate) struct AsyncReadBridge {
+	chunks: crossbeam_channel::Receiver<io::Result<Vec<u8>>>,
+	chunk: Vec<u8>,
+	// How much of chunk has been read.
+	position: usize,
+}
+
+impl AsyncReadBridge {
+	pub(crate) fn new<R: AsyncRead + Send + Unpin + 'static>(reader: R) -> io::Result<AsyncReadBridge> {
+		let (sender, chunks) = crossbeam_channel::bounded(ASYNC_CHUNKS);
+		let pump = pump(reader, sender);
+		let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+		std::thread::Builder::new().name(String::from("audios-async-read")).spawn(move || runtime.block_on(pump))?;
+		Ok(AsyncReadBridge { chunks, chunk: Vec::new(), position: 0 })
+	}
+}
+
+async fn pump<R: AsyncRead + Unpin>(mut reader: R, sender: crossbeam_channel::Sender<io::Result<Vec<u8>>>) {
+	loop {
+		let mut chunk = vec![0; ASYNC_CHUNK_LEN];
+		let chunk = match reader.read(&mut chunk).await {
+			Ok(0) => return,
+			Ok(read) => {
+				chunk.truncate(read);
+				Ok(chunk)
+			},
+			Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+			Err(err) => Err(err)
+		};
+		let failed = chunk.is_err();
+		if sender.send(chunk).is_err() || failed {
+			return;
+		}
+	}
+}
+
+impl Read for AsyncReadBridge {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		if self.position == self.chunk.len() {
+			match self.chunks.recv() {
+				Ok(chunk) => {
+					self.chunk = chunk?;
+					self.position = 0;
+				},
+				Err(_) => return Ok(0)
+			}
+		}
+		let read = (self.chunk.len() - self.position).min(buf.len());
+		buf[..read].copy_from_slice(&self.chunk[self.position..self.position + read]);
+		self.position += read;
+		Ok(read)
+	}
+}
@@ -0,0 +1,73 @@
+use crate::audio::metadata::{BroadcastInfo, BroadcastLoudness};
+
+use std::io::{Read, Seek, SeekFrom};
+
+// Size of the fixed part of a bext chunk, up to where the coding history starts.
+const BEXT_FIXED_LEN: usize = 602;
+// Larger bext chunks are assumed to be damaged rather than read into memory.
+const BEXT_MAX_LEN: u32 = 1 << 20;
+// What BWF version 2 stores in a loudness field that wasn't measured.
+const LOUDNESS_UNSET: i16 = 0x7fff;
+
+// Walks the RIFF chunks of a WAV file for a bext chunk, leaving `reader` wherever it stopped.
+// Anything that doesn't parse, from a missing RIFF header to a truncated chunk, means no
+// broadcast info rather than an error: the audio is still playable.
+pub(crate) fn read_broadcast_info<R: Read + Seek>(reader: &mut R) -> Option<BroadcastInfo> {
+	let mut header = [0u8; 12];
+	reader.read_exact(&mut header).ok()?;
+	if !matches!(&header[0..4], b"RIFF" | b"RF64" | b"BW64") || &header[8..12] != b"WAVE" {
+		return None;
+	}
+
+	loop {
+		let mut chunk = [0u8; 8];
+		reader.read_exact(&mut chunk).ok()?;
+		let size = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]);
+		if &chunk[0..4] == b"bext" && (BEXT_FIXED_LEN as u32..=BEXT_MAX_LEN).contains(&size) {
+			let mut data = vec![0u8; size as usize];
+			reader.read_exact(&mut data).ok()?;
+			return Some(parse_bext(&data));
+		}
+		// The audio follows data, and RF64 sizes past it don't fit 32 bits anyway.
+		if &chunk[0..4] == b"data" || size == u32::MAX {
+			return None;
+		}
+		// Chunks are padded to an even length.
+		reader.seek(SeekFrom::Current(size as i64 + (size & 1) as i64)).ok()?;
+	}
+}
+
+fn parse_bext(data: &[u8]) -> BroadcastInfo {
+	let u16_at = |offset: usize| u16::from_le_bytes([data[offset], data[offset + 1]]);
+	let u32_at = |offset: usize| u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]]);
+	let loudness_at = |offset: usize| Some(u16_at(offset) as i16).filter(|value| *value != LOUDNESS_UNSET).map(|value| value as f32 / 100.0);
+
+	let version = u16_at(346);
+	BroadcastInfo {
+		description: text(&data[0..256]),
+		originator: text(&data[256..288]),
+		originator_reference: text(&data[288..320]),
+		origination_date: text(&data[320..330]),
+		origination_time: text(&data[330..338]),
+		time_reference: u32_at(338) as u64 | (u32_at(342) as u64) << 32,
+		version,
+		loudness: if version >= 2 {
+			Some(BroadcastLoudness {
+				integrated: loudness_at(412),
+				range: loudness_at(414),
+				max_true_peak: loudness_at(416),
+				max_momentary: loudness_at(418),
+				max_short_term: loudness_at(420),
+			})
+		} else {
+			None
+		},
+		coding_history: text(&data[BEXT_FIXED_LEN..]),
+	}
+}
+
+// bext text is NUL padded ASCII; anything else is replaced rather than rejected.
+fn text(bytes: &[u8]) -> String {
+	let end = bytes.iter().position(|byte| *byte == 0).unwrap_or(bytes.len());
+	String::from_utf8_lossy(&bytes[..end]).trim().to_string()
+}
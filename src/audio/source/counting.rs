@@ -0,0 +1,47 @@
+use std::io::{self, Read, Seek, SeekFrom};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/* CountingReader: Passes everything through to the reader it wraps, counting the bytes read, for
+ * AudioSource::bytes_read. The count is shared, so it can still be read once a decoder owns the
+ * reader. Bytes read again after a seek count again.
+ */
+pub(crate) struct CountingReader<R> {
+	reader: R,
+	count: Arc<AtomicU64>,
+}
+
+impl<R> CountingReader<R> {
+	pub(crate) fn new(reader: R) -> CountingReader<R> {
+		CountingReader { reader, count: Arc::new(AtomicU64::new(0)) }
+	}
+
+	pub(crate) fn bytes_read(&self) -> u64 {
+		self.count.load(Ordering::Relaxed)
+	}
+
+	// The shared count, for once the reader has been handed over.
+	#[cfg(any(feature = "flac", feature = "aac", feature = "symphonia"))]
+	pub(crate) fn count(&self) -> Arc<AtomicU64> {
+		Arc::clone(&self.count)
+	}
+
+	#[cfg(any(feature = "aac", feature = "symphonia"))]
+	pub(crate) fn get_ref(&self) -> &R {
+		&self.reader
+	}
+}
+
+impl<R: Read> Read for CountingReader<R> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		let read = self.reader.read(buf)?;
+		self.count.fetch_add(read as u64, Ordering::Relaxed);
+		Ok(read)
+	}
+}
+
+impl<R: Seek> Seek for CountingReader<R> {
+	fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+		self.reader.seek(pos)
+	}
+}
@@ -0,0 +1,120 @@
+use super::AudioSource;
+use crate::audio::agc::{Agc, AgcControl};
+use crate::audio::AudioError;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+// How long read_block waits for input before handing back an empty block, so the producer keeps
+// handling commands while the device is quiet.
+const INPUT_POLL_TIMEOUT: Duration = Duration::from_millis(10);
+
+/* DeviceSource: Live audio captured from an input device, read from the Splitter output that
+ * InputCapture feeds.
+ *
+ * Frames are mapped onto the output's channel count: mono input is copied to every output
+ * channel, extra input channels are dropped and missing ones are silent. The automatic gain
+ * control, when enabled, runs on the mapped frames. The source ends once the capture stops, and
+ * can't seek.
+ */
+pub(crate) struct DeviceSource {
+	samples: crossbeam_channel::Receiver<f32>,
+	sample_rate: u32,
+	input_channels: usize,
+	output_channels: usize,
+	// Queued input, in samples, beyond which the oldest is skipped to keep up with the device.
+	max_backlog: Option<usize>,
+	frame: Vec<f32>,
+	agc: Agc,
+	agc_control: Arc<AgcControl>,
+}
+
+impl DeviceSource {
+	pub(crate) fn new(samples: crossbeam_channel::Receiver<f32>, sample_rate: u32, input_channels: u16, output_channels: u16, max_latency: Option<Duration>, agc: Agc, agc_control: Arc<AgcControl>) -> DeviceSource {
+		let input_channels = input_channels.max(1) as usize;
+		DeviceSource {
+			samples,
+			sample_rate,
+			input_channels,
+			output_channels: output_channels.max(1) as usize,
+			max_backlog: max_latency.map(|latency| (latency.as_secs_f64() * sample_rate as f64) as usize * input_channels),
+			frame: Vec::with_capacity(input_channels),
+			agc,
+			agc_control,
+		}
+	}
+
+	// Receives one whole input frame. Returns false once the capture has stopped.
+	fn next_frame(&mut self, first: f32) -> bool {
+		self.frame.clear();
+		self.frame.push(first);
+		while self.frame.len() < self.input_channels {
+			// The splitter hands over whole frames, so the rest of this one is already on its way.
+			match self.samples.recv() {
+				Ok(sample) => self.frame.push(sample),
+				Err(_) => return false
+			}
+		}
+		true
+	}
+
+	fn push_frame(&self, buffer: &mut Vec<f32>) {
+		for channel in 0..self.output_channels {
+			let sample = match self.input_channels {
+				1 => self.frame[0],
+				_ => self.frame.get(channel).copied().unwrap_or(0.0)
+			};
+			buffer.push(sample);
+		}
+	}
+}
+
+impl AudioSource for DeviceSource {
+	fn sample_rate(&self) -> u32 {
+		self.sample_rate
+	}
+
+	fn channels(&self) -> u16 {
+		self.output_channels as u16
+	}
+
+	fn read_block(&mut self, buffer: &mut Vec<f32>) -> Result<bool, AudioError> {
+		let start = buffer.len();
+		if let Some(max_backlog) = self.max_backlog {
+			let queued = self.samples.len();
+			if queued > max_backlog {
+				let skip = (queued - max_backlog) / self.input_channels * self.input_channels;
+				for _ in 0..skip {
+					if self.samples.try_recv().is_err() {
+						break;
+					}
+				}
+			}
+		}
+
+		let first = match self.samples.recv_timeout(INPUT_POLL_TIMEOUT) {
+			Ok(sample) => sample,
+			Err(crossbeam_channel::RecvTimeoutError::Timeout) => return Ok(true),
+			Err(crossbeam_channel::RecvTimeoutError::Disconnected) => return Ok(false)
+		};
+		if !self.next_frame(first) {
+			return Ok(false);
+		}
+		self.push_frame(buffer);
+
+		// Take whatever else has arrived, in whole frames.
+		let available = self.samples.len() / self.input_channels;
+		for _ in 0..available {
+			let first = match self.samples.try_recv() {
+				Ok(sample) => sample,
+				Err(_) => break
+			};
+			if !self.next_frame(first) {
+				return Ok(false);
+			}
+			self.push_frame(buffer);
+		}
+		self.agc.process(&self.agc_control, &mut buffer[start..], self.output_channels, self.sample_rate);
+		Ok(true)
+	}
+}
@@ -0,0 +1,411 @@
+use super::{AudioSource, CountingReader, CoverArt};
+use crate::audio::chapter::{self, Chapter};
+use crate::audio::md5::Md5;
+use crate::audio::replay_gain::ReplayGain;
+use crate::audio::AudioError;
+
+use std::fs::File;
+use std::io::{BufReader, ErrorKind, Read, Seek, SeekFrom};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+// FLAC metadata block types claxon doesn't parse, and the picture type of the front cover.
+const SEEKTABLE_BLOCK: u8 = 3;
+const CUESHEET_BLOCK: u8 = 5;
+const PICTURE_BLOCK: u8 = 6;
+const FRONT_COVER: u32 = 3;
+// The sample number of a seek point left as a placeholder by the encoder.
+const PLACEHOLDER_SEEK_POINT: u64 = u64::MAX;
+// Length of an ID3v2 header, and of the footer a tag may repeat it in.
+const ID3_HEADER_LEN: u64 = 10;
+
+/* FlacStream: Where claxon reads blocks from: the FlacReader that parsed the header, or, after a
+ * seek through the seek table, a plain frame reader started partway through the stream. claxon
+ * only builds a FlacReader from the fLaC marker on.
+ */
+enum FlacStream<R: Read> {
+	Header(claxon::FlacReader<R>),
+	Frames(claxon::input::BufferedReader<R>),
+}
+
+impl<R: Read> FlacStream<R> {
+	fn blocks(&mut self) -> claxon::frame::FrameReader<&mut claxon::input::BufferedReader<R>> {
+		match self {
+			FlacStream::Header(reader) => reader.blocks(),
+			FlacStream::Frames(reader) => claxon::frame::FrameReader::new(reader)
+		}
+	}
+
+	fn into_inner(self) -> R {
+		match self {
+			FlacStream::Header(reader) => reader.into_inner(),
+			FlacStream::Frames(reader) => reader.into_inner()
+		}
+	}
+}
+
+/* FlacSource: Decodes FLAC with claxon, one FLAC block at a time, from a file or any other
+ * reader.
+ */
+pub(crate) struct FlacSource<R: Read + Seek> {
+	// Only None after rewinding the reader for a seek failed.
+	reader: Option<FlacStream<CountingReader<R>>>,
+	bytes_read: Arc<AtomicU64>,
+	streaminfo: claxon::metadata::StreamInfo,
+	// Where the FLAC stream begins in the reader, for rewinding; None when the reader can't tell.
+	stream_start: Option<u64>,
+	// Where the first frame begins, past the metadata, relative to stream_start.
+	frames_offset: u64,
+	// (first frame, byte offset from the first frame) of each SEEKTABLE point, in order.
+	seek_points: Vec<(u64, u64)>,
+	// Reused between blocks so decoding doesn't allocate once warmed up.
+	decode_buffer: Vec<i32>,
+	// Frames consumed from the reader so far, i.e. the index of the next block's first frame.
+	decoded_frames: u64,
+	// Frames still to be dropped from the front of upcoming blocks to land a seek exactly.
+	skip_frames: u64,
+	cover_art: Option<CoverArt>,
+	chapters: Vec<Chapter>,
+	replay_gain: Option<ReplayGain>,
+	// What was skipped to get to the stream, until the producer reports it.
+	open_warning: Option<String>,
+}
+
+impl FlacSource<File> {
+	pub(crate) fn open(path: &str) -> Result<FlacSource<File>, AudioError> {
+		FlacSource::from_reader(File::open(path)?)
+	}
+}
+
+impl<R: Read + Seek> FlacSource<R> {
+	// Decodes the FLAC stream starting at the reader's current position, past any ID3v2 tags.
+	pub(crate) fn from_reader(reader: R) -> Result<FlacSource<R>, AudioError> {
+		let mut reader = CountingReader::new(reader);
+		let bytes_read = reader.count();
+		// claxon skips PICTURE, CUESHEET and SEEKTABLE blocks, so they're read in a separate pass first.
+		// Damaged metadata shouldn't keep the audio from playing, and a reader that can't report
+		// its position can't be rewound for the second pass, so it goes without.
+		let mut open_warning = None;
+		let (stream_start, extra) = match reader.stream_position() {
+			Ok(position) => {
+				let start = skip_id3_tags(&mut reader, position)?;
+				if start > position {
+					open_warning = Some(format!("skipped {} bytes of ID3v2 tags in front of the FLAC stream", start - position));
+				}
+				let extra = read_extra_metadata(&mut BufReader::new(&mut reader)).unwrap_or_default();
+				reader.seek(SeekFrom::Start(start))?;
+				(Some(start), extra)
+			},
+			Err(_) => (None, ExtraMetadata::default())
+		};
+
+		let reader = claxon::FlacReader::new(reader)?;
+		let streaminfo = reader.streaminfo();
+		let chapters = chapter::from_cue_tracks(extra.cue_tracks, streaminfo.sample_rate, reader.get_tag("CUESHEET").next());
+		let replay_gain = ReplayGain::from_tags(reader.tags());
+
+		Ok(FlacSource {
+			reader: Some(FlacStream::Header(reader)),
+			bytes_read,
+			streaminfo,
+			stream_start,
+			frames_offset: extra.frames_offset,
+			seek_points: extra.seek_points,
+			decode_buffer: Vec::new(),
+			decoded_frames: 0,
+			skip_frames: 0,
+			cover_art: extra.cover_art,
+			chapters,
+			replay_gain,
+			open_warning,
+		})
+	}
+}
+
+impl<R: Read + Seek> FlacSource<R> {
+	// Decodes the whole stream and checks it against the MD5 of the audio in STREAMINFO, which is
+	// taken over the samples as little-endian signed integers of whole bytes, interleaved.
+	pub(crate) fn verify_md5(mut self) -> Result<(), AudioError> {
+		let declared = self.streaminfo.md5sum;
+		// Encoders that can't work the MD5 out, e.g. when streaming, leave it all zeros.
+		if declared == [0; 16] {
+			return Err(AudioError::Unsupported(String::from("the FLAC file doesn't declare an MD5 of its audio")));
+		}
+		let width = (self.streaminfo.bits_per_sample as usize).div_ceil(8);
+		let channels = self.streaminfo.channels;
+		let reader = self.reader.as_mut()
+			.ok_or_else(|| AudioError::Decode(String::from("the FLAC stream was lost after a failed seek")))?;
+		let mut md5 = Md5::new();
+		let mut bytes = Vec::new();
+		let mut decode_buffer = Vec::new();
+		while let Some(block) = reader.blocks().read_next_or_eof(decode_buffer)? {
+			bytes.clear();
+			for frame in 0..block.duration() {
+				for channel in 0..channels {
+					bytes.extend_from_slice(&block.sample(channel, frame).to_le_bytes()[..width]);
+				}
+			}
+			md5.update(&bytes);
+			decode_buffer = block.into_buffer();
+		}
+		let decoded = md5.finish();
+		if decoded != declared {
+			return Err(AudioError::IntegrityMismatch { declared, decoded });
+		}
+		Ok(())
+	}
+}
+
+// Some taggers put an ID3v2 tag in front of the fLaC marker, where claxon refuses it. Returns where
+// the FLAC stream starts after any such tags, and leaves the reader there.
+fn skip_id3_tags(reader: &mut (impl Read + Seek), mut start: u64) -> std::io::Result<u64> {
+	loop {
+		let mut header = [0u8; ID3_HEADER_LEN as usize];
+		let is_tag = match reader.read_exact(&mut header) {
+			// The size is syncsafe: seven bits to a byte, the top one always clear.
+			Ok(()) => &header[..3] == b"ID3" && header[6..].iter().all(|&byte| byte & 0x80 == 0),
+			Err(err) if err.kind() == ErrorKind::UnexpectedEof => false,
+			Err(err) => return Err(err)
+		};
+		if !is_tag {
+			reader.seek(SeekFrom::Start(start))?;
+			return Ok(start);
+		}
+		let size = header[6..].iter().fold(0u64, |size, &byte| size << 7 | byte as u64);
+		let footer = if header[5] & 0x10 != 0 { ID3_HEADER_LEN } else { 0 };
+		start += ID3_HEADER_LEN + size + footer;
+		reader.seek(SeekFrom::Start(start))?;
+	}
+}
+
+#[derive(Default)]
+struct ExtraMetadata {
+	cover_art: Option<CoverArt>,
+	// (track number, first frame) of every CUE sheet track, lead-out included.
+	cue_tracks: Vec<(u8, u64)>,
+	seek_points: Vec<(u64, u64)>,
+	// Bytes from the fLaC marker to the first frame.
+	frames_offset: u64,
+}
+
+// Walks the metadata blocks for PICTURE blocks, preferring the front cover over any other, and
+// the CUESHEET and SEEKTABLE blocks.
+fn read_extra_metadata(file: &mut (impl Read + Seek)) -> std::io::Result<ExtraMetadata> {
+	let mut marker = [0u8; 4];
+	file.read_exact(&mut marker)?;
+	if &marker != b"fLaC" {
+		return Ok(ExtraMetadata::default());
+	}
+
+	let mut extra = ExtraMetadata { frames_offset: 4, ..ExtraMetadata::default() };
+	let mut front_cover_found = false;
+	loop {
+		let mut header = [0u8; 4];
+		file.read_exact(&mut header)?;
+		let last = header[0] & 0x80 != 0;
+		let length = u32::from_be_bytes([0, header[1], header[2], header[3]]);
+		extra.frames_offset += 4 + length as u64;
+
+		match header[0] & 0x7f {
+			PICTURE_BLOCK if !front_cover_found => {
+				let picture_type = read_u32(file)?;
+				let mime_type = String::from_utf8_lossy(&read_bytes(file)?).into_owned();
+				// Description, then width, height, colour depth and palette size.
+				read_bytes(file)?;
+				file.seek(SeekFrom::Current(16))?;
+				let data = read_bytes(file)?;
+
+				front_cover_found = picture_type == FRONT_COVER;
+				if front_cover_found || extra.cover_art.is_none() {
+					extra.cover_art = Some(CoverArt { mime_type, data });
+				}
+			},
+			CUESHEET_BLOCK => extra.cue_tracks = read_cue_tracks(file)?,
+			SEEKTABLE_BLOCK => extra.seek_points = read_seek_points(file, length)?,
+			_ => {
+				file.seek(SeekFrom::Current(length as i64))?;
+			}
+		}
+
+		if last {
+			break;
+		}
+	}
+	Ok(extra)
+}
+
+// Reads the tracks of a CUESHEET block. A track starts at its own offset plus that of its first
+// index point; the block is left fully consumed.
+fn read_cue_tracks(reader: &mut (impl Read + Seek)) -> std::io::Result<Vec<(u8, u64)>> {
+	// Media catalog number, lead-in samples, then the CD-DA flag and reserved bytes.
+	reader.seek(SeekFrom::Current(128 + 8 + 259))?;
+	let track_count = read_u8(reader)?;
+
+	let mut tracks = Vec::with_capacity(track_count as usize);
+	for _ in 0..track_count {
+		let offset = read_u64(reader)?;
+		let number = read_u8(reader)?;
+		// ISRC, then the track type, pre-emphasis flag and reserved bytes.
+		reader.seek(SeekFrom::Current(12 + 14))?;
+		let index_count = read_u8(reader)?;
+
+		let mut first_index = 0;
+		for index in 0..index_count {
+			let index_offset = read_u64(reader)?;
+			if index == 0 {
+				first_index = index_offset;
+			}
+			// Index point number and reserved bytes.
+			reader.seek(SeekFrom::Current(4))?;
+		}
+		tracks.push((number, offset + first_index));
+	}
+	Ok(tracks)
+}
+
+// Reads the points of a SEEKTABLE block of `length` bytes, 18 to a point, leaving out
+// placeholders and any that don't follow on from the one before.
+fn read_seek_points(reader: &mut (impl Read + Seek), length: u32) -> std::io::Result<Vec<(u64, u64)>> {
+	let mut points: Vec<(u64, u64)> = Vec::new();
+	for _ in 0..length / 18 {
+		let frame = read_u64(reader)?;
+		let offset = read_u64(reader)?;
+		// Frames in the target block.
+		reader.seek(SeekFrom::Current(2))?;
+		if frame != PLACEHOLDER_SEEK_POINT && points.last().is_none_or(|&(last_frame, last_offset)| frame > last_frame && offset > last_offset) {
+			points.push((frame, offset));
+		}
+	}
+	reader.seek(SeekFrom::Current((length % 18) as i64))?;
+	Ok(points)
+}
+
+fn read_u8(reader: &mut impl Read) -> std::io::Result<u8> {
+	let mut byte = [0u8; 1];
+	reader.read_exact(&mut byte)?;
+	Ok(byte[0])
+}
+
+fn read_u64(reader: &mut impl Read) -> std::io::Result<u64> {
+	let mut bytes = [0u8; 8];
+	reader.read_exact(&mut bytes)?;
+	Ok(u64::from_be_bytes(bytes))
+}
+
+fn read_u32(reader: &mut impl Read) -> std::io::Result<u32> {
+	let mut bytes = [0u8; 4];
+	reader.read_exact(&mut bytes)?;
+	Ok(u32::from_be_bytes(bytes))
+}
+
+// Reads a 32-bit big-endian length followed by that many bytes.
+fn read_bytes(reader: &mut impl Read) -> std::io::Result<Vec<u8>> {
+	let length = read_u32(reader)? as usize;
+	let mut bytes = vec![0u8; length];
+	reader.read_exact(&mut bytes)?;
+	Ok(bytes)
+}
+
+impl<R: Read + Seek + Send> AudioSource for FlacSource<R> {
+	fn sample_rate(&self) -> u32 {
+		self.streaminfo.sample_rate
+	}
+
+	fn channels(&self) -> u16 {
+		self.streaminfo.channels as u16
+	}
+
+	fn read_block(&mut self, buffer: &mut Vec<f32>) -> Result<bool, AudioError> {
+		let channels = self.streaminfo.channels;
+		// Full scale of the stream's bit depth, so samples land in [-1.0, 1.0).
+		let scale = 1.0 / (1u64 << (self.streaminfo.bits_per_sample - 1)) as f32;
+		let reader = self.reader.as_mut()
+			.ok_or_else(|| AudioError::Decode(String::from("the FLAC stream was lost after a failed seek")))?;
+		loop {
+			let decode_buffer = std::mem::take(&mut self.decode_buffer);
+			let block = match reader.blocks().read_next_or_eof(decode_buffer)? {
+				Some(block) => block,
+				None => return Ok(false)
+			};
+			let block_frames = block.duration();
+			self.decoded_frames += block_frames as u64;
+
+			let skip = self.skip_frames.min(block_frames as u64) as u32;
+			self.skip_frames -= skip as u64;
+
+			for frame in skip..block_frames {
+				for channel in 0..channels {
+					buffer.push(block.sample(channel, frame) as f32 * scale);
+				}
+			}
+			self.decode_buffer = block.into_buffer();
+			if skip < block_frames {
+				return Ok(true);
+			}
+		}
+	}
+
+	fn cover_art(&self) -> Option<&CoverArt> {
+		self.cover_art.as_ref()
+	}
+
+	fn chapters(&self) -> &[Chapter] {
+		&self.chapters
+	}
+
+	fn replay_gain(&self) -> Option<&ReplayGain> {
+		self.replay_gain.as_ref()
+	}
+
+	// STREAMINFO may leave the total unknown, e.g. for a stream encoded live.
+	fn declared_frames(&self) -> Option<u64> {
+		self.streaminfo.samples
+	}
+
+	fn take_open_warning(&mut self) -> Option<String> {
+		self.open_warning.take()
+	}
+
+	fn bytes_read(&self) -> u64 {
+		self.bytes_read.load(Ordering::Relaxed)
+	}
+
+	fn is_seekable(&self) -> bool {
+		self.stream_start.is_some()
+	}
+
+	// claxon can't seek, so a seek lands on the block the seek table lists closest before the target
+	// and decodes and discards from there. Without a seek table, or a point past where decoding has
+	// got to, seeking forward decodes up to the target and seeking backwards rewinds the reader to
+	// the start of the stream first. Readers that can't seek report Unsupported for the latter.
+	fn seek(&mut self, frame: u64) -> Result<(), AudioError> {
+		let point = self.seek_points.iter().rev().find(|&&(point_frame, _)| point_frame <= frame).copied();
+		if let (Some((point_frame, offset)), Some(stream_start)) = (point, self.stream_start) {
+			if point_frame > self.decoded_frames || frame < self.decoded_frames {
+				let mut inner = match self.reader.take() {
+					Some(reader) => reader.into_inner(),
+					None => return Err(AudioError::Decode(String::from("the FLAC stream was lost after a failed seek")))
+				};
+				inner.seek(SeekFrom::Start(stream_start + self.frames_offset + offset))?;
+				self.reader = Some(FlacStream::Frames(claxon::input::BufferedReader::new(inner)));
+				self.decoded_frames = point_frame;
+			}
+		}
+		if frame < self.decoded_frames {
+			let stream_start = self.stream_start
+				.ok_or_else(|| AudioError::Unsupported(String::from("the reader can't seek backwards")))?;
+			let mut inner = match self.reader.take() {
+				Some(reader) => reader.into_inner(),
+				None => return Err(AudioError::Decode(String::from("the FLAC stream was lost after a failed seek")))
+			};
+			inner.seek(SeekFrom::Start(stream_start)).map_err(|err| match err.kind() {
+				std::io::ErrorKind::Unsupported => AudioError::Unsupported(String::from("the reader can't seek backwards")),
+				_ => AudioError::Io(err)
+			})?;
+			self.reader = Some(FlacStream::Header(claxon::FlacReader::new(inner)?));
+			self.decoded_frames = 0;
+		}
+		self.skip_frames = frame - self.decoded_frames;
+		Ok(())
+	}
+}
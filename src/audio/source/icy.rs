@@ -0,0 +1,292 @@
+use crate::audio::metadata::MetadataFeed;
+use crate::audio::{AudioError, Format, PlaybackEvent};
+
+use std::io::{self, BufRead, BufReader, ErrorKind, Read};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::Duration;
+
+// Redirects followed before giving up, e.g. from a station's public URL to its current relay.
+const MAX_REDIRECTS: usize = 5;
+// Longest response header line read, so a misbehaving server can't grow it without bound.
+const MAX_HEADER_LINE: usize = 8192;
+// How long connecting, and then each read of the response headers, may take before the server is
+// given up on.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/* IcyStream: The audio of an HTTP stream such as an Icecast or SHOUTcast station, once its
+ * response headers have been read. Only plain http:// URLs are supported.
+ */
+pub(crate) struct IcyStream {
+	pub(crate) reader: BufReader<TcpStream>,
+	// The format named by the Content-Type header, if it's one of ours.
+	pub(crate) format: Option<Format>,
+	// Audio bytes between metadata blocks, from icy-metaint; None when the server sends none.
+	pub(crate) metaint: Option<usize>,
+}
+
+impl IcyStream {
+	// Requests `url`, asking for inline metadata, and reads the response headers. A server that
+	// doesn't answer within RESPONSE_TIMEOUT fails with AudioError::Io rather than blocking the
+	// decode thread, or the watchdog reopening it, for good.
+	pub(crate) fn connect(url: &str) -> Result<IcyStream, AudioError> {
+		let mut url = String::from(url);
+		for _ in 0..=MAX_REDIRECTS {
+			let (host, port, path) = split_url(&url)?;
+			let mut stream = connect_timeout(&host, port)?;
+			stream.set_read_timeout(Some(RESPONSE_TIMEOUT))?;
+			// HTTP/1.0 keeps the body free of chunked encoding.
+			let request = format!("GET {} HTTP/1.0\r\nHost: {}\r\nUser-Agent: audios-lib\r\nIcy-MetaData: 1\r\n\r\n", path, host);
+			io::Write::write_all(&mut stream, request.as_bytes())?;
+
+			let mut reader = BufReader::new(stream);
+			let status_line = read_line(&mut reader).map_err(timed_out)?;
+			// SHOUTcast 1 answers "ICY 200 OK" instead of an HTTP status line.
+			let status = status_line.split_whitespace().nth(1).and_then(|status| status.parse::<u16>().ok())
+				.ok_or_else(|| io::Error::new(ErrorKind::InvalidData, format!("not an HTTP response: \"{}\"", status_line)))?;
+
+			let (mut format, mut metaint, mut location) = (None, None, None);
+			loop {
+				let line = read_line(&mut reader).map_err(timed_out)?;
+				if line.is_empty() {
+					break;
+				}
+				let (name, value) = match line.split_once(':') {
+					Some((name, value)) => (name.trim().to_lowercase(), value.trim()),
+					None => continue
+				};
+				match name.as_str() {
+					"content-type" => format = Format::from_mime_type(value),
+					"icy-metaint" => metaint = value.parse::<usize>().ok().filter(|&metaint| metaint > 0),
+					"location" => location = Some(String::from(value)),
+					_ => {}
+				}
+			}
+
+			match (status, location) {
+				(200, _) => {
+					// The audio itself arrives at whatever pace the station sends it.
+					reader.get_ref().set_read_timeout(None)?;
+					return Ok(IcyStream { reader, format, metaint });
+				}
+				(301 | 302 | 303 | 307 | 308, Some(location)) => url = resolve_location(&host, port, &path, &location),
+				_ => return Err(AudioError::Io(io::Error::other(format!("{} answered with status {}", url, status))))
+			}
+		}
+		Err(AudioError::Io(io::Error::other(format!("more than {} redirects from {}", MAX_REDIRECTS, url))))
+	}
+}
+
+// Connects to the first of the host's addresses that answers within RESPONSE_TIMEOUT.
+fn connect_timeout(host: &str, port: u16) -> io::Result<TcpStream> {
+	let mut last_err = None;
+	for address in (host, port).to_socket_addrs()? {
+		match TcpStream::connect_timeout(&address, RESPONSE_TIMEOUT) {
+			Ok(stream) => return Ok(stream),
+			Err(err) => last_err = Some(err)
+		}
+	}
+	Err(last_err.unwrap_or_else(|| io::Error::new(ErrorKind::NotFound, format!("{} has no addresses", host))))
+}
+
+// Names a read that ran out of RESPONSE_TIMEOUT, which the platform may report as WouldBlock.
+fn timed_out(err: io::Error) -> io::Error {
+	match err.kind() {
+		ErrorKind::WouldBlock | ErrorKind::TimedOut => io::Error::new(ErrorKind::TimedOut, format!("no response within {:?}", RESPONSE_TIMEOUT)),
+		_ => err
+	}
+}
+
+// The URL a Location header points to. Servers may send a path, or a reference relative to the
+// request's own path, rather than a whole URL; those are taken to be on the same host.
+fn resolve_location(host: &str, port: u16, path: &str, location: &str) -> String {
+	if location.contains("://") {
+		return String::from(location);
+	}
+	if let Some(authority) = location.strip_prefix("//") {
+		return format!("http://{}", authority);
+	}
+	let path = if location.starts_with('/') {
+		String::from(location)
+	} else {
+		// Relative to the directory of the request's path, leaving out its query.
+		let path = path.split('?').next().unwrap_or(path);
+		format!("{}{}", &path[..=path.rfind('/').unwrap_or(0)], location)
+	};
+	format!("http://{}:{}{}", host, port, path)
+}
+
+// Splits an http:// URL into its host, port (80 by default) and path.
+fn split_url(url: &str) -> Result<(String, u16, String), AudioError> {
+	let rest = match url.split_once("://") {
+		Some((scheme, rest)) if scheme.eq_ignore_ascii_case("http") => rest,
+		Some((scheme, _)) => return Err(AudioError::Unsupported(format!("{}:// streams; only http:// can be played", scheme))),
+		None => return Err(AudioError::InvalidPath(String::from(url)))
+	};
+	let (authority, path) = match rest.find('/') {
+		Some(slash) => (&rest[..slash], &rest[slash..]),
+		None => (rest, "/")
+	};
+	let (host, port) = match authority.rsplit_once(':') {
+		Some((host, port)) => (host, port.parse::<u16>().map_err(|_| AudioError::InvalidPath(String::from(url)))?),
+		None => (authority, 80)
+	};
+	if host.is_empty() {
+		return Err(AudioError::InvalidPath(String::from(url)));
+	}
+	Ok((String::from(host), port, String::from(path)))
+}
+
+fn read_line<R: BufRead>(reader: &mut R) -> io::Result<String> {
+	let mut line = Vec::new();
+	reader.by_ref().take(MAX_HEADER_LINE as u64).read_until(b'\n', &mut line)?;
+	if line.last() != Some(&b'\n') {
+		return Err(io::Error::new(ErrorKind::UnexpectedEof, "the response headers ended early"));
+	}
+	Ok(String::from_utf8_lossy(&line).trim_end().to_string())
+}
+
+/* IcyReader: Separates the audio of an ICY stream from the metadata blocks the server inserts
+ * every `metaint` bytes of it.
+ *
+ * Each block is a length byte, counting 16-byte units, followed by text such as
+ * "StreamTitle='Artist - Song';". Whenever the StreamTitle changes it's stored in the metadata
+ * `feed`, which pushes it to the cable's metadata subscribers, and TrackChanged is sent. The
+ * decoder only ever sees the audio.
+ */
+pub(crate) struct IcyReader<R> {
+	reader: R,
+	metaint: usize,
+	// Audio bytes left before the next metadata block.
+	remaining: usize,
+	feed: Arc<MetadataFeed>,
+	events: crossbeam_channel::Sender<PlaybackEvent>,
+}
+
+impl<R: Read> IcyReader<R> {
+	pub(crate) fn new(reader: R, metaint: usize, feed: Arc<MetadataFeed>, events: crossbeam_channel::Sender<PlaybackEvent>) -> IcyReader<R> {
+		IcyReader { reader, metaint, remaining: metaint, feed, events }
+	}
+
+	// Reads the metadata block due next. Returns false when the stream ended where it would start.
+	fn read_metadata(&mut self) -> io::Result<bool> {
+		let mut len = [0u8; 1];
+		loop {
+			match self.reader.read(&mut len) {
+				Ok(0) => return Ok(false),
+				Ok(_) => break,
+				Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+				Err(err) => return Err(err)
+			}
+		}
+		let mut block = vec![0; len[0] as usize * 16];
+		self.reader.read_exact(&mut block)?;
+
+		let text = String::from_utf8_lossy(&block);
+		let title = match stream_title(&text) {
+			Some(title) => title,
+			// Empty blocks, the usual case, leave the title as it was.
+			None => return Ok(true)
+		};
+		if self.feed.update(|metadata| metadata.stream_title = Some(String::from(title))) {
+			let _ = self.events.send(PlaybackEvent::TrackChanged);
+		}
+		Ok(true)
+	}
+}
+
+impl<R: Read> Read for IcyReader<R> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		if buf.is_empty() {
+			return Ok(0);
+		}
+		if self.remaining == 0 {
+			if !self.read_metadata()? {
+				return Ok(0);
+			}
+			self.remaining = self.metaint;
+		}
+		let len = buf.len().min(self.remaining);
+		let read = self.reader.read(&mut buf[..len])?;
+		self.remaining -= read;
+		Ok(read)
+	}
+}
+
+// The value of StreamTitle='...'; in a metadata block. Titles may themselves contain quotes, so
+// the value runs up to the closing "';" rather than the next quote.
+fn stream_title(metadata: &str) -> Option<&str> {
+	let start = metadata.find("StreamTitle='")? + "StreamTitle='".len();
+	let rest = &metadata[start..];
+	let end = rest.find("';").or_else(|| rest.trim_end_matches('\0').strip_suffix('\'').map(str::len))?;
+	Some(&rest[..end])
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{resolve_location, stream_title, IcyReader};
+	use crate::audio::metadata::MetadataFeed;
+	use crate::audio::PlaybackEvent;
+
+	use std::io::{Cursor, Read};
+	use std::sync::Arc;
+
+	// A metadata block holding `text`, NUL-padded to whole 16-byte units.
+	fn metadata_block(text: &str) -> Vec<u8> {
+		let units = text.len().div_ceil(16);
+		let mut block = vec![units as u8];
+		block.extend_from_slice(text.as_bytes());
+		block.resize(1 + units * 16, 0);
+		block
+	}
+
+	#[test]
+	fn reader_strips_metadata() {
+		let mut stream = b"abcd".to_vec();
+		stream.extend(metadata_block("StreamTitle='Artist - Song';"));
+		stream.extend_from_slice(b"efgh");
+		// An empty block leaves the title as it was.
+		stream.push(0);
+		stream.extend_from_slice(b"ij");
+
+		let feed = Arc::new(MetadataFeed::new());
+		let (events, received) = crossbeam_channel::unbounded();
+		let mut reader = IcyReader::new(Cursor::new(stream), 4, Arc::clone(&feed), events);
+		let mut audio = Vec::new();
+		reader.read_to_end(&mut audio).unwrap();
+
+		assert_eq!(audio, b"abcdefghij");
+		assert_eq!(feed.current().stream_title.as_deref(), Some("Artist - Song"));
+		assert!(matches!(received.try_recv(), Ok(PlaybackEvent::TrackChanged)));
+		assert!(received.try_recv().is_err());
+	}
+
+	#[test]
+	fn reader_ends_at_block_boundary() {
+		let feed = Arc::new(MetadataFeed::new());
+		let (events, _received) = crossbeam_channel::unbounded();
+		let mut reader = IcyReader::new(Cursor::new(b"abcd".to_vec()), 4, feed, events);
+		let mut audio = Vec::new();
+		reader.read_to_end(&mut audio).unwrap();
+		assert_eq!(audio, b"abcd");
+	}
+
+	#[test]
+	fn titles() {
+		assert_eq!(stream_title("StreamTitle='Artist - Song';StreamUrl='';"), Some("Artist - Song"));
+		assert_eq!(stream_title("StreamTitle='Rock 'n' Roll';"), Some("Rock 'n' Roll"));
+		// Some servers leave out the closing semicolon.
+		assert_eq!(stream_title("StreamTitle='Song'\0\0\0"), Some("Song"));
+		assert_eq!(stream_title("StreamTitle='';"), Some(""));
+		assert_eq!(stream_title("StreamUrl='http://example.com';"), None);
+		assert_eq!(stream_title("StreamTitle='unterminated"), None);
+	}
+
+	#[test]
+	fn relative_locations() {
+		assert_eq!(resolve_location("example.com", 8000, "/live", "http://relay.example.com/live"), "http://relay.example.com/live");
+		assert_eq!(resolve_location("example.com", 8000, "/live", "//relay.example.com/live"), "http://relay.example.com/live");
+		assert_eq!(resolve_location("example.com", 8000, "/live", "/relay/live"), "http://example.com:8000/relay/live");
+		assert_eq!(resolve_location("example.com", 8000, "/radio/live?id=1", "hq.mp3"), "http://example.com:8000/radio/hq.mp3");
+	}
+}
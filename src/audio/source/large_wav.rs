@@ -0,0 +1,241 @@
+use super::{bwf, AudioSource, PcmSource};
+use crate::audio::format::WAVE64_RIFF;
+use crate::audio::{AudioError, BroadcastInfo, PcmFormat};
+
+use std::convert::TryFrom;
+use std::io::{Read, Seek, SeekFrom};
+
+// Sony Wave64 names its header and chunks by GUID rather than by four letters.
+const WAVE64_WAVE: [u8; 16] = [0x77, 0x61, 0x76, 0x65, 0xf3, 0xac, 0xd3, 0x11, 0x8c, 0xd1, 0x00, 0xc0, 0x4f, 0x8e, 0xdb, 0x8a];
+const WAVE64_FMT: [u8; 16] = [0x66, 0x6d, 0x74, 0x20, 0xf3, 0xac, 0xd3, 0x11, 0x8c, 0xd1, 0x00, 0xc0, 0x4f, 0x8e, 0xdb, 0x8a];
+const WAVE64_DATA: [u8; 16] = [0x64, 0x61, 0x74, 0x61, 0xf3, 0xac, 0xd3, 0x11, 0x8c, 0xd1, 0x00, 0xc0, 0x4f, 0x8e, 0xdb, 0x8a];
+
+// What a 32-bit RF64 size field holds when the real size is in the ds64 chunk.
+const RF64_SIZE_IN_DS64: u32 = u32::MAX;
+// The fmt format tags for integer and float PCM, and the one that defers to a subformat GUID.
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xfffe;
+// Larger fmt chunks are assumed to be damaged rather than read into memory.
+const FMT_MAX_LEN: u64 = 1 << 16;
+
+/* LargeWavSource: A WAV file in one of the containers for audio past 4 GiB, where the 32-bit
+ * sizes of plain RIFF run out: RF64 (and BW64, the same layout under another name), whose 64-bit
+ * sizes are in a ds64 chunk up front, and Sony Wave64, which has 64-bit sizes throughout.
+ * symphonia only reads plain RIFF, so these are parsed here and their data chunk played as PCM.
+ *
+ * Uncompressed integer and 32-bit float PCM are supported. The bext chunk of an RF64 file is
+ * read as for any Broadcast Wave file.
+ */
+pub(crate) struct LargeWavSource<R: Read + Seek> {
+	pcm: PcmSource<R>,
+	broadcast: Option<BroadcastInfo>,
+	// Bytes of header in front of the data, which the PcmSource didn't read.
+	header_len: u64,
+}
+
+impl<R: Read + Seek> LargeWavSource<R> {
+	// Tries `reader` from its current position. Ok(Err(reader)) hands it back, where it was, when
+	// it holds none of these containers, e.g. plain RIFF, or when it can't seek to find out.
+	pub(crate) fn probe(mut reader: R) -> Result<Result<LargeWavSource<R>, R>, AudioError> {
+		let start = match reader.stream_position() {
+			Ok(start) => start,
+			Err(_) => return Ok(Err(reader))
+		};
+		let mut magic = [0u8; 16];
+		let is_large = match read_full(&mut reader, &mut magic)? {
+			16 => matches!(&magic[0..4], b"RF64" | b"BW64") && &magic[8..12] == b"WAVE" || magic == WAVE64_RIFF,
+			_ => false
+		};
+		reader.seek(SeekFrom::Start(start))?;
+		if !is_large {
+			return Ok(Err(reader));
+		}
+
+		let layout = if magic == WAVE64_RIFF { read_wave64(&mut reader)? } else { read_rf64(&mut reader)? };
+		let data_start = reader.stream_position()?;
+		let broadcast = if magic == WAVE64_RIFF {
+			None
+		} else {
+			reader.seek(SeekFrom::Start(start))?;
+			let broadcast = bwf::read_broadcast_info(&mut reader);
+			reader.seek(SeekFrom::Start(data_start))?;
+			broadcast
+		};
+		let pcm = PcmSource::from_reader(reader, layout.format, layout.sample_rate, layout.channels)?.with_data_len(layout.data_len);
+		Ok(Ok(LargeWavSource { pcm, broadcast, header_len: data_start - start }))
+	}
+}
+
+// What a fmt chunk and the data chunk's size say about the audio.
+struct WavLayout {
+	format: PcmFormat,
+	sample_rate: u32,
+	channels: u16,
+	data_len: u64,
+}
+
+// Walks an RF64 file's chunks up to its data, taking the data size from ds64 when the data
+// chunk's own size says to, and leaves `reader` at the start of the samples.
+fn read_rf64<R: Read + Seek>(reader: &mut R) -> Result<WavLayout, AudioError> {
+	let mut header = [0u8; 12];
+	reader.read_exact(&mut header)?;
+	let mut ds64_data_len = None;
+	let mut format = None;
+	loop {
+		let mut chunk = [0u8; 8];
+		reader.read_exact(&mut chunk).map_err(|_| malformed("RF64", "no data chunk"))?;
+		let size = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]);
+		match &chunk[0..4] {
+			b"ds64" => {
+				let body = read_rf64_body(reader, size, 24)?;
+				ds64_data_len = Some(u64_at(&body, 8));
+			},
+			b"fmt " => {
+				format = Some(parse_fmt(&read_rf64_body(reader, size, 16)?)?);
+			},
+			b"data" => {
+				let data_len = match size {
+					RF64_SIZE_IN_DS64 => ds64_data_len.ok_or_else(|| malformed("RF64", "no ds64 chunk before the data"))?,
+					size => size as u64
+				};
+				return layout(format, data_len, "RF64");
+			},
+			_ if size == RF64_SIZE_IN_DS64 => return Err(malformed("RF64", "a chunk other than data is over 4 GiB")),
+			// Chunks are padded to an even length.
+			_ => {
+				reader.seek(SeekFrom::Current(size as i64 + (size & 1) as i64))?;
+			}
+		}
+	}
+}
+
+// Walks a Wave64 file's chunks up to its data like read_rf64. Sizes there count the 24-byte
+// chunk header, and chunks are padded to a multiple of 8 bytes.
+fn read_wave64<R: Read + Seek>(reader: &mut R) -> Result<WavLayout, AudioError> {
+	let mut header = [0u8; 40];
+	reader.read_exact(&mut header)?;
+	if header[24..40] != WAVE64_WAVE {
+		return Err(malformed("Wave64", "not a wave file"));
+	}
+	let mut format = None;
+	loop {
+		let mut chunk = [0u8; 24];
+		reader.read_exact(&mut chunk).map_err(|_| malformed("Wave64", "no data chunk"))?;
+		let size = u64_at(&chunk, 16).checked_sub(24).ok_or_else(|| malformed("Wave64", "a chunk is shorter than its header"))?;
+		if chunk[0..16] == WAVE64_FMT {
+			format = Some(parse_fmt(&read_body(reader, size, 16).map_err(|_| malformed("Wave64", "a header chunk is truncated or too large"))?)?);
+			reader.seek(SeekFrom::Current(((8 - size % 8) % 8) as i64))?;
+		} else if chunk[0..16] == WAVE64_DATA {
+			return layout(format, size, "Wave64");
+		} else {
+			let padded = size.checked_add((8 - size % 8) % 8).and_then(|padded| i64::try_from(padded).ok())
+				.ok_or_else(|| malformed("Wave64", "a chunk is too large"))?;
+			reader.seek(SeekFrom::Current(padded))?;
+		}
+	}
+}
+
+fn layout(format: Option<(PcmFormat, u32, u16)>, data_len: u64, container: &str) -> Result<WavLayout, AudioError> {
+	let (format, sample_rate, channels) = format.ok_or_else(|| malformed(container, "no fmt chunk before the data"))?;
+	Ok(WavLayout { format, sample_rate, channels, data_len })
+}
+
+// The encoding, sample rate and channel count of a fmt chunk.
+fn parse_fmt(fmt: &[u8]) -> Result<(PcmFormat, u32, u16), AudioError> {
+	let u16_at = |offset: usize| u16::from_le_bytes([fmt[offset], fmt[offset + 1]]);
+	let mut tag = u16_at(0);
+	let channels = u16_at(2);
+	let sample_rate = u32::from_le_bytes([fmt[4], fmt[5], fmt[6], fmt[7]]);
+	let block_align = u16_at(12);
+	let bits = u16_at(14);
+	// The extensible format's subformat GUID starts with the plain format tag.
+	if tag == WAVE_FORMAT_EXTENSIBLE && fmt.len() >= 40 {
+		tag = u16_at(24);
+	}
+	let format = match (tag, bits) {
+		(WAVE_FORMAT_PCM, 8) => PcmFormat::U8,
+		(WAVE_FORMAT_PCM, 16) => PcmFormat::S16LE,
+		(WAVE_FORMAT_PCM, 24) => PcmFormat::S24LE,
+		(WAVE_FORMAT_PCM, 32) => PcmFormat::S32LE,
+		(WAVE_FORMAT_IEEE_FLOAT, 32) => PcmFormat::F32LE,
+		(tag, bits) => return Err(AudioError::Unsupported(format!("large WAV files are only played as 8 to 32-bit PCM, not format {:#06x} at {} bits", tag, bits)))
+	};
+	if channels == 0 || sample_rate == 0 || block_align as usize != channels as usize * format.bytes_per_sample() {
+		return Err(malformed("WAV", "the fmt chunk doesn't describe a playable layout"));
+	}
+	Ok((format, sample_rate, channels))
+}
+
+// Reads a chunk body of `size` bytes, which has to hold at least `min_len`.
+fn read_body<R: Read>(reader: &mut R, size: u64, min_len: u64) -> Result<Vec<u8>, AudioError> {
+	if !(min_len..=FMT_MAX_LEN).contains(&size) {
+		return Err(AudioError::Decode(format!("a header chunk of {} bytes", size)));
+	}
+	let mut body = vec![0u8; size as usize];
+	reader.read_exact(&mut body)?;
+	Ok(body)
+}
+
+// Like read_body, then skips the padding byte of an odd-sized RF64 chunk.
+fn read_rf64_body<R: Read>(reader: &mut R, size: u32, min_len: u64) -> Result<Vec<u8>, AudioError> {
+	let body = read_body(reader, size as u64, min_len).map_err(|_| malformed("RF64", "a header chunk is truncated or too large"))?;
+	if size & 1 == 1 {
+		reader.read_exact(&mut [0u8; 1])?;
+	}
+	Ok(body)
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+	let mut field = [0u8; 8];
+	field.copy_from_slice(&bytes[offset..offset + 8]);
+	u64::from_le_bytes(field)
+}
+
+// Reads into `buf` until it's full or the reader ends, returning how much was read.
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<usize, AudioError> {
+	let mut filled = 0;
+	while filled < buf.len() {
+		match reader.read(&mut buf[filled..]) {
+			Ok(0) => break,
+			Ok(read) => filled += read,
+			Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+			Err(err) => return Err(AudioError::Io(err))
+		}
+	}
+	Ok(filled)
+}
+
+fn malformed(container: &str, reason: &str) -> AudioError {
+	AudioError::Decode(format!("malformed {} header: {}", container, reason))
+}
+
+impl<R: Read + Seek + Send> AudioSource for LargeWavSource<R> {
+	fn sample_rate(&self) -> u32 {
+		self.pcm.sample_rate()
+	}
+
+	fn channels(&self) -> u16 {
+		self.pcm.channels()
+	}
+
+	fn read_block(&mut self, buffer: &mut Vec<f32>) -> Result<bool, AudioError> {
+		self.pcm.read_block(buffer)
+	}
+
+	fn broadcast_info(&self) -> Option<&BroadcastInfo> {
+		self.broadcast.as_ref()
+	}
+
+	fn bytes_read(&self) -> u64 {
+		self.header_len + self.pcm.bytes_read()
+	}
+
+	fn is_seekable(&self) -> bool {
+		self.pcm.is_seekable()
+	}
+
+	fn seek(&mut self, frame: u64) -> Result<(), AudioError> {
+		self.pcm.seek(frame)
+	}
+}
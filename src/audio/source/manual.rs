@@ -0,0 +1,78 @@
+use super::AudioSource;
+use crate::audio::AudioError;
+
+use std::time::Duration;
+
+// How long read_block waits for samples before handing back an empty block, so the producer keeps
+// handling commands while nothing is being pushed.
+const PUSH_POLL_TIMEOUT: Duration = Duration::from_millis(10);
+
+// Most frames read_block takes in one go, so a caller pushing far ahead doesn't hold up the
+// producer's commands.
+const MAX_BLOCK_FRAMES: usize = 4096;
+
+/* ManualSource: Samples pushed by the application through a SampleSink, e.g. from a synthesizer
+ * or a decoder of its own.
+ *
+ * The sink's channel is bounded, so a caller pushing faster than the output plays blocks until
+ * there's room. The source ends once every sink has been dropped and what they pushed has been
+ * read, and can't seek.
+ */
+pub(crate) struct ManualSource {
+	samples: crossbeam_channel::Receiver<f32>,
+	sample_rate: u32,
+	channels: usize,
+}
+
+impl ManualSource {
+	pub(crate) fn new(samples: crossbeam_channel::Receiver<f32>, sample_rate: u32, channels: u16) -> ManualSource {
+		ManualSource { samples, sample_rate, channels: channels.max(1) as usize }
+	}
+
+	// Receives the rest of a frame begun with `first`. Returns false once the sinks are gone.
+	fn push_frame(&self, first: f32, buffer: &mut Vec<f32>) -> bool {
+		buffer.push(first);
+		for _ in 1..self.channels {
+			// SampleSink::push hands over whole frames, so the rest of this one is on its way.
+			match self.samples.recv() {
+				Ok(sample) => buffer.push(sample),
+				Err(_) => return false
+			}
+		}
+		true
+	}
+}
+
+impl AudioSource for ManualSource {
+	fn sample_rate(&self) -> u32 {
+		self.sample_rate
+	}
+
+	fn channels(&self) -> u16 {
+		self.channels as u16
+	}
+
+	fn read_block(&mut self, buffer: &mut Vec<f32>) -> Result<bool, AudioError> {
+		let first = match self.samples.recv_timeout(PUSH_POLL_TIMEOUT) {
+			Ok(sample) => sample,
+			Err(crossbeam_channel::RecvTimeoutError::Timeout) => return Ok(true),
+			Err(crossbeam_channel::RecvTimeoutError::Disconnected) => return Ok(false)
+		};
+		if !self.push_frame(first, buffer) {
+			return Ok(false);
+		}
+
+		// Take whatever else has been pushed, in whole frames.
+		let available = (self.samples.len() / self.channels).min(MAX_BLOCK_FRAMES - 1);
+		for _ in 0..available {
+			let first = match self.samples.try_recv() {
+				Ok(sample) => sample,
+				Err(_) => break
+			};
+			if !self.push_frame(first, buffer) {
+				return Ok(false);
+			}
+		}
+		Ok(true)
+	}
+}
@@ -0,0 +1,76 @@
+use super::AudioSource;
+use crate::audio::AudioError;
+
+use std::sync::Arc;
+
+// Frames handed out per block.
+const MEMORY_BLOCK_FRAMES: usize = 4096;
+
+// Interleaved samples held in memory, as f32 or, to take half the room, as 16-bit integers.
+#[derive(Clone)]
+pub(crate) enum MemorySamples {
+	F32(Arc<Vec<f32>>),
+	I16(Arc<Vec<i16>>),
+}
+
+impl MemorySamples {
+	pub(crate) fn len(&self) -> usize {
+		match self {
+			MemorySamples::F32(samples) => samples.len(),
+			MemorySamples::I16(samples) => samples.len(),
+		}
+	}
+
+	// Appends samples `start..end` to `buffer` as f32.
+	fn extend(&self, buffer: &mut Vec<f32>, start: usize, end: usize) {
+		match self {
+			MemorySamples::F32(samples) => buffer.extend_from_slice(&samples[start..end]),
+			MemorySamples::I16(samples) => buffer.extend(samples[start..end].iter().map(|&sample| sample as f32 / 32768.0)),
+		}
+	}
+}
+
+/* MemorySource: Replays interleaved samples already decoded into memory, e.g. by a CachedSource.
+ * The samples are shared, so any number of sources can play them at once. 16-bit samples are
+ * converted to f32 a block at a time as they're read.
+ */
+pub(crate) struct MemorySource {
+	samples: MemorySamples,
+	sample_rate: u32,
+	channels: u16,
+	// Index of the next sample to hand out.
+	position: usize,
+}
+
+impl MemorySource {
+	pub(crate) fn new(samples: MemorySamples, sample_rate: u32, channels: u16) -> MemorySource {
+		MemorySource { samples, sample_rate, channels, position: 0 }
+	}
+}
+
+impl AudioSource for MemorySource {
+	fn sample_rate(&self) -> u32 {
+		self.sample_rate
+	}
+
+	fn channels(&self) -> u16 {
+		self.channels
+	}
+
+	fn read_block(&mut self, buffer: &mut Vec<f32>) -> Result<bool, AudioError> {
+		let end = (self.position + MEMORY_BLOCK_FRAMES * self.channels.max(1) as usize).min(self.samples.len());
+		self.samples.extend(buffer, self.position, end);
+		self.position = end;
+		Ok(end < self.samples.len())
+	}
+
+	fn is_seekable(&self) -> bool {
+		true
+	}
+
+	fn seek(&mut self, frame: u64) -> Result<(), AudioError> {
+		let sample = frame.saturating_mul(self.channels.max(1) as u64);
+		self.position = sample.min(self.samples.len() as u64) as usize;
+		Ok(())
+	}
+}
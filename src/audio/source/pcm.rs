@@ -0,0 +1,117 @@
+use super::{AudioSource, CountingReader};
+use crate::audio::{AudioError, PcmFormat};
+
+use std::io::{ErrorKind, Read, Seek, SeekFrom};
+
+// Frames read from the reader per block.
+const PCM_BLOCK_FRAMES: usize = 4096;
+
+/* PcmSource: Raw, headerless interleaved PCM read from any reader, in the encoding, sample rate
+ * and channel count the caller says it has.
+ *
+ * A trailing partial frame at the end of the reader, or of the data length if one is set, is
+ * dropped.
+ */
+pub(crate) struct PcmSource<R: Read + Seek> {
+	reader: CountingReader<R>,
+	format: PcmFormat,
+	sample_rate: u32,
+	channels: u16,
+	// Where the PCM data begins in the reader, for seeking; None when the reader can't tell.
+	data_start: Option<u64>,
+	// Bytes of PCM data from data_start, for data followed by something else such as more chunks
+	// of a container; None reads to the end of the reader.
+	data_len: Option<u64>,
+	// Bytes of the data read since its start or the last seek.
+	data_read: u64,
+	// Reused between blocks so reading doesn't allocate once warmed up.
+	bytes: Vec<u8>,
+}
+
+impl<R: Read + Seek> PcmSource<R> {
+	// Reads PCM starting at the reader's current position.
+	pub(crate) fn from_reader(reader: R, format: PcmFormat, sample_rate: u32, channels: u16) -> Result<PcmSource<R>, AudioError> {
+		if sample_rate == 0 || channels == 0 {
+			return Err(AudioError::InvalidArgument(format!("raw PCM needs a sample rate and channel count, got {} Hz and {} channels", sample_rate, channels)));
+		}
+		let mut reader = CountingReader::new(reader);
+		// A reader that can't report its position can only be read straight through.
+		let data_start = reader.stream_position().ok();
+		Ok(PcmSource {
+			reader,
+			format,
+			sample_rate,
+			channels,
+			data_start,
+			data_len: None,
+			data_read: 0,
+			bytes: vec![0; PCM_BLOCK_FRAMES * channels as usize * format.bytes_per_sample()],
+		})
+	}
+
+	// Stops reading after `data_len` bytes instead of at the end of the reader.
+	#[cfg(feature = "symphonia")]
+	pub(crate) fn with_data_len(mut self, data_len: u64) -> PcmSource<R> {
+		self.data_len = Some(data_len);
+		self
+	}
+
+	fn frame_bytes(&self) -> usize {
+		self.channels as usize * self.format.bytes_per_sample()
+	}
+}
+
+impl<R: Read + Seek + Send> AudioSource for PcmSource<R> {
+	fn sample_rate(&self) -> u32 {
+		self.sample_rate
+	}
+
+	fn channels(&self) -> u16 {
+		self.channels
+	}
+
+	fn read_block(&mut self, buffer: &mut Vec<f32>) -> Result<bool, AudioError> {
+		// Fill the whole block unless the reader or the data ends, so short reads never split a
+		// frame.
+		let wanted = match self.data_len {
+			Some(data_len) => (data_len.saturating_sub(self.data_read)).min(self.bytes.len() as u64) as usize,
+			None => self.bytes.len()
+		};
+		let mut filled = 0;
+		while filled < wanted {
+			match self.reader.read(&mut self.bytes[filled..wanted]) {
+				Ok(0) => break,
+				Ok(read) => filled += read,
+				Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+				Err(err) => return Err(AudioError::Io(err))
+			}
+		}
+		self.data_read += filled as u64;
+
+		let whole = filled - filled % self.frame_bytes();
+		self.format.decode_into(&self.bytes[..whole], buffer);
+		Ok(filled == self.bytes.len())
+	}
+
+	fn bytes_read(&self) -> u64 {
+		self.reader.bytes_read()
+	}
+
+	fn is_seekable(&self) -> bool {
+		self.data_start.is_some()
+	}
+
+	fn seek(&mut self, frame: u64) -> Result<(), AudioError> {
+		let data_start = self.data_start.ok_or_else(|| AudioError::Unsupported(String::from("the reader can't seek")))?;
+		let mut data_offset = frame * self.frame_bytes() as u64;
+		if let Some(data_len) = self.data_len {
+			data_offset = data_offset.min(data_len);
+		}
+		self.reader.seek(SeekFrom::Start(data_start + data_offset)).map_err(|err| match err.kind() {
+			ErrorKind::Unsupported => AudioError::Unsupported(String::from("the reader can't seek")),
+			_ => AudioError::Io(err)
+		})?;
+		self.data_read = data_offset;
+		Ok(())
+	}
+}
@@ -0,0 +1,54 @@
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom};
+
+/* PipeReader: Adapts a stream that can only be read front to back, such as standard input, to
+ * the decoders, which take Read + Seek.
+ *
+ * Every seek fails with ErrorKind::Unsupported, which the sources take to mean the reader can't
+ * seek. Bytes peeked at to identify the format are kept and read again ahead of the rest of the
+ * stream, so nothing is lost to the detection.
+ */
+pub(crate) struct PipeReader<R> {
+	reader: R,
+	head: Vec<u8>,
+	// How much of head has been read back.
+	position: usize,
+}
+
+impl<R: Read> PipeReader<R> {
+	pub(crate) fn new(reader: R) -> PipeReader<R> {
+		PipeReader { reader, head: Vec::new(), position: 0 }
+	}
+
+	// Reads up to `len` bytes without consuming them. Returns fewer only when the stream ends
+	// first. Only valid before anything has been read.
+	pub(crate) fn peek(&mut self, len: usize) -> io::Result<&[u8]> {
+		while self.head.len() < len {
+			let mut chunk = vec![0; len - self.head.len()];
+			match self.reader.read(&mut chunk) {
+				Ok(0) => break,
+				Ok(read) => self.head.extend_from_slice(&chunk[..read]),
+				Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+				Err(err) => return Err(err)
+			}
+		}
+		Ok(&self.head)
+	}
+}
+
+impl<R: Read> Read for PipeReader<R> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		if self.position < self.head.len() {
+			let read = (self.head.len() - self.position).min(buf.len());
+			buf[..read].copy_from_slice(&self.head[self.position..self.position + read]);
+			self.position += read;
+			return Ok(read);
+		}
+		self.reader.read(buf)
+	}
+}
+
+impl<R> Seek for PipeReader<R> {
+	fn seek(&mut self, _: SeekFrom) -> io::Result<u64> {
+		Err(io::Error::new(ErrorKind::Unsupported, "a pipe can't seek"))
+	}
+}
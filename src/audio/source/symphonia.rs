@@ -0,0 +1,429 @@
+use super::{bwf, AudioSource, CountingReader, CoverArt};
+use crate::audio::chapter::{self, Chapter};
+use crate::audio::replay_gain::ReplayGain;
+use crate::audio::{AudioError, AudioTrackInfo, BroadcastInfo};
+
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{Decoder, DecoderOptions, CODEC_TYPE_AAC, CODEC_TYPE_NULL};
+use symphonia::core::errors::{Error, SeekErrorKind};
+use symphonia::core::formats::{FormatOptions, FormatReader, Packet, SeekMode, SeekTo, Track};
+use symphonia::core::io::{MediaSource, MediaSourceStream};
+use symphonia::core::meta::{MetadataOptions, MetadataRevision, StandardTagKey, StandardVisualKey, Visual};
+use symphonia::core::probe::Hint;
+use symphonia::core::units::Time;
+
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+
+// Packets a decoder has to reject in a row before SymphoniaSource tries a new one on them.
+const RENEW_DECODER_ERRORS: u32 = 3;
+
+/* SymphoniaSource: Decodes an audio track symphonia can probe in a file: the container's default
+ * one, or the first, unless another is selected. Which containers and codecs that covers depends
+ * on the enabled features: AAC-LC in ADTS or MP4 with aac, and everything symphonia ships (FLAC,
+ * MP3, ALAC, WAV, OGG/Vorbis, Matroska, ...) with symphonia.
+ */
+pub(crate) struct SymphoniaSource {
+	format: Box<dyn FormatReader>,
+	decoder: Box<dyn Decoder>,
+	track_id: u32,
+	sample_rate: u32,
+	channels: u16,
+	// Channels of the latest packet as decoded, which a live stream may change from the
+	// channels it started with; sample_buffer is laid out for them.
+	decoded_channels: u16,
+	sample_buffer: Option<SampleBuffer<f32>>,
+	// Packets in a row the decoder has rejected.
+	decode_errors: u32,
+	// Frames still to be dropped from the front of upcoming packets to land a seek exactly.
+	skip_frames: u64,
+	seekable: bool,
+	cover_art: Option<CoverArt>,
+	chapters: Vec<Chapter>,
+	broadcast: Option<BroadcastInfo>,
+	replay_gain: Option<ReplayGain>,
+	bytes_read: Arc<AtomicU64>,
+	// Every track with a codec, in the container's order.
+	audio_tracks: Vec<AudioTrackInfo>,
+}
+
+impl SymphoniaSource {
+	pub(crate) fn open(path: &str) -> Result<SymphoniaSource, AudioError> {
+		let extension = Path::new(path).extension().and_then(|extension| extension.to_str());
+		let mut file = CountingReader::new(File::open(path)?);
+		let broadcast = read_broadcast_info(&mut file, extension)?;
+		let bytes_read = file.count();
+		let mut source = SymphoniaSource::from_media_source(Box::new(file), extension, &format!("\"{}\"", path), bytes_read)?;
+		source.broadcast = broadcast;
+		Ok(source)
+	}
+
+	// `extension` is only a hint; symphonia identifies the container from its contents.
+	pub(crate) fn from_reader<R: Read + Seek + Send + 'static>(reader: R, extension: &str) -> Result<SymphoniaSource, AudioError> {
+		let mut reader = CountingReader::new(reader);
+		let broadcast = read_broadcast_info(&mut reader, Some(extension))?;
+		let bytes_read = reader.count();
+		let mut source = SymphoniaSource::from_media_source(Box::new(ReaderSource::new(reader)), Some(extension), "the reader", bytes_read)?;
+		source.broadcast = broadcast;
+		Ok(source)
+	}
+
+	fn from_media_source(source: Box<dyn MediaSource>, extension: Option<&str>, name: &str, bytes_read: Arc<AtomicU64>) -> Result<SymphoniaSource, AudioError> {
+		let seekable = source.is_seekable();
+		let stream = MediaSourceStream::new(source, Default::default());
+
+		let mut hint = Hint::new();
+		if let Some(extension) = extension {
+			hint.with_extension(extension);
+		}
+
+		// symphonia panics instead of failing on some malformed headers, e.g. a WAV claiming a
+		// sample rate of 0.
+		let mut probed = panic::catch_unwind(AssertUnwindSafe(|| {
+			symphonia::default::get_probe().format(&hint, stream, &FormatOptions::default(), &MetadataOptions::default())
+		})).map_err(|_| AudioError::Decode(format!("malformed header in {}", name)))??;
+		let mut format = probed.format;
+
+		// Tags in front of the container (ID3v2) come with the probe result, the container's own
+		// (FLAC PICTURE, MP4 covr) with the format reader.
+		let cover_art = probed.metadata.get().as_mut()
+			.and_then(|metadata| metadata.skip_to_latest().and_then(|revision| pick_cover_art(revision.visuals())))
+			.or_else(|| format.metadata().skip_to_latest().and_then(|revision| pick_cover_art(revision.visuals())));
+		let replay_gain = probed.metadata.get().as_mut()
+			.and_then(|metadata| metadata.skip_to_latest().and_then(read_replay_gain))
+			.or_else(|| format.metadata().skip_to_latest().and_then(read_replay_gain));
+
+		// symphonia only reports cues for FLAC CUESHEET blocks; their titles may be in a text cue
+		// sheet stored as a CUESHEET tag.
+		let cue_text = format.metadata().skip_to_latest()
+			.and_then(|revision| revision.tags().iter().find(|tag| tag.key.eq_ignore_ascii_case("CUESHEET")).map(|tag| tag.value.to_string()));
+		let cue_tracks: Vec<(u8, u64)> = format.cues().iter()
+			.filter_map(|cue| Some((u8::try_from(cue.index).ok()?, cue.start_ts + cue.points.first().map_or(0, |point| point.start_offset_ts))))
+			.collect();
+
+		let tracks = audio_tracks(&*format);
+		let default = format.default_track()
+			.filter(|default| tracks.iter().any(|track| track.id == default.id))
+			.or_else(|| tracks.first().copied())
+			.ok_or_else(|| AudioError::Decode(format!("no audio track in {}", name)))?;
+		let default_id = default.id;
+		let audio_tracks = tracks.iter().enumerate().map(|(index, track)| AudioTrackInfo {
+			index,
+			language: track.language.clone(),
+			sample_rate: track.codec_params.sample_rate,
+			channels: track.codec_params.channels.map(|channels| channels.count() as u16),
+			default: track.id == default_id,
+		}).collect();
+		let (decoder, sample_rate, channels) = open_decoder(default)?;
+
+		Ok(SymphoniaSource {
+			track_id: default_id,
+			format,
+			decoder,
+			sample_rate,
+			channels,
+			decoded_channels: channels,
+			sample_buffer: None,
+			decode_errors: 0,
+			skip_frames: 0,
+			seekable,
+			cover_art,
+			chapters: chapter::from_cue_tracks(cue_tracks, sample_rate, cue_text.as_deref()),
+			broadcast: None,
+			replay_gain,
+			bytes_read,
+			audio_tracks,
+		})
+	}
+
+	// Decodes `packet` with a decoder made afresh for the track, which takes it up in place of the
+	// current one if that works. Some decoders, such as MP3's, stay with the layout of the first
+	// frame they decode and reject every packet after a live stream changes it.
+	fn renew_decoder(&mut self, packet: &Packet) -> Result<bool, AudioError> {
+		let track = match audio_tracks(&*self.format).into_iter().find(|track| track.id == self.track_id) {
+			Some(track) => track,
+			None => return Ok(false)
+		};
+		let (mut decoder, _, _) = open_decoder(track)?;
+		if decoder.decode(packet).is_err() {
+			return Ok(false);
+		}
+		self.decoder = decoder;
+		self.decode_errors = 0;
+		Ok(true)
+	}
+}
+
+// The tracks of `format` with a codec, i.e. those that can be played.
+fn audio_tracks(format: &dyn FormatReader) -> Vec<&Track> {
+	format.tracks().iter().filter(|track| track.codec_params.codec != CODEC_TYPE_NULL).collect()
+}
+
+// A decoder for `track`, with the sample rate and channel count it decodes to.
+fn open_decoder(track: &Track) -> Result<(Box<dyn Decoder>, u32, u16), AudioError> {
+	let params = &track.codec_params;
+	// symphonia only implements AAC-LC; HE-AAC (SBR/PS) and the other object types are
+	// rejected when the decoder reads the AudioSpecificConfig.
+	let decoder = symphonia::default::get_codecs().make(params, &DecoderOptions::default())
+		.map_err(|err| match err {
+			Error::Unsupported(reason) if params.codec == CODEC_TYPE_AAC => AudioError::Unsupported(format!("only AAC-LC is supported ({})", reason)),
+			err => AudioError::from(err)
+		})?;
+
+	// Some containers leave the layout to the codec's own header, e.g. FLAC in Matroska, which the
+	// decoder has read by now.
+	let decoded = decoder.codec_params();
+	// A malformed header can give a rate of 0, which no frame count could be timed against.
+	let sample_rate = params.sample_rate.or(decoded.sample_rate).filter(|sample_rate| *sample_rate > 0)
+		.ok_or_else(|| AudioError::Decode(String::from("audio track has no sample rate")))?;
+	let channels = params.channels.or(decoded.channels).map(|channels| channels.count() as u16)
+		.ok_or_else(|| AudioError::Decode(String::from("audio track has no channel layout")))?;
+	Ok((decoder, sample_rate, channels))
+}
+
+// symphonia skips the bext chunk, so WAV files are scanned for it before they're probed. Readers
+// that can't seek are left alone since the scan couldn't be undone.
+fn read_broadcast_info<R: Read + Seek>(reader: &mut R, extension: Option<&str>) -> Result<Option<BroadcastInfo>, AudioError> {
+	if !extension.is_some_and(|extension| extension.eq_ignore_ascii_case("wav") || extension.eq_ignore_ascii_case("wave")) {
+		return Ok(None);
+	}
+	let start = match reader.stream_position() {
+		Ok(start) => start,
+		Err(_) => return Ok(None)
+	};
+	let broadcast = bwf::read_broadcast_info(reader);
+	reader.seek(SeekFrom::Start(start))?;
+	Ok(broadcast)
+}
+
+// Picks the front cover, or the first picture when none is marked as such.
+fn pick_cover_art(visuals: &[Visual]) -> Option<CoverArt> {
+	visuals.iter()
+		.find(|visual| visual.usage == Some(StandardVisualKey::FrontCover))
+		.or_else(|| visuals.first())
+		.map(|visual| CoverArt { mime_type: visual.media_type.clone(), data: visual.data.to_vec() })
+}
+
+// Reads the ReplayGain tags of a revision, under their standard keys (ID3v2 TXXX, APE, MP4 and
+// Vorbis comments alike) or their Vorbis comment names.
+fn read_replay_gain(revision: &MetadataRevision) -> Option<ReplayGain> {
+	let tags: Vec<(&str, String)> = revision.tags().iter()
+		.map(|tag| {
+			let key = match tag.std_key {
+				Some(StandardTagKey::ReplayGainTrackGain) => "REPLAYGAIN_TRACK_GAIN",
+				Some(StandardTagKey::ReplayGainTrackPeak) => "REPLAYGAIN_TRACK_PEAK",
+				Some(StandardTagKey::ReplayGainAlbumGain) => "REPLAYGAIN_ALBUM_GAIN",
+				Some(StandardTagKey::ReplayGainAlbumPeak) => "REPLAYGAIN_ALBUM_PEAK",
+				_ => tag.key.as_str()
+			};
+			(key, tag.value.to_string())
+		})
+		.collect();
+	ReplayGain::from_tags(tags.iter().map(|(key, value)| (*key, value.as_str())))
+}
+
+// Mixes frames of `from` channels to `to`, appending them to `output`. Fewer channels are made up
+// by repeating the ones there are in order, as ChannelFill::Duplicate does, so mono plays on both
+// sides of a stereo stream; more are folded down, each output channel averaging the input
+// channels that land on it in turn.
+fn remix_channels(input: &[f32], from: usize, to: usize, output: &mut Vec<f32>) {
+	output.reserve(input.len() / from * to);
+	for frame in input.chunks_exact(from) {
+		if from < to {
+			output.extend(frame.iter().cycle().take(to));
+			continue;
+		}
+		for channel in 0..to {
+			let folded = frame.iter().skip(channel).step_by(to);
+			let count = folded.clone().count();
+			output.push(folded.sum::<f32>() / count as f32);
+		}
+	}
+}
+
+impl AudioSource for SymphoniaSource {
+	fn sample_rate(&self) -> u32 {
+		self.sample_rate
+	}
+
+	fn channels(&self) -> u16 {
+		self.channels
+	}
+
+	fn read_block(&mut self, buffer: &mut Vec<f32>) -> Result<bool, AudioError> {
+		loop {
+			let packet = match self.format.next_packet() {
+				Ok(packet) => packet,
+				// symphonia reports the end of the stream as an unexpected EOF.
+				Err(Error::IoError(err)) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(false),
+				Err(err) => return Err(err.into())
+			};
+			if packet.track_id() != self.track_id {
+				continue;
+			}
+
+			match self.decoder.decode(&packet) {
+				Ok(_) => self.decode_errors = 0,
+				// A corrupt packet only costs one frame of audio; keep going. Packets that keep
+				// failing may be in a layout the decoder won't change to, so a new one is tried.
+				Err(Error::DecodeError(_)) => {
+					self.decode_errors += 1;
+					if self.decode_errors < RENEW_DECODER_ERRORS || !self.renew_decoder(&packet)? {
+						continue;
+					}
+				},
+				Err(err) => return Err(err.into())
+			}
+			let decoded = self.decoder.last_decoded();
+
+			let capacity = decoded.capacity();
+			let decoded_channels = decoded.spec().channels.count().max(1) as u16;
+			let sample_buffer = match &mut self.sample_buffer {
+				Some(sample_buffer) if decoded_channels == self.decoded_channels && sample_buffer.capacity() >= capacity * decoded_channels as usize => sample_buffer,
+				sample_buffer => sample_buffer.insert(SampleBuffer::new(capacity as u64, *decoded.spec()))
+			};
+			self.decoded_channels = decoded_channels;
+			sample_buffer.copy_interleaved_ref(decoded);
+
+			let samples = sample_buffer.samples();
+			let skip = (self.skip_frames as usize * decoded_channels as usize).min(samples.len());
+			self.skip_frames -= (skip / decoded_channels as usize) as u64;
+			if skip == samples.len() {
+				continue;
+			}
+			// The rest of the pipeline was set up for the channels the stream started with, so
+			// packets in another layout are mixed to it.
+			if decoded_channels == self.channels {
+				buffer.extend_from_slice(&samples[skip..]);
+			} else {
+				remix_channels(&samples[skip..], decoded_channels as usize, self.channels as usize, buffer);
+			}
+			return Ok(true);
+		}
+	}
+
+	fn cover_art(&self) -> Option<&CoverArt> {
+		self.cover_art.as_ref()
+	}
+
+	fn chapters(&self) -> &[Chapter] {
+		&self.chapters
+	}
+
+	fn audio_tracks(&self) -> Vec<AudioTrackInfo> {
+		self.audio_tracks.clone()
+	}
+
+	fn select_audio_track(&mut self, index: usize) -> Result<(), AudioError> {
+		let track = audio_tracks(&*self.format).get(index).copied()
+			.ok_or_else(|| AudioError::InvalidArgument(format!("audio track {} is out of range, the source has {}", index, self.audio_tracks.len())))?;
+		let track_id = track.id;
+		let (decoder, sample_rate, channels) = open_decoder(track)?;
+		self.track_id = track_id;
+		self.decoder = decoder;
+		self.sample_rate = sample_rate;
+		self.channels = channels;
+		self.decoded_channels = channels;
+		self.sample_buffer = None;
+		Ok(())
+	}
+
+	fn broadcast_info(&self) -> Option<&BroadcastInfo> {
+		self.broadcast.as_ref()
+	}
+
+	fn replay_gain(&self) -> Option<&ReplayGain> {
+		self.replay_gain.as_ref()
+	}
+
+	fn decoded_channels(&self) -> u16 {
+		self.decoded_channels
+	}
+
+	fn bytes_read(&self) -> u64 {
+		self.bytes_read.load(Ordering::Relaxed)
+	}
+
+	fn is_seekable(&self) -> bool {
+		self.seekable
+	}
+
+	// symphonia seeks to the packet containing the target; the remainder is skipped sample
+	// accurately in read_block.
+	fn seek(&mut self, frame: u64) -> Result<(), AudioError> {
+		let time = Time::new(frame / self.sample_rate as u64, (frame % self.sample_rate as u64) as f64 / self.sample_rate as f64);
+		let seeked = match self.format.seek(SeekMode::Accurate, SeekTo::Time { time, track_id: Some(self.track_id) }) {
+			Ok(seeked) => seeked,
+			Err(Error::SeekError(SeekErrorKind::Unseekable)) => return Err(AudioError::Unsupported(String::from("the reader can't seek"))),
+			Err(err) => return Err(err.into())
+		};
+		self.decoder.reset();
+
+		let actual_frame = match self.format.tracks().iter().find(|track| track.id == self.track_id).and_then(|track| track.codec_params.time_base) {
+			Some(time_base) => {
+				let actual = time_base.calc_time(seeked.actual_ts);
+				actual.seconds * self.sample_rate as u64 + (actual.frac * self.sample_rate as f64) as u64
+			},
+			None => seeked.actual_ts
+		};
+		self.skip_frames = frame.saturating_sub(actual_frame);
+		Ok(())
+	}
+}
+
+// Adapts a caller's reader to symphonia's MediaSource, which has to be Sync. The reader is only
+// ever used through &mut, so the mutex is never actually locked.
+struct ReaderSource<R> {
+	reader: Mutex<R>,
+	seekable: bool,
+}
+
+impl<R: Seek> ReaderSource<R> {
+	fn new(mut reader: R) -> ReaderSource<R> {
+		let seekable = reader.stream_position().is_ok();
+		ReaderSource { reader: Mutex::new(reader), seekable }
+	}
+
+	fn reader(&mut self) -> &mut R {
+		self.reader.get_mut().unwrap_or_else(PoisonError::into_inner)
+	}
+}
+
+impl<R: Read + Seek> Read for ReaderSource<R> {
+	fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+		self.reader().read(buf)
+	}
+}
+
+impl<R: Read + Seek> Seek for ReaderSource<R> {
+	fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+		self.reader().seek(pos)
+	}
+}
+
+// Keeps what a file tells symphonia about itself, e.g. its length for estimating an MP3's
+// duration.
+impl<R: MediaSource> MediaSource for CountingReader<R> {
+	fn is_seekable(&self) -> bool {
+		self.get_ref().is_seekable()
+	}
+
+	fn byte_len(&self) -> Option<u64> {
+		self.get_ref().byte_len()
+	}
+}
+
+impl<R: Read + Seek + Send> MediaSource for ReaderSource<R> {
+	fn is_seekable(&self) -> bool {
+		self.seekable
+	}
+
+	fn byte_len(&self) -> Option<u64> {
+		None
+	}
+}
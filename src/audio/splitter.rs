@@ -0,0 +1,52 @@
+/* Splitter: Fans one stream of interleaved f32 samples out to several bounded queues, e.g. an
+ * input device feeding both the output device and a recorder. Other items, such as whole blocks,
+ * can be split the same way.
+ *
+ * push never blocks, so it is safe to call from a device callback. A block that doesn't fit into
+ * an output's queue is dropped for that output only, whole, so a slow reader loses audio but never
+ * falls out of step with the channel layout; the other outputs are unaffected.
+ */
+pub(crate) struct Splitter<T = f32> {
+	outputs: Vec<crossbeam_channel::Sender<T>>,
+	// A receiving end of every output, kept for clear.
+	receivers: Vec<crossbeam_channel::Receiver<T>>,
+}
+
+impl<T: Clone> Splitter<T> {
+	pub(crate) fn new() -> Splitter<T> {
+		Splitter { outputs: Vec::new(), receivers: Vec::new() }
+	}
+
+	// Adds an output holding up to `capacity` items and returns its receiving end.
+	pub(crate) fn add_output(&mut self, capacity: usize) -> crossbeam_channel::Receiver<T> {
+		let (sender, receiver) = crossbeam_channel::bounded(capacity.max(1));
+		self.outputs.push(sender);
+		self.receivers.push(receiver.clone());
+		receiver
+	}
+
+	// Throws away everything still queued on every output.
+	pub(crate) fn clear(&self) {
+		for receiver in &self.receivers {
+			while receiver.try_recv().is_ok() {}
+		}
+	}
+
+	// Copies `samples` to every output with room for all of them. The splitter keeps a receiving
+	// end of its own for clear, so an output whose reader is gone isn't disconnected: it fills up
+	// and is then passed over like any full one, until clear empties it again.
+	pub(crate) fn push(&self, samples: &[T]) {
+		for output in &self.outputs {
+			// Only push calls send, so the room found here can't shrink before the block is in.
+			let room = output.capacity().unwrap_or(usize::MAX) - output.len();
+			if room < samples.len() {
+				continue;
+			}
+			for sample in samples {
+				if output.try_send(sample.clone()).is_err() {
+					break;
+				}
+			}
+		}
+	}
+}
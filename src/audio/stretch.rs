@@ -0,0 +1,165 @@
+use std::ops::RangeInclusive;
+
+// Tempo ratios set_tempo accepts. Beyond these WSOLA smears transients badly.
+pub(super) const TEMPO_RANGE: RangeInclusive<f32> = 0.25..=4.0;
+
+// Length of the crossfade between consecutive segments, which is also the output hop.
+const OVERLAP_MS: u32 = 12;
+// How far either side of the nominal input position a segment may be moved to line up with the
+// previous one.
+const TOLERANCE_MS: u32 = 6;
+// Trim consumed input once this many overlaps worth of frames have piled up in front of it.
+const TRIM_OVERLAPS: usize = 4;
+
+/* TimeStretch: Streaming WSOLA (waveform similarity overlap-add) tempo changer for interleaved f32
+ * audio.
+ *
+ * Output is built in hops of one overlap. For each hop the input is read from a position that
+ * advances by `overlap * tempo`, nudged within the tolerance to the offset whose waveform best
+ * matches the natural continuation of the previous segment, and crossfaded into it. Segments are
+ * played at their original speed, so the pitch is untouched while the length scales by 1 / tempo.
+ */
+pub(super) struct TimeStretch {
+	channels: usize,
+	tempo: f64,
+	overlap: usize,
+	tolerance: usize,
+	// Interleaved input frames that may still be read.
+	buffer: Vec<f32>,
+	// Nominal input position of the last emitted segment, in frames relative to `buffer`.
+	nominal: f64,
+	// Where the last emitted segment continues, in frames relative to `buffer`.
+	continuation: usize,
+	started: bool,
+}
+
+impl TimeStretch {
+	pub(super) fn new(channels: u16, sample_rate: u32, tempo: f32) -> TimeStretch {
+		TimeStretch {
+			channels: channels.max(1) as usize,
+			tempo: tempo as f64,
+			overlap: (sample_rate * OVERLAP_MS / 1000).max(1) as usize,
+			tolerance: (sample_rate * TOLERANCE_MS / 1000) as usize,
+			buffer: Vec::new(),
+			nominal: 0.0,
+			continuation: 0,
+			started: false,
+		}
+	}
+
+	pub(super) fn tempo(&self) -> f32 {
+		self.tempo as f32
+	}
+
+	// Takes effect from the next hop, so the change is seamless.
+	pub(super) fn set_tempo(&mut self, tempo: f32) {
+		self.tempo = tempo as f64;
+	}
+
+	pub(super) fn process(&mut self, input: &[f32], output: &mut Vec<f32>) {
+		self.buffer.extend_from_slice(input);
+		let channels = self.channels;
+		let overlap = self.overlap;
+
+		loop {
+			let frames = self.buffer.len() / channels;
+			if !self.started {
+				if frames < overlap {
+					return;
+				}
+				output.extend_from_slice(&self.buffer[..overlap * channels]);
+				self.continuation = overlap;
+				self.started = true;
+				continue;
+			}
+
+			let nominal = self.nominal + overlap as f64 * self.tempo;
+			let search_end = nominal as usize + self.tolerance;
+			if search_end + overlap > frames || self.continuation + overlap > frames {
+				break;
+			}
+
+			let segment = self.best_segment(nominal);
+			let fade = &self.buffer[self.continuation * channels..(self.continuation + overlap) * channels];
+			let rise = &self.buffer[segment * channels..(segment + overlap) * channels];
+			for frame in 0..overlap {
+				let weight = (frame as f32 + 0.5) / overlap as f32;
+				for channel in 0..channels {
+					let index = frame * channels + channel;
+					output.push(fade[index] * (1.0 - weight) + rise[index] * weight);
+				}
+			}
+			self.nominal = nominal;
+			self.continuation = segment + overlap;
+		}
+
+		self.trim();
+	}
+
+	// Emits what is left of the input, keeping the overall length at about input / tempo.
+	pub(super) fn flush(&mut self, output: &mut Vec<f32>) {
+		let frames = self.buffer.len() / self.channels;
+		let (from, remaining) = if self.started {
+			let consumed = self.nominal + self.overlap as f64 * self.tempo;
+			(self.continuation, ((frames as f64 - consumed) / self.tempo).max(0.0))
+		} else {
+			(0, frames as f64 / self.tempo)
+		};
+		let to = (from + remaining.round() as usize).min(frames);
+		if from < to {
+			output.extend_from_slice(&self.buffer[from * self.channels..to * self.channels]);
+		}
+		self.buffer.clear();
+		self.nominal = 0.0;
+		self.continuation = 0;
+		self.started = false;
+	}
+
+	// Finds the segment start within the tolerance of `nominal` whose waveform correlates best
+	// with the previous segment's continuation. Offset 0 is tried first so a tempo of 1.0 plays
+	// the input back unchanged.
+	fn best_segment(&self, nominal: f64) -> usize {
+		let nominal = nominal as usize;
+		let mut best = nominal;
+		let mut best_score = self.similarity(nominal);
+		for distance in 1..=self.tolerance {
+			for &candidate in [nominal.checked_sub(distance), Some(nominal + distance)].iter().flatten() {
+				let score = self.similarity(candidate);
+				if score > best_score {
+					best_score = score;
+					best = candidate;
+				}
+			}
+		}
+		best
+	}
+
+	// Normalised cross-correlation of the mono mix, sampled every other frame to halve the cost.
+	fn similarity(&self, candidate: usize) -> f32 {
+		let mut dot = 0.0;
+		let mut energy = 0.0;
+		for frame in (0..self.overlap).step_by(2) {
+			let target = self.mono(self.continuation + frame);
+			let sample = self.mono(candidate + frame);
+			dot += target * sample;
+			energy += sample * sample;
+		}
+		dot / (energy + 1e-9f32).sqrt()
+	}
+
+	fn mono(&self, frame: usize) -> f32 {
+		self.buffer[frame * self.channels..(frame + 1) * self.channels].iter().sum()
+	}
+
+	// Drops the frames no future hop can read.
+	fn trim(&mut self) {
+		let next_nominal = self.nominal + self.overlap as f64 * self.tempo;
+		let keep_from = (next_nominal as usize).saturating_sub(self.tolerance).min(self.continuation);
+		if keep_from < self.overlap * TRIM_OVERLAPS {
+			return;
+		}
+		self.buffer.drain(..keep_from * self.channels);
+		self.nominal -= keep_from as f64;
+		self.continuation -= keep_from;
+	}
+}
@@ -0,0 +1,75 @@
+use super::AudioError;
+
+// Mixer controls tried in turn for the master volume. Cards without a Master control usually
+// expose the output level as PCM.
+#[cfg(all(feature = "system-volume", target_os = "linux"))]
+const MASTER_CONTROLS: [&str; 2] = ["Master", "PCM"];
+
+// The OS master volume of the default output as a fraction of the mixer's range, 0.0 to 1.0, as
+// amixer shows it in percent, e.g. so a media-key handler can show the real system level. This is
+// separate from AudioCable::set_volume, which only scales the cable's own samples. With several
+// channels the first one is reported. Needs the system-volume feature and ALSA; returns
+// Unsupported elsewhere.
+#[cfg(all(feature = "system-volume", target_os = "linux"))]
+pub fn system_volume() -> Result<f32, AudioError> {
+	with_master_control(|control| {
+		let (min, max) = control.get_playback_volume_range();
+		let volume = control.get_playback_volume(alsa::mixer::SelemChannelId::mono()).map_err(mixer_error)?;
+		Ok(if max > min { (volume - min) as f32 / (max - min) as f32 } else { 0.0 })
+	})
+}
+
+// Sets every channel of the OS master volume of the default output, `volume` being a fraction of
+// the mixer's range as for system_volume. This changes the level for every application.
+#[cfg(all(feature = "system-volume", target_os = "linux"))]
+pub fn set_system_volume(volume: f32) -> Result<(), AudioError> {
+	check_volume(volume)?;
+	with_master_control(|control| {
+		let (min, max) = control.get_playback_volume_range();
+		let value = min + ((max - min) as f32 * volume).round() as i64;
+		control.set_playback_volume_all(value).map_err(mixer_error)
+	})
+}
+
+fn check_volume(volume: f32) -> Result<(), AudioError> {
+	if !(0.0..=1.0).contains(&volume) {
+		return Err(AudioError::InvalidArgument(format!("the system volume must be between 0 and 1, got {}", volume)));
+	}
+	Ok(())
+}
+
+#[cfg(all(feature = "system-volume", target_os = "linux"))]
+fn with_master_control<T>(f: impl FnOnce(&alsa::mixer::Selem) -> Result<T, AudioError>) -> Result<T, AudioError> {
+	let mixer = alsa::mixer::Mixer::new("default", false).map_err(mixer_error)?;
+	let control = MASTER_CONTROLS.iter()
+		.filter_map(|name| mixer.find_selem(&alsa::mixer::SelemId::new(name, 0)))
+		.find(|control| control.has_playback_volume())
+		.ok_or_else(|| AudioError::Unsupported(String::from("the default mixer has no master volume control")))?;
+	f(&control)
+}
+
+#[cfg(all(feature = "system-volume", target_os = "linux"))]
+fn mixer_error(err: alsa::Error) -> AudioError {
+	AudioError::Device(format!("error accessing the system mixer: {}", err))
+}
+
+// Only the ALSA mixer is supported so far.
+#[cfg(not(all(feature = "system-volume", target_os = "linux")))]
+pub fn system_volume() -> Result<f32, AudioError> {
+	Err(unsupported())
+}
+
+#[cfg(not(all(feature = "system-volume", target_os = "linux")))]
+pub fn set_system_volume(volume: f32) -> Result<(), AudioError> {
+	check_volume(volume)?;
+	Err(unsupported())
+}
+
+#[cfg(not(all(feature = "system-volume", target_os = "linux")))]
+fn unsupported() -> AudioError {
+	if cfg!(target_os = "linux") {
+		AudioError::Unsupported(String::from("the system volume needs the system-volume feature"))
+	} else {
+		AudioError::Unsupported(String::from("the system volume is only available through ALSA on Linux"))
+	}
+}
@@ -0,0 +1,53 @@
+use std::sync::atomic::{fence, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/* PlaybackTimestamp: The latest frame/time pair the output callback saw, for AV sync.
+ *
+ * Written by the callback and read from any thread without locking: a sequence counter that is
+ * odd while a write is under way lets readers retry instead of seeing a frame from one callback
+ * with the time of another. Times are kept as nanoseconds since `epoch` so they fit an atomic.
+ */
+pub(super) struct PlaybackTimestamp {
+	epoch: Instant,
+	sequence: AtomicU64,
+	frame: AtomicU64,
+	nanos: AtomicU64,
+}
+
+impl PlaybackTimestamp {
+	pub(super) fn new() -> PlaybackTimestamp {
+		PlaybackTimestamp {
+			epoch: Instant::now(),
+			sequence: AtomicU64::new(0),
+			frame: AtomicU64::new(0),
+			nanos: AtomicU64::new(0),
+		}
+	}
+
+	// Records that `frame` reaches the speakers at `at`. Only the output callback calls this.
+	pub(super) fn store(&self, frame: u64, at: Instant) {
+		let nanos = at.saturating_duration_since(self.epoch).as_nanos() as u64;
+		self.sequence.fetch_add(1, Ordering::Relaxed);
+		fence(Ordering::Release);
+		self.frame.store(frame, Ordering::Relaxed);
+		self.nanos.store(nanos, Ordering::Relaxed);
+		self.sequence.fetch_add(1, Ordering::Release);
+	}
+
+	// The last pair stored, or None before the first callback.
+	pub(super) fn load(&self) -> Option<(u64, Instant)> {
+		loop {
+			let before = self.sequence.load(Ordering::Acquire);
+			if before == 0 {
+				return None;
+			}
+			let frame = self.frame.load(Ordering::Relaxed);
+			let nanos = self.nanos.load(Ordering::Relaxed);
+			fence(Ordering::Acquire);
+			if before & 1 == 0 && self.sequence.load(Ordering::Relaxed) == before {
+				return Some((frame, self.epoch + Duration::from_nanos(nanos)));
+			}
+			std::hint::spin_loop();
+		}
+	}
+}
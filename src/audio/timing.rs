@@ -0,0 +1,96 @@
+use super::AudioError;
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+// Callback durations queued for the timing hook; more than this and the newest are dropped, so a
+// slow hook never holds the callback up.
+const TIMING_QUEUE: usize = 64;
+
+type TimingHook = Box<dyn Fn(Duration) + Send>;
+
+/* CallbackTiming: How long the output callback took over a window, from
+ * AudioConsumer::callback_timing. The time covers everything the callback does: waiting for the
+ * samples, the effects, the volume and the safe output stage.
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CallbackTiming {
+	// Callbacks measured in the window.
+	pub callbacks: u64,
+	pub average: Duration,
+	pub max: Duration,
+}
+
+/* CallbackTimer: Collects the output callback's durations. The callback only does a few atomic
+ * adds per block, and a try_send while a hook is installed; the hook itself runs on its own
+ * thread, out of the realtime path.
+ */
+pub(super) struct CallbackTimer {
+	// The current window, in nanoseconds.
+	total: AtomicU64,
+	max: AtomicU64,
+	callbacks: AtomicU64,
+	hooked: AtomicBool,
+	durations: crossbeam_channel::Sender<Duration>,
+	// Taken by the hook thread when the first hook is installed.
+	receiver: Mutex<Option<crossbeam_channel::Receiver<Duration>>>,
+	hook: Arc<Mutex<Option<TimingHook>>>,
+}
+
+impl CallbackTimer {
+	pub(super) fn new() -> CallbackTimer {
+		let (durations, receiver) = crossbeam_channel::bounded(TIMING_QUEUE);
+		CallbackTimer {
+			total: AtomicU64::new(0),
+			max: AtomicU64::new(0),
+			callbacks: AtomicU64::new(0),
+			hooked: AtomicBool::new(false),
+			durations,
+			receiver: Mutex::new(Some(receiver)),
+			hook: Arc::new(Mutex::new(None)),
+		}
+	}
+
+	// Called by the output callback once per block.
+	pub(super) fn record(&self, elapsed: Duration) {
+		let nanos = elapsed.as_nanos().min(u64::MAX as u128) as u64;
+		self.total.fetch_add(nanos, Ordering::Relaxed);
+		self.max.fetch_max(nanos, Ordering::Relaxed);
+		self.callbacks.fetch_add(1, Ordering::Relaxed);
+		if self.hooked.load(Ordering::Relaxed) {
+			let _ = self.durations.try_send(elapsed);
+		}
+	}
+
+	// The timing since the previous call, which starts a new window.
+	pub(super) fn take(&self) -> CallbackTiming {
+		let callbacks = self.callbacks.swap(0, Ordering::Relaxed);
+		let total = self.total.swap(0, Ordering::Relaxed);
+		let max = self.max.swap(0, Ordering::Relaxed);
+		CallbackTiming {
+			callbacks,
+			average: Duration::from_nanos(total.checked_div(callbacks).unwrap_or(0)),
+			max: Duration::from_nanos(max),
+		}
+	}
+
+	// Installs `hook`, starting the thread that calls it the first time. The thread ends once the
+	// consumer and its stream are gone.
+	pub(super) fn set_hook(&self, hook: TimingHook) -> Result<(), AudioError> {
+		*self.hook.lock().unwrap_or_else(PoisonError::into_inner) = Some(hook);
+		let receiver = self.receiver.lock().unwrap_or_else(PoisonError::into_inner).take();
+		if let Some(receiver) = receiver {
+			let hook = Arc::clone(&self.hook);
+			std::thread::Builder::new().name(String::from("audios-timing")).spawn(move || {
+				for elapsed in receiver {
+					if let Some(hook) = &*hook.lock().unwrap_or_else(PoisonError::into_inner) {
+						hook(elapsed);
+					}
+				}
+			})?;
+		}
+		self.hooked.store(true, Ordering::Relaxed);
+		Ok(())
+	}
+}
@@ -0,0 +1,144 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+/* FrameReceiver: The output side of the data channel, which takes samples out of it a whole frame
+ * at a time.
+ *
+ * The channel carries interleaved f32 samples, one per slot, in the output's channel layout: the
+ * producer only ever sends whole frames of `channels`, so sample n of the stream belongs to channel
+ * n % channels. It sends them a sample at a time, though, so the output can catch it part way
+ * through a frame. Whatever of a frame has arrived when the channel runs dry is held back here
+ * until the rest of it comes, rather than played, so the silence an underrun plays in its place is
+ * always whole frames, and the rest of a frame a flush cut in two is thrown away after it. Either
+ * way every channel carries on where it was, however many there are.
+ */
+pub(super) struct FrameReceiver {
+	samples: crossbeam_channel::Receiver<f32>,
+	channels: usize,
+	// The start of a frame still being sent.
+	partial: Vec<f32>,
+	// Samples still to come of a frame clear threw the start of away.
+	skip: usize,
+	// The consumer's count of samples sent, lowered by what's thrown away.
+	sent: Arc<AtomicU64>,
+}
+
+impl FrameReceiver {
+	// Room for a partial frame is made up front, so the output callback never allocates.
+	pub(super) fn new(samples: crossbeam_channel::Receiver<f32>, channels: usize, sent: Arc<AtomicU64>) -> FrameReceiver {
+		let channels = channels.max(1);
+		FrameReceiver { samples, channels, partial: Vec::with_capacity(channels), skip: 0, sent }
+	}
+
+	// Changes the layout taken out of the channel, for a consumer switching outputs before
+	// anything has been received.
+	pub(super) fn set_channels(&mut self, channels: usize) {
+		self.channels = channels.max(1);
+		self.partial = Vec::with_capacity(self.channels);
+	}
+
+	// Fills `frame`, one frame long, with the next frame, waiting until `deadline`, if there is
+	// one, for samples that haven't arrived. Returns false, leaving `frame` to be played as
+	// silence, if the whole of it isn't there by then; the part that is is kept for the next call.
+	pub(super) fn recv_frame(&mut self, frame: &mut [f32], deadline: Option<Instant>) -> bool {
+		while self.skip > 0 {
+			if self.next(deadline).is_none() {
+				return false;
+			}
+			self.skip -= 1;
+			self.sent.fetch_sub(1, Ordering::Relaxed);
+		}
+
+		let mut filled = self.partial.len().min(frame.len());
+		frame[..filled].copy_from_slice(&self.partial[..filled]);
+		while filled < frame.len() {
+			match self.next(deadline) {
+				Some(sample) => {
+					frame[filled] = sample;
+					filled += 1;
+				},
+				None => {
+					self.partial.clear();
+					self.partial.extend_from_slice(&frame[..filled]);
+					return false;
+				}
+			}
+		}
+		self.partial.clear();
+		true
+	}
+
+	// Throws away everything queued, a partial frame included, for a flush while the producer may
+	// still be sending. If that ends part way through a frame, the producer goes on to send the rest
+	// of it, which is thrown away as it arrives.
+	pub(super) fn clear(&mut self) {
+		let cleared = self.discard();
+		self.skip = (self.skip + self.channels - cleared % self.channels) % self.channels;
+	}
+
+	// Throws away everything queued, as clear does, for the producer while it isn't sending. It
+	// won't send the rest of a frame it was part way through either, so the next sample it sends
+	// starts a frame.
+	pub(super) fn reset(&mut self) {
+		self.discard();
+		self.skip = 0;
+	}
+
+	fn discard(&mut self) -> usize {
+		let mut discarded = self.partial.len();
+		self.partial.clear();
+		while self.samples.try_recv().is_ok() {
+			discarded += 1;
+		}
+		self.sent.fetch_sub(discarded as u64, Ordering::Relaxed);
+		discarded
+	}
+
+	fn next(&self, deadline: Option<Instant>) -> Option<f32> {
+		match self.samples.try_recv() {
+			Ok(sample) => Some(sample),
+			Err(crossbeam_channel::TryRecvError::Empty) => deadline.and_then(|deadline| deadline.checked_duration_since(Instant::now()))
+				.and_then(|wait| self.samples.recv_timeout(wait).ok()),
+			Err(crossbeam_channel::TryRecvError::Disconnected) => None
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::FrameReceiver;
+
+	use std::sync::atomic::AtomicU64;
+	use std::sync::Arc;
+
+	#[test]
+	fn stereo_underrun_keeps_channels_aligned() {
+		let (sender, samples) = crossbeam_channel::unbounded();
+		let mut receiver = FrameReceiver::new(samples, 2, Arc::new(AtomicU64::new(0)));
+		// Left counts up from 1 and right is its negative, so a swap shows as a sign flip.
+		let frames: Vec<[f32; 2]> = (1..=8).map(|n| [n as f32, -(n as f32)]).collect();
+		let stream: Vec<f32> = frames.iter().flatten().copied().collect();
+
+		// Odd bursts run the channel dry part way through a frame, and each runs out in an underrun.
+		let mut played = Vec::new();
+		let mut underruns = 0;
+		let mut sent = 0;
+		for burst in [3, 5, 1, 7] {
+			for &sample in &stream[sent..sent + burst] {
+				sender.send(sample).unwrap();
+			}
+			sent += burst;
+			loop {
+				let mut frame = [0.0f32; 2];
+				if !receiver.recv_frame(&mut frame, None) {
+					underruns += 1;
+					break;
+				}
+				played.push(frame);
+			}
+		}
+		assert_eq!(underruns, 4);
+		assert_eq!(played, frames);
+	}
+}
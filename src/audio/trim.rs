@@ -0,0 +1,61 @@
+// Longest run of silence held back in case the source ends with it. Longer silent passages are
+// taken to be part of the programme and played.
+const MAX_HELD_SILENCE_SECONDS: u32 = 10;
+
+/* SilenceTrim: Drops the silence at the start and end of a source, e.g. a badly cut file with
+ * seconds of digital silence around the track.
+ *
+ * A frame is silent when every channel is at or below the threshold. Leading silent frames are
+ * dropped outright. Silent frames after that are held back until audible ones follow, so only
+ * the run the source ends with is never played. Up to MAX_HELD_SILENCE_SECONDS are held; beyond
+ * that the silence is let through, so at most that much trailing silence is trimmed.
+ */
+pub(super) struct SilenceTrim {
+	threshold: f32,
+	channels: usize,
+	// Set until the first audible frame from the start of the source.
+	leading: bool,
+	held: Vec<f32>,
+	max_held: usize,
+}
+
+impl SilenceTrim {
+	pub(super) fn new(threshold_db: f32, sample_rate: u32, channels: usize) -> SilenceTrim {
+		let channels = channels.max(1);
+		SilenceTrim {
+			threshold: 10f32.powf(threshold_db / 20.0),
+			channels,
+			leading: true,
+			held: Vec::new(),
+			max_held: (MAX_HELD_SILENCE_SECONDS * sample_rate) as usize * channels,
+		}
+	}
+
+	// Appends the part of `input` that should be played to `output`, which may include silence
+	// held back from earlier blocks.
+	pub(super) fn process(&mut self, input: &[f32], output: &mut Vec<f32>) {
+		for frame in input.chunks_exact(self.channels) {
+			let silent = frame.iter().all(|sample| sample.abs() <= self.threshold);
+			if silent {
+				if self.leading {
+					continue;
+				}
+				if self.held.len() >= self.max_held {
+					output.append(&mut self.held);
+				}
+				self.held.extend_from_slice(frame);
+			} else {
+				self.leading = false;
+				output.append(&mut self.held);
+				output.extend_from_slice(frame);
+			}
+		}
+	}
+
+	// Forgets the silence held from before a seek. Leading silence is only trimmed again when the
+	// seek went back to the start.
+	pub(super) fn seek(&mut self, to_start: bool) {
+		self.held.clear();
+		self.leading = to_start;
+	}
+}
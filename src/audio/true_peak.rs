@@ -0,0 +1,87 @@
+// Input frames each phase of the oversampling interpolator looks at. The frames played are held
+// back as long, so the gain can come down before an inter-sample peak rather than after it.
+const TAPS: usize = 12;
+// Output samples worked out per input sample.
+const OVERSAMPLING: usize = 4;
+// Where between the input frames the interpolator estimates the signal: each phase lands after
+// the frame CENTRE frames back, the first on it exactly.
+const CENTRE: usize = TAPS / 2;
+
+/* TruePeak: The detection path of SafeOutput::true_peak, for the safe output stage's Limiter.
+ *
+ * Peaks between samples, which a DAC's reconstruction filter brings out and a sample peak meter
+ * misses, are found by interpolating each channel 4x with a windowed sinc, as ITU-R BS.1770
+ * describes for true-peak meters, before the gain is worked out. Frames are delayed by 12 frames
+ * (250 µs at 48 kHz) so the gain that catches a peak is already in place across every frame that
+ * shapes it, and the limiter can keep the output under a dBTP ceiling.
+ */
+#[derive(Clone, Debug)]
+pub(super) struct TruePeak {
+	phases: [[f32; TAPS]; OVERSAMPLING],
+	channels: usize,
+	// The latest TAPS frames, after the normalisation gain, in a ring; the oldest is next to play.
+	history: Vec<f32>,
+	at: usize,
+	// The gain each of the latest frames needed to stay under the ceiling, in a ring too.
+	needed: [f32; TAPS + 1],
+	needed_at: usize,
+}
+
+impl TruePeak {
+	pub(super) fn new() -> TruePeak {
+		let mut phases = [[0.0f32; TAPS]; OVERSAMPLING];
+		for (phase, taps) in phases.iter_mut().enumerate() {
+			for (tap, coefficient) in taps.iter_mut().enumerate() {
+				let offset = tap as f64 - CENTRE as f64 + phase as f64 / OVERSAMPLING as f64;
+				let sinc = if offset == 0.0 { 1.0 } else { (std::f64::consts::PI * offset).sin() / (std::f64::consts::PI * offset) };
+				// A Hann window reaching zero just past the outermost taps.
+				let window = 0.5 + 0.5 * (std::f64::consts::PI * offset / (CENTRE as f64 + 1.0)).cos();
+				*coefficient = (sinc * window) as f32;
+			}
+			// Each phase passes DC unchanged, so a steady level reads as itself.
+			let sum: f32 = taps.iter().sum();
+			for coefficient in taps.iter_mut() {
+				*coefficient /= sum;
+			}
+		}
+		TruePeak { phases, channels: 0, history: Vec::new(), at: 0, needed: [1.0; TAPS + 1], needed_at: 0 }
+	}
+
+	// Limits `buffer` in place like Limiter::process, but against the interpolated peaks, with
+	// `reduction` the limiter's and `release` its per-frame recovery.
+	pub(super) fn process(&mut self, buffer: &mut [f32], channels: u16, gain: f32, ceiling: f32, release: f32, reduction: &mut f32) {
+		let channels = channels.max(1) as usize;
+		// Only allocates for the first block, or if the layout changes.
+		if self.channels != channels {
+			self.channels = channels;
+			self.history = vec![0.0; TAPS * channels];
+			self.at = 0;
+		}
+		for frame in buffer.chunks_mut(channels) {
+			let slot = self.at;
+			self.at = (slot + 1) % TAPS;
+			let mut peak = 0.0f32;
+			for (channel, sample) in frame.iter_mut().enumerate() {
+				// The frame TAPS back plays now, and this one takes its place in the history.
+				let played = std::mem::replace(&mut self.history[slot * channels + channel], *sample * gain);
+				*sample = played;
+				for taps in &self.phases {
+					let mut interpolated = 0.0f32;
+					for (tap, coefficient) in taps.iter().enumerate() {
+						interpolated += coefficient * self.history[(slot + TAPS - tap) % TAPS * channels + channel];
+					}
+					peak = peak.max(interpolated.abs());
+				}
+			}
+
+			self.needed[self.needed_at] = if peak > ceiling { ceiling / peak } else { 1.0 };
+			self.needed_at = (self.needed_at + 1) % self.needed.len();
+			// Every frame the interpolator has seen since this one went in had a say in its peaks.
+			let needed = self.needed.iter().fold(1.0f32, |needed, &frame| needed.min(frame));
+			*reduction = if needed < *reduction { needed } else { needed + (*reduction - needed) * release };
+			for sample in frame.iter_mut() {
+				*sample *= *reduction;
+			}
+		}
+	}
+}
@@ -0,0 +1,46 @@
+use super::source::AudioSource;
+use super::AudioError;
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// How long a restart waits before opening the source again, e.g. for a station that dropped the
+// connection to come back.
+pub(super) const RESTART_DELAY: Duration = Duration::from_secs(1);
+// A failure this long after the last restart starts the count over, so a player left running for
+// days isn't worn down by the odd one.
+const RESTART_WINDOW: Duration = Duration::from_secs(60);
+
+// Connects to a source that has no file to reopen, such as an internet radio stream, again.
+pub(super) type Reopen = Arc<dyn Fn() -> Result<Box<dyn AudioSource>, AudioError> + Send + Sync>;
+
+/* Watchdog: Counts the restarts of a decode thread whose source failed, by panicking or with a
+ * decode error, for AudioCableBuilder::watchdog. Up to `limit` are allowed in a row; a failure
+ * a minute or more after the last restart counts as the first again.
+ */
+pub(super) struct Watchdog {
+	limit: u32,
+	restarts: u32,
+	last_restart: Option<Instant>,
+}
+
+impl Watchdog {
+	pub(super) fn new(limit: u32) -> Watchdog {
+		Watchdog { limit, restarts: 0, last_restart: None }
+	}
+
+	// Counts another restart and returns which one in a row it is, or None once the limit is
+	// used up.
+	pub(super) fn restart(&mut self) -> Option<u32> {
+		let now = Instant::now();
+		if self.last_restart.is_some_and(|at| now.duration_since(at) >= RESTART_WINDOW) {
+			self.restarts = 0;
+		}
+		if self.restarts >= self.limit {
+			return None;
+		}
+		self.restarts += 1;
+		self.last_restart = Some(now);
+		Some(self.restarts)
+	}
+}
@@ -0,0 +1,87 @@
+use super::render::DecodedBlocks;
+use super::AudioError;
+
+use std::path::Path;
+
+// The accumulator keeps at least this many peaks per requested bucket, so bucket edges fall
+// within a sixteenth of a bucket of where they belong.
+const PEAKS_PER_BUCKET: usize = 16;
+
+/* PeakAccumulator: Min/max peaks of a file whose length isn't known until it has been decoded.
+ *
+ * Every peak covers `span` frames. Once there are twice `limit` of them, neighbours are merged
+ * in pairs and the span doubles, so memory stays bounded however long the file is.
+ */
+struct PeakAccumulator {
+	peaks: Vec<(f32, f32)>,
+	limit: usize,
+	span: usize,
+	// The peak of the frames since the last full span, and how many there were.
+	current: (f32, f32),
+	frames: usize,
+}
+
+impl PeakAccumulator {
+	fn new(limit: usize) -> PeakAccumulator {
+		PeakAccumulator { peaks: Vec::new(), limit, span: 1, current: (0.0, 0.0), frames: 0 }
+	}
+
+	fn process(&mut self, block: &[f32], channels: u16) {
+		for frame in block.chunks(channels.max(1) as usize) {
+			if self.frames == 0 {
+				self.current = (frame[0], frame[0]);
+			}
+			for &sample in frame {
+				self.current = (self.current.0.min(sample), self.current.1.max(sample));
+			}
+			self.frames += 1;
+			if self.frames == self.span {
+				self.push();
+			}
+		}
+	}
+
+	fn push(&mut self) {
+		self.peaks.push(self.current);
+		self.frames = 0;
+		if self.peaks.len() >= self.limit * 2 {
+			self.peaks = self.peaks.chunks(2).map(merge).collect();
+			self.span *= 2;
+		}
+	}
+
+	// Spreads the peaks over `buckets`. When the file has fewer frames than buckets, each frame is
+	// repeated over several.
+	fn finish(mut self, buckets: usize) -> Vec<(f32, f32)> {
+		if self.frames > 0 {
+			self.peaks.push(self.current);
+		}
+		let len = self.peaks.len();
+		if len == 0 {
+			return vec![(0.0, 0.0); buckets];
+		}
+		(0..buckets).map(|bucket| {
+			let start = bucket * len / buckets;
+			let end = ((bucket + 1) * len / buckets).max(start + 1);
+			merge(&self.peaks[start..end])
+		}).collect()
+	}
+}
+
+fn merge(peaks: &[(f32, f32)]) -> (f32, f32) {
+	peaks.iter().skip(1).fold(peaks[0], |(min, max), &(low, high)| (min.min(low), max.max(high)))
+}
+
+// Decodes the file at `path` and returns the lowest and highest sample of each of `buckets`
+// equal stretches of it, for drawing a waveform overview. All channels go into the same peaks.
+// A file with fewer frames than buckets has its frames repeated; a silent or empty one reads
+// (0.0, 0.0) throughout.
+pub fn waveform_peaks(path: &Path, buckets: usize) -> Result<Vec<(f32, f32)>, AudioError> {
+	let blocks = DecodedBlocks::open(path)?;
+	let channels = blocks.channels();
+	let mut peaks = PeakAccumulator::new(buckets.max(1) * PEAKS_PER_BUCKET);
+	for block in blocks {
+		peaks.process(&block?, channels);
+	}
+	Ok(peaks.finish(buckets))
+}
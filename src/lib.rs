@@ -1 +1,3 @@
-pub mod audio;
\ No newline at end of file
+#![allow(clippy::upper_case_acronyms, clippy::new_ret_no_self)]
+
+pub mod audio;